@@ -0,0 +1,112 @@
+//! `#[derive(ValueSchema)]`, re-exported by `serde-value` with the `derive` feature.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, GenericParam};
+
+fn schema_fn(ty: &syn::Type) -> Tokens {
+    quote!(<#ty as ::serde_value::ValueSchema>::schema as ::serde_value::SchemaFn)
+}
+
+fn field_names(fields: &syn::FieldsNamed) -> Vec<String> {
+    fields
+        .named
+        .iter()
+        .map(|f| f.ident.as_ref().unwrap().to_string())
+        .collect()
+}
+
+fn schema_fns<'a, I: IntoIterator<Item = &'a syn::Field>>(fields: I) -> Vec<Tokens> {
+    fields.into_iter().map(|f| schema_fn(&f.ty)).collect()
+}
+
+fn struct_schema(name: &str, fields: &Fields) -> Tokens {
+    match *fields {
+        Fields::Named(ref named) => {
+            let names = field_names(named);
+            let schemas = schema_fns(&named.named);
+            quote!(::serde_value::Schema::Struct {
+                name: #name,
+                fields: &[#(#names),*],
+                schemas: vec![#(#schemas),*],
+            })
+        }
+        Fields::Unnamed(ref unnamed) if unnamed.unnamed.len() == 1 => {
+            let schema = schema_fn(&unnamed.unnamed[0].ty);
+            quote!(::serde_value::Schema::Newtype(#name, #schema))
+        }
+        Fields::Unnamed(ref unnamed) => {
+            let schemas = schema_fns(&unnamed.unnamed);
+            quote!(::serde_value::Schema::TupleStruct(#name, vec![#(#schemas),*]))
+        }
+        Fields::Unit => quote!(::serde_value::Schema::UnitStruct(#name)),
+    }
+}
+
+fn variant_schema(fields: &Fields) -> Tokens {
+    match *fields {
+        Fields::Named(ref named) => {
+            let names = field_names(named);
+            let schemas = schema_fns(&named.named);
+            quote!(::serde_value::VariantSchema::Struct {
+                fields: &[#(#names),*],
+                schemas: vec![#(#schemas),*],
+            })
+        }
+        Fields::Unnamed(ref unnamed) if unnamed.unnamed.len() == 1 => {
+            let schema = schema_fn(&unnamed.unnamed[0].ty);
+            quote!(::serde_value::VariantSchema::Newtype(#schema))
+        }
+        Fields::Unnamed(ref unnamed) => {
+            let schemas = schema_fns(&unnamed.unnamed);
+            quote!(::serde_value::VariantSchema::Tuple(vec![#(#schemas),*]))
+        }
+        Fields::Unit => quote!(::serde_value::VariantSchema::Unit),
+    }
+}
+
+/// Implements `ValueSchema` from the shape of a struct or enum, as serde's derives encode it
+/// without attributes.
+#[proc_macro_derive(ValueSchema)]
+pub fn derive_value_schema(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let schema = match input.data {
+        Data::Struct(ref data) => struct_schema(&name, &data.fields),
+        Data::Enum(ref data) => {
+            let variants: Vec<String> = data.variants.iter().map(|v| v.ident.to_string()).collect();
+            let kinds = data.variants.iter().map(|v| variant_schema(&v.fields));
+            quote!(::serde_value::Schema::Enum {
+                name: #name,
+                variants: &[#(#variants),*],
+                kinds: vec![#(#kinds),*],
+            })
+        }
+        Data::Union(ref data) => {
+            return syn::Error::new(data.union_token.span, "unions have no ValueSchema")
+                .to_compile_error()
+                .into();
+        }
+    };
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(ref mut ty) = *param {
+            ty.bounds.push(parse_quote!(::serde_value::ValueSchema));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::serde_value::ValueSchema for #ident #ty_generics #where_clause {
+            fn schema() -> ::serde_value::Schema {
+                #schema
+            }
+        }
+    };
+    expanded.into()
+}
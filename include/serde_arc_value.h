@@ -0,0 +1,72 @@
+/* C interface of serde-value, built with the `ffi` feature. See src/ffi.rs. */
+
+#ifndef SERDE_ARC_VALUE_H
+#define SERDE_ARC_VALUE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct serde_arc_value_t serde_arc_value_t;
+typedef struct serde_arc_dedup_t serde_arc_dedup_t;
+
+typedef enum serde_arc_value_kind_t {
+    SERDE_ARC_VALUE_NULL,
+    SERDE_ARC_VALUE_BOOL,
+    SERDE_ARC_VALUE_INTEGER,
+    SERDE_ARC_VALUE_FLOAT,
+    SERDE_ARC_VALUE_STRING,
+    SERDE_ARC_VALUE_BYTES,
+    SERDE_ARC_VALUE_SEQ,
+    SERDE_ARC_VALUE_MAP,
+    SERDE_ARC_VALUE_SET,
+    SERDE_ARC_VALUE_OTHER,
+} serde_arc_value_kind_t;
+
+/* building, every returned value must be freed */
+serde_arc_value_t *serde_arc_value_unit(void);
+serde_arc_value_t *serde_arc_value_bool(bool v);
+serde_arc_value_t *serde_arc_value_u64(uint64_t v);
+serde_arc_value_t *serde_arc_value_i64(int64_t v);
+serde_arc_value_t *serde_arc_value_f64(double v);
+serde_arc_value_t *serde_arc_value_string(const uint8_t *data, size_t len);
+serde_arc_value_t *serde_arc_value_bytes(const uint8_t *data, size_t len);
+serde_arc_value_t *serde_arc_value_seq(const serde_arc_value_t *const *items, size_t len);
+serde_arc_value_t *serde_arc_value_map(const serde_arc_value_t *const *keys,
+                                       const serde_arc_value_t *const *values, size_t len);
+serde_arc_value_t *serde_arc_value_clone(const serde_arc_value_t *v);
+void serde_arc_value_free(serde_arc_value_t *v);
+
+/* inspecting */
+bool serde_arc_value_eq(const serde_arc_value_t *a, const serde_arc_value_t *b);
+serde_arc_value_kind_t serde_arc_value_kind(const serde_arc_value_t *v);
+bool serde_arc_value_as_bool(const serde_arc_value_t *v, bool *out);
+bool serde_arc_value_as_u64(const serde_arc_value_t *v, uint64_t *out);
+bool serde_arc_value_as_i64(const serde_arc_value_t *v, int64_t *out);
+bool serde_arc_value_as_f64(const serde_arc_value_t *v, double *out);
+const uint8_t *serde_arc_value_as_str(const serde_arc_value_t *v, size_t *len);
+const uint8_t *serde_arc_value_as_bytes(const serde_arc_value_t *v, size_t *len);
+size_t serde_arc_value_len(const serde_arc_value_t *v);
+
+/* querying, returning NULL if there is nothing */
+serde_arc_value_t *serde_arc_value_at(const serde_arc_value_t *v, size_t index);
+serde_arc_value_t *serde_arc_value_key_at(const serde_arc_value_t *v, size_t index);
+serde_arc_value_t *serde_arc_value_value_at(const serde_arc_value_t *v, size_t index);
+serde_arc_value_t *serde_arc_value_get(const serde_arc_value_t *v, const uint8_t *key, size_t len);
+serde_arc_value_t *serde_arc_value_pointer(const serde_arc_value_t *v, const uint8_t *pointer,
+                                           size_t len);
+
+/* deduplication */
+serde_arc_dedup_t *serde_arc_dedup_new(void);
+void serde_arc_dedup_free(serde_arc_dedup_t *d);
+serde_arc_value_t *serde_arc_dedup_value(serde_arc_dedup_t *d, const serde_arc_value_t *v);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,443 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+
+use path;
+use pprof::ProfileBuilder;
+use report;
+use Value;
+
+/// Heap bytes of a value if nothing in it were shared.
+fn tree_bytes(value: &Value) -> usize {
+    match *value {
+        Value::String(ref v) | Value::UnitStruct(ref v) => report::string_size(v),
+        Value::Bytes(ref v) => report::blob_size(v),
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { ref data, .. } => report::blob_size(data),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => size_of::<Value>() + tree_bytes(v),
+        Value::Ext { ref value, .. } => size_of::<Value>() + tree_bytes(value),
+        Value::Seq(ref v) | Value::Tuple(ref v) => {
+            report::seq_size(v) + v.iter().map(tree_bytes).sum::<usize>()
+        }
+        Value::Set(ref v) => {
+            v.len() * size_of::<Value>() + v.iter().map(tree_bytes).sum::<usize>()
+        }
+        Value::Map(ref kv) => {
+            report::object_size(kv)
+                + report::seq_size(&kv.0)
+                + kv.0.iter().chain(kv.1.iter()).map(tree_bytes).sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+fn hash(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A HyperLogLog sketch, estimating the number of distinct hashes added in `2^precision`
+/// bytes, with a standard error of about `1.04 / sqrt(2^precision)`.
+#[derive(Clone, Debug)]
+struct HyperLogLog {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new(precision: u32) -> HyperLogLog {
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    fn insert(&mut self, hash: u64) {
+        let index = (hash >> (64 - self.precision)) as usize;
+        // the position of the first one bit in the remaining bits, capped for a hash of 0
+        let rank = ((hash << self.precision).leading_zeros() + 1).min(65 - self.precision) as u8;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank);
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-i32::from(r))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small counts
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Distinct {
+    // hashes rather than values, so the analysis does not keep the records alive
+    Exact(HashSet<u64>),
+    Approximate(HyperLogLog),
+}
+
+#[derive(Clone, Debug)]
+struct Accumulator {
+    occurrences: u64,
+    bytes: u64,
+    distinct_bytes: u64,
+    distinct: Distinct,
+}
+
+impl Accumulator {
+    fn new(precision: Option<u32>) -> Accumulator {
+        Accumulator {
+            occurrences: 0,
+            bytes: 0,
+            distinct_bytes: 0,
+            distinct: match precision {
+                Some(p) => Distinct::Approximate(HyperLogLog::new(p)),
+                None => Distinct::Exact(HashSet::new()),
+            },
+        }
+    }
+
+    fn distinct(&self) -> u64 {
+        match self.distinct {
+            Distinct::Exact(ref set) => set.len() as u64,
+            // never more than were seen, which the estimate can be for a handful of values
+            Distinct::Approximate(ref hll) => hll.estimate().min(self.occurrences),
+        }
+    }
+
+    fn distinct_bytes(&self) -> u64 {
+        match self.distinct {
+            Distinct::Exact(..) => self.distinct_bytes,
+            // assuming distinct values are as large as the average one
+            Distinct::Approximate(..) if self.occurrences > 0 => {
+                (self.bytes as f64 * self.distinct() as f64 / self.occurrences as f64) as u64
+            }
+            Distinct::Approximate(..) => 0,
+        }
+    }
+}
+
+/// Collects, for every path in a stream of records, how often values occur there and how
+/// much interning them would save. See `PathAnalysis::report`.
+///
+/// Paths are JSON pointers where `*` stands for any sequence index, so `/items/*/id` covers
+/// the `id` of every element of `items`. Options, newtypes and extension values are looked
+/// through, as in `from_value_at`.
+///
+/// The exact analysis keeps a hash of every distinct value per path. For corpora where that
+/// does not fit in memory, `approximate` estimates the distinct counts in fixed space.
+#[derive(Clone, Debug, Default)]
+pub struct PathAnalysis {
+    paths: BTreeMap<String, Accumulator>,
+    precision: Option<u32>,
+}
+
+impl PathAnalysis {
+    pub fn new() -> PathAnalysis {
+        PathAnalysis::default()
+    }
+
+    /// An analysis estimating the number of distinct values per path with a HyperLogLog
+    /// sketch of `2^precision` bytes, for a standard error of about
+    /// `1.04 / sqrt(2^precision)`, e.g. 1.6% for a precision of 12.
+    ///
+    /// The distinct bytes are then estimated from the average size of the values. Panics
+    /// unless `precision` is between 4 and 18.
+    pub fn approximate(precision: u32) -> PathAnalysis {
+        assert!((4..=18).contains(&precision), "precision must be between 4 and 18");
+        PathAnalysis {
+            paths: BTreeMap::new(),
+            precision: Some(precision),
+        }
+    }
+
+    /// Adds one record to the analysis.
+    pub fn add(&mut self, record: &Value) {
+        self.add_at(String::new(), record);
+    }
+
+    fn add_at(&mut self, path: String, value: &Value) {
+        let value = path::transparent(value);
+        match *value {
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                let child = path::join(&path, "*");
+                for x in v.iter() {
+                    self.add_at(child.clone(), x);
+                }
+            }
+            Value::Set(ref v) => {
+                let child = path::join(&path, "*");
+                for x in v.iter() {
+                    self.add_at(child.clone(), x);
+                }
+            }
+            Value::Map(ref kv) => {
+                for (k, v) in kv.0.iter().zip(kv.1.iter()) {
+                    self.add_at(path::join(&path, &path::key_segment(k)), v);
+                }
+            }
+            _ => {}
+        }
+        let bytes = tree_bytes(value) as u64;
+        let precision = self.precision;
+        let acc = self.paths.entry(path).or_insert_with(|| Accumulator::new(precision));
+        acc.occurrences += 1;
+        acc.bytes += bytes;
+        match acc.distinct {
+            Distinct::Exact(ref mut set) => {
+                if set.insert(hash(value)) {
+                    acc.distinct_bytes += bytes;
+                }
+            }
+            Distinct::Approximate(ref mut hll) => {
+                hll.insert(hash(value));
+            }
+        }
+    }
+
+    /// The bytes of the values at every path, as if none were shared, as a pprof profile.
+    ///
+    /// The stacks are the segments of the paths, below a `(root)` frame, so a flame graph
+    /// shows the record structure. The sample types are `space/bytes`, counting the bytes of
+    /// each path without those of the paths below it, so that pprof adds them up correctly,
+    /// and `values/count`, the number of values at the path.
+    pub fn to_pprof(&self) -> Vec<u8> {
+        let mut below: HashMap<&str, u64> = HashMap::new();
+        for (path, acc) in &self.paths {
+            if let Some(i) = path.rfind('/') {
+                *below.entry(&path[..i]).or_default() += acc.bytes;
+            }
+        }
+        let mut profile = ProfileBuilder::new(&[("space", "bytes"), ("values", "count")]);
+        for (path, acc) in &self.paths {
+            let bytes = below.get(path.as_str()).cloned().unwrap_or_default();
+            let mut stack = path::split(path).unwrap_or_default();
+            stack.insert(0, "(root)".to_owned());
+            stack.reverse();
+            profile.add(&stack, &[acc.bytes.saturating_sub(bytes), acc.occurrences]);
+        }
+        profile.finish()
+    }
+
+    /// Statistics for every path seen so far, the ones where interning saves the most first.
+    pub fn report(&self) -> Vec<PathStats> {
+        let mut stats: Vec<PathStats> = self
+            .paths
+            .iter()
+            .map(|(path, acc)| PathStats {
+                path: path.clone(),
+                occurrences: acc.occurrences,
+                distinct: acc.distinct(),
+                bytes: acc.bytes,
+                distinct_bytes: acc.distinct_bytes(),
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            b.saved_bytes()
+                .cmp(&a.saved_bytes())
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        stats
+    }
+}
+
+/// What `PathAnalysis` found for a single path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathStats {
+    pub path: String,
+    /// number of values at this path
+    pub occurrences: u64,
+    /// number of distinct values at this path, estimated by `PathAnalysis::approximate`
+    pub distinct: u64,
+    /// heap bytes of all values, as if none were shared
+    pub bytes: u64,
+    /// heap bytes of one copy of each distinct value, estimated by
+    /// `PathAnalysis::approximate`
+    pub distinct_bytes: u64,
+}
+
+impl PathStats {
+    /// Bytes that interning the values at this path would save at most.
+    pub fn saved_bytes(&self) -> u64 {
+        self.bytes - self.distinct_bytes
+    }
+}
+
+impl fmt::Display for PathStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} occurrences, {} distinct values, {} bytes -> {} bytes",
+            if self.path.is_empty() { "(root)" } else { &self.path },
+            self.occurrences,
+            self.distinct,
+            self.bytes,
+            self.distinct_bytes
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Counter {
+    value: Value,
+    count: u64,
+    error: u64,
+}
+
+/// The space-saving algorithm: at most `capacity` counters, where a value that is not
+/// counted yet replaces the one with the smallest count and inherits it as its error.
+#[derive(Clone, Debug)]
+struct SpaceSaving {
+    capacity: usize,
+    counters: Vec<Counter>,
+    slots: HashMap<Value, usize>,
+    // (count, slot), to find the smallest counter
+    by_count: BTreeSet<(u64, usize)>,
+    total: u64,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> SpaceSaving {
+        SpaceSaving {
+            capacity,
+            counters: Vec::with_capacity(capacity),
+            slots: HashMap::with_capacity(capacity),
+            by_count: BTreeSet::new(),
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, value: &Value) {
+        self.total += 1;
+        let slot = if let Some(&slot) = self.slots.get(value) {
+            slot
+        } else if self.counters.len() < self.capacity {
+            self.counters.push(Counter {
+                value: value.clone(),
+                count: 0,
+                error: 0,
+            });
+            self.slots.insert(value.clone(), self.counters.len() - 1);
+            self.counters.len() - 1
+        } else {
+            let &(min, slot) = self.by_count.iter().next().unwrap();
+            let evicted = &mut self.counters[slot];
+            self.slots.remove(&evicted.value);
+            evicted.value = value.clone();
+            evicted.error = min;
+            self.slots.insert(value.clone(), slot);
+            slot
+        };
+        let counter = &mut self.counters[slot];
+        self.by_count.remove(&(counter.count, slot));
+        counter.count += 1;
+        self.by_count.insert((counter.count, slot));
+    }
+
+    fn top(&self) -> Vec<HeavyHitter> {
+        let mut top: Vec<HeavyHitter> = self
+            .counters
+            .iter()
+            .map(|c| HeavyHitter {
+                value: c.value.clone(),
+                count: c.count,
+                error: c.error,
+            })
+            .collect();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        top
+    }
+}
+
+/// Finds the most frequent strings and records of a stream in fixed space, for corpora
+/// where a `Dedup` table or a `PathAnalysis` would not fit in memory.
+///
+/// Uses the space-saving algorithm with `k` counters each for strings and records. Every
+/// value occurring more than `n / k` times in `n` values is guaranteed to be reported, and
+/// reported counts are at most `HeavyHitter::error` too high.
+#[derive(Clone, Debug)]
+pub struct HeavyHitters {
+    strings: SpaceSaving,
+    records: SpaceSaving,
+}
+
+impl HeavyHitters {
+    /// Tracks the top `k` strings and records, `k` at least 1.
+    pub fn new(k: usize) -> HeavyHitters {
+        assert!(k > 0, "k must be at least 1");
+        HeavyHitters {
+            strings: SpaceSaving::new(k),
+            records: SpaceSaving::new(k),
+        }
+    }
+
+    /// Counts a record, and every string in it, keys included.
+    pub fn add(&mut self, record: &Value) {
+        self.records.add(record);
+        self.add_strings(record);
+    }
+
+    fn add_strings(&mut self, value: &Value) {
+        match *value {
+            Value::String(..) => self.strings.add(value),
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => self.add_strings(v),
+            Value::Ext { ref value, .. } => self.add_strings(value),
+            Value::Seq(ref v) | Value::Tuple(ref v) => v.iter().for_each(|x| self.add_strings(x)),
+            Value::Set(ref v) => v.iter().for_each(|x| self.add_strings(x)),
+            Value::Map(ref kv) => {
+                kv.keys().iter().chain(kv.values()).for_each(|x| self.add_strings(x))
+            }
+            _ => {}
+        }
+    }
+
+    /// The most frequent strings, most frequent first.
+    pub fn strings(&self) -> Vec<HeavyHitter> {
+        self.strings.top()
+    }
+
+    /// The most frequent records, most frequent first.
+    pub fn records(&self) -> Vec<HeavyHitter> {
+        self.records.top()
+    }
+
+    /// How many strings were counted.
+    pub fn total_strings(&self) -> u64 {
+        self.strings.total
+    }
+
+    /// How many records were counted.
+    pub fn total_records(&self) -> u64 {
+        self.records.total
+    }
+}
+
+/// A value reported by `HeavyHitters`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeavyHitter {
+    pub value: Value,
+    /// how often the value was seen, possibly overestimated by up to `error`
+    pub count: u64,
+    pub error: u64,
+}
+
+impl HeavyHitter {
+    /// How often the value was seen at least.
+    pub fn guaranteed(&self) -> u64 {
+        self.count - self.error
+    }
+}
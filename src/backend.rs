@@ -0,0 +1,52 @@
+//! The traversal behind the provided `Deduplicator::dedup`, for backends that only intern.
+
+use std::sync::Arc;
+
+use {Deduplicator, Value, KV};
+
+fn owned<T: Clone>(value: Arc<T>) -> T {
+    Arc::try_unwrap(value).unwrap_or_else(|value| (*value).clone())
+}
+
+fn children<D: Deduplicator + ?Sized>(dedup: &mut D, values: Vec<Value>) -> Vec<Value> {
+    values.into_iter().map(|x| dedup_tree(dedup, x)).collect()
+}
+
+fn elements<D: Deduplicator + ?Sized>(dedup: &mut D, elements: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+    let elements = children(dedup, owned(elements));
+    dedup.dedup_seq(Arc::new(elements))
+}
+
+/// Passes every string, blob, sequence and map of `value` to the matching method of `dedup`,
+/// children first, in the same order as `Dedup` interns them.
+///
+/// Map keys are interned as a sequence, and sets only have their elements interned.
+pub(crate) fn dedup_tree<D: Deduplicator + ?Sized>(dedup: &mut D, value: Value) -> Value {
+    match value {
+        Value::Bytes(v) => Value::Bytes(dedup.dedup_blob(v)),
+        Value::String(v) => Value::String(dedup.dedup_string(v)),
+        Value::UnitStruct(v) => Value::UnitStruct(dedup.dedup_string(v)),
+        Value::Seq(v) => Value::Seq(elements(dedup, v)),
+        Value::Tuple(v) => Value::Tuple(elements(dedup, v)),
+        Value::Map(object) => {
+            let KV(k, v) = owned(object);
+            let k = children(dedup, owned(k));
+            let v = children(dedup, v);
+            let k = dedup.dedup_seq(Arc::new(k));
+            Value::Map(dedup.dedup_map(Arc::new(KV(k, v))))
+        }
+        Value::Set(v) => Value::Set(Arc::new(
+            v.iter().map(|x| dedup_tree(dedup, x.clone())).collect(),
+        )),
+        Value::Ext { tag, value } => Value::Ext {
+            tag,
+            value: Box::new(dedup_tree(dedup, *value)),
+        },
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { codec, data } => Value::CompressedBytes {
+            codec,
+            data: dedup.dedup_blob(data),
+        },
+        x => x,
+    }
+}
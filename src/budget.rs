@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use filter::Fingerprint;
+use hooks::Outcome;
+use sampling::Sampler;
+use {Dedup, Value};
+
+/// What a `Dedup` does once its tables hold as many bytes as its budget allows, see
+/// `Dedup::with_memory_budget`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BudgetMode {
+    /// Stop adding entries. Values already in the tables are still found.
+    Freeze,
+    /// Drop the entries no longer referenced outside of the tables to make room, and stop
+    /// adding entries while that is not enough.
+    Evict,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Budget {
+    limit: usize,
+    mode: BudgetMode,
+    // bytes held by the tables, measured as in `Dedup::savings_report`
+    used: usize,
+    // entries turned away since the last eviction, which has to reach `evict_after` before
+    // the next one so that evicting costs amortized constant time per entry
+    refused: usize,
+    evict_after: usize,
+}
+
+/// Whether a value that was not found may be added to a table.
+pub(crate) struct Admission<'a> {
+    pub(crate) room: bool,
+    pub(crate) sampler: Option<&'a mut Sampler>,
+}
+
+impl Admission<'_> {
+    pub(crate) fn admits<T: Fingerprint + ?Sized>(&mut self, value: &T) -> bool {
+        self.room && self.sampler.as_mut().is_none_or(|s| s.admit(value))
+    }
+}
+
+impl Dedup {
+    /// Limits the bytes held by the tables to `bytes`, as measured by `savings_report`.
+    ///
+    /// This bounds the memory of the tables themselves during a spike of unique values.
+    /// Deduplicated values keep what they reference alive regardless of the budget. Entries
+    /// already in the tables count towards the budget.
+    pub fn with_memory_budget(mut self, bytes: usize, mode: BudgetMode) -> Dedup {
+        self.budget = Some(Budget {
+            limit: bytes,
+            mode,
+            used: self.stored_bytes(),
+            refused: 0,
+            evict_after: 0,
+        });
+        self
+    }
+
+    /// Bytes held by the tables, if there is a budget.
+    pub fn budget_used(&self) -> Option<usize> {
+        self.budget.as_ref().map(|b| b.used)
+    }
+
+    /// Whether an entry of `size` bytes may be added, evicting first if needed and allowed.
+    pub(crate) fn has_room(&mut self, size: usize) -> bool {
+        let evict = match self.budget {
+            None => return true,
+            Some(ref b) if b.used.saturating_add(size) <= b.limit => return true,
+            Some(ref b) => b.mode == BudgetMode::Evict && b.refused >= b.evict_after,
+        };
+        if !evict {
+            return false;
+        }
+        self.reclaim();
+        let entries =
+            self.blobs.len() + self.strings.len() + self.vectors.len() + self.objects.len();
+        let budget = self.budget.as_mut().unwrap();
+        budget.refused = 0;
+        budget.evict_after = entries;
+        budget.used.saturating_add(size) <= budget.limit
+    }
+
+    /// Drops the entries no longer referenced outside of the tables, and returns the bytes
+    /// they held, as measured by `savings_report`.
+    ///
+    /// Dropping a map or sequence can leave what it referenced unreferenced in turn, so this
+    /// repeats until nothing more is dropped.
+    pub fn reclaim(&mut self) -> usize {
+        let before = self.stored_bytes();
+        loop {
+            let entries =
+                self.blobs.len() + self.strings.len() + self.vectors.len() + self.objects.len();
+            self.objects.retain(|x| Arc::strong_count(x) > 1);
+            self.vectors.retain(|x| Arc::strong_count(x) > 1);
+            self.strings.retain(|x| Arc::strong_count(x) > 1);
+            self.blobs.retain(|x| Arc::strong_count(x) > 1);
+            let left =
+                self.blobs.len() + self.strings.len() + self.vectors.len() + self.objects.len();
+            if left == entries {
+                break;
+            }
+        }
+        if let Some(ref mut checksums) = self.checksums {
+            checksums.prune(&self.blobs);
+        }
+        let after = self.stored_bytes();
+        if let Some(ref mut budget) = self.budget {
+            budget.used = after;
+        }
+        before - after
+    }
+
+    /// Updates the budget and calls the hooks after a lookup.
+    pub(crate) fn interned<T, F>(
+        &mut self,
+        outcome: Outcome,
+        room: bool,
+        value: &Arc<T>,
+        size: usize,
+        wrap: F,
+    ) where
+        T: ?Sized,
+        F: FnOnce(Arc<T>) -> Value,
+    {
+        if let Some(ref mut budget) = self.budget {
+            match outcome {
+                Outcome::Inserted => budget.used += size,
+                Outcome::Skipped if !room => budget.refused += 1,
+                _ => {}
+            }
+        }
+        if let Some(ref hooks) = self.hooks {
+            hooks.notify(outcome, value, size, wrap);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Building values from a stream of events, for format parsers.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use {Deduplicator, Value, KV};
+
+/// An event passed to a `ValueBuilder` where it is not valid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// the named event, e.g. `end_map` inside a sequence or `value` where a key is expected
+    Unexpected(&'static str),
+    /// `finish` before all containers were ended, or before there was any value
+    Incomplete,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::Unexpected(event) => write!(f, "unexpected {} event", event),
+            BuildError::Incomplete => write!(f, "incomplete value"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+enum Frame {
+    Seq(Vec<Value>),
+    Map {
+        entries: Vec<(Value, Value)>,
+        key: Option<Value>,
+    },
+}
+
+/// Builds a `Value` from start, key, value and end events, interning every part as soon
+/// as it is complete.
+///
+/// Containers are kept on an explicit stack, so arbitrarily deep documents need no
+/// recursion, and every finished string, sequence and map goes through `dedup` before it is
+/// added to its parent. Map entries may come in any order. Of repeated keys, the last one
+/// wins.
+///
+/// After `finish`, the builder is ready for the next document, keeping its tables, so a
+/// stream of records is deduplicated against each other.
+pub struct ValueBuilder<D> {
+    dedup: D,
+    stack: Vec<Frame>,
+    root: Option<Value>,
+}
+
+impl<D: Deduplicator> ValueBuilder<D> {
+    pub fn new(dedup: D) -> ValueBuilder<D> {
+        ValueBuilder {
+            dedup,
+            stack: Vec::new(),
+            root: None,
+        }
+    }
+
+    /// How many containers are started and not yet ended.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    fn check_value(&self, event: &'static str) -> Result<(), BuildError> {
+        match self.stack.last() {
+            None if self.root.is_some() => Err(BuildError::Unexpected(event)),
+            Some(Frame::Map { key: None, .. }) => Err(BuildError::Unexpected(event)),
+            _ => Ok(()),
+        }
+    }
+
+    // adds a finished value to the innermost container
+    fn push(&mut self, value: Value) {
+        match self.stack.last_mut() {
+            None => self.root = Some(value),
+            Some(Frame::Seq(items)) => items.push(value),
+            Some(Frame::Map { entries, key }) => entries.push((key.take().unwrap(), value)),
+        }
+    }
+
+    pub fn start_seq(&mut self) -> Result<(), BuildError> {
+        self.check_value("start_seq")?;
+        self.stack.push(Frame::Seq(Vec::new()));
+        Ok(())
+    }
+
+    pub fn end_seq(&mut self) -> Result<(), BuildError> {
+        match self.stack.last() {
+            Some(Frame::Seq(..)) => {}
+            _ => return Err(BuildError::Unexpected("end_seq")),
+        }
+        if let Some(Frame::Seq(items)) = self.stack.pop() {
+            let seq = self.dedup.dedup_seq(Arc::new(items));
+            self.push(Value::Seq(seq));
+        }
+        Ok(())
+    }
+
+    pub fn start_map(&mut self) -> Result<(), BuildError> {
+        self.check_value("start_map")?;
+        self.stack.push(Frame::Map {
+            entries: Vec::new(),
+            key: None,
+        });
+        Ok(())
+    }
+
+    /// The key of the next map entry, whose value is the next value or container.
+    pub fn key(&mut self, key: Value) -> Result<(), BuildError> {
+        match self.stack.last() {
+            Some(Frame::Map { key: None, .. }) => {}
+            _ => return Err(BuildError::Unexpected("key")),
+        }
+        let key = self.dedup.dedup(key);
+        if let Some(Frame::Map { key: slot, .. }) = self.stack.last_mut() {
+            *slot = Some(key);
+        }
+        Ok(())
+    }
+
+    pub fn end_map(&mut self) -> Result<(), BuildError> {
+        match self.stack.last() {
+            Some(Frame::Map { key: None, .. }) => {}
+            _ => return Err(BuildError::Unexpected("end_map")),
+        }
+        if let Some(Frame::Map { mut entries, .. }) = self.stack.pop() {
+            if !entries.windows(2).all(|w| w[0].0 < w[1].0) {
+                // the sort is stable, so reversed, the last of equal keys comes first
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries.reverse();
+                entries.dedup_by(|a, b| a.0 == b.0);
+                entries.reverse();
+            }
+            let (keys, values): (Vec<Value>, Vec<Value>) = entries.into_iter().unzip();
+            let keys = self.dedup.dedup_seq(Arc::new(keys));
+            let map = self.dedup.dedup_map(Arc::new(KV(keys, values)));
+            self.push(Value::Map(map));
+        }
+        Ok(())
+    }
+
+    /// A complete value, such as a number or a string, interned with `Deduplicator::dedup`.
+    pub fn value(&mut self, value: Value) -> Result<(), BuildError> {
+        self.check_value("value")?;
+        let value = self.dedup.dedup(value);
+        self.push(value);
+        Ok(())
+    }
+
+    /// Takes the built value, leaving the builder ready for the next one.
+    ///
+    /// Fails if containers are still open or no value was built, leaving the builder as is.
+    pub fn finish(&mut self) -> Result<Value, BuildError> {
+        if !self.stack.is_empty() {
+            return Err(BuildError::Incomplete);
+        }
+        self.root.take().ok_or(BuildError::Incomplete)
+    }
+
+    /// Drops the value in progress, if any, keeping the tables.
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.root = None;
+    }
+
+    pub fn dedup(&self) -> &D {
+        &self.dedup
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dedup
+    }
+}
+
+impl<D: Deduplicator + Default> Default for ValueBuilder<D> {
+    fn default() -> ValueBuilder<D> {
+        ValueBuilder::new(D::default())
+    }
+}
+
+impl<D> fmt::Debug for ValueBuilder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ValueBuilder")
+            .field("depth", &self.stack.len())
+            .field("done", &self.root.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,158 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use number::Number;
+use Value;
+
+/// Logical view of a value, with wrappers stripped and scalars widened.
+enum Canonical<'a> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    Str(Str<'a>),
+    Bytes(Cow<'a, [u8]>),
+    Seq(&'a [Value]),
+    Set(&'a BTreeSet<Value>),
+    Map(&'a [Value], &'a [Value]),
+    Ext(u64, &'a Value),
+}
+
+enum Str<'a> {
+    Borrowed(&'a str),
+    Char([u8; 4], usize),
+}
+
+impl<'a> Str<'a> {
+    fn as_str(&self) -> &str {
+        match *self {
+            Str::Borrowed(s) => s,
+            Str::Char(ref buf, len) => std::str::from_utf8(&buf[..len]).unwrap(),
+        }
+    }
+}
+
+impl<'a> Canonical<'a> {
+    fn new(value: &'a Value) -> Canonical<'a> {
+        match *value {
+            Value::Unit | Value::UnitStruct(..) | Value::Option(None) => Canonical::Null,
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => Canonical::new(v),
+            Value::Bool(v) => Canonical::Bool(v),
+            Value::Number(n) => Canonical::Number(n),
+            #[cfg(feature = "half")]
+            Value::F16(v) => Canonical::Number(Number::F32(v.to_f32())),
+            Value::Char(v) => {
+                let mut buf = [0; 4];
+                let len = v.encode_utf8(&mut buf).len();
+                Canonical::Str(Str::Char(buf, len))
+            }
+            Value::String(ref v) => Canonical::Str(Str::Borrowed(v)),
+            Value::Bytes(ref v) => Canonical::Bytes(Cow::Borrowed(v)),
+            // compression is transparent, unreadable data is compared as stored
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => Canonical::Bytes(
+                codec
+                    .decompress(data)
+                    .map(Cow::Owned)
+                    .unwrap_or(Cow::Borrowed(data)),
+            ),
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => Canonical::Bytes(Cow::Borrowed(v)),
+            Value::Seq(ref v) | Value::Tuple(ref v) => Canonical::Seq(v),
+            Value::Set(ref v) => Canonical::Set(v),
+            Value::Map(ref v) => Canonical::Map(&v.0, &v.1),
+            Value::Ext { tag, ref value } => Canonical::Ext(tag, value),
+        }
+    }
+
+    fn rank(&self) -> usize {
+        match *self {
+            Canonical::Null => 0,
+            Canonical::Bool(..) => 1,
+            Canonical::Number(..) => 2,
+            Canonical::Str(..) => 3,
+            Canonical::Bytes(..) => 4,
+            Canonical::Seq(..) => 5,
+            Canonical::Set(..) => 6,
+            Canonical::Map(..) => 7,
+            Canonical::Ext(..) => 8,
+        }
+    }
+}
+
+// keys are sorted structurally, which need not agree with the canonical order
+fn canonical_entries<'a>(keys: &'a [Value], values: &'a [Value]) -> Vec<(&'a Value, &'a Value)> {
+    let mut entries: Vec<_> = keys.iter().zip(values.iter()).collect();
+    entries.sort_by(|a, b| a.0.canonical_cmp(b.0));
+    entries
+}
+
+fn cmp_map_entries(ak: &[Value], av: &[Value], bk: &[Value], bv: &[Value]) -> Ordering {
+    let a = canonical_entries(ak, av);
+    let b = canonical_entries(bk, bv);
+    for (&(ak, av), &(bk, bv)) in a.iter().zip(b.iter()) {
+        let ord = ak.canonical_cmp(bk).then_with(|| av.canonical_cmp(bv));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+// like map keys, set elements are sorted structurally
+fn cmp_set(a: &BTreeSet<Value>, b: &BTreeSet<Value>) -> Ordering {
+    let mut a: Vec<_> = a.iter().collect();
+    let mut b: Vec<_> = b.iter().collect();
+    a.sort_by(|x, y| x.canonical_cmp(y));
+    b.sort_by(|x, y| x.canonical_cmp(y));
+    for (a, b) in a.iter().zip(b.iter()) {
+        let ord = a.canonical_cmp(b);
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn cmp_seq(a: &[Value], b: &[Value]) -> Ordering {
+    for (a, b) in a.iter().zip(b.iter()) {
+        let ord = a.canonical_cmp(b);
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl Value {
+    /// Compares two values by logical content rather than by representation.
+    ///
+    /// Numbers compare by value regardless of width or signedness, integers and floats
+    /// included, and all NaNs are equal. `Unit` and `Option(None)` are both null, `Some`
+    /// and newtype wrappers are transparent, a `Char` equals the one-character string, a
+    /// tuple equals the sequence of its elements, and compressed bytes equal their content.
+    /// The derived `Ord` used by `Dedup` is unaffected.
+    pub fn canonical_cmp(&self, other: &Value) -> Ordering {
+        let (a, b) = (Canonical::new(self), Canonical::new(other));
+        let (rank_a, rank_b) = (a.rank(), b.rank());
+        match (a, b) {
+            (Canonical::Null, Canonical::Null) => Ordering::Equal,
+            (Canonical::Bool(a), Canonical::Bool(b)) => a.cmp(&b),
+            (Canonical::Number(a), Canonical::Number(b)) => a.cmp(&b),
+            (Canonical::Str(a), Canonical::Str(b)) => a.as_str().cmp(b.as_str()),
+            (Canonical::Bytes(a), Canonical::Bytes(b)) => a.cmp(&b),
+            (Canonical::Seq(a), Canonical::Seq(b)) => cmp_seq(a, b),
+            (Canonical::Set(a), Canonical::Set(b)) => cmp_set(a, b),
+            (Canonical::Map(ak, av), Canonical::Map(bk, bv)) => cmp_map_entries(ak, av, bk, bv),
+            (Canonical::Ext(at, a), Canonical::Ext(bt, b)) => {
+                at.cmp(&bt).then_with(|| a.canonical_cmp(b))
+            }
+            _ => rank_a.cmp(&rank_b),
+        }
+    }
+
+    /// Logical equality, consistent with `canonical_cmp`.
+    pub fn canonical_eq(&self, other: &Value) -> bool {
+        self.canonical_cmp(other) == Ordering::Equal
+    }
+}
@@ -0,0 +1,347 @@
+//! CBOR export and import, with optional support for the stringref extension.
+//!
+//! With stringref (tags 256 and 25, see <http://cbor.schmorp.de/stringref>), every text or
+//! byte string after its first occurrence is written as a small index, so interned strings
+//! stay deduplicated on the wire. Decoding turns every reference into a clone of the `Arc`
+//! read first, so sharing is rebuilt on the other side.
+//!
+//! The mapping follows the serde data model: options, newtypes and unit structs are
+//! transparent, tuples and sets become arrays, and `Ext` values become CBOR tags. Decoding
+//! produces `U64`, `I64`, `F32`, `F64`, `String`, `Bytes`, `Seq`, `Map` and `Ext` nodes, and
+//! `Unit` for `null` and `undefined`, whatever features are enabled, so half precision floats
+//! decode to `F32`. Indefinite length items are not supported.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use {Number, Value};
+
+const TAG_STRINGREF: u64 = 25;
+const TAG_STRINGREF_NAMESPACE: u64 = 256;
+// bounds to keep malformed input from exhausting memory or the stack
+const MAX_PREALLOCATE: usize = 4096;
+const MAX_DEPTH: usize = 512;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+/// Shortest string that is worth a reference at table index `index`, as fixed by the spec.
+fn min_len(index: usize) -> usize {
+    match index as u64 {
+        0..=23 => 3,
+        24..=255 => 4,
+        256..=65535 => 5,
+        65536..=0xffff_ffff => 6,
+        _ => 7,
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum StringKey {
+    Text(Arc<str>),
+    Bytes(Arc<Vec<u8>>),
+}
+
+struct Writer<W: Write> {
+    out: W,
+    // indices of the strings written so far, if using stringref
+    strings: Option<HashMap<StringKey, usize>>,
+}
+
+impl<W: Write> Writer<W> {
+    fn head(&mut self, major: u8, n: u64) -> io::Result<()> {
+        let major = major << 5;
+        match n {
+            0..=23 => self.out.write_all(&[major | n as u8]),
+            24..=0xff => self.out.write_all(&[major | 24, n as u8]),
+            0x100..=0xffff => {
+                self.out.write_all(&[major | 25])?;
+                self.out.write_all(&(n as u16).to_be_bytes())
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.out.write_all(&[major | 26])?;
+                self.out.write_all(&(n as u32).to_be_bytes())
+            }
+            _ => {
+                self.out.write_all(&[major | 27])?;
+                self.out.write_all(&n.to_be_bytes())
+            }
+        }
+    }
+
+    fn signed(&mut self, x: i64) -> io::Result<()> {
+        if x >= 0 {
+            self.head(UNSIGNED, x as u64)
+        } else {
+            self.head(NEGATIVE, !x as u64)
+        }
+    }
+
+    fn string(&mut self, key: StringKey) -> io::Result<()> {
+        if let Some(ref mut strings) = self.strings {
+            if let Some(&index) = strings.get(&key) {
+                self.head(TAG, TAG_STRINGREF)?;
+                return self.head(UNSIGNED, index as u64);
+            }
+            let len = match key {
+                StringKey::Text(ref s) => s.len(),
+                StringKey::Bytes(ref b) => b.len(),
+            };
+            if len >= min_len(strings.len()) {
+                let index = strings.len();
+                strings.insert(key.clone(), index);
+            }
+        }
+        let (major, bytes) = match key {
+            StringKey::Text(ref s) => (TEXT, s.as_bytes()),
+            StringKey::Bytes(ref b) => (BYTES, &b[..]),
+        };
+        self.head(major, bytes.len() as u64)?;
+        self.out.write_all(bytes)
+    }
+
+    fn array<'a, I: Iterator<Item = &'a Value>>(&mut self, len: usize, it: I) -> io::Result<()> {
+        self.head(ARRAY, len as u64)?;
+        for x in it {
+            self.value(x)?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, value: &Value) -> io::Result<()> {
+        match *value {
+            Value::Unit | Value::Option(None) | Value::UnitStruct(..) => self.head(SIMPLE, 22),
+            Value::Bool(v) => self.head(SIMPLE, if v { 21 } else { 20 }),
+            Value::Number(Number::U8(v)) => self.head(UNSIGNED, u64::from(v)),
+            Value::Number(Number::U16(v)) => self.head(UNSIGNED, u64::from(v)),
+            Value::Number(Number::U32(v)) => self.head(UNSIGNED, u64::from(v)),
+            Value::Number(Number::U64(v)) => self.head(UNSIGNED, v),
+            Value::Number(Number::I8(v)) => self.signed(i64::from(v)),
+            Value::Number(Number::I16(v)) => self.signed(i64::from(v)),
+            Value::Number(Number::I32(v)) => self.signed(i64::from(v)),
+            Value::Number(Number::I64(v)) => self.signed(v),
+            #[cfg(feature = "half")]
+            Value::F16(v) => {
+                self.out.write_all(&[SIMPLE << 5 | 25])?;
+                self.out.write_all(&v.to_be_bytes())
+            }
+            Value::Number(Number::F32(v)) => {
+                self.out.write_all(&[SIMPLE << 5 | 26])?;
+                self.out.write_all(&v.to_be_bytes())
+            }
+            Value::Number(Number::F64(v)) => {
+                self.out.write_all(&[SIMPLE << 5 | 27])?;
+                self.out.write_all(&v.to_be_bytes())
+            }
+            Value::Char(v) => self.string(StringKey::Text(Arc::from(v.to_string()))),
+            Value::String(ref v) => self.string(StringKey::Text(v.clone())),
+            Value::Bytes(ref v) => self.string(StringKey::Bytes(v.clone())),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { .. } => {
+                let data = value.byte_content().unwrap()?.into_owned();
+                self.string(StringKey::Bytes(Arc::new(data)))
+            }
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => self.string(StringKey::Bytes(Arc::new(v.to_vec()))),
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => self.value(v),
+            Value::Seq(ref v) | Value::Tuple(ref v) => self.array(v.len(), v.iter()),
+            Value::Set(ref v) => self.array(v.len(), v.iter()),
+            Value::Map(ref kv) => {
+                self.head(MAP, kv.keys().len() as u64)?;
+                for (k, v) in kv.keys().iter().zip(kv.values().iter()) {
+                    self.value(k)?;
+                    self.value(v)?;
+                }
+                Ok(())
+            }
+            Value::Ext { tag, ref value } => {
+                self.head(TAG, tag)?;
+                self.value(value)
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    // strings of the enclosing stringref namespaces, innermost last
+    namespaces: Vec<Vec<Value>>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<A: AsMut<[u8]> + Default>(&mut self) -> io::Result<A> {
+        let mut buf = A::default();
+        let n = buf.as_mut().len();
+        buf.as_mut().copy_from_slice(self.take(n)?);
+        Ok(buf)
+    }
+
+    /// The major type, additional information and argument of the next item.
+    fn head(&mut self) -> io::Result<(u8, u8, u64)> {
+        let initial = self.take(1)?[0];
+        let info = initial & 0x1f;
+        let n = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => u64::from(u16::from_be_bytes(self.array()?)),
+            26 => u64::from(u32::from_be_bytes(self.array()?)),
+            27 => u64::from_be_bytes(self.array()?),
+            31 => return Err(invalid("indefinite length items are not supported".to_owned())),
+            _ => return Err(invalid(format!("reserved additional information {}", info))),
+        };
+        Ok((initial >> 5, info, n))
+    }
+
+    fn len(n: u64) -> io::Result<usize> {
+        usize::try_from(n).map_err(|_| invalid(format!("length {} too large", n)))
+    }
+
+    fn string(&mut self, major: u8, n: u64) -> io::Result<Value> {
+        let bytes = self.take(Reader::len(n)?)?;
+        let value = if major == TEXT {
+            let s = std::str::from_utf8(bytes).map_err(|e| invalid(e.to_string()))?;
+            Value::String(Arc::from(s))
+        } else {
+            Value::Bytes(Arc::new(bytes.to_vec()))
+        };
+        if let Some(strings) = self.namespaces.last_mut() {
+            if bytes.len() >= min_len(strings.len()) {
+                strings.push(value.clone());
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> io::Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("values nested too deeply".to_owned()));
+        }
+        let (major, info, n) = self.head()?;
+        Ok(match major {
+            UNSIGNED => Value::Number(Number::U64(n)),
+            NEGATIVE => Value::Number(Number::I64(
+                i64::try_from(n)
+                    .map(|n| -1 - n)
+                    .map_err(|_| invalid(format!("integer -1-{} out of range", n)))?,
+            )),
+            BYTES | TEXT => self.string(major, n)?,
+            ARRAY => {
+                let len = Reader::len(n)?;
+                let mut elements = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+                for _ in 0..len {
+                    elements.push(self.value(depth + 1)?);
+                }
+                Value::seq(elements)
+            }
+            MAP => {
+                let mut map = BTreeMap::new();
+                for _ in 0..Reader::len(n)? {
+                    let k = self.value(depth + 1)?;
+                    let v = self.value(depth + 1)?;
+                    if map.insert(k, v).is_some() {
+                        return Err(invalid("duplicate map key".to_owned()));
+                    }
+                }
+                Value::map(map)
+            }
+            TAG if n == TAG_STRINGREF_NAMESPACE => {
+                self.namespaces.push(Vec::new());
+                let value = self.value(depth + 1);
+                self.namespaces.pop();
+                value?
+            }
+            TAG if n == TAG_STRINGREF => {
+                let index = match self.value(depth + 1)? {
+                    Value::Number(Number::U64(index)) => index,
+                    other => return Err(invalid(format!("invalid stringref {}", other))),
+                };
+                self.namespaces
+                    .last()
+                    .and_then(|strings| strings.get(usize::try_from(index).ok()?))
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("unknown stringref {}", index)))?
+            }
+            TAG => Value::Ext {
+                tag: n,
+                value: Box::new(self.value(depth + 1)?),
+            },
+            _ => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 | 23 => Value::Unit,
+                // the same with or without the `half` feature
+                25 => Value::Number(Number::F32(f16_to_f32(n as u16))),
+                26 => Value::Number(Number::F32(f32::from_bits(n as u32))),
+                27 => Value::Number(Number::F64(f64::from_bits(n))),
+                _ => return Err(invalid(format!("unsupported simple value {}", n))),
+            },
+        })
+    }
+}
+
+/// The value of an IEEE half precision float, which `f32` holds exactly.
+pub(crate) fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+impl Value {
+    /// Writes this value as CBOR.
+    pub fn write_cbor<W: Write>(&self, out: W) -> io::Result<()> {
+        Writer { out, strings: None }.value(self)
+    }
+
+    /// Writes this value as CBOR, with repeated strings replaced by stringrefs.
+    ///
+    /// The whole value is one stringref namespace. Readers that do not support the extension
+    /// see tags 256 and 25 instead of the strings.
+    pub fn write_cbor_stringref<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut writer = Writer {
+            out,
+            strings: Some(HashMap::new()),
+        };
+        writer.head(TAG, TAG_STRINGREF_NAMESPACE)?;
+        writer.value(self)
+    }
+
+    /// Reads a single CBOR item, resolving stringrefs to shared strings.
+    pub fn from_cbor(data: &[u8]) -> io::Result<Value> {
+        let mut reader = Reader {
+            data,
+            namespaces: Vec::new(),
+        };
+        let value = reader.value(0)?;
+        if !reader.data.is_empty() {
+            return Err(invalid("trailing data after CBOR item".to_owned()));
+        }
+        Ok(value)
+    }
+}
@@ -0,0 +1,97 @@
+//! Checksums of interned blobs, to detect corruption of long lived tables.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use {Dedup, Table};
+
+/// The 64 bit FNV-1a hash of `data`.
+///
+/// Unlike `std::hash`, this is the same across runs, platforms and versions of this crate, so
+/// it can be persisted next to a snapshot and compared after reloading it. It detects
+/// accidental corruption, not tampering.
+pub fn blob_checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Checksums of the blobs in a table, by address.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Checksums(pub(crate) HashMap<usize, u64>);
+
+fn address(blob: &Arc<Vec<u8>>) -> usize {
+    Arc::as_ptr(blob) as usize
+}
+
+impl Checksums {
+    pub(crate) fn record(&mut self, blob: &Arc<Vec<u8>>) {
+        self.0.insert(address(blob), blob_checksum(blob));
+    }
+
+    /// Forgets the blobs no longer in `blobs`.
+    pub(crate) fn prune(&mut self, blobs: &Table<Vec<u8>>) {
+        let live: HashSet<usize> = blobs.iter().map(address).collect();
+        self.0.retain(|x, _| live.contains(x));
+    }
+}
+
+/// Interned blobs whose content no longer matches the checksum taken when they were added.
+#[derive(Clone, Debug)]
+pub struct IntegrityError {
+    pub corrupt: Vec<Arc<Vec<u8>>>,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} interned blob(s) failed their checksum", self.corrupt.len())
+    }
+}
+
+impl Error for IntegrityError {}
+
+impl Dedup {
+    /// Records a `blob_checksum` for every blob added to the table from now on, and for the
+    /// blobs already in it, so that `verify` can detect corruption.
+    ///
+    /// This costs a pass over each new blob and 16 bytes per entry.
+    pub fn with_blob_checksums(mut self) -> Dedup {
+        let mut checksums = Checksums::default();
+        for blob in &self.blobs {
+            checksums.record(blob);
+        }
+        self.checksums = Some(checksums);
+        self
+    }
+
+    /// The checksum recorded for `blob`, if it is in the table and checksums are enabled.
+    pub fn recorded_checksum(&self, blob: &Arc<Vec<u8>>) -> Option<u64> {
+        self.checksums.as_ref()?.0.get(&address(blob)).cloned()
+    }
+
+    /// Recomputes the checksum of every interned blob, failing with the ones that changed.
+    ///
+    /// Succeeds trivially if checksums are not enabled.
+    pub fn verify(&self) -> Result<(), IntegrityError> {
+        let checksums = match self.checksums {
+            Some(ref c) => c,
+            None => return Ok(()),
+        };
+        let corrupt: Vec<Arc<Vec<u8>>> = self
+            .blobs
+            .iter()
+            .filter(|blob| match checksums.0.get(&address(blob)) {
+                Some(&sum) => blob_checksum(blob) != sum,
+                None => false,
+            })
+            .cloned()
+            .collect();
+        if corrupt.is_empty() {
+            Ok(())
+        } else {
+            Err(IntegrityError { corrupt })
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! A `tokio_util` codec for values in the binary format of the `encoding` module, behind
+//! the `codec` feature.
+//!
+//! ```ignore
+//! let mut framed = Framed::new(socket, ValueCodec::new(Dedup::new()));
+//! framed.send(value).await?;
+//! while let Some(value) = framed.next().await {
+//!     let value = value?;
+//! }
+//! ```
+
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use encoding;
+use {Dedup, Deduplicator, Value};
+
+/// The default of `ValueCodec::max_frame_length`.
+const MAX_FRAME_LENGTH: usize = 8 << 20;
+
+/// Frames of a 4 byte big endian length followed by one value, written as records of a
+/// `DictionaryEncoder` without a dictionary.
+///
+/// Each frame is self-contained, so the sharing of a value is lost on the wire. Decoded
+/// values go through a `Deduplicator` instead, usually a `Dedup` for the connection, which
+/// restores it and also shares the parts that are equal between values.
+#[derive(Debug)]
+pub struct ValueCodec<D = Dedup> {
+    dedup: D,
+    max_frame_length: usize,
+}
+
+impl<D: Deduplicator> ValueCodec<D> {
+    pub fn new(dedup: D) -> ValueCodec<D> {
+        ValueCodec {
+            dedup,
+            max_frame_length: MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Limits the length of frames, without the length prefix, which is 8 MiB by default.
+    /// Longer frames are an error both when encoding and decoding, which keeps a peer from
+    /// making the decoder buffer arbitrary amounts of data.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> ValueCodec<D> {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    pub fn dedup(&self) -> &D {
+        &self.dedup
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dedup
+    }
+
+    fn too_long(&self, len: usize) -> io::Error {
+        let message = format!(
+            "frame of {} bytes exceeds the limit of {}",
+            len, self.max_frame_length
+        );
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+}
+
+impl<D: Deduplicator> Encoder<&Value> for ValueCodec<D> {
+    type Error = io::Error;
+
+    fn encode(&mut self, value: &Value, dst: &mut BytesMut) -> io::Result<()> {
+        let start = dst.len();
+        dst.put_u32(0);
+        encoding::write_value(dst.writer(), value)?;
+        let len = dst.len() - start - 4;
+        if len > self.max_frame_length || len > u32::MAX as usize {
+            dst.truncate(start);
+            return Err(self.too_long(len));
+        }
+        dst[start..start + 4].copy_from_slice(&(len as u32).to_be_bytes());
+        Ok(())
+    }
+}
+
+impl<D: Deduplicator> Encoder<Value> for ValueCodec<D> {
+    type Error = io::Error;
+
+    fn encode(&mut self, value: Value, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&value, dst)
+    }
+}
+
+impl<D: Deduplicator> Decoder for ValueCodec<D> {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Value>> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if len > self.max_frame_length {
+            return Err(self.too_long(len));
+        }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        let frame = src.split_to(len);
+        let mut input = &frame[..];
+        let value = encoding::read_value(&mut input)?;
+        match value {
+            Some(value) if input.is_empty() => Ok(Some(self.dedup.dedup(value))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a frame does not hold exactly one value",
+            )),
+        }
+    }
+}
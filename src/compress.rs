@@ -0,0 +1,154 @@
+//! Compressed storage for large byte blobs.
+//!
+//! `Value::compress_bytes` replaces big `Bytes` nodes by `CompressedBytes`, which serialize and
+//! deserialize exactly like the original bytes. The data is only decompressed when it is
+//! actually read, so rarely used payloads cost their compressed size.
+//!
+//! Equality, hashing and ordering of `CompressedBytes` go by the representation, the codec
+//! and the compressed data, so that they never decompress. A blob is therefore not equal to
+//! its compressed form, and `Dedup` shares the two separately. Use `Value::canonical_eq` to
+//! compare the contents.
+
+use std::borrow::Cow;
+use std::io;
+use std::sync::Arc;
+
+use Value;
+
+/// Compression algorithm of a `Value::CompressedBytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Codec {
+    /// lz4 block format with the uncompressed size prepended, fast to decompress
+    Lz4,
+    /// zstd frame at the default level, smaller but slower
+    Zstd,
+}
+
+impl Codec {
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Codec::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Codec::Zstd => zstd::decode_all(data),
+        }
+    }
+}
+
+impl Value {
+    /// Returns a copy of this value where every `Bytes` node of at least `min_len` bytes is
+    /// compressed with `codec`. Blobs that do not get smaller, or fail to compress, are kept
+    /// as they are.
+    pub fn compress_bytes(&self, codec: Codec, min_len: usize) -> Value {
+        self.map_bytes(&mut |value| match *value {
+            Value::Bytes(ref v) if v.len() >= min_len => match codec.compress(v) {
+                Ok(data) if data.len() < v.len() => Value::CompressedBytes {
+                    codec,
+                    data: Arc::new(data),
+                },
+                _ => value.clone(),
+            },
+            _ => value.clone(),
+        })
+    }
+
+    /// Returns a copy of this value with every `CompressedBytes` node expanded to `Bytes`.
+    pub fn decompress_bytes(&self) -> io::Result<Value> {
+        let mut error = None;
+        let value = self.map_bytes(&mut |value| match *value {
+            Value::CompressedBytes { codec, ref data } => match codec.decompress(data) {
+                Ok(data) => Value::Bytes(Arc::new(data)),
+                Err(e) => {
+                    error = Some(e);
+                    value.clone()
+                }
+            },
+            _ => value.clone(),
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(value),
+        }
+    }
+
+    /// The content of a `Bytes`, `CompressedBytes` or `MappedBytes` node, decompressing if
+    /// needed.
+    pub fn byte_content(&self) -> Option<io::Result<Cow<'_, [u8]>>> {
+        match *self {
+            Value::Bytes(ref v) => Some(Ok(Cow::Borrowed(v))),
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => Some(Ok(Cow::Borrowed(v))),
+            Value::CompressedBytes { codec, ref data } => {
+                Some(codec.decompress(data).map(Cow::Owned))
+            }
+            _ => None,
+        }
+    }
+
+    // applies `f` to all byte nodes, rebuilding containers only when something changed
+    fn map_bytes<F: FnMut(&Value) -> Value>(&self, f: &mut F) -> Value {
+        match *self {
+            Value::Bytes(..) | Value::CompressedBytes { .. } => f(self),
+            Value::Option(Some(ref v)) => Value::Option(Some(Box::new(v.map_bytes(f)))),
+            Value::Newtype(ref v) => Value::Newtype(Box::new(v.map_bytes(f))),
+            Value::Ext { tag, ref value } => Value::Ext {
+                tag,
+                value: Box::new(value.map_bytes(f)),
+            },
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                let mapped: Vec<Value> = v.iter().map(|x| x.map_bytes(f)).collect();
+                if mapped.iter().zip(v.iter()).all(|(a, b)| same(a, b)) {
+                    self.clone()
+                } else if let Value::Tuple(..) = *self {
+                    Value::Tuple(Arc::new(mapped))
+                } else {
+                    Value::seq(mapped)
+                }
+            }
+            Value::Map(ref kv) => {
+                let mapped: Vec<Value> = kv.1.iter().map(|x| x.map_bytes(f)).collect();
+                if mapped.iter().zip(kv.1.iter()).all(|(a, b)| same(a, b)) {
+                    self.clone()
+                } else {
+                    Value::Map(kv.with_values(mapped))
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// Whether two nodes share their storage, used to keep untouched subtrees shared.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Bytes(a), Value::Bytes(b)) => Arc::ptr_eq(a, b),
+        (
+            Value::CompressedBytes { data: a, .. },
+            Value::CompressedBytes { data: b, .. },
+        ) => Arc::ptr_eq(a, b),
+        (Value::Seq(a), Value::Seq(b)) | (Value::Tuple(a), Value::Tuple(b)) => Arc::ptr_eq(a, b),
+        (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
+        (Value::Set(a), Value::Set(b)) => Arc::ptr_eq(a, b),
+        (Value::Option(Some(a)), Value::Option(Some(b))) | (Value::Newtype(a), Value::Newtype(b)) => {
+            same(a, b)
+        }
+        (Value::Ext { value: a, .. }, Value::Ext { value: b, .. }) => same(a, b),
+        (Value::Bytes(..), _)
+        | (Value::CompressedBytes { .. }, _)
+        | (Value::Seq(..), _)
+        | (Value::Tuple(..), _)
+        | (Value::Map(..), _)
+        | (Value::Set(..), _)
+        | (Value::Option(Some(..)), _)
+        | (Value::Newtype(..), _)
+        | (Value::Ext { .. }, _) => false,
+        // scalars are never touched
+        _ => true,
+    }
+}
@@ -0,0 +1,162 @@
+//! Memoized content hashes, for comparing large values repeatedly.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use share::is_shared;
+use Value;
+
+// two differently seeded hashers, for 128 bits
+struct Pair(DefaultHasher, DefaultHasher);
+
+impl Pair {
+    fn new() -> Pair {
+        let (mut a, mut b) = (DefaultHasher::new(), DefaultHasher::new());
+        a.write_u64(0x5eed_0000_0000_0001);
+        b.write_u64(0x5eed_0000_0000_0002);
+        Pair(a, b)
+    }
+
+    fn finish128(&self) -> u128 {
+        u128::from(self.0.finish()) << 64 | u128::from(self.1.finish())
+    }
+}
+
+impl Hasher for Pair {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+        self.1.write(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+}
+
+fn address<T: ?Sized>(v: &Arc<T>) -> usize {
+    Arc::as_ptr(v) as *const u8 as usize
+}
+
+// identifies a shared node, `None` for values without an allocation of their own
+fn node(value: &Value) -> Option<(usize, usize)> {
+    let address = match *value {
+        Value::String(ref v) | Value::UnitStruct(ref v) => address(v),
+        Value::Bytes(ref v) => address(v),
+        Value::Seq(ref v) | Value::Tuple(ref v) => address(v),
+        Value::Map(ref v) => address(v),
+        Value::Set(ref v) => address(v),
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { ref data, .. } => address(data),
+        _ => return None,
+    };
+    Some((value.discriminant(), address))
+}
+
+/// 128 bit hashes of values, memoized for every shared node, to speed up comparisons.
+///
+/// The hash of a sequence, map or set is computed from the hashes of its elements, so after
+/// a value was hashed once, hashing a new revision of it only visits the nodes that are not
+/// shared with the old one. `eq` compares pointers first, then hashes, and only if those
+/// agree the contents, unless the table was made with `trust_hashes`.
+///
+/// The table holds on to every node it hashed, so that their addresses stay valid. Drop or
+/// `clear` it to release them.
+#[derive(Default)]
+pub struct ContentHashes {
+    memo: HashMap<(usize, usize), (Value, u128)>,
+    trust: bool,
+}
+
+impl ContentHashes {
+    pub fn new() -> ContentHashes {
+        ContentHashes::default()
+    }
+
+    /// Treats equal hashes as equal values, without comparing the contents.
+    ///
+    /// Two different values colliding in 128 bits is vanishingly unlikely by accident, but
+    /// the hash is not cryptographic, so do not use this on adversarial input.
+    pub fn trust_hashes(mut self) -> ContentHashes {
+        self.trust = true;
+        self
+    }
+
+    /// The content hash of `value`. Equal values have equal hashes.
+    pub fn content_hash(&mut self, value: &Value) -> u128 {
+        let key = match node(value) {
+            Some(key) => key,
+            None => return self.compute(value),
+        };
+        if let Some(&(_, hash)) = self.memo.get(&key) {
+            return hash;
+        }
+        let hash = self.compute(value);
+        self.memo.insert(key, (value.clone(), hash));
+        hash
+    }
+
+    fn compute(&mut self, value: &Value) -> u128 {
+        let mut h = Pair::new();
+        h.write_usize(value.discriminant());
+        match *value {
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                h.write_usize(v.len());
+                for x in v.iter() {
+                    h.write_u128(self.content_hash(x));
+                }
+            }
+            Value::Map(ref kv) => {
+                h.write_usize(kv.keys().len());
+                for (k, v) in kv.keys().iter().zip(kv.values()) {
+                    h.write_u128(self.content_hash(k));
+                    h.write_u128(self.content_hash(v));
+                }
+            }
+            Value::Set(ref v) => {
+                h.write_usize(v.len());
+                for x in v.iter() {
+                    h.write_u128(self.content_hash(x));
+                }
+            }
+            Value::Option(None) => h.write_u8(0),
+            Value::Option(Some(ref v)) => {
+                h.write_u8(1);
+                h.write_u128(self.content_hash(v));
+            }
+            Value::Newtype(ref v) => h.write_u128(self.content_hash(v)),
+            Value::Ext { tag, ref value } => {
+                h.write_u64(tag);
+                h.write_u128(self.content_hash(value));
+            }
+            ref leaf => leaf.hash(&mut h),
+        }
+        h.finish128()
+    }
+
+    /// Whether `a` and `b` are equal, like `==`.
+    pub fn eq(&mut self, a: &Value, b: &Value) -> bool {
+        if is_shared(a, b) {
+            return true;
+        }
+        if self.content_hash(a) != self.content_hash(b) {
+            return false;
+        }
+        self.trust || a == b
+    }
+
+    /// How many nodes have a memoized hash.
+    pub fn len(&self) -> usize {
+        self.memo.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memo.is_empty()
+    }
+
+    /// Forgets all hashes, releasing the nodes.
+    pub fn clear(&mut self) {
+        self.memo.clear();
+    }
+}
@@ -0,0 +1,185 @@
+//! Conversion between CSV and sequences of records, behind the `csv` feature.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use csv_crate::{Reader, StringRecord, Writer};
+
+use path;
+use {Number, Value, KV};
+
+/// Why a CSV document could not be read or written.
+#[derive(Debug)]
+pub enum CsvError {
+    /// malformed input, or an error of the underlying reader or writer
+    Csv(csv_crate::Error),
+    /// a column name that appears twice in the header
+    DuplicateHeader(String),
+    /// a node with no CSV representation, with its JSON pointer
+    Unsupported(String),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CsvError::Csv(ref e) => e.fmt(f),
+            CsvError::DuplicateHeader(ref name) => {
+                write!(f, "the column '{}' appears twice in the header", name)
+            }
+            CsvError::Unsupported(ref pointer) => {
+                write!(f, "the value at '{}' has no CSV representation", pointer)
+            }
+        }
+    }
+}
+
+impl Error for CsvError {}
+
+impl From<csv_crate::Error> for CsvError {
+    fn from(e: csv_crate::Error) -> CsvError {
+        CsvError::Csv(e)
+    }
+}
+
+/// The value of a cell: units for empty cells, then booleans, integers and floats, and
+/// strings for everything else.
+fn infer(cell: &str) -> Value {
+    if cell.is_empty() {
+        return Value::Unit;
+    }
+    match cell {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(v) = cell.parse::<i64>() {
+        return Value::Number(Number::I64(v));
+    }
+    if let Ok(v) = cell.parse::<u64>() {
+        return Value::Number(Number::U64(v));
+    }
+    // `parse` also accepts words like "inf" and "NaN", which are more likely text
+    if cell.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) {
+        if let Ok(v) = cell.parse::<f64>() {
+            return Value::Number(Number::F64(v));
+        }
+    }
+    Value::String(cell.into())
+}
+
+/// Reads CSV with a header row into a `Seq` of `Map`s, one for each row, keyed by the
+/// column names.
+///
+/// All rows share one key vector, so the column names are stored once however long the
+/// input is. Cells are typed by their text: empty cells become `Unit`, `true` and `false`
+/// become booleans, integers become `I64`, or `U64` beyond its range, other numbers
+/// become `F64`, and the rest stays a string. Rows with a different number of cells than
+/// the header are an error.
+pub fn from_csv_reader<R: io::Read>(reader: R) -> Result<Value, CsvError> {
+    let mut reader = Reader::from_reader(reader);
+    let header = reader.headers()?.clone();
+    // the position of each column in the sorted keys
+    let mut columns: Vec<(&str, usize)> = header.iter().zip(0..).collect();
+    columns.sort();
+    if let Some(w) = columns.windows(2).find(|w| w[0].0 == w[1].0) {
+        return Err(CsvError::DuplicateHeader(w[0].0.to_owned()));
+    }
+    let keys: Arc<Vec<Value>> = Arc::new(
+        columns
+            .iter()
+            .map(|&(name, _)| Value::String(name.into()))
+            .collect(),
+    );
+    let mut rows = Vec::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let values = columns
+            .iter()
+            .map(|&(_, column)| infer(&record[column]))
+            .collect();
+        rows.push(Value::Map(Arc::new(KV(keys.clone(), values))));
+    }
+    Ok(Value::Seq(Arc::new(rows)))
+}
+
+/// The text of a cell, empty for units and `None`.
+fn cell(value: &Value, pointer: &str) -> Result<String, CsvError> {
+    Ok(match *value {
+        Value::Unit | Value::Option(None) => String::new(),
+        Value::Bool(v) => v.to_string(),
+        Value::Number(Number::U8(v)) => v.to_string(),
+        Value::Number(Number::U16(v)) => v.to_string(),
+        Value::Number(Number::U32(v)) => v.to_string(),
+        Value::Number(Number::U64(v)) => v.to_string(),
+        Value::Number(Number::I8(v)) => v.to_string(),
+        Value::Number(Number::I16(v)) => v.to_string(),
+        Value::Number(Number::I32(v)) => v.to_string(),
+        Value::Number(Number::I64(v)) => v.to_string(),
+        // the debug format keeps the fraction of whole numbers, so they read back as floats
+        Value::Number(Number::F32(v)) => format!("{:?}", v),
+        Value::Number(Number::F64(v)) => format!("{:?}", v),
+        #[cfg(feature = "half")]
+        Value::F16(v) => format!("{:?}", v.to_f32()),
+        Value::Char(v) => v.to_string(),
+        Value::String(ref v) | Value::UnitStruct(ref v) => v.to_string(),
+        Value::Option(Some(ref v))
+        | Value::Newtype(ref v)
+        | Value::Ext { value: ref v, .. } => return cell(v, pointer),
+        _ => return Err(CsvError::Unsupported(pointer.to_owned())),
+    })
+}
+
+fn record(value: &Value) -> Option<&KV> {
+    match *value {
+        Value::Map(ref kv) => Some(kv),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) | Value::Ext { value: ref v, .. } => {
+            record(v)
+        }
+        _ => None,
+    }
+}
+
+/// Writes a sequence of records as CSV with a header row, the inverse of
+/// `from_csv_reader`.
+///
+/// The columns are the string keys of all records in key order, and a record without one
+/// of them gets an empty cell. Cells must be scalars, and keys must be strings.
+pub fn to_csv_writer<W: io::Write>(value: &Value, writer: W) -> Result<(), CsvError> {
+    let rows = match *value {
+        Value::Seq(ref rows) | Value::Tuple(ref rows) => rows,
+        _ => return Err(CsvError::Unsupported(String::new())),
+    };
+    let mut records = Vec::with_capacity(rows.len());
+    let mut columns = BTreeSet::new();
+    for (i, row) in rows.iter().enumerate() {
+        let pointer = path::join("", &i.to_string());
+        let kv = record(row).ok_or_else(|| CsvError::Unsupported(pointer.clone()))?;
+        for key in kv.keys() {
+            match *key {
+                Value::String(ref key) => columns.insert(&**key),
+                _ => {
+                    let pointer = path::join(&pointer, &path::key_segment(key));
+                    return Err(CsvError::Unsupported(pointer));
+                }
+            };
+        }
+        records.push((kv, pointer));
+    }
+    let mut writer = Writer::from_writer(writer);
+    writer.write_record(&columns)?;
+    for (kv, pointer) in records {
+        let mut cells = Vec::with_capacity(columns.len());
+        for column in &columns {
+            cells.push(match kv.get(column) {
+                Some(value) => cell(value, &path::join(&pointer, column))?,
+                None => String::new(),
+            });
+        }
+        writer.write_record(&cells)?;
+    }
+    writer.flush().map_err(csv_crate::Error::from)?;
+    Ok(())
+}
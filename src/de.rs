@@ -3,8 +3,13 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::vec;
 
-use Value;
+pub(crate) use number::Num;
+use path;
+use shared;
+use {Number, Value, KV};
 
 #[derive(Debug)]
 pub enum Unexpected {
@@ -88,6 +93,7 @@ pub enum DeserializerError {
     UnknownField(String, &'static [&'static str]),
     MissingField(&'static str),
     DuplicateField(&'static str),
+    MissingPath(String),
 }
 
 impl de::Error for DeserializerError {
@@ -139,6 +145,9 @@ impl DeserializerError {
             DeserializerError::UnknownField(ref field, exp) => E::unknown_field(field, exp),
             DeserializerError::MissingField(field) => E::missing_field(field),
             DeserializerError::DuplicateField(field) => E::missing_field(field),
+            DeserializerError::MissingPath(ref path) => {
+                E::custom(format_args!("No value at {}", path))
+            }
         }
     }
 
@@ -186,6 +195,7 @@ impl fmt::Display for DeserializerError {
             ),
             DeserializerError::MissingField(field) => write!(f, "Missing field {}", field),
             DeserializerError::DuplicateField(field) => write!(f, "Duplicate field {}", field),
+            DeserializerError::MissingPath(ref path) => write!(f, "No value at {}", path),
         }
     }
 }
@@ -210,43 +220,43 @@ impl<'de> de::Visitor<'de> for ValueVisitor {
     }
 
     fn visit_i8<E>(self, value: i8) -> Result<Value, E> {
-        Ok(Value::I8(value))
+        Ok(Value::Number(Number::I8(value)))
     }
 
     fn visit_i16<E>(self, value: i16) -> Result<Value, E> {
-        Ok(Value::I16(value))
+        Ok(Value::Number(Number::I16(value)))
     }
 
     fn visit_i32<E>(self, value: i32) -> Result<Value, E> {
-        Ok(Value::I32(value))
+        Ok(Value::Number(Number::I32(value)))
     }
 
     fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
-        Ok(Value::I64(value))
+        Ok(Value::Number(Number::I64(value)))
     }
 
     fn visit_u8<E>(self, value: u8) -> Result<Value, E> {
-        Ok(Value::U8(value))
+        Ok(Value::Number(Number::U8(value)))
     }
 
     fn visit_u16<E>(self, value: u16) -> Result<Value, E> {
-        Ok(Value::U16(value))
+        Ok(Value::Number(Number::U16(value)))
     }
 
     fn visit_u32<E>(self, value: u32) -> Result<Value, E> {
-        Ok(Value::U32(value))
+        Ok(Value::Number(Number::U32(value)))
     }
 
     fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
-        Ok(Value::U64(value))
+        Ok(Value::Number(Number::U64(value)))
     }
 
     fn visit_f32<E>(self, value: f32) -> Result<Value, E> {
-        Ok(Value::F32(value))
+        Ok(Value::Number(Number::F32(value)))
     }
 
     fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
-        Ok(Value::F64(value))
+        Ok(Value::Number(Number::F64(value)))
     }
 
     fn visit_char<E>(self, value: char) -> Result<Value, E> {
@@ -280,8 +290,8 @@ impl<'de> de::Visitor<'de> for ValueVisitor {
     }
 
     fn visit_seq<V: de::SeqAccess<'de>>(self, mut visitor: V) -> Result<Value, V::Error> {
-        let mut values = Vec::new();
-        while let Some(elem) = try!(visitor.next_element()) {
+        let mut values = Vec::with_capacity(cautious(visitor.size_hint()));
+        while let Some(elem) = visitor.next_element()? {
             values.push(elem);
         }
         Ok(Value::seq(values))
@@ -289,7 +299,7 @@ impl<'de> de::Visitor<'de> for ValueVisitor {
 
     fn visit_map<V: de::MapAccess<'de>>(self, mut visitor: V) -> Result<Value, V::Error> {
         let mut values = BTreeMap::new();
-        while let Some((key, value)) = try!(visitor.next_entry()) {
+        while let Some((key, value)) = visitor.next_entry()? {
             values.insert(key, value);
         }
         Ok(Value::map(values))
@@ -304,6 +314,13 @@ impl<'de> de::Visitor<'de> for ValueVisitor {
     }
 }
 
+/// Preallocation for a size hint from another deserializer, capped since it may be untrusted.
+fn cautious(hint: Option<usize>) -> usize {
+    const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+    hint.unwrap_or(0)
+        .min(MAX_PREALLOC_BYTES / std::mem::size_of::<Value>())
+}
+
 impl<'de> de::Deserialize<'de> for Value {
     fn deserialize<D: de::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         d.deserialize_any(ValueVisitor)
@@ -318,15 +335,99 @@ impl<'de> de::IntoDeserializer<'de, DeserializerError> for Value {
     }
 }
 
+/// Deserializes only the subtree of `value` at the JSON pointer `pointer`, e.g.
+/// `"/results/3/user"`.
+///
+/// Sequence elements are addressed by index and map entries by key. Options, newtypes and
+/// extension values along the way are looked through.
+pub fn from_value_at<'de, T: de::Deserialize<'de>>(
+    value: &Value,
+    pointer: &str,
+) -> Result<T, DeserializerError> {
+    let node = path::split(pointer)
+        .and_then(|segments| path::lookup(value, &segments).cloned())
+        .ok_or_else(|| DeserializerError::MissingPath(pointer.to_owned()))?;
+    T::deserialize(node)
+}
+
+/// How numbers are converted when the target type differs from the stored variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NumberPolicy {
+    /// Fail if the value is out of range of the target, or is a float with a fractional
+    /// part and the target an integer. Rounding to the nearest float is allowed.
+    #[default]
+    Strict,
+    /// Clamp to the range of the target, truncating floats towards zero, like `as` casts.
+    Saturating,
+}
+
+pub(crate) fn num(value: &Value) -> Option<Num> {
+    match *value {
+        Value::Number(n) => Some(n.widen()),
+        #[cfg(feature = "half")]
+        Value::F16(v) => Some(Num::Float(v.to_f64())),
+        _ => None,
+    }
+}
+
+macro_rules! deserialize_int {
+    ($($method:ident => $ty:ident $visit:ident,)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, E> {
+                let n = match num(&self.value) {
+                    Some(n) => n,
+                    None => return match self.value {
+                        Value::Ext { value, .. } => {
+                            ValueDeserializer::nested(*value, self.numbers).$method(visitor)
+                        }
+                        _ => self.deserialize_any(visitor),
+                    },
+                };
+                let (min, max) = ($ty::MIN as i128, $ty::MAX as i128);
+                let v = match (n, self.numbers) {
+                    (Num::Int(i), _) if i >= min && i <= max => i as $ty,
+                    (Num::Int(i), NumberPolicy::Saturating) => i.max(min).min(max) as $ty,
+                    // the upper bound is a power of two, so it is exact as a float
+                    (Num::Float(f), _)
+                        if f.fract() == 0.0 && f >= min as f64 && f < max as f64 + 1.0 =>
+                    {
+                        f as $ty
+                    }
+                    (Num::Float(f), NumberPolicy::Saturating) => f as $ty,
+                    _ => {
+                        return Err(de::Error::invalid_value(
+                            self.value.unexpected(),
+                            &concat!("a number that fits in ", stringify!($ty)),
+                        ))
+                    }
+                };
+                visitor.$visit(v)
+            }
+        )*
+    };
+}
+
 pub struct ValueDeserializer<E> {
     value: Value,
+    numbers: NumberPolicy,
     error: PhantomData<fn() -> E>,
 }
 
 impl<E> ValueDeserializer<E> {
     pub fn new(value: Value) -> Self {
+        ValueDeserializer::nested(value, NumberPolicy::Strict)
+    }
+
+    /// Sets the policy for numeric conversions, applied to the whole tree.
+    pub fn with_number_policy(mut self, numbers: NumberPolicy) -> Self {
+        self.numbers = numbers;
+        self
+    }
+
+    fn nested(value: Value, numbers: NumberPolicy) -> Self {
         ValueDeserializer {
-            value: value,
+            value,
+            numbers,
             error: Default::default(),
         }
     }
@@ -345,38 +446,55 @@ where
     fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
         match self.value {
             Value::Bool(v) => visitor.visit_bool(v),
-            Value::U8(v) => visitor.visit_u8(v),
-            Value::U16(v) => visitor.visit_u16(v),
-            Value::U32(v) => visitor.visit_u32(v),
-            Value::U64(v) => visitor.visit_u64(v),
-            Value::I8(v) => visitor.visit_i8(v),
-            Value::I16(v) => visitor.visit_i16(v),
-            Value::I32(v) => visitor.visit_i32(v),
-            Value::I64(v) => visitor.visit_i64(v),
-            Value::F32(v) => visitor.visit_f32(v),
-            Value::F64(v) => visitor.visit_f64(v),
+            Value::Number(Number::U8(v)) => visitor.visit_u8(v),
+            Value::Number(Number::U16(v)) => visitor.visit_u16(v),
+            Value::Number(Number::U32(v)) => visitor.visit_u32(v),
+            Value::Number(Number::U64(v)) => visitor.visit_u64(v),
+            Value::Number(Number::I8(v)) => visitor.visit_i8(v),
+            Value::Number(Number::I16(v)) => visitor.visit_i16(v),
+            Value::Number(Number::I32(v)) => visitor.visit_i32(v),
+            Value::Number(Number::I64(v)) => visitor.visit_i64(v),
+            Value::Number(Number::F32(v)) => visitor.visit_f32(v),
+            Value::Number(Number::F64(v)) => visitor.visit_f64(v),
+            #[cfg(feature = "half")]
+            Value::F16(v) => visitor.visit_f32(v.to_f32()),
             Value::Char(v) => visitor.visit_char(v),
-            Value::String(v) => {
-                visitor.visit_str(std::str::from_utf8(v.as_ref().as_ref()).unwrap())
-            }
-            Value::Unit => visitor.visit_unit(),
+            Value::String(v) => visitor.visit_str(&v),
+            Value::Unit | Value::UnitStruct(..) => visitor.visit_unit(),
             Value::Option(None) => visitor.visit_none(),
-            Value::Option(Some(v)) => visitor.visit_some(ValueDeserializer::new(*v)),
-            Value::Newtype(v) => visitor.visit_newtype_struct(ValueDeserializer::new(*v)),
-            Value::Seq(v) => visitor.visit_seq(de::value::SeqDeserializer::new(
-                v.as_ref().iter().cloned().map(ValueDeserializer::new),
-            )),
-            Value::Map(v) => visitor
-                .visit_map(de::value::MapDeserializer::new(v.iter().map(|(k, v)| {
-                    (ValueDeserializer::new(k), ValueDeserializer::new(v))
-                }))),
+            Value::Option(Some(v)) => {
+                visitor.visit_some(ValueDeserializer::nested(*v, self.numbers))
+            }
+            Value::Newtype(v) => {
+                visitor.visit_newtype_struct(ValueDeserializer::nested(*v, self.numbers))
+            }
+            Value::Seq(v) | Value::Tuple(v) => visitor.visit_seq(SeqAccess::new(v, self.numbers)),
+            Value::Map(v) => visitor.visit_map(MapAccess::new(v, self.numbers)),
+            Value::Set(v) => {
+                let elements = Arc::try_unwrap(v)
+                    .map(|v| v.into_iter().collect())
+                    .unwrap_or_else(|v| v.iter().cloned().collect());
+                visitor.visit_seq(SeqAccess::new(Arc::new(elements), self.numbers))
+            }
             Value::Bytes(v) => visitor.visit_bytes(v.as_ref().as_ref()),
+            Value::Ext { value, .. } => {
+                ValueDeserializer::nested(*value, self.numbers).deserialize_any(visitor)
+            }
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, data } => {
+                visitor.visit_byte_buf(codec.decompress(&data).map_err(de::Error::custom)?)
+            }
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(v) => visitor.visit_bytes(&v),
         }
     }
 
     fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
         match self.value {
             Value::Option(..) => self.deserialize_any(visitor),
+            Value::Ext { value, .. } => {
+                ValueDeserializer::nested(*value, self.numbers).deserialize_option(visitor)
+            }
             Value::Unit => visitor.visit_unit(),
             _ => visitor.visit_some(self),
         }
@@ -384,30 +502,24 @@ where
 
     fn deserialize_enum<V: de::Visitor<'de>>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
         let (variant, value) = match self.value {
+            Value::Ext { value, .. } => {
+                return ValueDeserializer::nested(*value, self.numbers)
+                    .deserialize_enum(name, variants, visitor);
+            }
             Value::Map(value) => {
-                let mut iter = value.iter();
-                let (variant, value) = match iter.next() {
-                    Some(v) => v,
-                    None => {
-                        return Err(de::Error::invalid_value(
-                            de::Unexpected::Map,
-                            &"map with a single key",
-                        ));
-                    }
-                };
                 // enums are encoded as maps with a single key:value pair
-                if iter.next().is_some() {
+                if value.1.len() != 1 {
                     return Err(de::Error::invalid_value(
                         de::Unexpected::Map,
                         &"map with a single key",
                     ));
                 }
-                (variant, Some(value))
+                (value.0[0].clone(), Some(value.1[0].clone()))
             }
             Value::String(variant) => (Value::String(variant), None),
             other => {
@@ -419,8 +531,9 @@ where
         };
 
         let d = EnumDeserializer {
-            variant: variant,
-            value: value,
+            variant,
+            value,
+            numbers: self.numbers,
             error: Default::default(),
         };
         visitor.visit_enum(d)
@@ -428,19 +541,153 @@ where
 
     fn deserialize_newtype_struct<V: de::Visitor<'de>>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
         match self.value {
-            Value::Newtype(v) => visitor.visit_newtype_struct(ValueDeserializer::new(*v)),
+            // let the `shared` helpers pick up the Arc instead of copying the contents
+            Value::String(v) if name == shared::SHARED_TOKEN => {
+                shared::offer_str(v, |s| visitor.visit_str(s))
+            }
+            Value::Bytes(v) if name == shared::SHARED_TOKEN => {
+                shared::offer_bytes(v, |b| visitor.visit_bytes(b))
+            }
+            #[cfg(feature = "half")]
+            Value::F16(v) if name == "f16" => visitor.visit_newtype_struct(
+                ValueDeserializer::nested(Value::Number(Number::U16(v.to_bits())), self.numbers),
+            ),
+            Value::Newtype(v) => {
+                visitor.visit_newtype_struct(ValueDeserializer::nested(*v, self.numbers))
+            }
+            Value::Ext { value, .. } => ValueDeserializer::nested(*value, self.numbers)
+                .deserialize_newtype_struct(name, visitor),
             _ => visitor.visit_newtype_struct(self),
         }
     }
 
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            // hand over the buffer without copying if nobody else shares it
+            Value::Bytes(v) => {
+                visitor.visit_byte_buf(Arc::try_unwrap(v).unwrap_or_else(|v| v.as_ref().clone()))
+            }
+            Value::Ext { value, .. } => {
+                ValueDeserializer::nested(*value, self.numbers).deserialize_byte_buf(visitor)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::UnitStruct(ref found) if **found != *name => Err(de::Error::custom(format!(
+                "expected unit struct {}, found {}",
+                name, found
+            ))),
+            Value::Ext { value, .. } => ValueDeserializer::nested(*value, self.numbers)
+                .deserialize_unit_struct(name, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            // unlike sequences, tuples must match the arity exactly
+            Value::Tuple(v) => {
+                if v.len() != len {
+                    return Err(de::Error::invalid_length(v.len(), &ExpectedInSeq(len)));
+                }
+                let mut seq = SeqAccess::new(v, self.numbers);
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end().map(|_| value)
+            }
+            Value::Ext { value, .. } => {
+                ValueDeserializer::nested(*value, self.numbers).deserialize_tuple(len, visitor)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        // skipped subtrees are just dropped, which for shared nodes is a refcount decrement
+        drop(self);
+        visitor.visit_unit()
+    }
+
+    deserialize_int! {
+        deserialize_u8 => u8 visit_u8,
+        deserialize_u16 => u16 visit_u16,
+        deserialize_u32 => u32 visit_u32,
+        deserialize_u64 => u64 visit_u64,
+        deserialize_i8 => i8 visit_i8,
+        deserialize_i16 => i16 visit_i16,
+        deserialize_i32 => i32 visit_i32,
+        deserialize_i64 => i64 visit_i64,
+    }
+
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match num(&self.value) {
+            Some(Num::Float(f))
+                if f.is_finite()
+                    && f.abs() > f32::MAX as f64
+                    && self.numbers == NumberPolicy::Strict =>
+            {
+                Err(de::Error::invalid_value(
+                    self.value.unexpected(),
+                    &"a number that fits in f32",
+                ))
+            }
+            Some(Num::Float(f)) => visitor.visit_f32(f as f32),
+            Some(Num::Int(i)) => visitor.visit_f32(i as f32),
+            None => match self.value {
+                Value::Ext { value, .. } => {
+                    ValueDeserializer::nested(*value, self.numbers).deserialize_f32(visitor)
+                }
+                _ => self.deserialize_any(visitor),
+            },
+        }
+    }
+
+    fn deserialize_f64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match num(&self.value) {
+            Some(Num::Float(f)) => visitor.visit_f64(f),
+            Some(Num::Int(i)) => visitor.visit_f64(i as f64),
+            None => match self.value {
+                Value::Ext { value, .. } => {
+                    ValueDeserializer::nested(*value, self.numbers).deserialize_f64(visitor)
+                }
+                _ => self.deserialize_any(visitor),
+            },
+        }
+    }
+
     forward_to_deserialize_any! {
-        bool u8 u16 u32 u64 i8 i16 i32 i64 f32 f64 char str string unit
-        seq bytes byte_buf map unit_struct
-        tuple_struct struct tuple ignored_any identifier
+        bool char str string unit
+        seq bytes map
+        struct identifier
     }
 }
 
@@ -455,6 +702,16 @@ where
     }
 }
 
+macro_rules! forward_to_value_deserializer {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                ValueDeserializer::new(self).$method(visitor)
+            }
+        )*
+    };
+}
+
 impl<'de> de::Deserializer<'de> for Value {
     type Error = DeserializerError;
 
@@ -483,16 +740,246 @@ impl<'de> de::Deserializer<'de> for Value {
         ValueDeserializer::new(self).deserialize_newtype_struct(name, visitor)
     }
 
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        ValueDeserializer::new(self).deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        ValueDeserializer::new(self).deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        ValueDeserializer::new(self).deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        ValueDeserializer::new(self).deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        ValueDeserializer::new(self).deserialize_ignored_any(visitor)
+    }
+
+    forward_to_value_deserializer! {
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_f32 deserialize_f64
+    }
+
     forward_to_deserialize_any! {
-        bool u8 u16 u32 u64 i8 i16 i32 i64 f32 f64 char str string unit
-        seq bytes byte_buf map unit_struct
-        tuple_struct struct tuple ignored_any identifier
+        bool char str string unit
+        seq bytes map
+        struct identifier
+    }
+}
+
+/// The remaining elements of a seq or map, moved out if nobody else holds the vector and
+/// cloned one at a time otherwise.
+pub(crate) enum Elements {
+    Owned(vec::IntoIter<Value>),
+    Shared(Arc<Vec<Value>>, usize),
+    SharedValues(Arc<KV>, usize),
+}
+
+impl Elements {
+    pub(crate) fn seq(values: Arc<Vec<Value>>) -> Elements {
+        match Arc::try_unwrap(values) {
+            Ok(values) => Elements::Owned(values.into_iter()),
+            Err(values) => Elements::Shared(values, 0),
+        }
+    }
+
+    /// Keys and values of a map.
+    pub(crate) fn map(kv: Arc<KV>) -> (Elements, Elements) {
+        match Arc::try_unwrap(kv) {
+            Ok(KV(keys, values)) => (Elements::seq(keys), Elements::Owned(values.into_iter())),
+            Err(kv) => (
+                Elements::Shared(kv.0.clone(), 0),
+                Elements::SharedValues(kv, 0),
+            ),
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Value> {
+        match *self {
+            Elements::Owned(ref mut iter) => iter.next(),
+            Elements::Shared(ref values, ref mut index) => {
+                let value = values.get(*index).cloned();
+                *index += 1;
+                value
+            }
+            Elements::SharedValues(ref kv, ref mut index) => {
+                let value = kv.1.get(*index).cloned();
+                *index += 1;
+                value
+            }
+        }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        match *self {
+            Elements::Owned(ref iter) => iter.len(),
+            Elements::Shared(ref values, index) => values.len().saturating_sub(index),
+            Elements::SharedValues(ref kv, index) => kv.1.len().saturating_sub(index),
+        }
+    }
+}
+
+struct SeqAccess<E> {
+    elements: Elements,
+    count: usize,
+    numbers: NumberPolicy,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<E: de::Error> SeqAccess<E> {
+    fn new(values: Arc<Vec<Value>>, numbers: NumberPolicy) -> Self {
+        SeqAccess {
+            elements: Elements::seq(values),
+            count: 0,
+            numbers,
+            error: Default::default(),
+        }
+    }
+
+    fn end(&self) -> Result<(), E> {
+        match self.elements.remaining() {
+            0 => Ok(()),
+            remaining => Err(de::Error::invalid_length(
+                self.count + remaining,
+                &ExpectedInSeq(self.count),
+            )),
+        }
+    }
+}
+
+impl<'de, E> de::SeqAccess<'de> for SeqAccess<E>
+where
+    E: de::Error,
+{
+    type Error = E;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, E>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        match self.elements.next() {
+            Some(value) => {
+                self.count += 1;
+                seed.deserialize(ValueDeserializer::nested(value, self.numbers))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.remaining())
+    }
+}
+
+struct MapAccess<E> {
+    keys: Elements,
+    values: Elements,
+    count: usize,
+    numbers: NumberPolicy,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<E: de::Error> MapAccess<E> {
+    fn new(kv: Arc<KV>, numbers: NumberPolicy) -> Self {
+        let (keys, values) = Elements::map(kv);
+        MapAccess {
+            keys,
+            values,
+            count: 0,
+            numbers,
+            error: Default::default(),
+        }
+    }
+
+    fn end(&self) -> Result<(), E> {
+        match self.keys.remaining() {
+            0 => Ok(()),
+            remaining => Err(de::Error::invalid_length(
+                self.count + remaining,
+                &ExpectedInSeq(self.count),
+            )),
+        }
+    }
+}
+
+impl<'de, E> de::MapAccess<'de> for MapAccess<E>
+where
+    E: de::Error,
+{
+    type Error = E;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, E>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        match self.keys.next() {
+            Some(key) => {
+                self.count += 1;
+                seed.deserialize(ValueDeserializer::nested(key, self.numbers))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, E>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let value = self
+            .values
+            .next()
+            .expect("MapAccess::next_value called before next_key");
+        seed.deserialize(ValueDeserializer::nested(value, self.numbers))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.keys.remaining())
+    }
+}
+
+struct ExpectedInSeq(usize);
+
+impl de::Expected for ExpectedInSeq {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 1 {
+            write!(formatter, "1 element in sequence")
+        } else {
+            write!(formatter, "{} elements in sequence", self.0)
+        }
     }
 }
 
 struct EnumDeserializer<E> {
     variant: Value,
     value: Option<Value>,
+    numbers: NumberPolicy,
     error: PhantomData<fn() -> E>,
 }
 
@@ -512,15 +999,17 @@ where
     {
         let visitor = VariantDeserializer {
             value: self.value,
+            numbers: self.numbers,
             error: Default::default(),
         };
-        seed.deserialize(ValueDeserializer::new(self.variant))
+        seed.deserialize(ValueDeserializer::nested(self.variant, self.numbers))
             .map(|v| (v, visitor))
     }
 }
 
 struct VariantDeserializer<E> {
     value: Option<Value>,
+    numbers: NumberPolicy,
     error: PhantomData<fn() -> E>,
 }
 
@@ -532,7 +1021,9 @@ where
 
     fn unit_variant(self) -> Result<(), Self::Error> {
         match self.value {
-            Some(value) => de::Deserialize::deserialize(ValueDeserializer::new(value)),
+            Some(value) => {
+                de::Deserialize::deserialize(ValueDeserializer::nested(value, self.numbers))
+            }
             None => Ok(()),
         }
     }
@@ -542,7 +1033,7 @@ where
         T: de::DeserializeSeed<'de>,
     {
         match self.value {
-            Some(value) => seed.deserialize(ValueDeserializer::new(value)),
+            Some(value) => seed.deserialize(ValueDeserializer::nested(value, self.numbers)),
             None => Err(de::Error::invalid_type(
                 de::Unexpected::UnitVariant,
                 &"newtype variant",
@@ -555,12 +1046,11 @@ where
         V: de::Visitor<'de>,
     {
         match self.value {
-            Some(Value::Seq(v)) => de::Deserializer::deserialize_any(
-                de::value::SeqDeserializer::new(
-                    v.as_ref().clone().into_iter().map(ValueDeserializer::new),
-                ),
-                visitor,
-            ),
+            Some(Value::Seq(v)) | Some(Value::Tuple(v)) => {
+                let mut seq = SeqAccess::new(v, self.numbers);
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end().map(|_| value)
+            }
             Some(other) => Err(de::Error::invalid_type(
                 other.unexpected(),
                 &"tuple variant",
@@ -581,13 +1071,11 @@ where
         V: de::Visitor<'de>,
     {
         match self.value {
-            Some(Value::Map(v)) => de::Deserializer::deserialize_any(
-                de::value::MapDeserializer::new(
-                    v.iter()
-                        .map(|(k, v)| (ValueDeserializer::new(k), ValueDeserializer::new(v))),
-                ),
-                visitor,
-            ),
+            Some(Value::Map(v)) => {
+                let mut map = MapAccess::new(v, self.numbers);
+                let value = visitor.visit_map(&mut map)?;
+                map.end().map(|_| value)
+            }
             Some(other) => Err(de::Error::invalid_type(
                 other.unexpected(),
                 &"struct variant",
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(feature = "dedup")]
+use Dedup;
+
+/// The interned strings of a `Dedup`, numbered, see `Dedup::string_dictionary`.
+///
+/// Ids are indices into the strings in sorted order, so the same set of strings always gets
+/// the same ids, however they were interned.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StringDictionary {
+    strings: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, usize>,
+}
+
+impl StringDictionary {
+    /// Numbers `strings` in the order given. Repeated strings keep their first id.
+    pub fn from_strings(strings: Vec<Arc<str>>) -> StringDictionary {
+        let mut ids = HashMap::with_capacity(strings.len());
+        for (id, s) in strings.iter().enumerate() {
+            ids.entry(s.clone()).or_insert(id);
+        }
+        StringDictionary { strings, ids }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// The string with id `id`.
+    pub fn get(&self, id: usize) -> Option<&Arc<str>> {
+        self.strings.get(id)
+    }
+
+    /// The id of `s`, if it is in the dictionary.
+    pub fn id(&self, s: &str) -> Option<usize> {
+        self.ids.get(s).cloned()
+    }
+
+    /// All strings, the index of each being its id.
+    pub fn strings(&self) -> &[Arc<str>] {
+        &self.strings
+    }
+}
+
+#[cfg(feature = "dedup")]
+impl Dedup {
+    /// Numbers the strings interned so far, so encoders can replace them with their ids.
+    ///
+    /// The dictionary shares the strings with the table, so decoding ids through it yields
+    /// the interned `Arc`s.
+    pub fn string_dictionary(&self) -> StringDictionary {
+        let mut strings: Vec<Arc<str>> = self.strings.iter().cloned().collect();
+        strings.sort();
+        StringDictionary::from_strings(strings)
+    }
+}
@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use path;
+use Value;
+
+/// A single place where two values differ, see `diff`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    /// JSON pointer style path to the differing node, empty for the root
+    pub path: String,
+    /// `None` if the node only exists in the actual value
+    pub expected: Option<Value>,
+    /// `None` if the node only exists in the expected value
+    pub actual: Option<Value>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |v: &Option<Value>, debug: bool| match *v {
+            Some(ref v) if debug => format!("{:?}", v),
+            Some(ref v) => v.to_string(),
+            None => "nothing".to_owned(),
+        };
+        let path = if self.path.is_empty() { "/" } else { &self.path };
+        let (mut expected, mut actual) = (show(&self.expected, false), show(&self.actual, false));
+        if expected == actual {
+            // e.g. 'a' and "a", the variant is what differs
+            expected = show(&self.expected, true);
+            actual = show(&self.actual, true);
+        }
+        write!(f, "{}: expected {}, found {}", path, expected, actual)
+    }
+}
+
+fn leaf(out: &mut Vec<Difference>, path: &str, expected: Option<&Value>, actual: Option<&Value>) {
+    out.push(Difference {
+        path: path.to_owned(),
+        expected: expected.cloned(),
+        actual: actual.cloned(),
+    });
+}
+
+fn diff_into(out: &mut Vec<Difference>, path: &str, expected: &Value, actual: &Value) {
+    if expected == actual {
+        return;
+    }
+    match (expected, actual) {
+        (Value::Option(Some(e)), Value::Option(Some(a)))
+        | (Value::Newtype(e), Value::Newtype(a)) => diff_into(out, path, e, a),
+        (
+            Value::Ext { tag: te, value: e },
+            Value::Ext { tag: ta, value: a },
+        ) if te == ta => diff_into(out, path, e, a),
+        (Value::Seq(e), Value::Seq(a)) | (Value::Tuple(e), Value::Tuple(a)) => {
+            for i in 0..e.len().max(a.len()) {
+                let path = path::join(path, &i.to_string());
+                match (e.get(i), a.get(i)) {
+                    (Some(e), Some(a)) => diff_into(out, &path, e, a),
+                    (e, a) => leaf(out, &path, e, a),
+                }
+            }
+        }
+        (Value::Map(e), Value::Map(a)) => {
+            // the keys of every map are sorted, see `KV`, so walk them like a merge
+            let (mut i, mut j) = (0, 0);
+            while i < e.0.len() || j < a.0.len() {
+                let ord = match (e.0.get(i), a.0.get(j)) {
+                    (Some(ek), Some(ak)) => ek.cmp(ak),
+                    (Some(_), None) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                match ord {
+                    Ordering::Less => {
+                        let path = path::join(path, &path::key_segment(&e.0[i]));
+                        leaf(out, &path, Some(&e.1[i]), None);
+                        i += 1;
+                    }
+                    Ordering::Greater => {
+                        let path = path::join(path, &path::key_segment(&a.0[j]));
+                        leaf(out, &path, None, Some(&a.1[j]));
+                        j += 1;
+                    }
+                    Ordering::Equal => {
+                        let path = path::join(path, &path::key_segment(&e.0[i]));
+                        diff_into(out, &path, &e.1[i], &a.1[j]);
+                        i += 1;
+                        j += 1;
+                    }
+                }
+            }
+        }
+        (e, a) => leaf(out, path, Some(e), Some(a)),
+    }
+}
+
+/// Lists the paths at which `actual` differs from `expected`, outermost first.
+///
+/// Only the smallest differing subtrees are reported: a changed leaf inside an otherwise
+/// equal map yields a single entry for that leaf.
+pub fn diff(expected: &Value, actual: &Value) -> Vec<Difference> {
+    let mut out = Vec::new();
+    diff_into(&mut out, "", expected, actual);
+    out
+}
+
+fn close(a: f64, b: f64, epsilon: f64) -> bool {
+    if a == b || (a.is_nan() && b.is_nan()) {
+        return true;
+    }
+    let d = (a - b).abs();
+    d <= epsilon || d <= epsilon * a.abs().max(b.abs())
+}
+
+fn all_close(a: &[Value], b: &[Value], epsilon: f64) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.approx_eq(b, epsilon))
+}
+
+impl Value {
+    /// Like `==`, but numbers of which either is a float only need to be within `epsilon` of
+    /// each other, either absolutely or relative to the larger magnitude.
+    ///
+    /// Map keys and set elements are still compared exactly, and like `==`, `NaN` equals
+    /// `NaN`.
+    pub fn approx_eq(&self, other: &Value, epsilon: f64) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) if a.is_float() || b.is_float() => {
+                close(a.as_f64(), b.as_f64(), epsilon)
+            }
+            #[cfg(feature = "half")]
+            (Value::F16(a), Value::F16(b)) => close(a.to_f64(), b.to_f64(), epsilon),
+            (Value::Option(Some(a)), Value::Option(Some(b)))
+            | (Value::Newtype(a), Value::Newtype(b)) => a.approx_eq(b, epsilon),
+            (
+                Value::Ext { tag: ta, value: a },
+                Value::Ext { tag: tb, value: b },
+            ) => ta == tb && a.approx_eq(b, epsilon),
+            (Value::Seq(a), Value::Seq(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
+                Arc::ptr_eq(a, b) || all_close(a, b, epsilon)
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                Arc::ptr_eq(a, b) || (a.0 == b.0 && all_close(&a.1, &b.1, epsilon))
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_value_eq_impl(expected: &Value, actual: &Value) {
+    let differences = diff(expected, actual);
+    if !differences.is_empty() {
+        let lines: Vec<String> = differences.iter().map(|d| format!("  {}", d)).collect();
+        panic!("values differ at {} path(s):\n{}", differences.len(), lines.join("\n"));
+    }
+}
+
+/// Asserts that two `Value`s are equal, listing the differing paths on failure.
+///
+/// Unlike `assert_eq!`, the panic message does not dump both trees but only the
+/// expected and actual leaves at each path where they differ.
+#[macro_export]
+macro_rules! assert_value_eq {
+    ($expected:expr, $actual:expr) => {
+        $crate::assert_value_eq_impl(&$expected, &$actual)
+    };
+}
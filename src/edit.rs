@@ -0,0 +1,143 @@
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use {Deduplicator, Value, KV};
+
+impl KV {
+    /// The values of this map, in key order. There is no counterpart for the keys, as
+    /// changing them in place could break their order.
+    pub fn values_mut(&mut self) -> &mut [Value] {
+        &mut self.1
+    }
+}
+
+impl Value {
+    /// The elements of a `Seq`, cloned first if they are shared with another value.
+    pub fn seq_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match *self {
+            Value::Seq(ref mut v) => Some(Arc::make_mut(v)),
+            _ => None,
+        }
+    }
+
+    /// The contents of a `Map`, cloned first if they are shared with another value.
+    pub fn map_mut(&mut self) -> Option<&mut KV> {
+        match *self {
+            Value::Map(ref mut kv) => Some(Arc::make_mut(kv)),
+            _ => None,
+        }
+    }
+
+    /// The value for the string key `key` if this is a `Map`, see `map_mut`.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        let kv = self.map_mut()?;
+        let i = kv.string_key_index(key)?;
+        Some(&mut kv.1[i])
+    }
+
+    /// Mutable access to this value that passes it through `dedup` again when done.
+    ///
+    /// Use `seq_mut`, `map_mut` and `get_mut` on the guard to change nested values without
+    /// affecting other values they are shared with. Parts that were not changed are found in
+    /// the tables again, so they keep their identity.
+    pub fn edit<D: Deduplicator>(&mut self, dedup: D) -> CowGuard<'_, D> {
+        CowGuard {
+            value: self,
+            dedup,
+            modified: false,
+        }
+    }
+
+    /// Mutable access to this value whose changes are undone unless committed, see
+    /// `Transaction`.
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        let original = self.clone();
+        Transaction {
+            value: self,
+            original: Some(original),
+        }
+    }
+}
+
+/// Mutable access to a `Value` that is deduplicated again when dropped, see `Value::edit`.
+///
+/// The value is only passed through the deduplicator if it was accessed mutably.
+pub struct CowGuard<'a, D: Deduplicator> {
+    value: &'a mut Value,
+    dedup: D,
+    modified: bool,
+}
+
+impl<D: Deduplicator> Deref for CowGuard<'_, D> {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        self.value
+    }
+}
+
+impl<D: Deduplicator> DerefMut for CowGuard<'_, D> {
+    fn deref_mut(&mut self) -> &mut Value {
+        self.modified = true;
+        self.value
+    }
+}
+
+impl<D: Deduplicator> Drop for CowGuard<'_, D> {
+    fn drop(&mut self) {
+        if self.modified {
+            let value = mem::replace(self.value, Value::Unit);
+            *self.value = self.dedup.dedup(value);
+        }
+    }
+}
+
+/// Changes to a `Value` that take effect together or not at all, see `Value::transaction`.
+///
+/// The guard keeps the value as it was, which shares everything with the value being
+/// changed, so the first change to a container through `seq_mut`, `map_mut` or `get_mut`
+/// copies it, and the original tree is never touched. `commit` keeps the changes, and
+/// `abort` or dropping the guard, including by a panic, puts the original tree back.
+pub struct Transaction<'a> {
+    value: &'a mut Value,
+    // `None` once committed
+    original: Option<Value>,
+}
+
+impl Transaction<'_> {
+    /// The value as it was before the transaction.
+    pub fn original(&self) -> &Value {
+        self.original.as_ref().expect("the transaction is not committed")
+    }
+
+    /// Keeps the changes.
+    pub fn commit(mut self) {
+        self.original = None;
+    }
+
+    /// Undoes the changes, as dropping the guard does.
+    pub fn abort(self) {}
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        self.value
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Value {
+        self.value
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if let Some(original) = self.original.take() {
+            *self.value = original;
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! A compact binary format for corpora of deduplicated values.
+//!
+//! A stream starts with a string dictionary, followed by any number of records. Strings and
+//! map keys found in the dictionary are written as their id, so a string shared by many
+//! records is written once. When decoding, every reference to the same id yields the same
+//! `Arc`, so the sharing survives the round trip.
+//!
+//! ```ignore
+//! let mut encoder = DictionaryEncoder::new(Vec::new(), dedup.string_dictionary())?;
+//! for record in records.iter() {
+//!     encoder.encode(record)?;
+//! }
+//! let bytes = encoder.finish()?;
+//!
+//! for record in DictionaryDecoder::new(&bytes[..])? {
+//!     let record = record?;
+//! }
+//! ```
+//!
+//! All integers but the fixed size ones are LEB128 varints, signed ones zigzag encoded first.
+//!
+//! With the `compression` feature, `DictionaryEncoder::with_zstd` compresses the whole stream
+//! with zstd, using a zstd dictionary trained over the string table. The trained dictionary
+//! is stored uncompressed in front of the stream, and `DictionaryDecoder::new` recognizes
+//! such streams and decompresses them as it reads.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+#[cfg(not(feature = "half"))]
+use cbor;
+#[cfg(feature = "compression")]
+use compress::Codec;
+use dictionary::StringDictionary;
+use {Number, Value, KV};
+
+const MAGIC: &[u8; 4] = b"SVD\x01";
+#[cfg(feature = "compression")]
+const ZSTD_MAGIC: &[u8; 4] = b"SVZ\x01";
+// the default size limit of the zstd command line tool
+#[cfg(feature = "compression")]
+const MAX_ZSTD_DICTIONARY: usize = 110 * 1024;
+// bounds to keep malformed input from exhausting memory or the stack
+const MAX_PREALLOCATE: usize = 4096;
+const MAX_DEPTH: usize = 512;
+
+const UNIT: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const U8: u8 = 3;
+const U16: u8 = 4;
+const U32: u8 = 5;
+const U64: u8 = 6;
+const I8: u8 = 7;
+const I16: u8 = 8;
+const I32: u8 = 9;
+const I64: u8 = 10;
+const F32: u8 = 11;
+const F64: u8 = 12;
+const CHAR: u8 = 13;
+const NONE: u8 = 14;
+const SOME: u8 = 15;
+const NEWTYPE: u8 = 16;
+const UNIT_STRUCT: u8 = 17;
+const STRING: u8 = 18;
+const STRING_REF: u8 = 19;
+const BYTES: u8 = 20;
+const SEQ: u8 = 21;
+const TUPLE: u8 = 22;
+const MAP: u8 = 23;
+const SET: u8 = 24;
+const EXT: u8 = 25;
+#[cfg(feature = "compression")]
+const COMPRESSED_BYTES: u8 = 26;
+const F16: u8 = 27;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_varint<W: Write>(out: &mut W, mut x: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut n = 0;
+    while x >= 0x80 {
+        buf[n] = x as u8 | 0x80;
+        x >>= 7;
+        n += 1;
+    }
+    buf[n] = x as u8;
+    out.write_all(&buf[..=n])
+}
+
+fn write_signed<W: Write>(out: &mut W, x: i64) -> io::Result<()> {
+    write_varint(out, ((x << 1) ^ (x >> 63)) as u64)
+}
+
+fn write_len<W: Write>(out: &mut W, len: usize) -> io::Result<()> {
+    write_varint(out, len as u64)
+}
+
+/// Where an encoder writes to, compressing or not.
+enum Sink<W: Write> {
+    Plain(W),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Sink<W> {
+    // without compression, `Plain` is the only variant
+    #[allow(clippy::infallible_destructuring_match)]
+    fn finish(self) -> io::Result<W> {
+        let out = match self {
+            Sink::Plain(out) => out,
+            #[cfg(feature = "compression")]
+            Sink::Zstd(out) => out.finish()?,
+        };
+        Ok(out)
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Sink::Plain(ref mut out) => out.write(buf),
+            #[cfg(feature = "compression")]
+            Sink::Zstd(ref mut out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Sink::Plain(ref mut out) => out.flush(),
+            #[cfg(feature = "compression")]
+            Sink::Zstd(ref mut out) => out.flush(),
+        }
+    }
+}
+
+/// Where a decoder reads from, decompressing or not.
+enum Source<R: Read> {
+    Plain(R),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<R>>),
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Source::Plain(ref mut input) => input.read(buf),
+            #[cfg(feature = "compression")]
+            Source::Zstd(ref mut input) => input.read(buf),
+        }
+    }
+}
+
+/// Writes a dictionary and records in the format of this module.
+pub struct DictionaryEncoder<W: Write> {
+    out: Sink<W>,
+    dictionary: StringDictionary,
+}
+
+impl<W: Write> DictionaryEncoder<W> {
+    /// Writes the header with `dictionary` to `out`.
+    pub fn new(out: W, dictionary: StringDictionary) -> io::Result<DictionaryEncoder<W>> {
+        DictionaryEncoder::start(Sink::Plain(out), dictionary)
+    }
+
+    /// Like `new`, but compresses everything written to `out` with zstd at `level`, 0 being
+    /// the default level.
+    ///
+    /// The compressor is primed with a dictionary trained over the strings of `dictionary`,
+    /// which is written in front of the compressed stream. Small string tables, that zstd
+    /// cannot train on, get no dictionary. `finish` must be called to end the stream.
+    #[cfg(feature = "compression")]
+    pub fn with_zstd(
+        mut out: W,
+        dictionary: StringDictionary,
+        level: i32,
+    ) -> io::Result<DictionaryEncoder<W>> {
+        let samples: Vec<&[u8]> = dictionary.strings().iter().map(|s| s.as_bytes()).collect();
+        let total: usize = samples.iter().map(|s| s.len()).sum();
+        let trained = zstd::dict::from_samples(&samples, (total / 4).min(MAX_ZSTD_DICTIONARY))
+            .unwrap_or_default();
+        out.write_all(ZSTD_MAGIC)?;
+        write_len(&mut out, trained.len())?;
+        out.write_all(&trained)?;
+        let out = zstd::stream::write::Encoder::with_dictionary(out, level, &trained)?;
+        DictionaryEncoder::start(Sink::Zstd(out), dictionary)
+    }
+
+    fn start(mut out: Sink<W>, dictionary: StringDictionary) -> io::Result<DictionaryEncoder<W>> {
+        out.write_all(MAGIC)?;
+        write_len(&mut out, dictionary.len())?;
+        for s in dictionary.strings() {
+            write_len(&mut out, s.len())?;
+            out.write_all(s.as_bytes())?;
+        }
+        Ok(DictionaryEncoder { out, dictionary })
+    }
+
+    /// Writes one record. Strings missing from the dictionary are written in full.
+    pub fn encode(&mut self, record: &Value) -> io::Result<()> {
+        self.write_value(record)
+    }
+
+    /// Ends the compressed stream, if any, then flushes and returns the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        let mut out = self.out.finish()?;
+        out.flush()?;
+        Ok(out)
+    }
+
+    fn write_tag(&mut self, tag: u8) -> io::Result<()> {
+        self.out.write_all(&[tag])
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        match self.dictionary.id(s) {
+            Some(id) => {
+                self.write_tag(STRING_REF)?;
+                write_len(&mut self.out, id)
+            }
+            None => {
+                self.write_tag(STRING)?;
+                write_len(&mut self.out, s.len())?;
+                self.out.write_all(s.as_bytes())
+            }
+        }
+    }
+
+    fn write_bytes(&mut self, tag: u8, b: &[u8]) -> io::Result<()> {
+        self.write_tag(tag)?;
+        write_len(&mut self.out, b.len())?;
+        self.out.write_all(b)
+    }
+
+    fn write_elements<'a, I>(&mut self, tag: u8, len: usize, elements: I) -> io::Result<()>
+    where
+        I: Iterator<Item = &'a Value>,
+    {
+        self.write_tag(tag)?;
+        write_len(&mut self.out, len)?;
+        for x in elements {
+            self.write_value(x)?;
+        }
+        Ok(())
+    }
+
+    fn write_value(&mut self, value: &Value) -> io::Result<()> {
+        match *value {
+            Value::Unit => self.write_tag(UNIT),
+            Value::Bool(false) => self.write_tag(FALSE),
+            Value::Bool(true) => self.write_tag(TRUE),
+            Value::Number(Number::U8(v)) => self.out.write_all(&[U8, v]),
+            Value::Number(Number::U16(v)) => {
+                self.write_tag(U16)?;
+                write_varint(&mut self.out, u64::from(v))
+            }
+            Value::Number(Number::U32(v)) => {
+                self.write_tag(U32)?;
+                write_varint(&mut self.out, u64::from(v))
+            }
+            Value::Number(Number::U64(v)) => {
+                self.write_tag(U64)?;
+                write_varint(&mut self.out, v)
+            }
+            Value::Number(Number::I8(v)) => self.out.write_all(&[I8, v as u8]),
+            Value::Number(Number::I16(v)) => {
+                self.write_tag(I16)?;
+                write_signed(&mut self.out, i64::from(v))
+            }
+            Value::Number(Number::I32(v)) => {
+                self.write_tag(I32)?;
+                write_signed(&mut self.out, i64::from(v))
+            }
+            Value::Number(Number::I64(v)) => {
+                self.write_tag(I64)?;
+                write_signed(&mut self.out, v)
+            }
+            Value::Number(Number::F32(v)) => {
+                self.write_tag(F32)?;
+                self.out.write_all(&v.to_le_bytes())
+            }
+            Value::Number(Number::F64(v)) => {
+                self.write_tag(F64)?;
+                self.out.write_all(&v.to_le_bytes())
+            }
+            #[cfg(feature = "half")]
+            Value::F16(v) => {
+                self.write_tag(F16)?;
+                self.out.write_all(&v.to_le_bytes())
+            }
+            Value::Char(v) => {
+                self.write_tag(CHAR)?;
+                write_varint(&mut self.out, u64::from(v))
+            }
+            Value::Option(None) => self.write_tag(NONE),
+            Value::Option(Some(ref v)) => {
+                self.write_tag(SOME)?;
+                self.write_value(v)
+            }
+            Value::Newtype(ref v) => {
+                self.write_tag(NEWTYPE)?;
+                self.write_value(v)
+            }
+            Value::UnitStruct(ref name) => {
+                self.write_tag(UNIT_STRUCT)?;
+                self.write_str(name)
+            }
+            Value::String(ref v) => self.write_str(v),
+            Value::Bytes(ref v) => self.write_bytes(BYTES, v),
+            // read back as `Bytes`, the mapping is not part of the encoding
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => self.write_bytes(BYTES, v),
+            Value::Seq(ref v) => self.write_elements(SEQ, v.len(), v.iter()),
+            Value::Tuple(ref v) => self.write_elements(TUPLE, v.len(), v.iter()),
+            Value::Set(ref v) => self.write_elements(SET, v.len(), v.iter()),
+            Value::Map(ref kv) => {
+                let KV(ref keys, ref values) = **kv;
+                self.write_elements(MAP, keys.len(), keys.iter().chain(values.iter()))
+            }
+            Value::Ext { tag, ref value } => {
+                self.write_tag(EXT)?;
+                write_varint(&mut self.out, tag)?;
+                self.write_value(value)
+            }
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => {
+                let codec = match codec {
+                    Codec::Lz4 => 0,
+                    Codec::Zstd => 1,
+                };
+                self.out.write_all(&[COMPRESSED_BYTES, codec])?;
+                write_len(&mut self.out, data.len())?;
+                self.out.write_all(data)
+            }
+        }
+    }
+}
+
+/// Reads what a `DictionaryEncoder` wrote, one record at a time.
+pub struct DictionaryDecoder<R: Read> {
+    input: Source<R>,
+    dictionary: StringDictionary,
+}
+
+impl<R: Read> DictionaryDecoder<R> {
+    /// Reads the header with the dictionary from `input`.
+    ///
+    /// With the `compression` feature, this also reads streams written by
+    /// `DictionaryEncoder::with_zstd`, decompressing them as records are read.
+    pub fn new(mut input: R) -> io::Result<DictionaryDecoder<R>> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        let mut decoder = DictionaryDecoder {
+            input: Source::Plain(input),
+            dictionary: StringDictionary::default(),
+        };
+        #[cfg(feature = "compression")]
+        {
+            if &magic == ZSTD_MAGIC {
+                let trained = decoder.read_buf()?;
+                let input = match decoder.input {
+                    Source::Plain(input) => io::BufReader::new(input),
+                    Source::Zstd(..) => unreachable!("compressed twice"),
+                };
+                let input = zstd::stream::read::Decoder::with_dictionary(input, &trained)?;
+                decoder.input = Source::Zstd(input);
+                decoder.input.read_exact(&mut magic)?;
+            }
+        }
+        if &magic != MAGIC {
+            return Err(invalid("not a dictionary encoded stream".to_owned()));
+        }
+        let len = decoder.read_len()?;
+        let mut strings = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+        for _ in 0..len {
+            strings.push(decoder.read_string()?);
+        }
+        decoder.dictionary = StringDictionary::from_strings(strings);
+        Ok(decoder)
+    }
+
+    /// The dictionary from the header.
+    pub fn dictionary(&self) -> &StringDictionary {
+        &self.dictionary
+    }
+
+    /// The next record, or `None` at the end of the input.
+    pub fn decode(&mut self) -> io::Result<Option<Value>> {
+        let mut tag = [0u8; 1];
+        loop {
+            match self.input.read(&mut tag) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return self.read_value(tag[0], 0).map(Some),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.input.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_array<A: AsMut<[u8]> + Default>(&mut self) -> io::Result<A> {
+        let mut buf = A::default();
+        self.input.read_exact(buf.as_mut())?;
+        Ok(buf)
+    }
+
+    fn read_varint(&mut self) -> io::Result<u64> {
+        let mut x = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.read_u8()?;
+            x |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(invalid("varint too long".to_owned()))
+    }
+
+    fn read_signed(&mut self) -> io::Result<i64> {
+        let x = self.read_varint()?;
+        Ok((x >> 1) as i64 ^ -((x & 1) as i64))
+    }
+
+    fn read_narrow<T: TryFrom<u64>>(&mut self) -> io::Result<T> {
+        let x = self.read_varint()?;
+        T::try_from(x).map_err(|_| invalid(format!("integer {} out of range", x)))
+    }
+
+    fn read_narrow_signed<T: TryFrom<i64>>(&mut self) -> io::Result<T> {
+        let x = self.read_signed()?;
+        T::try_from(x).map_err(|_| invalid(format!("integer {} out of range", x)))
+    }
+
+    fn read_len(&mut self) -> io::Result<usize> {
+        self.read_narrow()
+    }
+
+    fn read_buf(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_len()?;
+        let mut buf = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+        (&mut self.input).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buf)
+    }
+
+    fn read_string(&mut self) -> io::Result<Arc<str>> {
+        let buf = self.read_buf()?;
+        String::from_utf8(buf)
+            .map(Arc::from)
+            .map_err(|e| invalid(e.to_string()))
+    }
+
+    fn read_str(&mut self, tag: u8) -> io::Result<Arc<str>> {
+        match tag {
+            STRING => self.read_string(),
+            STRING_REF => {
+                let id = self.read_len()?;
+                self.dictionary
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("unknown string id {}", id)))
+            }
+            tag => Err(invalid(format!("expected a string, found tag {}", tag))),
+        }
+    }
+
+    fn read_elements(&mut self, len: usize, depth: usize) -> io::Result<Vec<Value>> {
+        let mut elements = Vec::with_capacity(len.min(MAX_PREALLOCATE));
+        for _ in 0..len {
+            let tag = self.read_u8()?;
+            elements.push(self.read_value(tag, depth + 1)?);
+        }
+        Ok(elements)
+    }
+
+    fn read_boxed(&mut self, depth: usize) -> io::Result<Box<Value>> {
+        let tag = self.read_u8()?;
+        self.read_value(tag, depth + 1).map(Box::new)
+    }
+
+    fn read_value(&mut self, tag: u8, depth: usize) -> io::Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("values nested too deeply".to_owned()));
+        }
+        Ok(match tag {
+            UNIT => Value::Unit,
+            FALSE => Value::Bool(false),
+            TRUE => Value::Bool(true),
+            U8 => Value::Number(Number::U8(self.read_u8()?)),
+            U16 => Value::Number(Number::U16(self.read_narrow()?)),
+            U32 => Value::Number(Number::U32(self.read_narrow()?)),
+            U64 => Value::Number(Number::U64(self.read_varint()?)),
+            I8 => Value::Number(Number::I8(self.read_u8()? as i8)),
+            I16 => Value::Number(Number::I16(self.read_narrow_signed()?)),
+            I32 => Value::Number(Number::I32(self.read_narrow_signed()?)),
+            I64 => Value::Number(Number::I64(self.read_signed()?)),
+            F32 => Value::Number(Number::F32(f32::from_le_bytes(self.read_array()?))),
+            F64 => Value::Number(Number::F64(f64::from_le_bytes(self.read_array()?))),
+            #[cfg(feature = "half")]
+            F16 => Value::F16(half::f16::from_le_bytes(self.read_array()?)),
+            // the same number, as `f32` holds every `f16` exactly
+            #[cfg(not(feature = "half"))]
+            F16 => {
+                let bits = u16::from_le_bytes(self.read_array()?);
+                Value::Number(Number::F32(cbor::f16_to_f32(bits)))
+            }
+            CHAR => {
+                let c: u32 = self.read_narrow()?;
+                Value::Char(
+                    std::char::from_u32(c).ok_or_else(|| invalid(format!("invalid char {}", c)))?,
+                )
+            }
+            NONE => Value::Option(None),
+            SOME => Value::Option(Some(self.read_boxed(depth)?)),
+            NEWTYPE => Value::Newtype(self.read_boxed(depth)?),
+            UNIT_STRUCT => {
+                let tag = self.read_u8()?;
+                Value::UnitStruct(self.read_str(tag)?)
+            }
+            STRING | STRING_REF => Value::String(self.read_str(tag)?),
+            BYTES => Value::Bytes(Arc::new(self.read_buf()?)),
+            SEQ => {
+                let len = self.read_len()?;
+                Value::Seq(Arc::new(self.read_elements(len, depth)?))
+            }
+            TUPLE => {
+                let len = self.read_len()?;
+                Value::Tuple(Arc::new(self.read_elements(len, depth)?))
+            }
+            SET => {
+                let len = self.read_len()?;
+                let elements = self.read_elements(len, depth)?;
+                Value::Set(Arc::new(elements.into_iter().collect::<BTreeSet<_>>()))
+            }
+            MAP => {
+                let len = self.read_len()?;
+                let keys = self.read_elements(len, depth)?;
+                let values = self.read_elements(len, depth)?;
+                if keys.windows(2).any(|w| w[0] >= w[1]) {
+                    return Err(invalid("map keys out of order".to_owned()));
+                }
+                Value::Map(Arc::new(KV(Arc::new(keys), values)))
+            }
+            EXT => {
+                let tag = self.read_varint()?;
+                Value::Ext {
+                    tag,
+                    value: self.read_boxed(depth)?,
+                }
+            }
+            #[cfg(feature = "compression")]
+            COMPRESSED_BYTES => {
+                let codec = match self.read_u8()? {
+                    0 => Codec::Lz4,
+                    1 => Codec::Zstd,
+                    codec => return Err(invalid(format!("unknown codec {}", codec))),
+                };
+                Value::CompressedBytes {
+                    codec,
+                    data: Arc::new(self.read_buf()?),
+                }
+            }
+            tag => return Err(invalid(format!("unknown tag {}", tag))),
+        })
+    }
+}
+
+/// Writes `value` without a header or dictionary, so strings are written in full, for
+/// transports that frame values themselves.
+#[cfg(feature = "codec")]
+pub(crate) fn write_value<W: Write>(out: W, value: &Value) -> io::Result<()> {
+    let mut encoder = DictionaryEncoder {
+        out: Sink::Plain(out),
+        dictionary: StringDictionary::default(),
+    };
+    encoder.write_value(value)?;
+    encoder.out.flush()
+}
+
+/// Reads what `write_value` wrote, or `None` at the end of the input.
+#[cfg(feature = "codec")]
+pub(crate) fn read_value<R: Read>(input: R) -> io::Result<Option<Value>> {
+    DictionaryDecoder {
+        input: Source::Plain(input),
+        dictionary: StringDictionary::default(),
+    }
+    .decode()
+}
+
+impl<R: Read> Iterator for DictionaryDecoder<R> {
+    type Item = io::Result<Value>;
+
+    fn next(&mut self) -> Option<io::Result<Value>> {
+        self.decode().transpose()
+    }
+}
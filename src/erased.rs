@@ -0,0 +1,16 @@
+//! Conversion of type erased values, behind the `erased-serde` feature.
+//!
+//! `Value` itself already implements `erased_serde::Serialize` through the blanket impl for
+//! all `serde::Serialize` types, so it can be boxed as `Box<dyn erased_serde::Serialize>`
+//! next to other types.
+
+use erased_serde;
+
+use {to_value, SerializerError, Value};
+
+/// Like `to_value`, for a value of a type only known at runtime.
+///
+/// All callers share a single instance of the serializer, instead of one per type.
+pub fn to_value_dyn(value: &dyn erased_serde::Serialize) -> Result<Value, SerializerError> {
+    to_value(value)
+}
@@ -0,0 +1,273 @@
+//! A `DedupJson` extractor for web handlers, behind the `axum-extractor` and
+//! `actix-extractor` features.
+//!
+//! Request bodies are parsed as JSON and interned through a `DedupHandle` of the
+//! application, so that the strings, arrays and objects that requests repeat are kept once
+//! for as long as any request holds on to them.
+//!
+//! ```ignore
+//! let service = DedupService::spawn(Dedup::new(), 64);
+//! // axum, with `DedupHandle: FromRef<S>`
+//! let app = Router::new().route("/events", post(ingest)).with_state(service.handle());
+//! // actix-web
+//! let app = App::new().app_data(service.handle()).route("/events", web::post().to(ingest));
+//!
+//! fn ingest(DedupJson(event): DedupJson) -> impl Future<Output = StatusCode> { ... }
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use serde_json;
+
+use service::{DedupHandle, Intern, ServiceStopped};
+use Value;
+
+/// A JSON request body as a `Value` interned by the `DedupHandle` of the application.
+///
+/// Like the JSON extractors of the frameworks, the request must have a content type of
+/// `application/json` or `application/*+json`, and the body is limited by their usual
+/// limits on payloads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DedupJson(pub Value);
+
+/// Why a request could not be extracted as `DedupJson`.
+#[derive(Debug)]
+pub enum DedupJsonRejection {
+    /// The content type is missing or not JSON.
+    ContentType,
+    /// The body could not be read, as the framework reported it.
+    Body {
+        status: u16,
+        message: String,
+    },
+    Json(serde_json::Error),
+    Stopped(ServiceStopped),
+    /// The application has no `DedupHandle`.
+    MissingHandle,
+}
+
+impl DedupJsonRejection {
+    /// The HTTP status code of the response to the request.
+    pub fn status(&self) -> u16 {
+        match *self {
+            DedupJsonRejection::ContentType => 415,
+            DedupJsonRejection::Body { status, .. } => status,
+            DedupJsonRejection::Json(_) => 400,
+            DedupJsonRejection::Stopped(_) => 503,
+            DedupJsonRejection::MissingHandle => 500,
+        }
+    }
+}
+
+impl fmt::Display for DedupJsonRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DedupJsonRejection::ContentType => {
+                write!(
+                    f,
+                    "expected a request with a content type of application/json"
+                )
+            }
+            DedupJsonRejection::Body { ref message, .. } => f.write_str(message),
+            DedupJsonRejection::Json(ref e) => write!(f, "invalid JSON: {}", e),
+            DedupJsonRejection::Stopped(ref e) => e.fmt(f),
+            DedupJsonRejection::MissingHandle => {
+                write!(f, "the application has no DedupHandle for DedupJson")
+            }
+        }
+    }
+}
+
+impl Error for DedupJsonRejection {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DedupJsonRejection::Json(ref e) => Some(e),
+            DedupJsonRejection::Stopped(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a content type is `application/json` or `application/*+json`, with any
+/// parameters.
+fn is_json(content_type: Option<&str>) -> bool {
+    let essence = match content_type {
+        Some(content_type) => content_type.split(';').next().unwrap().trim(),
+        None => return false,
+    };
+    let mut parts = essence.splitn(2, '/');
+    let (kind, subtype) = match (parts.next(), parts.next()) {
+        (Some(kind), Some(subtype)) => (kind, subtype.to_ascii_lowercase()),
+        _ => return false,
+    };
+    kind.eq_ignore_ascii_case("application") && (subtype == "json" || subtype.ends_with("+json"))
+}
+
+enum State<F> {
+    Rejected(Option<DedupJsonRejection>),
+    Body(Pin<Box<F>>, DedupHandle),
+    Intern(Intern),
+}
+
+/// The future of a `DedupJson` extraction, reading the body with `F`, then interning it.
+pub struct DedupJsonFuture<F> {
+    state: State<F>,
+}
+
+impl<F> DedupJsonFuture<F> {
+    fn new(body: Result<(F, DedupHandle), DedupJsonRejection>) -> DedupJsonFuture<F> {
+        let state = match body {
+            Ok((body, handle)) => State::Body(Box::pin(body), handle),
+            Err(rejection) => State::Rejected(Some(rejection)),
+        };
+        DedupJsonFuture { state }
+    }
+}
+
+impl<F, B, E> Future for DedupJsonFuture<F>
+where
+    F: Future<Output = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<DedupJsonRejection>,
+{
+    type Output = Result<DedupJson, DedupJsonRejection>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            let intern = match this.state {
+                State::Rejected(ref mut rejection) => {
+                    let rejection = rejection.take().expect("polled after completion");
+                    return Poll::Ready(Err(rejection));
+                }
+                State::Body(ref mut body, ref handle) => {
+                    let body = match body.as_mut().poll(cx) {
+                        Poll::Ready(body) => body.map_err(Into::into)?,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let value = serde_json::from_slice(body.as_ref());
+                    handle.intern_async(value.map_err(DedupJsonRejection::Json)?)
+                }
+                State::Intern(ref mut intern) => {
+                    return Pin::new(intern)
+                        .poll(cx)
+                        .map(|value| value.map(DedupJson).map_err(DedupJsonRejection::Stopped));
+                }
+            };
+            this.state = State::Intern(intern);
+        }
+    }
+}
+
+impl<F> fmt::Debug for DedupJsonFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DedupJsonFuture").finish()
+    }
+}
+
+#[cfg(feature = "axum-extractor")]
+mod axum_impl {
+    use std::future::Future;
+
+    use axum::body::Bytes;
+    use axum::extract::rejection::BytesRejection;
+    use axum::extract::{FromRef, FromRequest, Request};
+    use axum::http::{header, StatusCode};
+    use axum::response::{IntoResponse, Response};
+
+    use super::{is_json, DedupJson, DedupJsonFuture, DedupJsonRejection};
+    use service::DedupHandle;
+
+    impl From<BytesRejection> for DedupJsonRejection {
+        fn from(rejection: BytesRejection) -> DedupJsonRejection {
+            DedupJsonRejection::Body {
+                status: rejection.status().as_u16(),
+                message: rejection.body_text(),
+            }
+        }
+    }
+
+    impl IntoResponse for DedupJsonRejection {
+        fn into_response(self) -> Response {
+            let status = StatusCode::from_u16(self.status()).unwrap();
+            (status, self.to_string()).into_response()
+        }
+    }
+
+    /// Takes the `DedupHandle` from the state of the router.
+    impl<S> FromRequest<S> for DedupJson
+    where
+        DedupHandle: FromRef<S>,
+        S: Send + Sync,
+    {
+        type Rejection = DedupJsonRejection;
+
+        fn from_request(
+            req: Request,
+            state: &S,
+        ) -> impl Future<Output = Result<DedupJson, DedupJsonRejection>> + Send {
+            let content_type = req.headers().get(header::CONTENT_TYPE);
+            let body = if is_json(content_type.and_then(|v| v.to_str().ok())) {
+                Ok((
+                    Bytes::from_request(req, state),
+                    DedupHandle::from_ref(state),
+                ))
+            } else {
+                Err(DedupJsonRejection::ContentType)
+            };
+            DedupJsonFuture::new(body)
+        }
+    }
+}
+
+#[cfg(feature = "actix-extractor")]
+mod actix_impl {
+    use actix_web::dev::Payload;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::web::{Bytes, Data};
+    use actix_web::{FromRequest, HttpRequest, ResponseError};
+
+    use super::{is_json, DedupJson, DedupJsonFuture, DedupJsonRejection};
+    use service::DedupHandle;
+
+    impl From<actix_web::Error> for DedupJsonRejection {
+        fn from(e: actix_web::Error) -> DedupJsonRejection {
+            DedupJsonRejection::Body {
+                status: e.as_response_error().status_code().as_u16(),
+                message: e.to_string(),
+            }
+        }
+    }
+
+    impl ResponseError for DedupJsonRejection {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::from_u16(self.status()).unwrap()
+        }
+    }
+
+    /// Takes the `DedupHandle` from the app data, either as is or in a `web::Data`.
+    impl FromRequest for DedupJson {
+        type Error = DedupJsonRejection;
+        type Future = DedupJsonFuture<<Bytes as FromRequest>::Future>;
+
+        fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+            let handle = req.app_data::<DedupHandle>().or_else(|| {
+                req.app_data::<Data<DedupHandle>>()
+                    .map(|data| data.get_ref())
+            });
+            let content_type = req.headers().get(header::CONTENT_TYPE);
+            let body = match handle {
+                None => Err(DedupJsonRejection::MissingHandle),
+                Some(_) if !is_json(content_type.and_then(|v| v.to_str().ok())) => {
+                    Err(DedupJsonRejection::ContentType)
+                }
+                Some(handle) => Ok((Bytes::from_request(req, payload), handle.clone())),
+            };
+            DedupJsonFuture::new(body)
+        }
+    }
+}
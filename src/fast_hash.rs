@@ -0,0 +1,193 @@
+//! Faster hashing of long strings and blobs in the `Dedup` tables, behind the `fast-hash`
+//! feature, see `Dedup::with_fast_hashing`.
+//!
+//! # Hash flooding
+//!
+//! A hash table degrades to a linear scan for keys with equal hashes, so whoever can send
+//! many colliding strings or blobs to a `Dedup` can make each lookup cost the whole
+//! table. By default, every table hashes with SipHash under random keys, which makes
+//! collisions impossible to craft without the keys.
+//!
+//! `with_fast_hashing` gives up part of that for speed: long values are hashed with XXH3
+//! under a random seed, but as XXH3 is not keyed like SipHash, there are inputs that collide
+//! under any seed. Use it for trusted input. `with_keyed_fast_hashing` instead keys XXH3
+//! with a random secret of its full size, so that collisions depend on the secret, which
+//! never leaves the process. Every table draws its secret from the operating system, so
+//! one table revealing its hashes, such as by the iteration order of `iter_strings`, tells
+//! nothing about the secret of another. XXH3 is still no cryptographic hash, so this raises
+//! the bar for input from the internet rather than matching SipHash.
+//!
+//! The SipHash that every value finally goes through is keyed like any `HashMap`, by
+//! `RandomState`. Its keys are random for each thread, but the tables created on one thread
+//! get keys that only differ in a counter, so they are related rather than independent.
+
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use twox_hash::xxhash3_64::DEFAULT_SECRET_LENGTH;
+use twox_hash::XxHash3_64;
+
+use {Dedup, Table};
+
+/// What long values are hashed with before they go through SipHash.
+#[derive(Clone)]
+enum Key {
+    Seed(u64),
+    Secret(Arc<[u8]>),
+}
+
+// like `RandomState`, which keeps its keys to itself
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Key::Seed(_) => f.pad("Seed { .. }"),
+            Key::Secret(_) => f.pad("Secret { .. }"),
+        }
+    }
+}
+
+impl Key {
+    fn hash(&self, bytes: &[u8]) -> u64 {
+        match *self {
+            Key::Seed(seed) => XxHash3_64::oneshot_with_seed(seed, bytes),
+            Key::Secret(ref secret) => XxHash3_64::oneshot_with_secret(secret, bytes)
+                .expect("secret shorter than the minimum"),
+        }
+    }
+}
+
+/// The hasher of the `Dedup` tables.
+///
+/// This is SipHash with random keys, as with `RandomState`, except that byte strings of at
+/// least `threshold` bytes are first reduced to their XXH3 hash, which uses SIMD where
+/// available. Equal values have equal lengths, so they always take the same path.
+#[derive(Clone, Debug)]
+pub(crate) struct TableHashing {
+    random: RandomState,
+    key: Key,
+    threshold: usize,
+}
+
+impl TableHashing {
+    /// Draws a new key from the operating system, independent of those of other tables.
+    fn new(threshold: usize, keyed: bool) -> TableHashing {
+        let key = if keyed {
+            let mut secret = vec![0; DEFAULT_SECRET_LENGTH];
+            os_random(&mut secret);
+            Key::Secret(secret.into())
+        } else {
+            let mut seed = [0; 8];
+            os_random(&mut seed);
+            Key::Seed(u64::from_le_bytes(seed))
+        };
+        TableHashing {
+            random: RandomState::new(),
+            key,
+            threshold,
+        }
+    }
+}
+
+fn os_random(bytes: &mut [u8]) {
+    getrandom::fill(bytes).expect("no random numbers from the operating system");
+}
+
+/// Never takes the fast path, so tables hash as with `RandomState`.
+impl Default for TableHashing {
+    fn default() -> TableHashing {
+        TableHashing {
+            random: RandomState::new(),
+            key: Key::Seed(0),
+            threshold: usize::MAX,
+        }
+    }
+}
+
+impl BuildHasher for TableHashing {
+    type Hasher = TableHasher;
+
+    fn build_hasher(&self) -> TableHasher {
+        TableHasher {
+            inner: self.random.build_hasher(),
+            key: self.key.clone(),
+            threshold: self.threshold,
+        }
+    }
+}
+
+pub(crate) struct TableHasher {
+    inner: DefaultHasher,
+    key: Key,
+    threshold: usize,
+}
+
+impl Hasher for TableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        if bytes.len() >= self.threshold {
+            self.inner.write_u64(self.key.hash(bytes));
+        } else {
+            self.inner.write(bytes);
+        }
+    }
+
+    // lengths, discriminants and numbers are never long
+    fn write_u8(&mut self, i: u8) {
+        self.inner.write_u8(i);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.inner.write_u64(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.inner.write_usize(i);
+    }
+
+    fn finish(&self) -> u64 {
+        self.inner.finish()
+    }
+}
+
+fn rehash<T: Hash + Eq + ?Sized>(table: &mut Table<T>, threshold: usize, keyed: bool) {
+    let hashing = TableHashing::new(threshold, keyed);
+    let mut rehashed = HashSet::with_capacity_and_hasher(table.len(), hashing);
+    rehashed.extend(table.drain());
+    *table = rehashed;
+}
+
+impl Dedup {
+    fn rehash(&mut self, threshold: usize, keyed: bool) {
+        rehash(&mut self.blobs, threshold, keyed);
+        rehash(&mut self.strings, threshold, keyed);
+        rehash(&mut self.vectors, threshold, keyed);
+        rehash(&mut self.objects, threshold, keyed);
+    }
+
+    /// Hashes strings and blobs of at least `threshold` bytes with XXH3 before they go
+    /// through SipHash, for the tables and for the contents of interned sequences and maps.
+    ///
+    /// Hashing dominates the lookup of large values, as a hit is then confirmed with a
+    /// `memcmp` of the candidate, and XXH3 is many times faster than SipHash for them. A
+    /// threshold of a few hundred bytes is a good start. The entries already interned are
+    /// rehashed.
+    ///
+    /// Long values from untrusted sources can be crafted to collide, see the module
+    /// documentation, so use `with_keyed_fast_hashing` for those.
+    pub fn with_fast_hashing(mut self, threshold: usize) -> Dedup {
+        self.rehash(threshold, false);
+        self
+    }
+
+    /// Like `with_fast_hashing`, but with XXH3 keyed by a random secret for each table, for
+    /// tables filled from untrusted sources.
+    ///
+    /// Building a hasher then also clones a reference to the secret, which costs a little
+    /// on small values.
+    pub fn with_keyed_fast_hashing(mut self, threshold: usize) -> Dedup {
+        self.rehash(threshold, true);
+        self
+    }
+}
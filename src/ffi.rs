@@ -0,0 +1,386 @@
+//! C bindings, behind the `ffi` feature. `include/serde_arc_value.h` declares them.
+//!
+//! Values and dedup tables are handed out as owned pointers, which must be released with
+//! `serde_arc_value_free` and `serde_arc_dedup_free`. Functions returning a value return a
+//! new handle, or `NULL` if there is none; handles passed in are only borrowed. Copying a
+//! handle is cheap, as values share their contents.
+//!
+//! # Safety
+//!
+//! All pointers passed in must be `NULL` only where documented, point to live objects
+//! returned by this module, and `(ptr, len)` pairs must describe readable memory. Strings
+//! and bytes returned by `serde_arc_value_as_str` and `serde_arc_value_as_bytes` are valid
+//! as long as the value they came from.
+//!
+//! To link these into a C program, build a static or dynamic library with
+//! `cargo rustc --release --features ffi --crate-type staticlib` (or `cdylib`).
+
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use std::collections::BTreeMap;
+use std::ptr;
+use std::slice;
+use std::str;
+
+use path;
+use {Dedup, Deduplicator, Number, Value};
+
+/// An opaque value.
+pub struct serde_arc_value_t(pub(crate) Value);
+
+/// An opaque dedup table.
+pub struct serde_arc_dedup_t(Dedup);
+
+/// The kind of a value, as returned by `serde_arc_value_kind`.
+///
+/// Options, newtypes and extension values are looked through, here and by all accessors.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum serde_arc_value_kind_t {
+    SERDE_ARC_VALUE_NULL,
+    SERDE_ARC_VALUE_BOOL,
+    SERDE_ARC_VALUE_INTEGER,
+    SERDE_ARC_VALUE_FLOAT,
+    SERDE_ARC_VALUE_STRING,
+    SERDE_ARC_VALUE_BYTES,
+    SERDE_ARC_VALUE_SEQ,
+    SERDE_ARC_VALUE_MAP,
+    SERDE_ARC_VALUE_SET,
+    SERDE_ARC_VALUE_OTHER,
+}
+
+fn handle(value: Value) -> *mut serde_arc_value_t {
+    Box::into_raw(Box::new(serde_arc_value_t(value)))
+}
+
+fn handle_opt(value: Option<&Value>) -> *mut serde_arc_value_t {
+    value.map_or(ptr::null_mut(), |v| handle(v.clone()))
+}
+
+unsafe fn value<'a>(v: *const serde_arc_value_t) -> &'a Value {
+    path::transparent(&(*v).0)
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+unsafe fn values(items: *const *const serde_arc_value_t, len: usize) -> Vec<Value> {
+    if len == 0 {
+        return Vec::new();
+    }
+    slice::from_raw_parts(items, len)
+        .iter()
+        .map(|&v| (*v).0.clone())
+        .collect()
+}
+
+#[no_mangle]
+pub extern "C" fn serde_arc_value_unit() -> *mut serde_arc_value_t {
+    handle(Value::Unit)
+}
+
+#[no_mangle]
+pub extern "C" fn serde_arc_value_bool(v: bool) -> *mut serde_arc_value_t {
+    handle(Value::Bool(v))
+}
+
+#[no_mangle]
+pub extern "C" fn serde_arc_value_u64(v: u64) -> *mut serde_arc_value_t {
+    handle(Value::Number(Number::U64(v)))
+}
+
+#[no_mangle]
+pub extern "C" fn serde_arc_value_i64(v: i64) -> *mut serde_arc_value_t {
+    handle(Value::Number(Number::I64(v)))
+}
+
+#[no_mangle]
+pub extern "C" fn serde_arc_value_f64(v: f64) -> *mut serde_arc_value_t {
+    handle(Value::Number(Number::F64(v)))
+}
+
+/// A string value, or `NULL` if the data is not UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_string(
+    data: *const u8,
+    len: usize,
+) -> *mut serde_arc_value_t {
+    match str::from_utf8(bytes(data, len)) {
+        Ok(s) => handle(Value::string(s.to_owned())),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_bytes(
+    data: *const u8,
+    len: usize,
+) -> *mut serde_arc_value_t {
+    handle(Value::bytes(bytes(data, len).to_vec()))
+}
+
+/// A sequence of the `len` values at `items`.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_seq(
+    items: *const *const serde_arc_value_t,
+    len: usize,
+) -> *mut serde_arc_value_t {
+    handle(Value::seq(values(items, len)))
+}
+
+/// A map of `keys[i]` to `values[i]`. Of repeated keys, the last one wins.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_map(
+    keys: *const *const serde_arc_value_t,
+    values: *const *const serde_arc_value_t,
+    len: usize,
+) -> *mut serde_arc_value_t {
+    let map: BTreeMap<Value, Value> = self::values(keys, len)
+        .into_iter()
+        .zip(self::values(values, len))
+        .collect();
+    handle(Value::map(map))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_clone(
+    v: *const serde_arc_value_t,
+) -> *mut serde_arc_value_t {
+    handle((*v).0.clone())
+}
+
+/// Releases a value. `v` may be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_free(v: *mut serde_arc_value_t) {
+    if !v.is_null() {
+        drop(Box::from_raw(v));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_eq(
+    a: *const serde_arc_value_t,
+    b: *const serde_arc_value_t,
+) -> bool {
+    (*a).0 == (*b).0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_kind(
+    v: *const serde_arc_value_t,
+) -> serde_arc_value_kind_t {
+    use self::serde_arc_value_kind_t::*;
+    match *value(v) {
+        Value::Unit | Value::Option(None) => SERDE_ARC_VALUE_NULL,
+        Value::Bool(..) => SERDE_ARC_VALUE_BOOL,
+        Value::String(..) => SERDE_ARC_VALUE_STRING,
+        Value::Bytes(..) => SERDE_ARC_VALUE_BYTES,
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(..) => SERDE_ARC_VALUE_BYTES,
+        Value::Seq(..) | Value::Tuple(..) => SERDE_ARC_VALUE_SEQ,
+        Value::Map(..) => SERDE_ARC_VALUE_MAP,
+        Value::Set(..) => SERDE_ARC_VALUE_SET,
+        ref other => match other.as_number() {
+            Some(n) if n.is_float() => SERDE_ARC_VALUE_FLOAT,
+            Some(..) => SERDE_ARC_VALUE_INTEGER,
+            None => SERDE_ARC_VALUE_OTHER,
+        },
+    }
+}
+
+/// Stores a boolean value in `out`, returning whether it was one.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_as_bool(
+    v: *const serde_arc_value_t,
+    out: *mut bool,
+) -> bool {
+    match *value(v) {
+        Value::Bool(b) => {
+            *out = b;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Stores an integer that fits a `uint64_t` in `out`, returning whether it was one.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_as_u64(
+    v: *const serde_arc_value_t,
+    out: *mut u64,
+) -> bool {
+    match value(v).as_number().and_then(|n| n.as_u64()) {
+        Some(n) => {
+            *out = n;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stores an integer that fits an `int64_t` in `out`, returning whether it was one.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_as_i64(
+    v: *const serde_arc_value_t,
+    out: *mut i64,
+) -> bool {
+    match value(v).as_number().and_then(|n| n.as_i64()) {
+        Some(n) => {
+            *out = n;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stores any number in `out`, possibly rounded, returning whether it was one.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_as_f64(
+    v: *const serde_arc_value_t,
+    out: *mut f64,
+) -> bool {
+    match value(v).as_number() {
+        Some(n) => {
+            *out = n.as_f64();
+            true
+        }
+        None => false,
+    }
+}
+
+/// The UTF-8 data of a string value, not NUL terminated, with its length stored in `len`.
+/// `NULL` for other values.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_as_str(
+    v: *const serde_arc_value_t,
+    len: *mut usize,
+) -> *const u8 {
+    match *value(v) {
+        Value::String(ref s) => {
+            *len = s.len();
+            s.as_ptr()
+        }
+        _ => ptr::null(),
+    }
+}
+
+/// The data of a bytes value, with its length stored in `len`. `NULL` for other values.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_as_bytes(
+    v: *const serde_arc_value_t,
+    len: *mut usize,
+) -> *const u8 {
+    match *value(v) {
+        Value::Bytes(ref b) => {
+            *len = b.len();
+            b.as_ptr()
+        }
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(ref b) => {
+            *len = b.len();
+            b.as_ptr()
+        }
+        _ => ptr::null(),
+    }
+}
+
+/// The number of elements of a sequence, map or set, and 0 for other values.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_len(v: *const serde_arc_value_t) -> usize {
+    match *value(v) {
+        Value::Seq(ref v) | Value::Tuple(ref v) => v.len(),
+        Value::Map(ref kv) => kv.keys().len(),
+        Value::Set(ref v) => v.len(),
+        _ => 0,
+    }
+}
+
+/// The element at `index` of a sequence or set.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_at(
+    v: *const serde_arc_value_t,
+    index: usize,
+) -> *mut serde_arc_value_t {
+    match *value(v) {
+        Value::Seq(ref v) | Value::Tuple(ref v) => handle_opt(v.get(index)),
+        Value::Set(ref v) => handle_opt(v.iter().nth(index)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// The key at `index` of a map, in key order.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_key_at(
+    v: *const serde_arc_value_t,
+    index: usize,
+) -> *mut serde_arc_value_t {
+    match *value(v) {
+        Value::Map(ref kv) => handle_opt(kv.keys().get(index)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// The value at `index` of a map, in key order.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_value_at(
+    v: *const serde_arc_value_t,
+    index: usize,
+) -> *mut serde_arc_value_t {
+    match *value(v) {
+        Value::Map(ref kv) => handle_opt(kv.values().get(index)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// The value for the string key `(key, len)` of a map.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_get(
+    v: *const serde_arc_value_t,
+    key: *const u8,
+    len: usize,
+) -> *mut serde_arc_value_t {
+    match str::from_utf8(bytes(key, len)) {
+        Ok(key) => handle_opt(value(v).get(key)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// The node at the JSON pointer `(pointer, len)`, see `Value::pointer`.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_value_pointer(
+    v: *const serde_arc_value_t,
+    pointer: *const u8,
+    len: usize,
+) -> *mut serde_arc_value_t {
+    match str::from_utf8(bytes(pointer, len)) {
+        Ok(pointer) => handle_opt((*v).0.pointer(pointer)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn serde_arc_dedup_new() -> *mut serde_arc_dedup_t {
+    Box::into_raw(Box::new(serde_arc_dedup_t(Dedup::new())))
+}
+
+/// Releases a dedup table. Values deduplicated with it stay valid. `d` may be `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_dedup_free(d: *mut serde_arc_dedup_t) {
+    if !d.is_null() {
+        drop(Box::from_raw(d));
+    }
+}
+
+/// A copy of `v` sharing everything it has in common with values deduplicated before.
+#[no_mangle]
+pub unsafe extern "C" fn serde_arc_dedup_value(
+    d: *mut serde_arc_dedup_t,
+    v: *const serde_arc_value_t,
+) -> *mut serde_arc_value_t {
+    handle((*d).0.dedup((*v).0.clone()))
+}
@@ -0,0 +1,150 @@
+//! Bloom filter in front of the `Dedup` tables, see `Dedup::with_miss_filter`.
+//!
+//! The filter is keyed by a fingerprint that looks at a bounded part of each entry, so a
+//! definite miss skips the lookup, which would hash the whole string or vector, and goes
+//! straight to the insert.
+
+use std::sync::Arc;
+
+use {Value, KV};
+
+const MIN_BITS: usize = 1 << 16;
+// enough bits per entry for a few percent false positives with three probes
+const BITS_PER_ENTRY: usize = 16;
+
+/// A cheap summary of a table entry. Equal entries must have equal fingerprints for the
+/// filter to help, but a mismatch only costs an extra lookup.
+pub(crate) trait Fingerprint {
+    fn fingerprint(&self) -> u64;
+}
+
+fn mix(mut h: u64, x: u64) -> u64 {
+    h ^= x;
+    h = h.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    h ^ (h >> 29)
+}
+
+// length and up to 8 bytes from each end
+fn bytes_fingerprint(b: &[u8]) -> u64 {
+    let mut head = [0u8; 8];
+    let mut tail = [0u8; 8];
+    let n = b.len().min(8);
+    head[..n].copy_from_slice(&b[..n]);
+    tail[..n].copy_from_slice(&b[b.len() - n..]);
+    let h = mix(b.len() as u64, u64::from_le_bytes(head));
+    mix(h, u64::from_le_bytes(tail))
+}
+
+fn address<T: ?Sized>(v: &Arc<T>) -> u64 {
+    Arc::as_ptr(v) as *const u8 as usize as u64
+}
+
+/// Summary of a single element without looking into containers. The children of anything
+/// looked up in a table have been interned already, so their addresses identify them.
+fn shallow(v: &Value) -> u64 {
+    let x = match *v {
+        Value::Bool(v) => v as u64,
+        // equal numbers of different widths must agree
+        Value::Number(n) => match n.integral() {
+            Some(v) => v as u64,
+            None => n.as_f64().to_bits(),
+        },
+        Value::Char(v) => v as u64,
+        Value::String(ref v) | Value::UnitStruct(ref v) => address(v),
+        Value::Bytes(ref v) => address(v),
+        Value::Seq(ref v) | Value::Tuple(ref v) => address(v),
+        Value::Map(ref v) => address(v),
+        Value::Set(ref v) => address(v),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => shallow(v),
+        Value::Ext { tag, ref value } => mix(tag, shallow(value)),
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { ref data, .. } => address(data),
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(ref v) => mix(address(v.shared()), v.offset() as u64),
+        _ => 0,
+    };
+    mix(v.discriminant() as u64, x)
+}
+
+fn elements_fingerprint(v: &[Value]) -> u64 {
+    let h = mix(v.len() as u64, v.first().map_or(0, shallow));
+    mix(h, v.last().map_or(0, shallow))
+}
+
+impl Fingerprint for str {
+    fn fingerprint(&self) -> u64 {
+        bytes_fingerprint(self.as_bytes())
+    }
+}
+
+impl Fingerprint for Vec<u8> {
+    fn fingerprint(&self) -> u64 {
+        bytes_fingerprint(self)
+    }
+}
+
+impl Fingerprint for Vec<Value> {
+    fn fingerprint(&self) -> u64 {
+        elements_fingerprint(self)
+    }
+}
+
+impl Fingerprint for KV {
+    fn fingerprint(&self) -> u64 {
+        // the key vector is interned before the map
+        mix(address(&self.0), elements_fingerprint(&self.1))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MissFilter {
+    bits: Vec<u64>,
+}
+
+impl Default for MissFilter {
+    fn default() -> MissFilter {
+        MissFilter {
+            bits: vec![0; MIN_BITS / 64],
+        }
+    }
+}
+
+impl MissFilter {
+    fn probes(&self, fingerprint: u64) -> [usize; 3] {
+        let n = self.bits.len() * 64;
+        let h1 = mix(fingerprint, 0x5851_f42d_4c95_7f2d);
+        let h2 = mix(h1, fingerprint) | 1;
+        [
+            (h1 % n as u64) as usize,
+            (h1.wrapping_add(h2) % n as u64) as usize,
+            (h1.wrapping_add(h2.wrapping_mul(2)) % n as u64) as usize,
+        ]
+    }
+
+    pub(crate) fn might_contain(&self, fingerprint: u64) -> bool {
+        self.probes(fingerprint)
+            .iter()
+            .all(|&i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    pub(crate) fn insert(&mut self, fingerprint: u64) {
+        for i in self.probes(fingerprint).iter() {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    /// Grows the filter once the table outgrows it, so the false positive rate stays low.
+    pub(crate) fn reserve<'a, T, I>(&mut self, entries: usize, table: I)
+    where
+        T: Fingerprint + ?Sized + 'a,
+        I: Iterator<Item = &'a Arc<T>>,
+    {
+        if entries * BITS_PER_ENTRY <= self.bits.len() * 64 {
+            return;
+        }
+        self.bits = vec![0; self.bits.len() * 4];
+        for entry in table {
+            self.insert(entry.fingerprint());
+        }
+    }
+}
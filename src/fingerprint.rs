@@ -0,0 +1,274 @@
+//! Stable fingerprints of values, see `Value::fingerprint` and `Value::fingerprint_keyed`.
+//!
+//! The `Hash` implementation of `Value` feeds `std::hash`, whose hashers are randomly
+//! seeded and whose output may change between Rust versions, so its hashes cannot leave the
+//! process. A fingerprint is the 64 bit FNV-1a hash of an encoding of the value fixed here,
+//! the same on every platform, in every run and with every set of features.
+//!
+//! # Encoding
+//!
+//! Every node starts with a tag byte, followed by its content:
+//!
+//! | tag | variant | content |
+//! |----:|---------|---------|
+//! | 0 | `Unit` | |
+//! | 1 | `Bool` | 1 byte, 0 or 1 |
+//! | 2 | `Number`, an integer or integral float | the value as an `i128`, little endian |
+//! | 11 | `Number`, any other float | the bits of the value as an `f64`, little endian |
+//! | 12 | `Char` | the code point as 4 bytes, little endian |
+//! | 13 | `Option(None)` | |
+//! | 14 | `Option(Some)` | the value |
+//! | 15 | `Newtype` | the value |
+//! | 16 | `UnitStruct` | the name as a string |
+//! | 17 | `String` | the length in bytes, then the UTF-8 bytes |
+//! | 18 | `Bytes`, `MappedBytes` | the length, then the bytes |
+//! | 19 | `Seq` | the length, then the elements |
+//! | 20 | `Tuple` | the length, then the elements |
+//! | 21 | `Map` | the number of entries, then each key and its value, in key order |
+//! | 22 | `Set` | the length, then the elements in order |
+//! | 23 | `Ext` | the tag as 8 bytes, little endian, then the value |
+//! | 24 | `CompressedBytes` | 0 for lz4 or 1 for zstd, then the compressed data as bytes |
+//! | 25 | `F16` | the bits of the value as an `f32`, little endian |
+//!
+//! Lengths are 8 bytes, little endian. Numbers are written by value, without their width,
+//! as they compare by value, and tags 3 to 10 are unused. Floats are compared by value, so
+//! every NaN is written as the quiet NaN with no payload and `-0.0` as `0.0`. Mapped bytes
+//! are written as the bytes they stand for, so mapping a blob from a file keeps its
+//! fingerprint.
+//!
+//! Equal values have equal fingerprints. As with any 64 bit hash, different values can
+//! collide, and FNV-1a is no defense against collisions crafted on purpose, nor does it
+//! hide the values, see `Value::fingerprint_keyed` for both.
+
+use std::hash::Hasher;
+
+#[cfg(feature = "compression")]
+use compress::Codec;
+use Value;
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0100_0000_01b3;
+
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// SipHash-2-4, as in the paper, which `std::hash` only offers deprecated or with its own
+/// choice of keys.
+#[derive(Clone)]
+pub(crate) struct Sip {
+    v: [u64; 4],
+    tail: u64,
+    length: usize,
+}
+
+impl Sip {
+    pub(crate) fn new(key: &[u8; 16]) -> Sip {
+        let mut k = [0; 8];
+        k.copy_from_slice(&key[..8]);
+        let k0 = u64::from_le_bytes(k);
+        k.copy_from_slice(&key[8..]);
+        let k1 = u64::from_le_bytes(k);
+        Sip {
+            v: [
+                k0 ^ 0x736f_6d65_7073_6575,
+                k1 ^ 0x646f_7261_6e64_6f6d,
+                k0 ^ 0x6c79_6765_6e65_7261,
+                k1 ^ 0x7465_6462_7974_6573,
+            ],
+            tail: 0,
+            length: 0,
+        }
+    }
+
+    fn rounds(&mut self, n: usize) {
+        let [mut v0, mut v1, mut v2, mut v3] = self.v;
+        for _ in 0..n {
+            v0 = v0.wrapping_add(v1);
+            v1 = v1.rotate_left(13) ^ v0;
+            v0 = v0.rotate_left(32);
+            v2 = v2.wrapping_add(v3);
+            v3 = v3.rotate_left(16) ^ v2;
+            v0 = v0.wrapping_add(v3);
+            v3 = v3.rotate_left(21) ^ v0;
+            v2 = v2.wrapping_add(v1);
+            v1 = v1.rotate_left(17) ^ v2;
+            v2 = v2.rotate_left(32);
+        }
+        self.v = [v0, v1, v2, v3];
+    }
+
+    fn compress(&mut self, m: u64) {
+        self.v[3] ^= m;
+        self.rounds(2);
+        self.v[0] ^= m;
+    }
+}
+
+impl Hasher for Sip {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.tail |= u64::from(b) << (8 * (self.length % 8));
+            self.length += 1;
+            if self.length.is_multiple_of(8) {
+                let m = self.tail;
+                self.tail = 0;
+                self.compress(m);
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut sip = self.clone();
+        sip.compress((self.length as u64) << 56 | self.tail);
+        sip.v[2] ^= 0xff;
+        sip.rounds(4);
+        sip.v[0] ^ sip.v[1] ^ sip.v[2] ^ sip.v[3]
+    }
+}
+
+fn len<H: Hasher>(h: &mut H, len: usize) {
+    h.write(&(len as u64).to_le_bytes());
+}
+
+fn bytes<H: Hasher>(h: &mut H, tag: u8, bytes: &[u8]) {
+    h.write(&[tag]);
+    len(h, bytes.len());
+    h.write(bytes);
+}
+
+#[cfg(feature = "half")]
+fn f32<H: Hasher>(h: &mut H, tag: u8, v: f32) {
+    let bits = if v.is_nan() {
+        f32::NAN.to_bits()
+    } else if v == 0.0 {
+        0
+    } else {
+        v.to_bits()
+    };
+    h.write(&[tag]);
+    h.write(&bits.to_le_bytes());
+}
+
+fn f64<H: Hasher>(h: &mut H, tag: u8, v: f64) {
+    let bits = if v.is_nan() {
+        f64::NAN.to_bits()
+    } else if v == 0.0 {
+        0
+    } else {
+        v.to_bits()
+    };
+    h.write(&[tag]);
+    h.write(&bits.to_le_bytes());
+}
+
+fn elements<'a, H, I>(h: &mut H, tag: u8, n: usize, elements: I)
+where
+    H: Hasher,
+    I: IntoIterator<Item = &'a Value>,
+{
+    h.write(&[tag]);
+    len(h, n);
+    for x in elements {
+        value(h, x);
+    }
+}
+
+/// Writes the encoding of `v` to `h`.
+fn value<H: Hasher>(h: &mut H, v: &Value) {
+    match *v {
+        Value::Unit => h.write(&[0]),
+        Value::Bool(v) => h.write(&[1, v as u8]),
+        Value::Number(n) => match n.integral() {
+            Some(v) => {
+                h.write(&[2]);
+                h.write(&v.to_le_bytes())
+            }
+            None => f64(h, 11, n.as_f64()),
+        },
+        #[cfg(feature = "half")]
+        Value::F16(v) => f32(h, 25, v.to_f32()),
+        Value::Char(v) => {
+            h.write(&[12]);
+            h.write(&u32::from(v).to_le_bytes())
+        }
+        Value::Option(None) => h.write(&[13]),
+        Value::Option(Some(ref v)) => {
+            h.write(&[14]);
+            value(h, v)
+        }
+        Value::Newtype(ref v) => {
+            h.write(&[15]);
+            value(h, v)
+        }
+        Value::UnitStruct(ref v) => bytes(h, 16, v.as_bytes()),
+        Value::String(ref v) => bytes(h, 17, v.as_bytes()),
+        Value::Bytes(ref v) => bytes(h, 18, v),
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(ref v) => bytes(h, 18, v),
+        Value::Seq(ref v) => elements(h, 19, v.len(), v.iter()),
+        Value::Tuple(ref v) => elements(h, 20, v.len(), v.iter()),
+        Value::Map(ref kv) => {
+            h.write(&[21]);
+            len(h, kv.keys().len());
+            for (k, v) in kv.keys().iter().zip(kv.values()) {
+                value(h, k);
+                value(h, v);
+            }
+        }
+        Value::Set(ref v) => elements(h, 22, v.len(), v.iter()),
+        Value::Ext { tag, value: ref v } => {
+            h.write(&[23]);
+            h.write(&tag.to_le_bytes());
+            value(h, v)
+        }
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { codec, ref data } => {
+            let codec = match codec {
+                Codec::Lz4 => 0,
+                Codec::Zstd => 1,
+            };
+            h.write(&[24, codec]);
+            len(h, data.len());
+            h.write(data)
+        }
+    }
+}
+
+impl Value {
+    /// A 64 bit hash of this value that is stable across processes, platforms and versions
+    /// of this crate, for persisting and for cache keys, see the `fingerprint` module.
+    ///
+    /// Shared parts are hashed every time they occur, so this takes time linear in the size
+    /// of the value as written out, not in its size in memory.
+    pub fn fingerprint(&self) -> u64 {
+        let mut fnv = Fnv(OFFSET_BASIS);
+        value(&mut fnv, self);
+        fnv.finish()
+    }
+
+    /// A fingerprint under a secret key, the SipHash-2-4 of the encoding of the `fingerprint`
+    /// module instead of its FNV-1a.
+    ///
+    /// Without the key, the fingerprint of a guessed value cannot be computed, so keyed
+    /// fingerprints can be reported, such as to count repeated values in telemetry, without
+    /// revealing values that are easy to guess, like email addresses or small numbers.
+    /// Fingerprints under different keys are unrelated, so giving each tenant its own key
+    /// keeps their fingerprints from being joined. The key is 16 random bytes, and like the
+    /// fingerprints, the result is stable across processes and platforms.
+    pub fn fingerprint_keyed(&self, key: &[u8; 16]) -> u64 {
+        let mut sip = Sip::new(key);
+        value(&mut sip, self);
+        sip.finish()
+    }
+}
@@ -0,0 +1,118 @@
+//! The total order of floats in values: NaN is equal to itself and greater than anything
+//! else, and the zeros are equal.
+//!
+//! This is `ordered_float::OrderedFloat` with the `ordered-float` feature, and an
+//! equivalent of it otherwise, which also hashes the same, so content hashes do not
+//! depend on the feature.
+
+#[cfg(feature = "ordered-float")]
+pub(crate) use ordered_float::OrderedFloat;
+
+#[cfg(not(feature = "ordered-float"))]
+pub(crate) use self::fallback::OrderedFloat;
+
+#[cfg(not(feature = "ordered-float"))]
+mod fallback {
+    use std::cmp::Ordering;
+    use std::hash::{Hash, Hasher};
+
+    const CANONICAL_NAN_BITS: u64 = 0x7ff8_0000_0000_0000;
+    const MAN_MASK: u64 = 0x000f_ffff_ffff_ffff;
+    const EXP_MASK: u64 = 0x7ff0_0000_0000_0000;
+    const SIGN_MASK: u64 = 0x8000_0000_0000_0000;
+
+    pub(crate) trait Float: Copy + PartialOrd {
+        fn is_nan(self) -> bool;
+        /// Mantissa, exponent and sign, as `num_traits::Float::integer_decode`.
+        fn integer_decode(self) -> (u64, i16, i8);
+    }
+
+    impl Float for f32 {
+        fn is_nan(self) -> bool {
+            f32::is_nan(self)
+        }
+
+        fn integer_decode(self) -> (u64, i16, i8) {
+            let bits = self.to_bits();
+            let sign = if bits >> 31 == 0 { 1 } else { -1 };
+            let exponent = ((bits >> 23) & 0xff) as i16;
+            let mantissa = if exponent == 0 {
+                (bits & 0x7f_ffff) << 1
+            } else {
+                (bits & 0x7f_ffff) | 0x80_0000
+            };
+            (u64::from(mantissa), exponent - (127 + 23), sign)
+        }
+    }
+
+    impl Float for f64 {
+        fn is_nan(self) -> bool {
+            f64::is_nan(self)
+        }
+
+        fn integer_decode(self) -> (u64, i16, i8) {
+            let bits = self.to_bits();
+            let sign = if bits >> 63 == 0 { 1 } else { -1 };
+            let exponent = ((bits >> 52) & 0x7ff) as i16;
+            let mantissa = if exponent == 0 {
+                (bits & 0xf_ffff_ffff_ffff) << 1
+            } else {
+                (bits & 0xf_ffff_ffff_ffff) | 0x10_0000_0000_0000
+            };
+            (mantissa, exponent - (1023 + 52), sign)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct OrderedFloat<T>(pub T);
+
+    impl<T: Float> PartialEq for OrderedFloat<T> {
+        fn eq(&self, other: &OrderedFloat<T>) -> bool {
+            if self.0.is_nan() {
+                other.0.is_nan()
+            } else {
+                self.0 == other.0
+            }
+        }
+    }
+
+    impl<T: Float> Eq for OrderedFloat<T> {}
+
+    impl<T: Float> PartialOrd for OrderedFloat<T> {
+        fn partial_cmp(&self, other: &OrderedFloat<T>) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl<T: Float> Ord for OrderedFloat<T> {
+        fn cmp(&self, other: &OrderedFloat<T>) -> Ordering {
+            match self.0.partial_cmp(&other.0) {
+                Some(ordering) => ordering,
+                None if self.0.is_nan() && other.0.is_nan() => Ordering::Equal,
+                None if self.0.is_nan() => Ordering::Greater,
+                None => Ordering::Less,
+            }
+        }
+    }
+
+    impl<T: Float> Hash for OrderedFloat<T> {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            let bits = if self.0.is_nan() {
+                CANONICAL_NAN_BITS
+            } else {
+                match self.0.integer_decode() {
+                    (0, _, _) => 0,
+                    (mantissa, exponent, sign) => {
+                        let exponent = u64::from(exponent as u16);
+                        // sic, positive numbers set the sign bit, as ordered_float does
+                        let sign = if sign > 0 { 1 } else { 0 };
+                        (mantissa & MAN_MASK)
+                            | ((exponent << 52) & EXP_MASK)
+                            | ((sign << 63) & SIGN_MASK)
+                    }
+                }
+            };
+            bits.hash(state)
+        }
+    }
+}
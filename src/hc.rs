@@ -0,0 +1,211 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use {Dedup, Deduplicator, Value};
+
+struct HcNode {
+    hash: u64,
+    value: Value,
+}
+
+impl Hash for HcNode {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.value.hash(hasher)
+    }
+}
+
+impl PartialEq for HcNode {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.value == rhs.value
+    }
+}
+
+impl Eq for HcNode {}
+
+// lets the table be probed with a plain `Value`
+struct HcEntry(Arc<HcNode>);
+
+impl Borrow<Value> for HcEntry {
+    fn borrow(&self) -> &Value {
+        &self.0.value
+    }
+}
+
+impl Hash for HcEntry {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.0.hash(hasher)
+    }
+}
+
+impl PartialEq for HcEntry {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.0 == rhs.0
+    }
+}
+
+impl Eq for HcEntry {}
+
+struct HcTable {
+    dedup: Dedup,
+    nodes: HashSet<HcEntry>,
+}
+
+/// A shared interning table producing `HcValue`s.
+///
+/// Cloning the factory is cheap and yields a handle to the same table. Interned nodes live as
+/// long as the table.
+#[derive(Clone)]
+pub struct HcFactory(Arc<Mutex<HcTable>>);
+
+impl HcFactory {
+    pub fn new() -> HcFactory {
+        HcFactory(Arc::new(Mutex::new(HcTable {
+            dedup: Dedup::new(),
+            nodes: HashSet::new(),
+        })))
+    }
+
+    /// Interns an arbitrary value. Its shared subtrees are interned through the factory's
+    /// `Dedup` as well.
+    pub fn intern(&self, value: Value) -> HcValue {
+        let mut table = self.0.lock().unwrap();
+        if let Some(entry) = table.nodes.get(&value) {
+            return HcValue(entry.0.clone());
+        }
+        let value = table.dedup.dedup(value);
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let node = Arc::new(HcNode {
+            hash: hasher.finish(),
+            value,
+        });
+        table.nodes.insert(HcEntry(node.clone()));
+        HcValue(node)
+    }
+
+    pub fn unit(&self) -> HcValue {
+        self.intern(Value::Unit)
+    }
+
+    pub fn string<S: Into<String>>(&self, value: S) -> HcValue {
+        self.intern(Value::string(value.into()))
+    }
+
+    pub fn bytes<B: Into<Vec<u8>>>(&self, value: B) -> HcValue {
+        self.intern(Value::bytes(value.into()))
+    }
+
+    pub fn seq<I: IntoIterator<Item = HcValue>>(&self, elements: I) -> HcValue {
+        self.intern(Value::seq(
+            elements.into_iter().map(|x| x.value().clone()).collect(),
+        ))
+    }
+
+    pub fn map<I: IntoIterator<Item = (HcValue, HcValue)>>(&self, entries: I) -> HcValue {
+        let map: BTreeMap<Value, Value> = entries
+            .into_iter()
+            .map(|(k, v)| (k.value().clone(), v.value().clone()))
+            .collect();
+        self.intern(Value::map(map))
+    }
+
+    /// Number of distinct values interned so far.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for HcFactory {
+    fn default() -> HcFactory {
+        HcFactory::new()
+    }
+}
+
+impl fmt::Debug for HcFactory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HcFactory").field("len", &self.len()).finish()
+    }
+}
+
+/// A hash-consed `Value` with a precomputed hash.
+///
+/// Equal values interned through the same `HcFactory` share one node, so equality is a
+/// pointer comparison and hashing writes a single `u64`. Values from different factories
+/// are still compared correctly, just not in constant time.
+#[derive(Clone)]
+pub struct HcValue(Arc<HcNode>);
+
+impl HcValue {
+    pub fn value(&self) -> &Value {
+        &self.0.value
+    }
+
+    /// The precomputed hash of the value.
+    pub fn hash_code(&self) -> u64 {
+        self.0.hash
+    }
+
+    pub fn ptr_eq(&self, rhs: &HcValue) -> bool {
+        Arc::ptr_eq(&self.0, &rhs.0)
+    }
+}
+
+impl Deref for HcValue {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        self.value()
+    }
+}
+
+impl PartialEq for HcValue {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.ptr_eq(rhs) || (self.0.hash == rhs.0.hash && self.0.value == rhs.0.value)
+    }
+}
+
+impl Eq for HcValue {}
+
+impl Hash for HcValue {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        hasher.write_u64(self.0.hash)
+    }
+}
+
+impl Ord for HcValue {
+    fn cmp(&self, rhs: &Self) -> Ordering {
+        if self.ptr_eq(rhs) {
+            Ordering::Equal
+        } else {
+            self.0.value.cmp(&rhs.0.value)
+        }
+    }
+}
+
+impl PartialOrd for HcValue {
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl fmt::Debug for HcValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.value.fmt(f)
+    }
+}
+
+impl fmt::Display for HcValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.value.fmt(f)
+    }
+}
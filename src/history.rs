@@ -0,0 +1,91 @@
+//! Revisions of a document that share all unchanged parts.
+
+use std::collections::VecDeque;
+
+use diff::{diff, Difference};
+use Value;
+
+/// The last few revisions of a value.
+///
+/// Each new revision is stored with `Value::share_with` against the previous one, so memory
+/// grows with the size of the changes rather than the size of the document. Revisions are
+/// numbered from 0 in the order they were pushed; once more than `capacity` are kept, the
+/// oldest are dropped.
+#[derive(Clone, Debug)]
+pub struct VersionedValue {
+    revisions: VecDeque<Value>,
+    // version of `revisions[0]`
+    first: u64,
+    capacity: usize,
+}
+
+impl VersionedValue {
+    /// Creates a history with `initial` as version 0, keeping at most `capacity` revisions.
+    pub fn new(initial: Value, capacity: usize) -> VersionedValue {
+        assert!(capacity > 0, "capacity must be at least 1");
+        let mut revisions = VecDeque::with_capacity(capacity);
+        revisions.push_back(initial);
+        VersionedValue {
+            revisions,
+            first: 0,
+            capacity,
+        }
+    }
+
+    /// Stores a new revision and returns its version.
+    pub fn push(&mut self, value: Value) -> u64 {
+        let value = value.share_with(self.current());
+        if self.revisions.len() == self.capacity {
+            self.revisions.pop_front();
+            self.first += 1;
+        }
+        self.revisions.push_back(value);
+        self.version()
+    }
+
+    /// The latest revision.
+    pub fn current(&self) -> &Value {
+        self.revisions.back().unwrap()
+    }
+
+    /// The version of the latest revision.
+    pub fn version(&self) -> u64 {
+        self.first + self.revisions.len() as u64 - 1
+    }
+
+    /// The oldest version still kept.
+    pub fn oldest_version(&self) -> u64 {
+        self.first
+    }
+
+    /// The revision with the given version, if it is still kept.
+    pub fn get(&self, version: u64) -> Option<&Value> {
+        let index = version.checked_sub(self.first)?;
+        self.revisions.get(index as usize)
+    }
+
+    /// Changes from version `from` to version `to`, or `None` if either is not kept.
+    pub fn diff(&self, from: u64, to: u64) -> Option<Vec<Difference>> {
+        Some(diff(self.get(from)?, self.get(to)?))
+    }
+
+    /// Makes `version` the latest revision again, discarding all newer ones.
+    ///
+    /// Returns `false`, leaving the history unchanged, if the version is not kept.
+    pub fn rollback(&mut self, version: u64) -> bool {
+        if self.get(version).is_none() {
+            return false;
+        }
+        self.revisions.truncate((version - self.first) as usize + 1);
+        true
+    }
+
+    /// Iterates over the kept revisions with their versions, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Value)> {
+        let first = self.first;
+        self.revisions
+            .iter()
+            .enumerate()
+            .map(move |(i, v)| (first + i as u64, v))
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+use std::sync::Arc;
+
+use {Dedup, Value};
+
+/// Callbacks for what happens in the tables of a `Dedup`, see `Dedup::with_hooks`.
+///
+/// The values passed are strings, byte blobs, sequences (including the keys of maps) and
+/// maps, sharing their contents with the table entry. Both methods do nothing by default.
+pub trait DedupHooks: Send + Sync {
+    /// A new entry of `size` bytes, as measured by `Dedup::savings_report`, was added.
+    fn on_insert(&self, _value: &Value, _size: usize) {}
+
+    /// An entry was found, and is now referenced `strong_count` times, including once by
+    /// the table and once by the value being deduplicated.
+    fn on_hit(&self, _value: &Value, _strong_count: usize) {}
+}
+
+/// What a table lookup did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Hit,
+    Inserted,
+    // not found, and not added because it was not sampled
+    Skipped,
+}
+
+#[derive(Clone)]
+pub(crate) struct Hooks(Arc<dyn DedupHooks>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Hooks")
+    }
+}
+
+impl Hooks {
+    pub(crate) fn notify<T, F>(&self, outcome: Outcome, value: &Arc<T>, size: usize, wrap: F)
+    where
+        T: ?Sized,
+        F: FnOnce(Arc<T>) -> Value,
+    {
+        match outcome {
+            Outcome::Hit => {
+                let strong_count = Arc::strong_count(value);
+                self.0.on_hit(&wrap(value.clone()), strong_count)
+            }
+            Outcome::Inserted => self.0.on_insert(&wrap(value.clone()), size),
+            Outcome::Skipped => {}
+        }
+    }
+}
+
+impl Dedup {
+    /// Calls `hooks` for every entry added to or found in the tables, to log, sample or
+    /// alert on what gets interned. Replaces any hooks set before.
+    ///
+    /// The hooks are called synchronously while deduplicating, so they should be cheap.
+    pub fn with_hooks(mut self, hooks: Arc<dyn DedupHooks>) -> Dedup {
+        self.hooks = Some(Hooks(hooks));
+        self
+    }
+}
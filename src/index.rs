@@ -0,0 +1,83 @@
+//! Memoized JSON pointer lookups for documents that are queried repeatedly.
+
+use std::collections::HashMap;
+use std::mem;
+
+use path;
+use Value;
+
+/// A value together with the nodes found at the JSON pointers queried so far.
+///
+/// The first `pointer` call for a path walks the value like `Value::pointer`, later ones
+/// are a hash lookup. The remembered nodes are clones, so they share their contents with
+/// the value. Nodes changed through `pointer_mut` or `value_mut` are forgotten on the next
+/// query, together with everything above and below them, while unrelated paths stay cached.
+#[derive(Clone, Debug)]
+pub struct ValueIndex {
+    root: Value,
+    // node at each queried pointer, with the pointer split into segments
+    pub(crate) cache: HashMap<String, (Vec<String>, Option<Value>)>,
+    // paths handed out mutably since the last query
+    dirty: Vec<Vec<String>>,
+}
+
+/// Whether one path is a prefix of the other.
+fn overlaps(a: &[String], b: &[String]) -> bool {
+    let n = a.len().min(b.len());
+    a[..n] == b[..n]
+}
+
+impl ValueIndex {
+    pub fn new(root: Value) -> ValueIndex {
+        ValueIndex {
+            root,
+            cache: HashMap::new(),
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.root
+    }
+
+    pub fn into_inner(self) -> Value {
+        self.root
+    }
+
+    /// The node at the JSON pointer `pointer`, see `Value::pointer`.
+    pub fn pointer(&mut self, pointer: &str) -> Option<&Value> {
+        self.invalidate();
+        if !self.cache.contains_key(pointer) {
+            let segments = path::split(pointer)?;
+            let node = path::lookup(&self.root, &segments).cloned();
+            self.cache.insert(pointer.to_owned(), (segments, node));
+        }
+        self.cache[pointer].1.as_ref()
+    }
+
+    /// The node at `pointer` for changing it, see `Value::pointer_mut`.
+    ///
+    /// As the index holds on to the nodes it has seen, containers on the way are usually
+    /// copied.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        let segments = path::split(pointer)?;
+        path::lookup(&self.root, &segments)?;
+        self.dirty.push(segments.clone());
+        path::lookup_mut(&mut self.root, &segments)
+    }
+
+    /// The whole value for changing it, which forgets all paths.
+    pub fn value_mut(&mut self) -> &mut Value {
+        self.dirty.push(Vec::new());
+        &mut self.root
+    }
+
+    fn invalidate(&mut self) {
+        if self.dirty.is_empty() {
+            return;
+        }
+        let dirty = mem::take(&mut self.dirty);
+        self.cache
+            .retain(|_, &mut (ref segments, _)| !dirty.iter().any(|d| overlaps(d, segments)));
+    }
+}
@@ -0,0 +1,56 @@
+//! Helpers for the `tracing` instrumentation of conversions and deduplication.
+
+use tracing::Level;
+
+use ser::SerializerError;
+#[cfg(feature = "dedup")]
+use Dedup;
+use Value;
+
+fn node_count(value: &Value) -> usize {
+    1 + match *value {
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => node_count(v),
+        Value::Ext { ref value, .. } => node_count(value),
+        Value::Seq(ref v) | Value::Tuple(ref v) => v.iter().map(node_count).sum(),
+        Value::Set(ref v) => v.iter().map(node_count).sum(),
+        Value::Map(ref kv) => kv.0.iter().chain(kv.1.iter()).map(node_count).sum(),
+        _ => 0,
+    }
+}
+
+/// Number of nodes in `value`, counting shared nodes every time they appear, or `None` if
+/// nobody listens, as it takes a full traversal.
+pub(crate) fn nodes(value: &Value) -> Option<usize> {
+    if tracing::enabled!(Level::DEBUG) {
+        Some(node_count(value))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn serialized(result: &Result<Value, SerializerError>) {
+    match *result {
+        Ok(ref value) => tracing::debug!(nodes = nodes(value), "serialized"),
+        Err(ref e) => tracing::debug!(error = %e, "serialization failed"),
+    }
+}
+
+/// Total lookups, hits and entries over all tables.
+#[cfg(feature = "dedup")]
+pub(crate) fn dedup_totals(dedup: &Dedup) -> (u64, u64, usize) {
+    let seen = &dedup.seen;
+    let lookups = seen.blobs.count + seen.strings.count + seen.vectors.count + seen.objects.count;
+    let hits = seen.blobs.hits + seen.strings.hits + seen.vectors.hits + seen.objects.hits;
+    let entries =
+        dedup.blobs.len() + dedup.strings.len() + dedup.vectors.len() + dedup.objects.len();
+    (lookups, hits, entries)
+}
+
+#[cfg(feature = "dedup")]
+pub(crate) fn deduplicated(before: (u64, u64, usize), after: (u64, u64, usize)) {
+    let lookups = after.0 - before.0;
+    let hits = after.1 - before.1;
+    // net, as a memory budget may evict entries
+    let added = after.2.saturating_sub(before.2) as u64;
+    tracing::debug!(lookups, hits, added, "deduplicated");
+}
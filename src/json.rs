@@ -0,0 +1,215 @@
+//! Conversion to `serde_json::Value`, behind the `serde_json` feature.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use serde_json::{self, Map};
+
+use path;
+use {Number, Value};
+
+/// Integers beyond this magnitude are not exact as JSON numbers in most implementations,
+/// as they are doubles there.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// What made a value not convertible to JSON.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonErrorKind {
+    Bytes,
+    Char,
+    /// a map key that is not a string
+    Key,
+    /// an integer beyond 2^53 - 1 in magnitude, or a float that is not finite
+    Number,
+    /// an extension value
+    Ext,
+}
+
+/// A node that has no exact JSON representation, with its JSON pointer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonError {
+    pub pointer: String,
+    pub kind: JsonErrorKind,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            JsonErrorKind::Bytes => "bytes",
+            JsonErrorKind::Char => "a char",
+            JsonErrorKind::Key => "a non-string map key",
+            JsonErrorKind::Number => "a number out of range",
+            JsonErrorKind::Ext => "an extension value",
+        };
+        write!(
+            f,
+            "{} at '{}' has no JSON representation",
+            what, self.pointer
+        )
+    }
+}
+
+impl Error for JsonError {}
+
+fn int(v: i64) -> Option<serde_json::Value> {
+    if v.unsigned_abs() > MAX_SAFE_INTEGER {
+        return None;
+    }
+    Some(serde_json::Value::Number(v.into()))
+}
+
+fn uint(v: u64) -> Option<serde_json::Value> {
+    if v > MAX_SAFE_INTEGER {
+        return None;
+    }
+    Some(serde_json::Value::Number(v.into()))
+}
+
+fn float(v: f64) -> Option<serde_json::Value> {
+    serde_json::Number::from_f64(v).map(serde_json::Value::Number)
+}
+
+fn strict(value: &Value, pointer: &str) -> Result<serde_json::Value, JsonError> {
+    let error = |kind| JsonError {
+        pointer: pointer.to_owned(),
+        kind,
+    };
+    let number = |n: Option<serde_json::Value>| n.ok_or_else(|| error(JsonErrorKind::Number));
+    Ok(match *value {
+        Value::Unit | Value::Option(None) => serde_json::Value::Null,
+        Value::Bool(v) => serde_json::Value::Bool(v),
+        Value::Number(Number::U8(v)) => number(uint(v.into()))?,
+        Value::Number(Number::U16(v)) => number(uint(v.into()))?,
+        Value::Number(Number::U32(v)) => number(uint(v.into()))?,
+        Value::Number(Number::U64(v)) => number(uint(v))?,
+        Value::Number(Number::I8(v)) => number(int(v.into()))?,
+        Value::Number(Number::I16(v)) => number(int(v.into()))?,
+        Value::Number(Number::I32(v)) => number(int(v.into()))?,
+        Value::Number(Number::I64(v)) => number(int(v))?,
+        Value::Number(Number::F32(v)) => number(float(v.into()))?,
+        Value::Number(Number::F64(v)) => number(float(v))?,
+        #[cfg(feature = "half")]
+        Value::F16(v) => number(float(v.into()))?,
+        Value::Char(..) => return Err(error(JsonErrorKind::Char)),
+        Value::String(ref s) | Value::UnitStruct(ref s) => serde_json::Value::String(s.to_string()),
+        Value::Bytes(..) => return Err(error(JsonErrorKind::Bytes)),
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { .. } => return Err(error(JsonErrorKind::Bytes)),
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(..) => return Err(error(JsonErrorKind::Bytes)),
+        Value::Ext { .. } => return Err(error(JsonErrorKind::Ext)),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => strict(v, pointer)?,
+        Value::Seq(ref v) | Value::Tuple(ref v) => serde_json::Value::Array(
+            v.iter()
+                .enumerate()
+                .map(|(i, x)| strict(x, &path::join(pointer, &i.to_string())))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Set(ref v) => serde_json::Value::Array(
+            v.iter()
+                .enumerate()
+                .map(|(i, x)| strict(x, &path::join(pointer, &i.to_string())))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Map(ref kv) => {
+            let mut map = Map::with_capacity(kv.keys().len());
+            for (k, v) in kv.keys().iter().zip(kv.values().iter()) {
+                let child = path::join(pointer, &path::key_segment(k));
+                let key = match *path::transparent(k) {
+                    Value::String(ref s) => s.to_string(),
+                    _ => {
+                        return Err(JsonError {
+                            pointer: child,
+                            kind: JsonErrorKind::Key,
+                        })
+                    }
+                };
+                let v = strict(v, &child)?;
+                map.insert(key, v);
+            }
+            serde_json::Value::Object(map)
+        }
+    })
+}
+
+/// Converts exactly, failing on what JSON cannot represent, see `Value::to_json_lossy` for
+/// a conversion that always succeeds.
+///
+/// Options, newtypes and unit structs are represented as serde would serialize them, and
+/// sets and tuples become arrays. Integers are only accepted up to 2^53 - 1 in magnitude,
+/// which is what JSON parsers using doubles read exactly.
+impl TryFrom<Value> for serde_json::Value {
+    type Error = JsonError;
+
+    fn try_from(value: Value) -> Result<serde_json::Value, JsonError> {
+        strict(&value, "")
+    }
+}
+
+impl Value {
+    /// Converts to JSON, coercing what JSON cannot represent:
+    ///
+    /// - bytes become arrays of numbers, as serde_json serializes them
+    /// - chars become strings of one char
+    /// - map keys that are not strings become strings, as they appear in paths
+    /// - integers beyond 2^53 - 1 in magnitude are kept, and may lose precision where
+    ///   they are read as doubles
+    /// - floats that are not finite become `null`
+    /// - extension values become their content, dropping the tag
+    ///
+    /// Of two keys becoming the same string, the last one in key order wins.
+    pub fn to_json_lossy(&self) -> serde_json::Value {
+        match *self {
+            Value::Unit | Value::Option(None) => serde_json::Value::Null,
+            Value::Bool(v) => serde_json::Value::Bool(v),
+            Value::Number(Number::F32(v)) => float(v.into()).unwrap_or(serde_json::Value::Null),
+            Value::Number(Number::F64(v)) => float(v).unwrap_or(serde_json::Value::Null),
+            #[cfg(feature = "half")]
+            Value::F16(v) => float(v.into()).unwrap_or(serde_json::Value::Null),
+            Value::Char(v) => serde_json::Value::String(v.to_string()),
+            Value::String(ref s) | Value::UnitStruct(ref s) => {
+                serde_json::Value::String(s.to_string())
+            }
+            Value::Bytes(ref b) => bytes(b),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { .. } => match self.byte_content() {
+                Some(Ok(b)) => bytes(&b),
+                _ => serde_json::Value::Null,
+            },
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref b) => bytes(b),
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => v.to_json_lossy(),
+            Value::Ext { ref value, .. } => value.to_json_lossy(),
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                serde_json::Value::Array(v.iter().map(Value::to_json_lossy).collect())
+            }
+            Value::Set(ref v) => {
+                serde_json::Value::Array(v.iter().map(Value::to_json_lossy).collect())
+            }
+            Value::Map(ref kv) => serde_json::Value::Object(
+                kv.keys()
+                    .iter()
+                    .zip(kv.values().iter())
+                    .map(|(k, v)| (path::key_segment(path::transparent(k)), v.to_json_lossy()))
+                    .collect(),
+            ),
+            ref other => {
+                let n = other.as_number().unwrap();
+                match (n.as_u64(), n.as_i64()) {
+                    (Some(v), _) => serde_json::Value::Number(v.into()),
+                    (None, Some(v)) => serde_json::Value::Number(v.into()),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+fn bytes(b: &[u8]) -> serde_json::Value {
+    serde_json::Value::Array(
+        b.iter()
+            .map(|&x| serde_json::Value::Number(x.into()))
+            .collect(),
+    )
+}
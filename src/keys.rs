@@ -0,0 +1,235 @@
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, SerializeTuple};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use rewrite::{rewrite_strings, Strings};
+use ser::SerializerError;
+use Value;
+
+/// Canonical string form of a map key, or `None` if the key has no scalar representation.
+fn key_string(key: &Value) -> Option<String> {
+    match *key {
+        Value::String(ref v) => Some(v.to_string()),
+        Value::Bool(..)
+        | Value::Number(..)
+        | Value::Char(..) => Some(key.to_string()),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => key_string(v),
+        Value::Ext { ref value, .. } => key_string(value),
+        _ => None,
+    }
+}
+
+fn unsupported_key(key: &Value) -> String {
+    format!("map key {} can not be represented as a string", key)
+}
+
+fn duplicate_key(key: &str) -> String {
+    format!("duplicate map key {} after stringification", key)
+}
+
+impl Value {
+    /// Returns a copy of this value where every map key is a `Value::String`.
+    ///
+    /// Scalar keys are converted to their display form, newtypes and options around them are
+    /// unwrapped. Compound keys, and keys that collide once stringified, are an error.
+    /// Maps that already have only string keys keep sharing their key vector.
+    pub fn stringify_keys(&self) -> Result<Value, SerializerError> {
+        match *self {
+            Value::Option(Some(ref v)) => Ok(Value::Option(Some(Box::new(v.stringify_keys()?)))),
+            Value::Newtype(ref v) => Ok(Value::Newtype(Box::new(v.stringify_keys()?))),
+            Value::Ext { tag, ref value } => Ok(Value::Ext {
+                tag,
+                value: Box::new(value.stringify_keys()?),
+            }),
+            Value::Seq(ref v) => Ok(Value::seq(
+                v.iter()
+                    .map(Value::stringify_keys)
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::Tuple(ref v) => Ok(Value::Tuple(Arc::new(
+                v.iter()
+                    .map(Value::stringify_keys)
+                    .collect::<Result<_, _>>()?,
+            ))),
+            Value::Set(ref v) => Ok(Value::Set(Arc::new(
+                v.iter()
+                    .map(Value::stringify_keys)
+                    .collect::<Result<_, _>>()?,
+            ))),
+            Value::Map(ref kv) => {
+                if kv.0.iter().all(|k| matches!(*k, Value::String(..))) {
+                    let values = kv.1.iter().map(Value::stringify_keys).collect::<Result<_, _>>()?;
+                    return Ok(Value::Map(kv.with_values(values)));
+                }
+                let mut map = BTreeMap::new();
+                for (k, v) in kv.0.iter().zip(kv.1.iter()) {
+                    let key = key_string(k)
+                        .ok_or_else(|| SerializerError::Custom(unsupported_key(k)))?;
+                    let value = v.stringify_keys()?;
+                    if map.insert(Value::string(key.clone()), value).is_some() {
+                        return Err(SerializerError::Custom(duplicate_key(&key)));
+                    }
+                }
+                Ok(Value::map(map))
+            }
+            ref other => Ok(other.clone()),
+        }
+    }
+
+    /// Serializes this value with all map keys converted to strings on the fly.
+    ///
+    /// Uses the same conversion as [`stringify_keys`](#method.stringify_keys), but without
+    /// building an intermediate value. Useful for formats like JSON that require string keys.
+    pub fn with_string_keys(&self) -> StringKeys<'_> {
+        StringKeys(self)
+    }
+
+    /// Serializes this value with the entries of every map in the order of `cmp` on their
+    /// keys, for output formats where key order is visible, like JSON.
+    ///
+    /// Maps keep their keys sorted in memory, as lookups, comparisons and `Dedup` rely on
+    /// it, so the order is only applied on the way out. The sort is stable, so keys that
+    /// `cmp` considers equal keep their usual order. See `keys_first` for a common order.
+    pub fn canonicalize_with<F>(&self, cmp: F) -> KeyOrdered<'_, F>
+    where
+        F: Fn(&Value, &Value) -> Ordering,
+    {
+        KeyOrdered { value: self, cmp }
+    }
+
+    /// Returns a copy of this value where every string map key is in ASCII lowercase, so
+    /// that keys differing only in case, like HTTP header names, land in the same slot.
+    ///
+    /// Other strings are left alone, and so are maps whose keys are lowercase already. If
+    /// two keys of a map become equal, the value of the one that sorted last is kept, which
+    /// is the one with the most lowercase letters in the first place they differ.
+    pub fn fold_keys_ascii_lowercase(&self) -> Value {
+        let mut fold = |s: &Arc<str>| {
+            if s.bytes().any(|b| b.is_ascii_uppercase()) {
+                Some(Arc::from(s.to_ascii_lowercase()))
+            } else {
+                None
+            }
+        };
+        rewrite_strings(self, Strings::Keys, &mut fold).unwrap_or_else(|| self.clone())
+    }
+}
+
+/// Serialization adapter returned by `Value::with_string_keys`.
+#[derive(Clone, Copy, Debug)]
+pub struct StringKeys<'a>(&'a Value);
+
+impl<'a> Serialize for StringKeys<'a> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match *self.0 {
+            Value::Option(Some(ref v)) => s.serialize_some(&StringKeys(v)),
+            Value::Newtype(ref v) => s.serialize_newtype_struct("", &StringKeys(v)),
+            Value::Ext { ref value, .. } => StringKeys(value).serialize(s),
+            Value::Seq(ref v) => {
+                let mut seq = s.serialize_seq(Some(v.len()))?;
+                for element in v.iter() {
+                    seq.serialize_element(&StringKeys(element))?;
+                }
+                seq.end()
+            }
+            Value::Tuple(ref v) => {
+                let mut tuple = s.serialize_tuple(v.len())?;
+                for element in v.iter() {
+                    tuple.serialize_element(&StringKeys(element))?;
+                }
+                tuple.end()
+            }
+            Value::Set(ref v) => {
+                let mut seq = s.serialize_seq(Some(v.len()))?;
+                for element in v.iter() {
+                    seq.serialize_element(&StringKeys(element))?;
+                }
+                seq.end()
+            }
+            Value::Map(ref kv) => {
+                let mut seen = BTreeSet::new();
+                let mut map = s.serialize_map(Some(kv.1.len()))?;
+                for (k, v) in kv.0.iter().zip(kv.1.iter()) {
+                    let key = key_string(k).ok_or_else(|| ser::Error::custom(unsupported_key(k)))?;
+                    if !seen.insert(key.clone()) {
+                        return Err(ser::Error::custom(duplicate_key(&key)));
+                    }
+                    map.serialize_entry(&key, &StringKeys(v))?;
+                }
+                map.end()
+            }
+            ref other => other.serialize(s),
+        }
+    }
+}
+
+/// A key order for `Value::canonicalize_with` that puts the string keys in `first` in the
+/// order given, before all other keys in their usual order, as in `keys_first(&["id"])`.
+pub fn keys_first<'a>(first: &'a [&'a str]) -> impl Fn(&Value, &Value) -> Ordering + 'a {
+    move |a, b| {
+        let rank = |key: &Value| match *key {
+            Value::String(ref s) => first.iter().position(|f| **f == **s),
+            _ => None,
+        };
+        rank(a).unwrap_or(first.len()).cmp(&rank(b).unwrap_or(first.len()))
+    }
+}
+
+/// Serialization adapter returned by `Value::canonicalize_with`.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyOrdered<'a, F> {
+    value: &'a Value,
+    cmp: F,
+}
+
+impl<'a, F: Fn(&Value, &Value) -> Ordering> Serialize for KeyOrdered<'a, F> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        Ordered(self.value, &self.cmp).serialize(s)
+    }
+}
+
+// borrows the order, so nested nodes can share it
+struct Ordered<'a, F>(&'a Value, &'a F);
+
+impl<'a, F: Fn(&Value, &Value) -> Ordering> Serialize for Ordered<'a, F> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let Ordered(value, cmp) = *self;
+        match *value {
+            Value::Option(Some(ref v)) => s.serialize_some(&Ordered(v, cmp)),
+            Value::Newtype(ref v) => s.serialize_newtype_struct("", &Ordered(v, cmp)),
+            Value::Ext { ref value, .. } => Ordered(value, cmp).serialize(s),
+            Value::Seq(ref v) => {
+                let mut seq = s.serialize_seq(Some(v.len()))?;
+                for element in v.iter() {
+                    seq.serialize_element(&Ordered(element, cmp))?;
+                }
+                seq.end()
+            }
+            Value::Tuple(ref v) => {
+                let mut tuple = s.serialize_tuple(v.len())?;
+                for element in v.iter() {
+                    tuple.serialize_element(&Ordered(element, cmp))?;
+                }
+                tuple.end()
+            }
+            Value::Set(ref v) => {
+                let mut seq = s.serialize_seq(Some(v.len()))?;
+                for element in v.iter() {
+                    seq.serialize_element(&Ordered(element, cmp))?;
+                }
+                seq.end()
+            }
+            Value::Map(ref kv) => {
+                let mut entries: Vec<(&Value, &Value)> = kv.0.iter().zip(kv.1.iter()).collect();
+                entries.sort_by(|a, b| cmp(a.0, b.0));
+                let mut map = s.serialize_map(Some(entries.len()))?;
+                for (k, v) in entries {
+                    map.serialize_entry(&Ordered(k, cmp), &Ordered(v, cmp))?;
+                }
+                map.end()
+            }
+            ref other => other.serialize(s),
+        }
+    }
+}
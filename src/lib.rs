@@ -1,228 +1,756 @@
 #[macro_use]
 extern crate serde;
+#[cfg(feature = "actix-extractor")]
+extern crate actix_web;
+#[cfg(feature = "axum-extractor")]
+extern crate axum;
+#[cfg(feature = "ordered-float")]
 extern crate ordered_float;
+#[cfg(feature = "erased-serde")]
+extern crate erased_serde;
+#[cfg(feature = "codec")]
+extern crate bytes;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+#[cfg(feature = "tokio")]
+extern crate futures_sink;
+#[cfg(feature = "fast-hash")]
+extern crate getrandom;
+#[cfg(feature = "half")]
+extern crate half;
+#[cfg(feature = "csv")]
+extern crate csv as csv_crate;
+#[cfg(feature = "compression")]
+extern crate lz4_flex;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+// the code `pyo3` generates refers to `::core`
+#[cfg(feature = "pyo3")]
+extern crate core;
+#[cfg(feature = "prost-reflect")]
+extern crate prost_reflect;
+#[cfg(feature = "pyo3")]
+extern crate pyo3;
+#[cfg(feature = "rusqlite")]
+extern crate rusqlite;
+#[cfg(feature = "tokio-postgres")]
+extern crate tokio_postgres;
+#[cfg(feature = "codec")]
+extern crate tokio_util;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+#[cfg(feature = "quick-xml")]
+extern crate quick_xml;
+#[cfg(any(test, feature = "derive"))]
+extern crate serde_value_derive;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "fast-hash")]
+extern crate twox_hash;
+#[cfg(feature = "compression")]
+extern crate zstd;
 
 #[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
+// the code `serde_value_derive` generates refers to `::serde_value`
+#[cfg(test)]
+extern crate self as serde_value;
 
-use ordered_float::OrderedFloat;
+#[cfg(feature = "half")]
+use float::OrderedFloat;
 use serde::Deserialize;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "dedup")]
+use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
 use std::hash::{Hash, Hasher};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "dedup")]
+use budget::{Admission, Budget};
+#[cfg(feature = "dedup")]
+use checksum::Checksums;
+#[cfg(feature = "dedup")]
+use filter::{Fingerprint, MissFilter};
+#[cfg(feature = "dedup")]
+use hooks::{Hooks, Outcome};
+#[cfg(feature = "dedup")]
+use pool::Pool;
+#[cfg(feature = "dedup")]
+use sampling::Sampler;
 
+#[cfg(feature = "dedup")]
+pub use analysis::{HeavyHitter, HeavyHitters, PathAnalysis, PathStats};
+#[cfg(feature = "dedup")]
+pub use budget::BudgetMode;
+#[cfg(feature = "dedup")]
+pub use checksum::{blob_checksum, IntegrityError};
+#[cfg(feature = "codec")]
+pub use codec::ValueCodec;
+pub use builder::{BuildError, ValueBuilder};
+#[cfg(feature = "compression")]
+pub use compress::Codec;
+pub use content_hash::ContentHashes;
+#[cfg(feature = "csv")]
+pub use csv::{from_csv_reader, to_csv_writer, CsvError};
 pub use de::*;
+pub use dictionary::StringDictionary;
+pub use diff::{assert_value_eq_impl, diff, Difference};
+pub use edit::{CowGuard, Transaction};
+pub use encoding::{DictionaryDecoder, DictionaryEncoder};
+#[cfg(feature = "erased-serde")]
+pub use erased::to_value_dyn;
+#[cfg(any(feature = "axum-extractor", feature = "actix-extractor"))]
+pub use extract::{DedupJson, DedupJsonFuture, DedupJsonRejection};
+#[cfg(feature = "dedup")]
+pub use hc::{HcFactory, HcValue};
+#[cfg(feature = "dedup")]
+pub use hooks::DedupHooks;
+pub use index::ValueIndex;
+#[cfg(feature = "serde_json")]
+pub use json::{JsonError, JsonErrorKind};
+pub use keys::*;
+#[cfg(feature = "mmap")]
+pub use mmap::MappedBytes;
+pub use merge::update_from_value;
+#[cfg(feature = "dedup")]
+pub use middleware::{LookupStats, Sampled, StatsCollector, ThresholdFilter};
+pub use number::Number;
+pub use path::{Path, PathError};
+#[cfg(feature = "dedup")]
+pub use policy::DedupPolicy;
+#[cfg(feature = "prost-reflect")]
+pub use protobuf::{from_dynamic_message, to_dynamic_message, ProtobufError, ProtobufErrorKind};
+pub use query::{from_form_urlencoded, from_query_str, to_query_string, QueryError};
+pub use refs::RefError;
+#[cfg(feature = "dedup")]
+pub use report::{Category, CategorySavings, Contributor, SavingsReport};
+#[cfg(feature = "dedup")]
+pub use sampling::Sampling;
+pub use schema::{Schema, SchemaError, SchemaErrorKind, SchemaFn, ValueSchema, VariantSchema};
 pub use ser::*;
+#[cfg(feature = "derive")]
+pub use serde_value_derive::ValueSchema;
+#[cfg(feature = "dedup")]
+pub use service::{DedupHandle, DedupService, Intern, ServiceStopped};
+#[cfg(feature = "dedup")]
+pub use session::{DedupSession, Document, DocumentSavings};
+pub use slice::{ChunkedSeq, SeqSlice};
+#[cfg(feature = "rusqlite")]
+pub use sqlite::{execute_values, query_values};
+#[cfg(feature = "tokio-postgres")]
+pub use postgres::{rows_to_value, RowConverter};
+pub use strict::from_value_strict;
+pub use truncate::TruncateLimits;
+#[cfg(feature = "tokio")]
+pub use stream::{DedupSink, DedupStream};
+#[cfg(feature = "unicode-normalization")]
+pub use unicode::Form;
+pub use value_ref::ValueRef;
+pub use weak::WeakValue;
+#[cfg(feature = "quick-xml")]
+pub use xml::{from_xml_reader, to_xml_writer, XmlError, XmlOptions};
 
+#[cfg(feature = "dedup")]
+mod analysis;
+mod backend;
+#[cfg(feature = "dedup")]
+mod budget;
+mod builder;
+mod canonical;
+mod cbor;
+#[cfg(feature = "dedup")]
+mod checksum;
+#[cfg(feature = "codec")]
+mod codec;
+#[cfg(feature = "compression")]
+mod compress;
+mod content_hash;
+#[cfg(feature = "csv")]
+mod csv;
 mod de;
+mod dictionary;
+#[macro_use]
+mod diff;
+mod edit;
+mod encoding;
+#[cfg(feature = "erased-serde")]
+mod erased;
+#[cfg(any(feature = "axum-extractor", feature = "actix-extractor"))]
+mod extract;
+#[cfg(feature = "fast-hash")]
+mod fast_hash;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "dedup")]
+mod filter;
+mod fingerprint;
+mod float;
+#[cfg(feature = "dedup")]
+mod hc;
+#[cfg(feature = "dedup")]
+mod hooks;
+pub mod history;
+mod index;
+#[cfg(feature = "tracing")]
+mod instrument;
+#[cfg(feature = "serde_json")]
+mod json;
+mod keys;
+mod literal;
+mod merge;
+#[cfg(feature = "dedup")]
+mod middleware;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod number;
+#[macro_use]
+mod path;
+#[cfg(all(feature = "serde_json", feature = "dedup"))]
+pub mod pipeline;
+#[cfg(feature = "dedup")]
+mod policy;
+#[cfg(feature = "dedup")]
+mod pool;
+#[cfg(feature = "dedup")]
+mod pprof;
+#[cfg(feature = "prost-reflect")]
+mod protobuf;
+#[cfg(feature = "pyo3")]
+mod python;
+mod query;
+mod refs;
+#[cfg(feature = "dedup")]
+mod report;
+mod rewrite;
+#[cfg(feature = "dedup")]
+mod sampling;
+mod schema;
 mod ser;
+#[cfg(feature = "dedup")]
+mod service;
+#[cfg(feature = "dedup")]
+mod session;
+pub mod set;
+mod share;
+pub mod shared;
+mod size;
+mod slice;
+#[cfg(feature = "rusqlite")]
+mod sqlite;
+#[cfg(feature = "tokio-postgres")]
+mod postgres;
+mod strict;
+#[cfg(any(test, feature = "testing"))]
+#[macro_use]
+pub mod testing;
+mod truncate;
+#[cfg(feature = "unicode-normalization")]
+mod unicode;
+mod unique;
+mod value_ref;
+mod weak;
+#[cfg(feature = "quick-xml")]
+mod xml;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "tokio")]
+mod stream;
 
+/// A serialized value.
+///
+/// Some variants only exist with a feature, `F16` with `half`, `CompressedBytes` with
+/// `compression` and `MappedBytes` with `mmap`. Any crate in the build can turn those on, so
+/// matches on `Value` outside of this crate need a wildcard arm.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum Value {
     Unit,
 
     Bool(bool),
 
-    U8(u8),
-    U16(u16),
-    U32(u32),
-    U64(u64),
-
-    I8(i8),
-    I16(i16),
-    I32(i32),
-    I64(i64),
-
-    F32(f32),
-    F64(f64),
+    // any integer or `f32`/`f64`, with its width, compared and hashed by value, see `Number`
+    Number(Number),
+    // half precision, produced when serializing a `half::f16`
+    #[cfg(feature = "half")]
+    F16(half::f16),
 
     Char(char),
 
     Option(Option<Box<Value>>),
     Newtype(Box<Value>),
+    // a unit struct with its name, see `ToValueOptions::unit_names` and `to_value_with`
+    UnitStruct(Arc<str>),
 
     // complex, possibly shared, values
-    String(Arc<String>),
+    String(Arc<str>),
     Bytes(Arc<Vec<u8>>),
     Seq(Arc<Vec<Value>>),
+    // fixed size, from tuples, tuple structs and arrays
+    Tuple(Arc<Vec<Value>>),
     Map(Arc<KV>),
+    // opt-in, see the `set` module; serialized and deserialized as a sequence
+    Set(Arc<BTreeSet<Value>>),
+
+    // application defined extension, like a MessagePack ext or a CBOR tag; serialized and
+    // deserialized as its value, only `to_value` keeps the tag
+    Ext { tag: u64, value: Box<Value> },
+
+    // bytes stored compressed, behaving like `Bytes` when serialized or deserialized; equal,
+    // hashed and ordered by the codec and compressed data, see the `compress` module
+    #[cfg(feature = "compression")]
+    CompressedBytes { codec: Codec, data: Arc<Vec<u8>> },
+
+    // bytes in a memory mapped file, behaving like `Bytes` when serialized or deserialized
+    #[cfg(feature = "mmap")]
+    MappedBytes(MappedBytes),
 }
 
+/// Something that makes equal parts of values share their allocations.
+///
+/// A storage backend, like a bounded cache or a table of weak references, implements the
+/// per-category methods, each returning an `Arc` equal to the one passed in, and gets the
+/// traversal of `dedup` for free. All methods return their input by default, so a backend
+/// can leave out what it does not intern. Wrappers can instead override `dedup` as a whole.
 pub trait Deduplicator {
-    fn dedup(&mut self, value: Value) -> Value;
+    /// Returns a value equal to `value`, with its parts shared where possible.
+    fn dedup(&mut self, value: Value) -> Value {
+        backend::dedup_tree(self, value)
+    }
+
+    /// Interns a string, also used for the names of unit structs.
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        value
+    }
+
+    /// Interns a byte blob.
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        value
+    }
+
+    /// Interns a sequence, tuple or vector of map keys, whose elements are already interned.
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        value
+    }
+
+    /// Interns a map, whose keys and values are already interned.
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        value
+    }
+}
+
+impl<D: Deduplicator + ?Sized> Deduplicator for &mut D {
+    fn dedup(&mut self, value: Value) -> Value {
+        (**self).dedup(value)
+    }
+
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        (**self).dedup_string(value)
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        (**self).dedup_blob(value)
+    }
+
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        (**self).dedup_seq(value)
+    }
+
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        (**self).dedup_map(value)
+    }
+}
+
+/// A `Deduplicator` that can be shared, by handing out handles that intern into the same
+/// storage, such as one for each thread of a server or worker of a pipeline.
+///
+/// A handle is a `Deduplicator` itself, so code that takes any `Deduplicator` works with
+/// every shareable backend. `Arc<Mutex<D>>` shares any backend behind a lock, and
+/// `DedupService` shares a `Dedup` owned by a thread of its own.
+pub trait SharedDeduplicator {
+    type Handle: Deduplicator + Clone + Send + 'static;
+
+    /// A new handle to this storage.
+    fn handle(&self) -> Self::Handle;
+}
+
+/// Locks the backend for each call, once for a whole value in `dedup`.
+impl<D: Deduplicator + Send + 'static> Deduplicator for Arc<Mutex<D>> {
+    fn dedup(&mut self, value: Value) -> Value {
+        self.lock().unwrap().dedup(value)
+    }
+
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        self.lock().unwrap().dedup_string(value)
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        self.lock().unwrap().dedup_blob(value)
+    }
+
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        self.lock().unwrap().dedup_seq(value)
+    }
+
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        self.lock().unwrap().dedup_map(value)
+    }
 }
 
+impl<D: Deduplicator + Send + 'static> SharedDeduplicator for Arc<Mutex<D>> {
+    type Handle = Arc<Mutex<D>>;
+
+    fn handle(&self) -> Arc<Mutex<D>> {
+        self.clone()
+    }
+}
+
+/// An interning table of `Dedup`.
+#[cfg(all(feature = "dedup", not(feature = "fast-hash")))]
+type Table<T> = HashSet<Arc<T>>;
+#[cfg(feature = "fast-hash")]
+type Table<T> = HashSet<Arc<T>, fast_hash::TableHashing>;
+
+#[cfg(feature = "dedup")]
 #[derive(Clone, Debug)]
 pub struct Dedup {
-    blobs: HashSet<Arc<Vec<u8>>>,
-    strings: HashSet<Arc<String>>,
-    vectors: HashSet<Arc<Vec<Value>>>,
-    objects: HashSet<Arc<KV>>,
+    blobs: Table<Vec<u8>>,
+    strings: Table<str>,
+    vectors: Table<Vec<Value>>,
+    objects: Table<KV>,
+    seen: report::SeenTables,
+    filters: Option<Box<MissFilters>>,
+    policy: Option<Arc<DedupPolicy>>,
+    sampler: Option<Sampler>,
+    hooks: Option<Hooks>,
+    budget: Option<Budget>,
+    checksums: Option<Checksums>,
+    pool: Pool,
+}
+
+#[cfg(feature = "dedup")]
+#[derive(Clone, Debug, Default)]
+struct MissFilters {
+    blobs: MissFilter,
+    strings: MissFilter,
+    vectors: MissFilter,
+    objects: MissFilter,
 }
 
+#[cfg(feature = "dedup")]
 impl Dedup {
     pub fn new() -> Dedup {
         Dedup {
-            blobs: HashSet::new(),
-            strings: HashSet::new(),
-            vectors: HashSet::new(),
-            objects: HashSet::new(),
+            blobs: Table::default(),
+            strings: Table::default(),
+            vectors: Table::default(),
+            objects: Table::default(),
+            seen: Default::default(),
+            filters: None,
+            policy: None,
+            sampler: None,
+            hooks: None,
+            budget: None,
+            checksums: None,
+            pool: Pool::default(),
+        }
+    }
+
+    /// Like `new`, with a Bloom filter in front of each table.
+    ///
+    /// The filter looks only at the length and the ends of each string, blob or vector, so
+    /// a value that is certainly new is inserted without a lookup that hashes all of it.
+    /// This pays off when most values are unique, and costs a filter probe per value and
+    /// two bytes per entry otherwise.
+    pub fn with_miss_filter() -> Dedup {
+        Dedup {
+            filters: Some(Default::default()),
+            ..Dedup::new()
         }
     }
 
-    fn strings(&self) -> Vec<(String, usize)> {
-        self.strings.iter().cloned().map(|x| (x.as_ref().clone(), Arc::strong_count(&x))).collect()
+    /// Iterates over the interned strings, together with their strong count.
+    ///
+    /// The count is taken before the entry is cloned for the iterator, so it includes the
+    /// reference held by the table itself but not the one handed out.
+    pub fn iter_strings(&self) -> impl Iterator<Item = (Arc<str>, usize)> + '_ {
+        self.strings.iter().map(with_strong_count)
+    }
+
+    /// Iterates over the interned byte blobs, see `iter_strings`.
+    pub fn iter_blobs(&self) -> impl Iterator<Item = (Arc<Vec<u8>>, usize)> + '_ {
+        self.blobs.iter().map(with_strong_count)
     }
 
-    fn objects(&self) -> Vec<(KV, usize)> {
-        self.objects.iter().cloned().map(|x| (x.as_ref().clone(), Arc::strong_count(&x))).collect()
+    /// Iterates over the interned sequences, including map key vectors, see `iter_strings`.
+    pub fn iter_seqs(&self) -> impl Iterator<Item = (Arc<Vec<Value>>, usize)> + '_ {
+        self.vectors.iter().map(with_strong_count)
     }
 
-    fn vectors(&self) -> Vec<(Vec<Value>, usize)> {
-        self.vectors.iter().cloned().map(|x| (x.as_ref().clone(), Arc::strong_count(&x))).collect()
+    /// Iterates over the interned maps, see `iter_strings`.
+    pub fn iter_objects(&self) -> impl Iterator<Item = (Arc<KV>, usize)> + '_ {
+        self.objects.iter().map(with_strong_count)
     }
 
-    fn size(&self) -> usize {
+    /// Bytes of all entries as measured by `savings_report`, which is what budgets count.
+    fn stored_bytes(&self) -> usize {
         let mut res: usize = 0;
         for blob in self.blobs.iter() {
-            res += blob.len();
+            res += report::blob_size(blob);
         }
         for string in self.strings.iter() {
-            res += string.len();
+            res += report::string_size(string);
         }
         for vector in self.vectors.iter() {
-            res += vector.len() * std::mem::size_of::<Value>();
+            res += report::seq_size(vector);
         }
         for object in self.objects.iter() {
-            res += std::mem::size_of::<KV>();
-            let KV(_,v) = object.as_ref();
-            res += v.len() * std::mem::size_of::<Value>();
+            res += report::object_size(object);
         }
         res
     }
 
     fn dedup_value_vec(&mut self, vec: Vec<Value>) -> Vec<Value> {
-        vec.into_iter().map(|x| self.dedup(x)).collect()
+        vec.into_iter().map(|x| self.dedup_value(x)).collect()
     }
+}
 
-    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
-        match self.blobs.get(value.as_ref()) {
-            Some(value) => value.clone(),
-            None => {
-                self.blobs.insert(value.clone());
-                value
+/// Looks up `value` in one of the `Dedup` tables, inserting it if it is new and admitted.
+#[cfg(feature = "dedup")]
+fn intern<T: Hash + Eq + Fingerprint + ?Sized>(
+    table: &mut Table<T>,
+    filter: Option<&mut MissFilter>,
+    mut admission: Admission,
+    seen: &mut report::Seen,
+    category: Category,
+    size: usize,
+    value: Arc<T>,
+) -> (Arc<T>, Outcome) {
+    seen.record(size);
+    let (value, outcome) = match filter {
+        Some(filter) => {
+            let fingerprint = value.fingerprint();
+            let maybe = filter.might_contain(fingerprint);
+            let found = if maybe { table.get(&value).cloned() } else { None };
+            match found {
+                Some(value) => (value, Outcome::Hit),
+                None if !admission.admits(&*value) => (value, Outcome::Skipped),
+                // a filter miss is certain unless two equal values got different
+                // fingerprints, which the insert still catches
+                None if table.insert(value.clone()) => {
+                    filter.insert(fingerprint);
+                    filter.reserve(table.len(), table.iter());
+                    (value, Outcome::Inserted)
+                }
+                None => (table.get(&value).unwrap().clone(), Outcome::Hit),
             }
         }
+        None => match table.get(&value) {
+            Some(value) => (value.clone(), Outcome::Hit),
+            None if admission.admits(&*value) => {
+                table.insert(value.clone());
+                (value, Outcome::Inserted)
+            }
+            None => (value, Outcome::Skipped),
+        },
+    };
+    let hit = outcome == Outcome::Hit;
+    if hit {
+        seen.hits += 1;
     }
+    report::observe(category, hit, size, table.len(), seen);
+    (value, outcome)
+}
 
-    fn dedup_string(&mut self, value: Arc<String>) -> Arc<String> {
-        match self.strings.get(value.as_ref()) {
-            Some(value) => value.clone(),
-            None => {
-                self.strings.insert(value.clone());
-                value
-            }
+#[cfg(feature = "dedup")]
+fn with_strong_count<T: ?Sized>(entry: &Arc<T>) -> (Arc<T>, usize) {
+    let count = Arc::strong_count(entry);
+    (entry.clone(), count)
+}
+
+#[cfg(feature = "dedup")]
+impl Default for Dedup {
+    fn default() -> Dedup {
+        Dedup::new()
+    }
+}
+
+#[cfg(feature = "dedup")]
+impl Deduplicator for Dedup {
+    fn dedup(&mut self, value: Value) -> Value {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("dedup").entered();
+        #[cfg(feature = "tracing")]
+        let before = instrument::dedup_totals(self);
+        let value = match self.policy.clone() {
+            Some(policy) => self.dedup_with_policy(value, &policy),
+            None => self.dedup_value(value),
+        };
+        #[cfg(feature = "tracing")]
+        instrument::deduplicated(before, instrument::dedup_totals(self));
+        value
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        let size = report::blob_size(&value);
+        let room = self.has_room(size);
+        let filter = self.filters.as_mut().map(|f| &mut f.blobs);
+        let (value, outcome) = intern(
+            &mut self.blobs,
+            filter,
+            Admission {
+                room,
+                sampler: self.sampler.as_mut(),
+            },
+            &mut self.seen.blobs,
+            Category::Blob,
+            size,
+            value,
+        );
+        self.interned(outcome, room, &value, size, Value::Bytes);
+        if let (Outcome::Inserted, Some(checksums)) = (outcome, self.checksums.as_mut()) {
+            checksums.record(&value);
         }
+        value
+    }
+
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        let size = report::string_size(&value);
+        let room = self.has_room(size);
+        let filter = self.filters.as_mut().map(|f| &mut f.strings);
+        let (value, outcome) = intern(
+            &mut self.strings,
+            filter,
+            Admission {
+                room,
+                sampler: self.sampler.as_mut(),
+            },
+            &mut self.seen.strings,
+            Category::String,
+            size,
+            value,
+        );
+        self.interned(outcome, room, &value, size, Value::String);
+        value
     }
 
     fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
-        match self.vectors.get(value.as_ref()) {
-            Some(value) => value.clone(),
-            None => {
-                self.vectors.insert(value.clone());
-                value
-            }
+        let size = report::seq_size(&value);
+        let room = self.has_room(size);
+        let filter = self.filters.as_mut().map(|f| &mut f.vectors);
+        let candidate = value.clone();
+        let (value, outcome) = intern(
+            &mut self.vectors,
+            filter,
+            Admission {
+                room,
+                sampler: self.sampler.as_mut(),
+            },
+            &mut self.seen.vectors,
+            Category::Seq,
+            size,
+            value,
+        );
+        self.interned(outcome, room, &value, size, Value::Seq);
+        if !Arc::ptr_eq(&value, &candidate) {
+            self.pool.recycle(candidate);
         }
+        value
     }
 
     fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
-        match self.objects.get(value.as_ref()) {
-            Some(value) => value.clone(),
-            None => {
-                self.objects.insert(value.clone());
-                value
-            }
+        let size = report::object_size(&value);
+        let room = self.has_room(size);
+        let filter = self.filters.as_mut().map(|f| &mut f.objects);
+        let candidate = value.clone();
+        let (value, outcome) = intern(
+            &mut self.objects,
+            filter,
+            Admission {
+                room,
+                sampler: self.sampler.as_mut(),
+            },
+            &mut self.seen.objects,
+            Category::Object,
+            size,
+            value,
+        );
+        self.interned(outcome, room, &value, size, Value::Map);
+        if !Arc::ptr_eq(&value, &candidate) {
+            self.pool.recycle_object(candidate);
         }
+        value
     }
 }
 
-impl Deduplicator for Dedup {
-    fn dedup(&mut self, value: Value) -> Value {
+#[cfg(feature = "dedup")]
+impl Dedup {
+    fn dedup_value(&mut self, value: Value) -> Value {
         match value {
             Value::Bytes(v) => Value::Bytes(self.dedup_blob(v)),
             Value::String(v) => Value::String(self.dedup_string(v)),
+            Value::UnitStruct(v) => Value::UnitStruct(self.dedup_string(v)),
             Value::Seq(elements) => {
-                let elements = Arc::new(self.dedup_value_vec(elements.as_ref().clone()));
+                let elements = self.pool.take(elements);
+                let elements = Arc::new(self.dedup_value_vec(elements));
                 Value::Seq(self.dedup_seq(elements))
             }
+            // tuples share the table with sequences, as they have the same representation
+            Value::Tuple(elements) => {
+                let elements = self.pool.take(elements);
+                let elements = Arc::new(self.dedup_value_vec(elements));
+                Value::Tuple(self.dedup_seq(elements))
+            }
             Value::Map(object) => {
-                let KV(k, v) = object.as_ref();
-                let k = Arc::new(self.dedup_value_vec(k.as_ref().clone()));
-                let v = self.dedup_value_vec(v.clone());
+                let KV(k, v) = self.pool.take_object(object);
+                let k = self.pool.take(k);
+                let k = Arc::new(self.dedup_value_vec(k));
+                let v = self.dedup_value_vec(v);
                 let k = self.dedup_seq(k);
                 let object = Arc::new(KV(k, v));
                 Value::Map(self.dedup_map(object))
             }
+            // sets themselves are not interned, only their elements
+            Value::Set(elements) => {
+                let elements = self.dedup_value_vec(elements.iter().cloned().collect());
+                Value::Set(Arc::new(elements.into_iter().collect()))
+            }
+            Value::Ext { tag, value } => Value::Ext {
+                tag,
+                value: Box::new(self.dedup_value(*value)),
+            },
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, data } => Value::CompressedBytes {
+                codec,
+                data: self.dedup_blob(data),
+            },
             x => x,
         }
     }
 }
 
+#[cfg(feature = "dedup")]
 impl Display for Dedup {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // writeln!(
-        //     f,
-        //     "blobs:{}",
-        //     DisplayableVec(
-        //         &self
-        //             .blobs
-        //             .iter()
-        //             .map(|x| format!("{}:{}", DisplayableBlob(x), Arc::strong_count(x)))
-        //             .collect()
-        //     )
-        // )?;
-        writeln!(
-            f,
-            "strings:{}",
-            DisplayableVec(
-                &self
-                    .strings
-                    .iter()
-                    .filter(|x| Arc::strong_count(x) > 100)
-                    .map(|x| format!("{}:{}\n", x, Arc::strong_count(x)))
-                    .collect()
-            )
-        )?;
-        writeln!(
-            f,
-            "vectors:{}",
-            DisplayableVec(
-                &self
-                    .vectors
-                    .iter()
-                    .filter(|x| Arc::strong_count(x) > 100)
-                    .map(|x| format!("{}:{}\n", DisplayableVec(x), Arc::strong_count(x)))
-                    .collect()
-            )
-        )?;
-        writeln!(
-            f,
-            "objects:{}",
-            DisplayableVec(
-                &self
-                    .objects
-                    .iter()
-                    .filter(|x| Arc::strong_count(x) > 100)
-                    .map(|x| format!("{}:{}\n", DisplayableMap(&x.0, &x.1), Arc::strong_count(x)))
-                    .collect()
-            )
-        )
+        write!(f, "{}", self.report())
     }
 }
 
+/// The entries of a `Value::Map`: the keys, sorted and unique, and their values in the same
+/// order.
+///
+/// Maps with only string keys use this representation as well. `get` finds a string key by
+/// binary search over the contents, without allocating, and after `dedup` maps of the same
+/// shape share their key vector, so a separate variant for string keyed objects would save
+/// little, while every consumer of `Value` would have to handle two kinds of maps.
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct KV(Arc<Vec<Value>>, Vec<Value>);
 
@@ -232,11 +760,73 @@ impl KV {
             .as_ref()
             .clone()
             .into_iter()
-            .zip(self.1.clone().into_iter())
+            .zip(self.1.clone())
     }
     fn as_map(&self) -> BTreeMap<Value, Value> {
         self.iter().collect()
     }
+    fn with_values(&self, values: Vec<Value>) -> Arc<KV> {
+        Arc::new(KV(self.0.clone(), values))
+    }
+
+    /// A map from keys that are already sorted and unique, and their values in the same
+    /// order, without sorting or checking the keys in release builds.
+    ///
+    /// Unsorted or repeated keys make lookups and comparisons of the map give wrong
+    /// results. Panics if `keys` and `values` differ in length.
+    pub fn from_sorted_vecs(keys: Vec<Value>, values: Vec<Value>) -> KV {
+        assert_eq!(keys.len(), values.len(), "keys and values differ in length");
+        debug_assert!(keys.windows(2).all(|w| w[0] < w[1]), "keys are not sorted and unique");
+        KV(Arc::new(keys), values)
+    }
+
+    /// Index of the string key `key`, found by binary search.
+    pub(crate) fn string_key_index(&self, key: &str) -> Option<usize> {
+        // all string keys sort next to each other, in the order of their contents, and keys
+        // of other variants compare with any string by their variant alone
+        let mut string = None;
+        self.0
+            .binary_search_by(|k| match *k {
+                Value::String(ref s) => (**s).cmp(key),
+                ref other => other.cmp(string.get_or_insert_with(|| Value::String("".into()))),
+            })
+            .ok()
+    }
+
+    /// The value for the string key `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.string_key_index(key).map(|i| &self.1[i])
+    }
+
+    /// The value for the first string key equal to `key` ignoring ASCII case, in key order.
+    ///
+    /// This scans the keys. For repeated lookups, `Value::fold_keys_ascii_lowercase` and
+    /// `get` with a lowercase key are faster.
+    pub fn get_ignore_ascii_case(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .position(|k| match *k {
+                Value::String(ref s) => s.eq_ignore_ascii_case(key),
+                _ => false,
+            })
+            .map(|i| &self.1[i])
+    }
+
+    /// The sorted keys of this map.
+    pub fn keys(&self) -> &[Value] {
+        &self.0
+    }
+
+    /// The values of this map, in key order.
+    pub fn values(&self) -> &[Value] {
+        &self.1
+    }
+
+    /// The key vector itself. After dedup, maps with the same set of keys share it, so
+    /// `Arc::ptr_eq` on the result is a cheap schema comparison.
+    pub fn keys_shared(&self) -> Arc<Vec<Value>> {
+        self.0.clone()
+    }
 }
 
 impl Value {
@@ -251,14 +841,142 @@ impl Value {
     }
 
     fn string(value: String) -> Value {
-        Value::String(Arc::new(value))
+        Value::String(Arc::from(value))
     }
 
     fn bytes(value: Vec<u8>) -> Value {
         Value::Bytes(Arc::new(value))
     }
+
+    /// A map from key value pairs in ascending key order without repeated keys, skipping the
+    /// detour through a `BTreeMap`, see `KV::from_sorted_vecs`.
+    pub fn map_from_sorted_iter<I: IntoIterator<Item = (Value, Value)>>(iter: I) -> Value {
+        let (keys, values) = iter.into_iter().unzip();
+        Value::Map(Arc::new(KV::from_sorted_vecs(keys, values)))
+    }
+
+    /// The value for the string key `key` if this is a `Map`, without scanning all keys.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Map(ref kv) => kv.get(key),
+            _ => None,
+        }
+    }
+
+    /// The value for a string key equal to `key` ignoring ASCII case if this is a `Map`, see
+    /// `KV::get_ignore_ascii_case`.
+    pub fn get_ignore_ascii_case(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Map(ref kv) => kv.get_ignore_ascii_case(key),
+            _ => None,
+        }
+    }
+
+    /// The shared element vector if this is a `Seq`, without copying the elements.
+    pub fn as_shared_seq(&self) -> Option<Arc<Vec<Value>>> {
+        match *self {
+            Value::Seq(ref v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+
+    /// The shared string if this is a `String`, for comparing or caching by `Arc::as_ptr`.
+    pub fn as_shared_str(&self) -> Option<&Arc<str>> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The shared blob if this is `Bytes`.
+    pub fn as_shared_bytes(&self) -> Option<&Arc<Vec<u8>>> {
+        match *self {
+            Value::Bytes(ref b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// The shared keys and values if this is a `Map`.
+    pub fn as_shared_map(&self) -> Option<&Arc<KV>> {
+        match *self {
+            Value::Map(ref kv) => Some(kv),
+            _ => None,
+        }
+    }
+
+    /// The number of elements of a sequence, tuple or set, of entries of a map, or of bytes
+    /// of a string or blob. `None` for scalars and wrappers, and for compressed bytes, whose
+    /// length is not known without decompressing them.
+    pub fn len(&self) -> Option<usize> {
+        match *self {
+            Value::String(ref v) => Some(v.len()),
+            Value::Bytes(ref v) => Some(v.len()),
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => Some(v.len()),
+            Value::Seq(ref v) | Value::Tuple(ref v) => Some(v.len()),
+            Value::Set(ref v) => Some(v.len()),
+            Value::Map(ref kv) => Some(kv.0.len()),
+            _ => None,
+        }
+    }
+
+    /// Whether `len` is `Some(0)`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Whether a sequence, tuple or set has the element `x`, a map has the key `x`, or a
+    /// string has the string or char `x` as a substring. Elements and keys are compared with
+    /// the total `Ord` of `Value`, so `1u8` is found in a sequence of `u64`s, but not the
+    /// string `"1"`. False for anything else.
+    pub fn contains(&self, x: &Value) -> bool {
+        match *self {
+            Value::Seq(ref v) | Value::Tuple(ref v) => v.contains(x),
+            Value::Set(ref v) => v.contains(x),
+            Value::Map(ref kv) => kv.0.binary_search(x).is_ok(),
+            Value::String(ref s) => match *x {
+                Value::String(ref x) => s.contains(&**x),
+                Value::Char(x) => s.contains(x),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The element vector if this is a `Seq`, or the value itself otherwise.
+    pub fn try_into_seq(self) -> Result<Arc<Vec<Value>>, Value> {
+        match self {
+            Value::Seq(v) => Ok(v),
+            other => Err(other),
+        }
+    }
+
+    /// The keys and values if this is a `Map`, or the value itself otherwise.
+    pub fn try_into_map(self) -> Result<Arc<KV>, Value> {
+        match self {
+            Value::Map(kv) => Ok(kv),
+            other => Err(other),
+        }
+    }
+
+    /// The string if this is a `String`, or the value itself otherwise.
+    pub fn try_into_string(self) -> Result<Arc<str>, Value> {
+        match self {
+            Value::String(s) => Ok(s),
+            other => Err(other),
+        }
+    }
+
+    /// The blob if this is `Bytes`, or the value itself otherwise.
+    pub fn try_into_bytes(self) -> Result<Arc<Vec<u8>>, Value> {
+        match self {
+            Value::Bytes(b) => Ok(b),
+            other => Err(other),
+        }
+    }
 }
 
+#[allow(dead_code)]
 struct DisplayableBlob<'a>(&'a Vec<u8>);
 
 impl Display for DisplayableBlob<'_> {
@@ -305,17 +1023,11 @@ impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
             Value::Unit => write!(f, "()"),
+            Value::UnitStruct(ref v) => write!(f, "{}", v),
             Value::Bool(v) => write!(f, "{}", v),
-            Value::U8(v) => write!(f, "{}", v),
-            Value::U16(v) => write!(f, "{}", v),
-            Value::U32(v) => write!(f, "{}", v),
-            Value::U64(v) => write!(f, "{}", v),
-            Value::I8(v) => write!(f, "{}", v),
-            Value::I16(v) => write!(f, "{}", v),
-            Value::I32(v) => write!(f, "{}", v),
-            Value::I64(v) => write!(f, "{}", v),
-            Value::F32(v) => write!(f, "{}", v),
-            Value::F64(v) => write!(f, "{}", v),
+            Value::Number(ref v) => write!(f, "{}", v),
+            #[cfg(feature = "half")]
+            Value::F16(v) => write!(f, "{}", v),
             Value::Char(v) => write!(f, "{}", v),
             Value::String(ref v) => write!(f, "{}", v),
             Value::Bytes(ref v) => write!(f, "{:?}", v),
@@ -325,7 +1037,34 @@ impl Display for Value {
                 .unwrap_or_else(|| write!(f, "None")),
             Value::Newtype(ref v) => write!(f, "{}", v),
             Value::Seq(ref v) => write!(f, "{}", DisplayableVec(v)),
+            Value::Tuple(ref v) => {
+                write!(f, "(")?;
+                for (i, elem) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, ")")
+            }
             Value::Map(ref v) => write!(f, "{}", DisplayableMap(&v.0, &v.1)),
+            Value::Set(ref v) => {
+                write!(f, "#{{")?;
+                for (i, elem) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Ext { tag, ref value } => write!(f, "{}({})", tag, value),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => {
+                write!(f, "<{} bytes {:?}>", data.len(), codec)
+            }
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => write!(f, "<{} mapped bytes>", v.len()),
         }
     }
 }
@@ -338,24 +1077,31 @@ impl Hash for Value {
         self.discriminant().hash(hasher);
         match *self {
             Value::Bool(v) => v.hash(hasher),
-            Value::U8(v) => v.hash(hasher),
-            Value::U16(v) => v.hash(hasher),
-            Value::U32(v) => v.hash(hasher),
-            Value::U64(v) => v.hash(hasher),
-            Value::I8(v) => v.hash(hasher),
-            Value::I16(v) => v.hash(hasher),
-            Value::I32(v) => v.hash(hasher),
-            Value::I64(v) => v.hash(hasher),
-            Value::F32(v) => OrderedFloat(v).hash(hasher),
-            Value::F64(v) => OrderedFloat(v).hash(hasher),
+            Value::Number(ref v) => v.hash(hasher),
+            #[cfg(feature = "half")]
+            Value::F16(v) => OrderedFloat(v.to_f32()).hash(hasher),
             Value::Char(v) => v.hash(hasher),
             Value::String(ref v) => v.hash(hasher),
-            Value::Unit => ().hash(hasher),
+            Value::Unit => {}
+            Value::UnitStruct(ref v) => v.hash(hasher),
             Value::Option(ref v) => v.hash(hasher),
             Value::Newtype(ref v) => v.hash(hasher),
             Value::Seq(ref v) => v.hash(hasher),
+            Value::Tuple(ref v) => v.hash(hasher),
             Value::Map(ref v) => v.hash(hasher),
+            Value::Set(ref v) => v.hash(hasher),
+            Value::Ext { tag, ref value } => {
+                tag.hash(hasher);
+                value.hash(hasher)
+            }
             Value::Bytes(ref v) => v.hash(hasher),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => {
+                codec.hash(hasher);
+                data.hash(hasher)
+            }
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => v.hash(hasher),
         }
     }
 }
@@ -363,25 +1109,34 @@ impl Hash for Value {
 impl PartialEq for Value {
     fn eq(&self, rhs: &Self) -> bool {
         match (self, rhs) {
-            (&Value::Bool(v0), &Value::Bool(v1)) => v0 == v1,
-            (&Value::U8(v0), &Value::U8(v1)) => v0 == v1,
-            (&Value::U16(v0), &Value::U16(v1)) => v0 == v1,
-            (&Value::U32(v0), &Value::U32(v1)) => v0 == v1,
-            (&Value::U64(v0), &Value::U64(v1)) => v0 == v1,
-            (&Value::I8(v0), &Value::I8(v1)) => v0 == v1,
-            (&Value::I16(v0), &Value::I16(v1)) => v0 == v1,
-            (&Value::I32(v0), &Value::I32(v1)) => v0 == v1,
-            (&Value::I64(v0), &Value::I64(v1)) => v0 == v1,
-            (&Value::F32(v0), &Value::F32(v1)) => OrderedFloat(v0) == OrderedFloat(v1),
-            (&Value::F64(v0), &Value::F64(v1)) => OrderedFloat(v0) == OrderedFloat(v1),
-            (&Value::Char(v0), &Value::Char(v1)) => v0 == v1,
-            (&Value::String(ref v0), &Value::String(ref v1)) => v0 == v1,
-            (&Value::Unit, &Value::Unit) => true,
-            (&Value::Option(ref v0), &Value::Option(ref v1)) => v0 == v1,
-            (&Value::Newtype(ref v0), &Value::Newtype(ref v1)) => v0 == v1,
-            (&Value::Seq(ref v0), &Value::Seq(ref v1)) => v0 == v1,
-            (&Value::Map(ref v0), &Value::Map(ref v1)) => v0 == v1,
-            (&Value::Bytes(ref v0), &Value::Bytes(ref v1)) => v0 == v1,
+            (Value::Bool(v0), Value::Bool(v1)) => v0 == v1,
+            (Value::Number(v0), Value::Number(v1)) => v0 == v1,
+            #[cfg(feature = "half")]
+            (Value::F16(v0), Value::F16(v1)) => {
+                OrderedFloat(v0.to_f32()) == OrderedFloat(v1.to_f32())
+            }
+            (Value::Char(v0), Value::Char(v1)) => v0 == v1,
+            (Value::String(v0), Value::String(v1)) => v0 == v1,
+            (Value::Unit, Value::Unit) => true,
+            (Value::UnitStruct(v0), Value::UnitStruct(v1)) => v0 == v1,
+            (Value::Option(v0), Value::Option(v1)) => v0 == v1,
+            (Value::Newtype(v0), Value::Newtype(v1)) => v0 == v1,
+            (Value::Seq(v0), Value::Seq(v1)) => v0 == v1,
+            (Value::Tuple(v0), Value::Tuple(v1)) => v0 == v1,
+            (Value::Map(v0), Value::Map(v1)) => v0 == v1,
+            (Value::Set(v0), Value::Set(v1)) => v0 == v1,
+            (
+                Value::Ext { tag: t0, value: v0 },
+                Value::Ext { tag: t1, value: v1 },
+            ) => t0 == t1 && v0 == v1,
+            (Value::Bytes(v0), Value::Bytes(v1)) => v0 == v1,
+            #[cfg(feature = "compression")]
+            (
+                Value::CompressedBytes { codec: c0, data: d0 },
+                Value::CompressedBytes { codec: c1, data: d1 },
+            ) => c0 == c1 && d0 == d1,
+            #[cfg(feature = "mmap")]
+            (Value::MappedBytes(v0), Value::MappedBytes(v1)) => v0 == v1,
             _ => false,
         }
     }
@@ -390,26 +1145,35 @@ impl PartialEq for Value {
 impl Ord for Value {
     fn cmp(&self, rhs: &Self) -> Ordering {
         match (self, rhs) {
-            (&Value::Bool(v0), &Value::Bool(ref v1)) => v0.cmp(v1),
-            (&Value::U8(v0), &Value::U8(ref v1)) => v0.cmp(v1),
-            (&Value::U16(v0), &Value::U16(ref v1)) => v0.cmp(v1),
-            (&Value::U32(v0), &Value::U32(ref v1)) => v0.cmp(v1),
-            (&Value::U64(v0), &Value::U64(ref v1)) => v0.cmp(v1),
-            (&Value::I8(v0), &Value::I8(ref v1)) => v0.cmp(v1),
-            (&Value::I16(v0), &Value::I16(ref v1)) => v0.cmp(v1),
-            (&Value::I32(v0), &Value::I32(ref v1)) => v0.cmp(v1),
-            (&Value::I64(v0), &Value::I64(ref v1)) => v0.cmp(v1),
-            (&Value::F32(v0), &Value::F32(v1)) => OrderedFloat(v0).cmp(&OrderedFloat(v1)),
-            (&Value::F64(v0), &Value::F64(v1)) => OrderedFloat(v0).cmp(&OrderedFloat(v1)),
-            (&Value::Char(v0), &Value::Char(ref v1)) => v0.cmp(v1),
-            (&Value::String(ref v0), &Value::String(ref v1)) => v0.cmp(v1),
-            (&Value::Unit, &Value::Unit) => Ordering::Equal,
-            (&Value::Option(ref v0), &Value::Option(ref v1)) => v0.cmp(v1),
-            (&Value::Newtype(ref v0), &Value::Newtype(ref v1)) => v0.cmp(v1),
-            (&Value::Seq(ref v0), &Value::Seq(ref v1)) => v0.cmp(v1),
-            (&Value::Map(ref v0), &Value::Map(ref v1)) => v0.cmp(v1),
-            (&Value::Bytes(ref v0), &Value::Bytes(ref v1)) => v0.cmp(v1),
-            (ref v0, ref v1) => v0.discriminant().cmp(&v1.discriminant()),
+            (Value::Bool(v0), Value::Bool(v1)) => v0.cmp(v1),
+            (Value::Number(v0), Value::Number(v1)) => v0.cmp(v1),
+            #[cfg(feature = "half")]
+            (Value::F16(v0), Value::F16(v1)) => {
+                OrderedFloat(v0.to_f32()).cmp(&OrderedFloat(v1.to_f32()))
+            }
+            (Value::Char(v0), Value::Char(v1)) => v0.cmp(v1),
+            (Value::String(v0), Value::String(v1)) => v0.cmp(v1),
+            (Value::Unit, Value::Unit) => Ordering::Equal,
+            (Value::UnitStruct(v0), Value::UnitStruct(v1)) => v0.cmp(v1),
+            (Value::Option(v0), Value::Option(v1)) => v0.cmp(v1),
+            (Value::Newtype(v0), Value::Newtype(v1)) => v0.cmp(v1),
+            (Value::Seq(v0), Value::Seq(v1)) => v0.cmp(v1),
+            (Value::Tuple(v0), Value::Tuple(v1)) => v0.cmp(v1),
+            (Value::Map(v0), Value::Map(v1)) => v0.cmp(v1),
+            (Value::Set(v0), Value::Set(v1)) => v0.cmp(v1),
+            (
+                Value::Ext { tag: t0, value: v0 },
+                Value::Ext { tag: t1, value: v1 },
+            ) => t0.cmp(t1).then_with(|| v0.cmp(v1)),
+            (Value::Bytes(v0), Value::Bytes(v1)) => v0.cmp(v1),
+            #[cfg(feature = "compression")]
+            (
+                Value::CompressedBytes { codec: c0, data: d0 },
+                Value::CompressedBytes { codec: c1, data: d1 },
+            ) => c0.cmp(c1).then_with(|| d0.cmp(d1)),
+            #[cfg(feature = "mmap")]
+            (Value::MappedBytes(v0), Value::MappedBytes(v1)) => v0.cmp(v1),
+            (v0, v1) => v0.discriminant().cmp(&v1.discriminant()),
         }
     }
 }
@@ -418,53 +1182,76 @@ impl Value {
     fn discriminant(&self) -> usize {
         match *self {
             Value::Bool(..) => 0,
-            Value::U8(..) => 1,
-            Value::U16(..) => 2,
-            Value::U32(..) => 3,
-            Value::U64(..) => 4,
-            Value::I8(..) => 5,
-            Value::I16(..) => 6,
-            Value::I32(..) => 7,
-            Value::I64(..) => 8,
-            Value::F32(..) => 9,
-            Value::F64(..) => 10,
-            Value::Char(..) => 11,
-            Value::String(..) => 12,
-            Value::Unit => 13,
-            Value::Option(..) => 14,
-            Value::Newtype(..) => 15,
-            Value::Seq(..) => 16,
-            Value::Map(..) => 17,
-            Value::Bytes(..) => 18,
-        }
-    }
-
-    fn unexpected(&self) -> serde::de::Unexpected {
+            Value::Number(..) => 1,
+            Value::Char(..) => 2,
+            Value::String(..) => 3,
+            Value::Unit => 4,
+            Value::Option(..) => 5,
+            Value::Newtype(..) => 6,
+            Value::Seq(..) => 7,
+            Value::Map(..) => 8,
+            Value::Bytes(..) => 9,
+            Value::Ext { .. } => 10,
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { .. } => 11,
+            Value::Set(..) => 12,
+            Value::Tuple(..) => 13,
+            Value::UnitStruct(..) => 14,
+            #[cfg(feature = "half")]
+            Value::F16(..) => 15,
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(..) => 16,
+        }
+    }
+
+    fn unexpected(&self) -> serde::de::Unexpected<'_> {
         match *self {
             Value::Bool(b) => serde::de::Unexpected::Bool(b),
-            Value::U8(n) => serde::de::Unexpected::Unsigned(n as u64),
-            Value::U16(n) => serde::de::Unexpected::Unsigned(n as u64),
-            Value::U32(n) => serde::de::Unexpected::Unsigned(n as u64),
-            Value::U64(n) => serde::de::Unexpected::Unsigned(n),
-            Value::I8(n) => serde::de::Unexpected::Signed(n as i64),
-            Value::I16(n) => serde::de::Unexpected::Signed(n as i64),
-            Value::I32(n) => serde::de::Unexpected::Signed(n as i64),
-            Value::I64(n) => serde::de::Unexpected::Signed(n),
-            Value::F32(n) => serde::de::Unexpected::Float(n as f64),
-            Value::F64(n) => serde::de::Unexpected::Float(n),
+            Value::Number(Number::U8(n)) => serde::de::Unexpected::Unsigned(n as u64),
+            Value::Number(Number::U16(n)) => serde::de::Unexpected::Unsigned(n as u64),
+            Value::Number(Number::U32(n)) => serde::de::Unexpected::Unsigned(n as u64),
+            Value::Number(Number::U64(n)) => serde::de::Unexpected::Unsigned(n),
+            Value::Number(Number::I8(n)) => serde::de::Unexpected::Signed(n as i64),
+            Value::Number(Number::I16(n)) => serde::de::Unexpected::Signed(n as i64),
+            Value::Number(Number::I32(n)) => serde::de::Unexpected::Signed(n as i64),
+            Value::Number(Number::I64(n)) => serde::de::Unexpected::Signed(n),
+            Value::Number(Number::F32(n)) => serde::de::Unexpected::Float(n as f64),
+            Value::Number(Number::F64(n)) => serde::de::Unexpected::Float(n),
+            #[cfg(feature = "half")]
+            Value::F16(n) => serde::de::Unexpected::Float(n.to_f64()),
             Value::Char(c) => serde::de::Unexpected::Char(c),
             Value::String(ref s) => serde::de::Unexpected::Str(s),
-            Value::Unit => serde::de::Unexpected::Unit,
+            Value::Unit | Value::UnitStruct(_) => serde::de::Unexpected::Unit,
             Value::Option(_) => serde::de::Unexpected::Option,
             Value::Newtype(_) => serde::de::Unexpected::NewtypeStruct,
-            Value::Seq(_) => serde::de::Unexpected::Seq,
+            Value::Seq(_) | Value::Tuple(_) => serde::de::Unexpected::Seq,
             Value::Map(_) => serde::de::Unexpected::Map,
+            Value::Set(_) => serde::de::Unexpected::Seq,
             Value::Bytes(ref b) => serde::de::Unexpected::Bytes(b),
+            Value::Ext { ref value, .. } => value.unexpected(),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { .. } => serde::de::Unexpected::Other("compressed bytes"),
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => serde::de::Unexpected::Bytes(v),
         }
     }
 
     pub fn deserialize_into<'de, T: Deserialize<'de>>(self) -> Result<T, DeserializerError> {
-        T::deserialize(self)
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "deserialize_into",
+            ty = std::any::type_name::<T>(),
+            nodes = instrument::nodes(&self)
+        )
+        .entered();
+        let result = T::deserialize(self);
+        #[cfg(feature = "tracing")]
+        {
+            if let Err(ref e) = result {
+                tracing::debug!(error = %e, "deserialization failed");
+            }
+        }
+        result
     }
 }
 
@@ -479,9 +1266,9 @@ impl PartialOrd for Value {
 fn de_smoke_test() {
     // some convoluted Value
     let value = Value::Option(Some(Box::new(Value::seq(vec![
-        Value::U16(8),
+        Value::Number(Number::U16(8)),
         Value::Char('a'),
-        Value::F32(1.0),
+        Value::Number(Number::F32(1.0)),
         Value::string("hello".into()),
         Value::map(
             vec![
@@ -518,7 +1305,7 @@ fn ser_smoke_test() {
 
     let expected = Value::map(
         vec![
-            (Value::string("a".into()), Value::U32(15)),
+            (Value::string("a".into()), Value::Number(Number::U32(15))),
             (Value::string("b".into()), Value::string("hello".into())),
             (
                 Value::string("c".into()),
@@ -545,7 +1332,7 @@ fn deserialize_into_enum() {
     assert_eq!(Foo::deserialize(value).unwrap(), Foo::Bar);
 
     let value = Value::map(
-        vec![(Value::string("Baz".into()), Value::U8(1))]
+        vec![(Value::string("Baz".into()), Value::Number(Number::U8(1)))]
             .into_iter()
             .collect(),
     );
@@ -591,7 +1378,7 @@ fn deserialize_inside_deserialize_impl() {
                 Value::string("kind".to_owned()),
                 Value::string("ADDED".to_owned()),
             ),
-            (Value::string("object".to_owned()), Value::U32(5)),
+            (Value::string("object".to_owned()), Value::Number(Number::U32(5))),
         ]
         .into_iter()
         .collect(),
@@ -605,7 +1392,7 @@ fn deserialize_inside_deserialize_impl() {
                 Value::string("kind".to_owned()),
                 Value::string("ERROR".to_owned()),
             ),
-            (Value::string("object".to_owned()), Value::U8(5)),
+            (Value::string("object".to_owned()), Value::Number(Number::U8(5))),
         ]
         .into_iter()
         .collect(),
@@ -632,7 +1419,7 @@ fn deserialize_newtype() {
     #[derive(Debug, Deserialize, PartialEq)]
     struct Foo(i32);
 
-    let input = Value::I32(5);
+    let input = Value::Number(Number::I32(5));
     let foo = Foo::deserialize(input).unwrap();
     assert_eq!(foo, Foo(5));
 }
@@ -648,7 +1435,7 @@ fn deserialize_newtype2() {
     }
 
     let input = Value::map(
-        vec![(Value::string("foo".to_owned()), Value::I32(5))]
+        vec![(Value::string("foo".to_owned()), Value::Number(Number::I32(5)))]
             .into_iter()
             .collect(),
     );
@@ -656,64 +1443,3870 @@ fn deserialize_newtype2() {
     assert_eq!(bar, Bar { foo: Foo(5) });
 }
 
-#[cfg(test)]
-mod dedup_tests {
+#[test]
+#[cfg(feature = "dedup")]
+fn ext_pass_through() {
     extern crate serde_json;
 
-    use self::serde_json::json;
-    use super::*;
-
-    #[test]
-    fn dedup_simple() {
-        let input = Value::seq(vec![
-            Value::string("a".to_owned()),
-            Value::string("a".to_owned()),
-        ]);
-        let mut dedup = Dedup::new();
-        let result = dedup.dedup(input);
-        if let Value::Seq(x) = result {
-            if let Value::String(ref a) = x[0] {
-                if let Value::String(ref b) = x[1] {
-                    assert!(Arc::ptr_eq(a, b));
-                } else {
-                    panic!();
-                }
-            } else {
-                panic!();
-            }
-        } else {
-            panic!();
-        }
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Foo {
+        a: u32,
     }
 
-    #[test]
-    fn dedup_record() {
-        let input = json!(
-            [{ "x": 0, "y":0},{ "x": 0, "y":1},{ "x": 1, "y":1},{ "x": 1, "y":0}]
-        );
-        let value: Value = to_value(input).unwrap();
-        let mut dedup = Dedup::new();
-        let result = dedup.dedup(value);
-        println!("{}", dedup);
-        println!("{}", result);
+    let payload = Value::map(
+        vec![(Value::string("a".to_owned()), Value::Number(Number::U32(5)))]
+            .into_iter()
+            .collect(),
+    );
+    let value = Value::Ext {
+        tag: 42,
+        value: Box::new(payload.clone()),
+    };
+    assert_eq!(Foo::deserialize(value.clone()).unwrap(), Foo { a: 5 });
+    assert_eq!(to_value(&value).unwrap(), value);
+    let nested = Value::Ext {
+        tag: 1,
+        value: Box::new(Value::seq(vec![value.clone(), Value::Unit])),
+    };
+    assert_eq!(to_value(&nested).unwrap(), nested);
+    // other formats see the bare value
+    assert_eq!(serde_json::to_string(&value).unwrap(), r#"{"a":5}"#);
+    assert_eq!(serde_json::to_string(&nested).unwrap(), r#"[{"a":5},null]"#);
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(serde_json::from_str::<Foo>(&json).unwrap(), Foo { a: 5 });
 
-        let mut strings: Vec<&str> = dedup.strings.iter().map(|x| x.as_ref().as_ref()).collect();
-        strings.sort();
-        assert_eq!(strings, vec!["x", "y"]);
+    let other = Value::Ext {
+        tag: 43,
+        value: Box::new(payload.clone()),
+    };
+    assert!(value < other);
+    assert_ne!(value, payload);
 
-        if let Value::Seq(x) = result {
-            if let Value::Map(ref a) = x[0] {
-                if let Value::Map(ref b) = x[1] {
-                    assert!(Arc::ptr_eq(&a.as_ref().0, &b.as_ref().0));
-                } else {
-                    panic!();
-                }
-            } else {
-                panic!();
-            }
-        } else {
-            panic!();
-        }
+    let mut dedup = Dedup::new();
+    assert_eq!(dedup.dedup(value.clone()), value);
+}
+
+#[test]
+fn serde_bytes_round_trip() {
+    extern crate serde_bytes;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Foo {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    }
+
+    let foo = Foo {
+        data: b"hello".to_vec(),
+    };
+    let value = to_value(&foo).unwrap();
+    let expected = Value::map(
+        vec![(Value::string("data".to_owned()), Value::bytes(b"hello".to_vec()))]
+            .into_iter()
+            .collect(),
+    );
+    assert_eq!(value, expected);
+    assert_eq!(Foo::deserialize(value).unwrap(), foo);
+
+    // a uniquely owned buffer is moved out instead of copied
+    let bytes = b"world".to_vec();
+    let ptr = bytes.as_ptr();
+    let buf = serde_bytes::ByteBuf::deserialize(Value::bytes(bytes)).unwrap();
+    assert_eq!(buf.as_ptr(), ptr);
+}
+
+#[test]
+fn stringify_keys() {
+    extern crate serde_json;
+
+    let value = Value::map(
+        vec![
+            (Value::Number(Number::U32(1)), Value::Bool(true)),
+            (
+                Value::Char('x'),
+                Value::seq(vec![Value::map(
+                    vec![(Value::Bool(false), Value::Unit)].into_iter().collect(),
+                )]),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let expected = Value::map(
+        vec![
+            (Value::string("1".into()), Value::Bool(true)),
+            (
+                Value::string("x".into()),
+                Value::seq(vec![Value::map(
+                    vec![(Value::string("false".into()), Value::Unit)].into_iter().collect(),
+                )]),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(value.stringify_keys().unwrap(), expected);
+    assert_eq!(
+        serde_json::to_string(&value.with_string_keys()).unwrap(),
+        r#"{"1":true,"x":[{"false":null}]}"#
+    );
+
+    let compound = Value::map(
+        vec![(Value::seq(vec![]), Value::Unit)].into_iter().collect(),
+    );
+    assert!(compound.stringify_keys().is_err());
+    assert!(serde_json::to_string(&compound.with_string_keys()).is_err());
+
+    let colliding = Value::map(
+        vec![
+            (Value::Number(Number::U8(1)), Value::Unit),
+            (Value::string("1".into()), Value::Unit),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert!(colliding.stringify_keys().is_err());
+}
+
+#[test]
+fn canonical_comparison() {
+    assert!(Value::Number(Number::U8(1)).canonical_eq(&Value::Number(Number::I64(1))));
+    assert!(Value::Number(Number::U32(3)).canonical_eq(&Value::Number(Number::F64(3.0))));
+    let nan = Value::Number(Number::F32(f32::NAN));
+    assert!(nan.canonical_eq(&Value::Number(Number::F64(f64::NAN))));
+    assert!(Value::Unit.canonical_eq(&Value::Option(None)));
+    assert!(Value::Char('a').canonical_eq(&Value::string("a".into())));
+    let cmp = |a: Number, b: Number| Value::Number(a).canonical_cmp(&Value::Number(b));
+    assert_eq!(cmp(Number::I8(-1), Number::U64(0)), Ordering::Less);
+    assert_eq!(cmp(Number::F64(1.5), Number::U8(1)), Ordering::Greater);
+    assert_eq!(cmp(Number::I8(-2), Number::F32(-1.5)), Ordering::Less);
+
+    // structural comparison already ignores the width of numbers, but not the rest
+    assert_eq!(Value::Number(Number::U8(1)), Value::Number(Number::I64(1)));
+    assert_ne!(Value::Char('a'), Value::string("a".into()));
+
+    let a = Value::map(
+        vec![
+            (Value::Number(Number::U8(2)), Value::string("x".into())),
+            (Value::Number(Number::U16(1)), Value::Unit),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let b = Value::map(
+        vec![
+            (Value::Number(Number::U64(1)), Value::Option(None)),
+            (Value::Number(Number::I32(2)), Value::Char('x')),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert!(a.canonical_eq(&b));
+    assert!(!a.canonical_eq(&Value::seq(vec![])));
+}
+
+#[test]
+fn number_by_value() {
+    extern crate serde_json;
+    use std::collections::HashSet;
+
+    let a = Value::Number(Number::U8(3)).as_number().unwrap();
+    let b = Value::Number(Number::F64(3.0)).as_number().unwrap();
+    let c = Value::Number(Number::I64(-3)).as_number().unwrap();
+    assert_eq!(a, b);
+    assert!(c < a);
+    assert_eq!(c.as_u64(), None);
+    assert_eq!(c.as_i64(), Some(-3));
+    assert_eq!(Value::from(b), Value::Number(Number::F64(3.0)));
+
+    let set: HashSet<Number> = vec![a, b, Number::U64(3), Number::F32(3.5)].into_iter().collect();
+    assert_eq!(set.len(), 2);
+    assert_eq!(Value::Char('3').as_number(), None);
+
+    // values compare and hash by value too, and keep their width when serialized
+    let values: HashSet<Value> = vec![a.into(), b.into(), c.into()].into_iter().collect();
+    assert_eq!(values.len(), 2);
+    assert_eq!(Value::Number(Number::U8(1)), Value::Number(Number::F32(1.0)));
+    assert!(Value::Number(Number::I8(-1)) < Value::Number(Number::U64(0)));
+    assert_eq!(serde_json::to_string(&Value::Number(b)).unwrap(), "3.0");
+    assert_eq!(serde_json::to_string(&Value::Number(a)).unwrap(), "3");
+}
+
+#[test]
+fn structural_diff() {
+    let expected = Value::map(
+        vec![
+            (
+                Value::string("a".into()),
+                Value::seq(vec![Number::U8(1).into(), Number::U8(2).into()]),
+            ),
+            (Value::string("b/c".into()), Value::Bool(true)),
+            (Value::string("same".into()), Value::Unit),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let actual = Value::map(
+        vec![
+            (
+                Value::string("a".into()),
+                Value::seq(vec![Number::U8(1).into(), Number::U8(3).into(), Number::U8(4).into()]),
+            ),
+            (Value::string("d".into()), Value::Char('x')),
+            (Value::string("same".into()), Value::Unit),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let paths: Vec<String> = diff(&expected, &actual).iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "/a/1: expected 2, found 3",
+            "/a/2: expected nothing, found 4",
+            "/b~1c: expected true, found nothing",
+            "/d: expected nothing, found x",
+        ]
+    );
+    assert_value_eq!(expected, expected.clone());
+
+    assert_value_eq!(Value::Number(Number::U8(1)), Value::Number(Number::U16(1)));
+    let result = std::panic::catch_unwind(|| {
+        assert_value_eq!(Value::Char('a'), Value::string("a".into()))
+    });
+    let message = result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("/: expected Char('a'), found String("), "{}", message);
+}
+
+#[test]
+fn diff_hash_maps() {
+    use std::collections::HashMap;
+
+    let expected: HashMap<String, u32> = (0..16).map(|i| (format!("key{}", i), i)).collect();
+    let mut actual = expected.clone();
+    actual.insert("key3".to_owned(), 30);
+    actual.remove("key7");
+    let (expected, actual) = (to_value(&expected).unwrap(), to_value(&actual).unwrap());
+    let paths: Vec<String> = diff(&expected, &actual).iter().map(|d| d.to_string()).collect();
+    assert_eq!(paths, vec!["/key3: expected 3, found 30", "/key7: expected 7, found nothing"]);
+}
+
+#[test]
+fn approx_eq() {
+    let row = |x: f64, y: f32| {
+        Value::map(
+            vec![
+                (Value::string("x".into()), Value::Number(Number::F64(x))),
+                (
+                    Value::string("y".into()),
+                    Value::seq(vec![Number::F32(y).into(), Number::U8(1).into()]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    };
+    let expected = row(1000.0, 0.5);
+    assert!(expected.approx_eq(&row(1000.0 + 1e-7, 0.5), 1e-9));
+    assert!(expected.approx_eq(&row(1000.001, 0.5000001), 1e-6));
+    assert!(!expected.approx_eq(&row(1000.01, 0.5), 1e-6));
+    assert!(!expected.approx_eq(&row(1000.0, 0.6), 1e-6));
+    // absolute tolerance near zero
+    assert!(Value::Number(Number::F64(0.0)).approx_eq(&Value::Number(Number::F64(1e-12)), 1e-9));
+    let nan = Value::Number(Number::F64(f64::NAN));
+    assert!(nan.approx_eq(&nan.clone(), 0.0));
+    assert!(Value::Number(Number::F64(1.05)).approx_eq(&Value::Number(Number::F32(1.0)), 0.1));
+    assert!(Value::Number(Number::F32(1.05)).approx_eq(&Value::Number(Number::U8(1)), 0.1));
+    assert!(!Value::Number(Number::U8(1)).approx_eq(&Value::Number(Number::U8(2)), 10.0));
+}
+
+#[test]
+fn map_from_sorted() {
+    let pairs: Vec<(Value, Value)> =
+        (0..10u8).map(|i| (Value::string(format!("k{}", i)), Number::U8(i).into())).collect();
+    let value = Value::map_from_sorted_iter(pairs.clone());
+    assert_eq!(value, Value::map(pairs.iter().cloned().collect()));
+    assert_eq!(value.get("k3"), Some(&Value::Number(Number::U8(3))));
+
+    let (keys, values) = pairs.into_iter().unzip();
+    let kv = KV::from_sorted_vecs(keys, values);
+    assert_eq!(Value::Map(Arc::new(kv)), value);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "keys are not sorted and unique")]
+fn map_from_unsorted() {
+    let key = |s: &str| Value::string(s.to_owned());
+    Value::map_from_sorted_iter(vec![(key("b"), Value::Unit), (key("a"), Value::Unit)]);
+}
+
+#[test]
+fn seq_slices() {
+    let seq = Value::seq((0..100u8).map(Number::U8).map(Value::Number).collect());
+    let page = seq.slice(10..20).unwrap();
+    assert_eq!(page.len(), 10);
+    assert_eq!(page[0], Value::Number(Number::U8(10)));
+    assert_eq!(page.offset(), 10);
+    match seq {
+        Value::Seq(ref v) => assert!(Arc::ptr_eq(v, page.shared())),
+        _ => unreachable!(),
+    }
+
+    let inner = page.slice(2..=3).unwrap();
+    assert_eq!(&*inner, &[Value::Number(Number::U8(12)), Value::Number(Number::U8(13))][..]);
+    let expected = vec![Value::Number(Number::U8(12)), Value::Number(Number::U8(13))];
+    assert_eq!(inner.to_value(), Value::seq(expected));
+    assert_eq!(to_value(&inner).unwrap(), inner.to_value());
+    assert!(page.slice(5..11).is_none());
+    assert!(seq.slice(..=100).is_none());
+    assert!(Value::Number(Number::U8(1)).slice(..).is_none());
+
+    let all = seq.slice(..).unwrap();
+    match (all.to_value(), &seq) {
+        (Value::Seq(a), Value::Seq(b)) => assert!(Arc::ptr_eq(&a, b)),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn chunked_seq() {
+    let corpus = Value::seq((0..10u8).map(Number::U8).map(Value::Number).collect());
+    let mut seq = ChunkedSeq::from(corpus.slice(..).unwrap());
+    seq.push(Value::Number(Number::U8(10)));
+    // the corpus is shared, so the push started a new chunk
+    assert_eq!(seq.chunks().len(), 2);
+    seq.push(Value::Number(Number::U8(11)));
+    assert_eq!(seq.chunks().len(), 2);
+    assert_eq!(seq.len(), 12);
+
+    let mut both = seq.clone();
+    both.append(&seq);
+    assert_eq!(both.len(), 24);
+    assert_eq!(both.chunks().len(), 4);
+    assert_eq!(both.get(11), Some(&Value::Number(Number::U8(11))));
+    assert_eq!(both.get(12), Some(&Value::Number(Number::U8(0))));
+    assert_eq!(both.get(23), Some(&Value::Number(Number::U8(11))));
+    assert_eq!(both.get(24), None);
+    match (&both.chunks()[0], &corpus) {
+        (chunk, Value::Seq(v)) => assert!(Arc::ptr_eq(chunk.shared(), v)),
+        _ => unreachable!(),
+    }
+
+    let expected: Vec<Value> = (0..12u8).chain(0..12).map(Number::U8).map(Value::Number).collect();
+    assert_eq!(both.to_value(), Value::seq(expected));
+    assert_eq!(to_value(&both).unwrap(), both.to_value());
+    assert_eq!(ChunkedSeq::from(corpus.slice(..).unwrap()).to_value(), corpus);
+    assert!(ChunkedSeq::new().is_empty());
+}
+
+#[test]
+#[cfg(feature = "dedup")]
+fn value_builder() {
+    let key = |s: &str| Value::string(s.to_owned());
+    let mut builder = ValueBuilder::new(Dedup::new());
+    let record = |b: &mut ValueBuilder<Dedup>, id: u64| -> Result<Value, BuildError> {
+        b.start_map()?;
+        b.key(key("tags"))?;
+        b.start_seq()?;
+        b.value(key("a"))?;
+        b.value(key("b"))?;
+        b.end_seq()?;
+        b.key(key("id"))?;
+        b.value(Value::Number(Number::U64(0)))?;
+        b.key(key("id"))?;
+        b.value(Value::Number(Number::U64(id)))?;
+        b.end_map()?;
+        b.finish()
+    };
+    let first = record(&mut builder, 1).unwrap();
+    let second = record(&mut builder, 2).unwrap();
+    assert_eq!(first.get("id"), Some(&Value::Number(Number::U64(1))));
+    assert_eq!(first.get("tags"), Some(&Value::seq(vec![key("a"), key("b")])));
+    match (first.get("tags"), second.get("tags")) {
+        (Some(Value::Seq(a)), Some(Value::Seq(b))) => assert!(Arc::ptr_eq(a, b)),
+        _ => panic!("expected sequences"),
+    }
+    let same = Value::map(
+        vec![
+            (key("id"), Value::Number(Number::U64(2))),
+            (key("tags"), first.get("tags").unwrap().clone()),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(second, same);
+
+    // deep nesting without recursion
+    for _ in 0..1000 {
+        builder.start_seq().unwrap();
+    }
+    assert_eq!(builder.depth(), 1000);
+    assert_eq!(builder.finish(), Err(BuildError::Incomplete));
+    for _ in 0..1000 {
+        builder.end_seq().unwrap();
+    }
+    assert!(builder.finish().is_ok());
+
+    assert_eq!(builder.end_map(), Err(BuildError::Unexpected("end_map")));
+    builder.start_map().unwrap();
+    assert_eq!(builder.value(Value::Unit), Err(BuildError::Unexpected("value")));
+    builder.key(key("k")).unwrap();
+    assert_eq!(builder.end_map(), Err(BuildError::Unexpected("end_map")));
+    builder.reset();
+    builder.value(Value::Unit).unwrap();
+    assert_eq!(builder.value(Value::Unit), Err(BuildError::Unexpected("value")));
+}
+
+#[test]
+fn snapshot_with_redaction() {
+    use testing::{to_snapshot, Redactions};
+
+    let record = |id: u64, name: &str| {
+        Value::map(
+            vec![
+                (Value::string("id".into()), Value::Number(Number::U64(id))),
+                (Value::string("name".into()), Value::string(name.into())),
+                (Value::string("tags".into()), Value::seq(vec![Value::Char('x')])),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    };
+    let value = Value::map(
+        vec![
+            (Value::string("created".into()), Value::Number(Number::I64(1_571_234_567))),
+            (Value::string("items".into()), Value::seq(vec![record(17, "a"), record(23, "b")])),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let redactions = Redactions::new().redact("/created").redact("/items/*/id");
+    assert_value_snapshot!("redacted_record", value, redactions);
+
+    let other = Value::map(
+        vec![
+            (Value::string("created".into()), Value::Number(Number::I64(0))),
+            (Value::string("items".into()), Value::seq(vec![record(1, "a"), record(2, "b")])),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(
+        to_snapshot(&redactions.apply(&value)),
+        to_snapshot(&redactions.apply(&other))
+    );
+    // equal, but the snapshot keeps the width
+    let (a, b) = (Value::Number(Number::U8(1)), Value::Number(Number::U16(1)));
+    assert_ne!(to_snapshot(&a), to_snapshot(&b));
+
+    // a missing snapshot fails instead of being written, unless updating
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_none() {
+        let missing = std::env::temp_dir().join("serde-value-missing.snap");
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            testing::assert_snapshot(&missing, &value, &redactions)
+        }));
+        assert!(result.is_err());
+        assert!(!missing.exists());
+    }
+}
+
+#[test]
+fn struct_field_names_are_shared() {
+    #[derive(Serialize)]
+    struct Foo {
+        a: u32,
+    }
+
+    let keys = |value: Value| match value {
+        Value::Map(kv) => match kv.0[0] {
+            Value::String(ref s) => s.clone(),
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+    let a = keys(to_value(Foo { a: 1 }).unwrap());
+    let b = keys(to_value(Foo { a: 2 }).unwrap());
+    assert!(Arc::ptr_eq(&a, &b));
+}
+
+#[test]
+fn struct_key_vectors_are_shared() {
+    #[derive(Serialize)]
+    struct Foo {
+        b: u32,
+        a: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        c: Option<u32>,
+    }
+
+    let keys = |value: Value| match value {
+        Value::Map(kv) => kv.0.clone(),
+        _ => panic!(),
+    };
+    let a = keys(to_value(Foo { b: 1, a: 2, c: None }).unwrap());
+    let b = keys(to_value(Foo { b: 3, a: 4, c: None }).unwrap());
+    let c = keys(to_value(Foo { b: 3, a: 4, c: Some(5) }).unwrap());
+    assert!(Arc::ptr_eq(&a, &b));
+    assert!(!Arc::ptr_eq(&a, &c));
+    assert_eq!(*a, vec![Value::string("a".into()), Value::string("b".into())]);
+    assert_eq!(c.len(), 3);
+
+    let value = to_value(Foo { b: 1, a: 2, c: None }).unwrap();
+    let expected = Value::map(
+        vec![
+            (Value::string("a".into()), Value::Number(Number::U32(2))),
+            (Value::string("b".into()), Value::Number(Number::U32(1))),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(value, expected);
+}
+
+#[test]
+fn shared_fields_keep_arcs() {
+    extern crate serde_json;
+
+    #[derive(Deserialize, Serialize)]
+    struct Event {
+        #[serde(with = "shared::arc_str")]
+        host: Arc<str>,
+        #[serde(with = "shared::arc_bytes")]
+        payload: Arc<Vec<u8>>,
+    }
+
+    let host: Arc<str> = Arc::from("example.org");
+    let payload = Arc::new(vec![1u8, 2, 3]);
+    let value = Value::map(
+        vec![
+            (Value::string("host".into()), Value::String(host.clone())),
+            (Value::string("payload".into()), Value::Bytes(payload.clone())),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let event: Event = value.clone().deserialize_into().unwrap();
+    assert!(Arc::ptr_eq(&event.host, &host));
+    assert!(Arc::ptr_eq(&event.payload, &payload));
+    assert_eq!(to_value(&event).unwrap(), value);
+
+    // other formats still work, they just can't share
+    let json = serde_json::to_string(&event).unwrap();
+    let event: Event = serde_json::from_str(&json).unwrap();
+    assert_eq!(&*event.host, "example.org");
+    assert_eq!(*event.payload, vec![1, 2, 3]);
+}
+
+#[test]
+fn enum_representations() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Inner {
+        x: i8,
+        n: Option<u64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum External {
+        Unit,
+        New(u32),
+        Tuple(u8, String),
+        Struct { a: Option<u16>, b: Vec<u8> },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(tag = "type")]
+    enum Internal {
+        Unit,
+        New(Inner),
+        Struct { a: Option<u16>, c: char },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(tag = "t", content = "c")]
+    enum Adjacent {
+        Unit,
+        New(u32),
+        Tuple(u8, String),
+        Struct { a: Option<u16>, b: Vec<u8> },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(untagged)]
+    enum Untagged {
+        Unit,
+        Tuple(u8, String),
+        Struct { a: Option<u16>, b: Vec<u8> },
+        Inner(Inner),
+        Text(String),
+    }
+
+    fn round_trip<T>(value: T)
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let v = to_value(&value).unwrap();
+        assert_eq!(v.deserialize_into::<T>().unwrap(), value);
+    }
+
+    let s = |x: &str| Value::string(x.to_owned());
+    assert_eq!(to_value(External::Unit).unwrap(), s("Unit"));
+    assert_eq!(
+        to_value(External::New(1)).unwrap(),
+        Value::map(vec![(s("New"), Value::Number(Number::U32(1)))].into_iter().collect())
+    );
+    round_trip(vec![
+        External::Unit,
+        External::New(1),
+        External::Tuple(2, "x".into()),
+        External::Struct { a: Some(3), b: vec![4] },
+    ]);
+    round_trip(vec![
+        Internal::Unit,
+        Internal::New(Inner { x: -1, n: Some(7) }),
+        Internal::Struct { a: None, c: 'z' },
+    ]);
+    round_trip(vec![
+        Adjacent::Unit,
+        Adjacent::New(1),
+        Adjacent::Tuple(2, "x".into()),
+        Adjacent::Struct { a: Some(3), b: vec![] },
+    ]);
+    round_trip(vec![
+        Untagged::Unit,
+        Untagged::Tuple(2, "x".into()),
+        Untagged::Struct { a: Some(3), b: vec![4] },
+        Untagged::Inner(Inner { x: -1, n: None }),
+        Untagged::Text("hi".into()),
+    ]);
+}
+
+#[test]
+fn deserialize_shared_and_owned_containers() {
+    let map = Value::map(
+        vec![
+            (
+                Value::string("a".into()),
+                Value::seq(vec![Number::U8(1).into(), Number::U8(2).into()]),
+            ),
+            (Value::string("b".into()), Value::seq(vec![])),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let expected: BTreeMap<String, Vec<u8>> =
+        vec![("a".into(), vec![1, 2]), ("b".into(), vec![])].into_iter().collect();
+    // shared with `map`, so the elements are cloned one at a time
+    let shared = map.clone();
+    assert_eq!(shared.deserialize_into::<BTreeMap<String, Vec<u8>>>().unwrap(), expected);
+    // the last reference, so the elements are moved out
+    assert_eq!(map.deserialize_into::<BTreeMap<String, Vec<u8>>>().unwrap(), expected);
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum E {
+        Pair(u8, u8),
+    }
+    let pair = |items: Vec<Value>| {
+        Value::map(vec![(Value::string("Pair".into()), Value::seq(items))].into_iter().collect())
+    };
+    let ok = pair(vec![Value::Number(Number::U8(1)), Value::Number(Number::U8(2))]);
+    assert_eq!(ok.deserialize_into::<E>().unwrap(), E::Pair(1, 2));
+    let long = pair(vec![Number::U8(1).into(), Number::U8(2).into(), Number::U8(3).into()]);
+    assert!(long.deserialize_into::<E>().is_err());
+}
+
+#[test]
+fn deserialize_size_hints() {
+    let n = 1000;
+    let value = Value::seq((0..n).map(Number::U32).map(Value::Number).collect());
+    // a vector grown by pushing would end up with capacity 1024
+    let v: Vec<u32> = value.deserialize_into().unwrap();
+    assert_eq!(v.capacity(), n as usize);
+}
+
+#[test]
+fn ignore_unknown_fields() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Id {
+        id: u32,
+    }
+
+    let big = Value::seq((0..1000).map(|i| Value::string(i.to_string())).collect());
+    let record = Value::map(
+        vec![
+            (Value::string("id".into()), Value::Number(Number::U32(7))),
+            (Value::string("payload".into()), big.clone()),
+            (Value::string("meta".into()), Value::Option(Some(Box::new(big.clone())))),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(record.deserialize_into::<Id>().unwrap(), Id { id: 7 });
+    // nothing of the skipped subtrees is kept alive
+    match big {
+        Value::Seq(ref v) => assert_eq!(Arc::strong_count(v), 1),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn deserialize_sub_path() {
+    #[derive(Serialize)]
+    struct Doc {
+        results: Vec<Entry>,
+    }
+    #[derive(Serialize)]
+    struct Entry {
+        user: Option<User>,
+    }
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct User {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    let user = User {
+        name: "ann".into(),
+        tags: vec!["a/b".into()],
+    };
+    let doc = to_value(Doc {
+        results: vec![Entry { user: None }, Entry { user: Some(user.clone()) }],
+    })
+    .unwrap();
+    assert_eq!(from_value_at::<User>(&doc, "/results/1/user").unwrap(), user);
+    assert_eq!(from_value_at::<String>(&doc, "/results/1/user/tags/0").unwrap(), "a/b");
+    assert_eq!(from_value_at::<Option<User>>(&doc, "/results/0/user").unwrap(), None);
+    for missing in &["/results/2/user", "/results/x", "/results/1/user/name/0", "results"] {
+        match from_value_at::<Value>(&doc, missing) {
+            Err(DeserializerError::MissingPath(ref p)) => assert_eq!(p, missing),
+            other => panic!("{:?}", other),
+        }
+    }
+}
+
+#[test]
+fn update_at_path() {
+    let s = |x: &str| Value::string(x.to_owned());
+    let users = Value::seq(vec![s("ann"), s("bob")]);
+    let config = Value::map(vec![(s("mode"), s("fast"))].into_iter().collect());
+    let doc = Value::map(
+        vec![
+            (s("users"), users.clone()),
+            (s("config"), Value::Option(Some(Box::new(config)))),
+        ]
+        .into_iter()
+        .collect(),
+    );
+
+    let updated = doc.update("/config/mode", |_| s("slow")).unwrap();
+    assert_eq!(from_value_at::<String>(&updated, "/config/mode").unwrap(), "slow");
+    assert_eq!(from_value_at::<String>(&doc, "/config/mode").unwrap(), "fast");
+    // untouched subtrees are shared with the original
+    match (&updated, &users) {
+        (Value::Map(kv), Value::Seq(users)) => match kv.1[1] {
+            Value::Seq(ref v) => assert!(Arc::ptr_eq(v, users)),
+            _ => panic!(),
+        },
+        _ => panic!(),
+    }
+
+    let updated = doc.update("/config", |_| Value::Option(None)).unwrap();
+    assert_eq!(from_value_at::<Option<String>>(&updated, "/config").unwrap(), None);
+    let updated = doc.update("", |_| Value::Unit).unwrap();
+    assert_eq!(updated, Value::Unit);
+    assert_eq!(
+        doc.update("/users/2", |v| v).unwrap_err(),
+        PathError {
+            pointer: "/users/2".into()
+        }
+    );
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn compressed_bytes() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Record {
+        id: u32,
+        #[serde(with = "serde_bytes")]
+        payload: Vec<u8>,
+    }
+
+    let record = Record {
+        id: 1,
+        payload: b"abc".repeat(1000),
+    };
+    let value = to_value(&record).unwrap();
+    for &codec in &[Codec::Lz4, Codec::Zstd] {
+        let compressed = value.compress_bytes(codec, 100);
+        match path::lookup(&compressed, &["payload".to_owned()]) {
+            Some(Value::CompressedBytes { data, .. }) => assert!(data.len() < 3000),
+            _ => panic!("payload not compressed"),
+        }
+        assert_ne!(compressed, value);
+        assert!(compressed.canonical_eq(&value));
+        assert_eq!(to_value(&compressed).unwrap(), value);
+        assert_eq!(compressed.decompress_bytes().unwrap(), value);
+        assert_eq!(compressed.deserialize_into::<Record>().unwrap(), record);
+    }
+    // equality goes by the representation, `canonical_eq` by the contents
+    let lz4 = value.compress_bytes(Codec::Lz4, 100);
+    let zstd = value.compress_bytes(Codec::Zstd, 100);
+    assert_ne!(lz4, zstd);
+    assert!(lz4.canonical_eq(&zstd));
+    // small blobs are left alone
+    assert_eq!(value.compress_bytes(Codec::Lz4, 10_000), value);
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn mapped_bytes() {
+    use std::io::Write;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Record {
+        id: u32,
+        #[serde(with = "serde_bytes")]
+        payload: Vec<u8>,
+    }
+
+    let path = std::env::temp_dir().join(format!("serde-value-mmap-{}", std::process::id()));
+    std::fs::File::create(&path).unwrap().write_all(b"headerpayload").unwrap();
+    let file = unsafe { MappedBytes::open(&path).unwrap() };
+    let payload = file.slice(6..).unwrap();
+    assert_eq!(&*payload, b"payload");
+    assert!(file.slice(6..14).is_none());
+
+    let mapped = Value::map(
+        vec![
+            (Value::string("id".to_owned()), Value::Number(Number::U32(1))),
+            (Value::string("payload".to_owned()), Value::MappedBytes(payload.clone())),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let record = Record {
+        id: 1,
+        payload: b"payload".to_vec(),
+    };
+    let value = to_value(&record).unwrap();
+    assert_ne!(mapped, value);
+    assert!(mapped.canonical_eq(&value));
+    assert_eq!(to_value(&mapped).unwrap(), value);
+    assert_eq!(mapped.clone().deserialize_into::<Record>().unwrap(), record);
+
+    // equal by content, but never interned
+    let copy = Value::MappedBytes(file.slice(6..).unwrap());
+    assert_eq!(copy, Value::MappedBytes(payload));
+    #[cfg(feature = "dedup")]
+    assert_eq!(Dedup::new().dedup(mapped.clone()), mapped);
+    drop((file, copy, mapped));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "erased-serde")]
+fn erased_values() {
+    #[derive(Serialize)]
+    struct Plugin {
+        name: &'static str,
+        version: u32,
+    }
+
+    let value = Value::seq(vec![Value::Number(Number::U8(1)), Value::string("x".to_owned())]);
+    let boxed: Vec<Box<dyn erased_serde::Serialize>> = vec![
+        Box::new(Plugin {
+            name: "a",
+            version: 2,
+        }),
+        Box::new(value.clone()),
+        Box::new(vec![1u16, 2]),
+    ];
+    let values = boxed.iter().map(|x| to_value_dyn(&**x).unwrap()).collect::<Vec<_>>();
+    assert_eq!(
+        values[0],
+        to_value(Plugin {
+            name: "a",
+            version: 2
+        })
+        .unwrap()
+    );
+    assert_eq!(values[1], value);
+    let expected = vec![Value::Number(Number::U16(1)), Value::Number(Number::U16(2))];
+    assert_eq!(values[2], Value::seq(expected));
+}
+
+#[test]
+#[cfg(feature = "dedup")]
+fn shared_handles() {
+    let s = Value::string("s".to_owned());
+    let b = Value::bytes(vec![1]);
+    let map = Value::map(vec![(s.clone(), b.clone())].into_iter().collect());
+    let mut dedup = Dedup::new();
+    let values = dedup.dedup(Value::seq(vec![map.clone(), map]));
+    let (first, second) = (values.pointer("/0").unwrap(), values.pointer("/1").unwrap());
+    assert!(Arc::ptr_eq(first.as_shared_map().unwrap(), second.as_shared_map().unwrap()));
+    let key = &first.as_shared_map().unwrap().keys()[0];
+    assert_eq!(&**key.as_shared_str().unwrap(), "s");
+    assert_eq!(first.get("s").unwrap().as_shared_bytes(), Some(&Arc::new(vec![1])));
+    assert!(s.as_shared_bytes().is_none() && b.as_shared_str().is_none());
+}
+
+#[test]
+#[cfg(feature = "dedup")]
+fn weak_values() {
+    let mut dedup = Dedup::new();
+    let value = dedup.dedup(Value::seq(vec![Value::string("cached".to_owned())]));
+    let weak = value.downgrade().unwrap();
+    assert!(weak.ptr_eq(&value));
+    assert!(!weak.ptr_eq(&Value::seq(vec![Value::string("cached".to_owned())])));
+    assert_eq!(weak.upgrade(), Some(value.clone()));
+    assert_eq!(weak.strong_count(), 2);
+
+    // the table keeps it alive after the value is gone, until the table goes too
+    drop(value);
+    assert!(weak.upgrade().is_some());
+    drop(dedup);
+    assert_eq!(weak.strong_count(), 0);
+    assert_eq!(weak.upgrade(), None);
+    assert!(Value::Number(Number::U8(1)).downgrade().is_none());
+}
+
+#[test]
+fn content_hashes() {
+    let doc = |n: u32, last: &str| {
+        let mut items: Vec<Value> = (0..n).map(|i| Value::string(format!("item {}", i))).collect();
+        items.push(Value::string(last.to_owned()));
+        Value::map(vec![(Value::string("items".into()), Value::seq(items))].into_iter().collect())
+    };
+    let (a, b, c) = (doc(100, "x"), doc(100, "x"), doc(100, "y"));
+    let mut hashes = ContentHashes::new();
+    assert!(hashes.eq(&a, &b));
+    assert!(!hashes.eq(&a, &c));
+    assert_eq!(hashes.content_hash(&a), hashes.content_hash(&b));
+    // shared nodes are hashed once, by address
+    let memoized = hashes.len();
+    assert!(hashes.eq(&a, &a.clone()));
+    assert_eq!(hashes.len(), memoized);
+    let float = |x: f64| Value::seq(vec![Value::Number(Number::F64(x))]);
+    assert!(hashes.eq(&float(f64::NAN), &float(f64::NAN)));
+    assert!(!hashes.eq(&Value::Option(None), &Value::Option(Some(Box::new(Value::Unit)))));
+    assert!(!hashes.eq(&Value::seq(vec![]), &Value::Tuple(Arc::new(vec![]))));
+
+    let mut trusting = ContentHashes::new().trust_hashes();
+    assert!(trusting.eq(&a, &b) && !trusting.eq(&b, &c));
+    hashes.clear();
+    assert!(hashes.is_empty());
+}
+
+#[test]
+#[cfg(feature = "dedup")]
+fn dedup_service() {
+    extern crate futures;
+
+    let service = DedupService::spawn(Dedup::new(), 2);
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let handle = service.handle();
+            std::thread::spawn(move || {
+                (0..20)
+                    .map(|i| handle.intern(Value::string(format!("key {}", i % 5))).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let results: Vec<Vec<Value>> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    for values in &results[1..] {
+        for (a, b) in values.iter().zip(&results[0]) {
+            assert!(Arc::ptr_eq(a.as_shared_str().unwrap(), b.as_shared_str().unwrap()));
+        }
+    }
+
+    let handle = service.handle();
+    let value = futures::executor::block_on(handle.intern_async(Value::string("key 1".into())));
+    assert!(Arc::ptr_eq(
+        value.unwrap().as_shared_str().unwrap(),
+        results[0][1].as_shared_str().unwrap()
+    ));
+
+    let dedup = service.shutdown();
+    assert_eq!(dedup.iter_strings().count(), 5);
+    assert_eq!(handle.intern(Value::Unit), Err(ServiceStopped));
+    // as a `Deduplicator`, a stopped service leaves values as they are
+    assert_eq!(handle.clone().dedup(Value::string("new".into())), Value::string("new".into()));
+    let late = futures::executor::block_on(handle.intern_async(Value::Unit));
+    assert_eq!(late, Err(ServiceStopped));
+}
+
+#[test]
+#[cfg(feature = "dedup")]
+fn shared_deduplicators() {
+    fn intern_on_threads<S: SharedDeduplicator>(shared: &S) -> Vec<Value> {
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let mut handle = shared.handle();
+                let value = to_value(vec![format!("key {}", i % 2), "common".to_owned()]).unwrap();
+                std::thread::spawn(move || handle.dedup(value))
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    }
+
+    let locked = Arc::new(Mutex::new(Dedup::new()));
+    let service = DedupService::spawn(Dedup::new(), 4);
+    for values in [intern_on_threads(&locked), intern_on_threads(&service)] {
+        assert_eq!(values[0], values[2]);
+        match (&values[0], &values[2]) {
+            (Value::Seq(a), Value::Seq(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("not sequences"),
+        }
+    }
+    assert_eq!(locked.lock().unwrap().iter_strings().count(), 3);
+    assert_eq!(service.shutdown().iter_strings().count(), 3);
+}
+
+#[test]
+fn try_into_containers() {
+    let seq = Value::seq(vec![Value::Number(Number::U8(1))]);
+    let shared = seq.as_shared_seq().unwrap();
+    assert!(Arc::ptr_eq(&seq.clone().try_into_seq().unwrap(), &shared));
+    assert_eq!(seq.clone().try_into_map(), Err(seq.clone()));
+
+    let map = Value::map(vec![(Value::Number(Number::U8(1)), Value::Unit)].into_iter().collect());
+    assert_eq!(map.clone().try_into_map().unwrap().keys(), &[Value::Number(Number::U8(1))][..]);
+    assert_eq!(map.clone().try_into_seq(), Err(map));
+
+    let s = Value::string("s".to_owned());
+    assert_eq!(&*s.clone().try_into_string().unwrap(), "s");
+    assert_eq!(s.clone().try_into_bytes(), Err(s));
+    let b = Value::bytes(vec![1]);
+    assert_eq!(*b.clone().try_into_bytes().unwrap(), vec![1]);
+    assert_eq!(b.clone().try_into_string(), Err(b));
+}
+
+#[test]
+fn share_with_previous_revision() {
+    let s = |x: &str| Value::string(x.to_owned());
+    let doc = |mode: &str, extra: bool| {
+        let mut items = vec![s("a"), s("b")];
+        if extra {
+            items.push(s("c"));
+        }
+        Value::map(
+            vec![
+                (s("mode"), s(mode)),
+                (s("items"), Value::seq(items)),
+                (
+                    s("limits"),
+                    Value::map(vec![(s("max"), Number::U32(10).into())].into_iter().collect()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    };
+    let get = |v: &Value, key: &str| path::lookup(v, &[key.to_owned()]).unwrap().clone();
+    let ptr_eq = |a: &Value, b: &Value| match (a, b) {
+        (Value::String(a), Value::String(b)) => Arc::ptr_eq(a, b),
+        (Value::Seq(a), Value::Seq(b)) => Arc::ptr_eq(a, b),
+        (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
+        _ => false,
+    };
+
+    let old = doc("fast", false);
+    // an equal document collapses into the old one
+    assert!(ptr_eq(&doc("fast", false).share_with(&old), &old));
+
+    let new = doc("slow", false).share_with(&old);
+    assert_eq!(new, doc("slow", false));
+    assert!(!ptr_eq(&new, &old));
+    assert!(ptr_eq(&get(&new, "items"), &get(&old, "items")));
+    assert!(ptr_eq(&get(&new, "limits"), &get(&old, "limits")));
+    match (&new, &old) {
+        (Value::Map(a), Value::Map(b)) => assert!(Arc::ptr_eq(&a.0, &b.0)),
+        _ => unreachable!(),
+    }
+
+    // appended elements are new, the existing ones are shared
+    let new = doc("fast", true).share_with(&old);
+    let (items, old_items) = (get(&new, "items"), get(&old, "items"));
+    assert!(!ptr_eq(&items, &old_items));
+    match (&items, &old_items) {
+        (Value::Seq(a), Value::Seq(b)) => assert!(ptr_eq(&a[1], &b[1])),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn versioned_value() {
+    use history::VersionedValue;
+
+    let s = |x: &str| Value::string(x.to_owned());
+    let config = |mode: &str| {
+        Value::map(
+            vec![
+                (s("mode"), s(mode)),
+                (s("hosts"), Value::seq(vec![s("a"), s("b")])),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    };
+
+    let mut history = VersionedValue::new(config("fast"), 3);
+    assert_eq!(history.push(config("slow")), 1);
+    assert_eq!(history.push(config("off")), 2);
+    let hosts = |v: &Value| match path::lookup(v, &["hosts".to_owned()]) {
+        Some(Value::Seq(v)) => v.clone(),
+        _ => panic!(),
+    };
+    assert!(Arc::ptr_eq(&hosts(history.get(0).unwrap()), &hosts(history.current())));
+
+    let changes = history.diff(0, 2).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].path, "/mode");
+
+    // the oldest revision is dropped beyond the capacity
+    assert_eq!(history.push(config("auto")), 3);
+    assert_eq!(history.oldest_version(), 1);
+    assert!(history.get(0).is_none());
+    assert!(history.diff(0, 3).is_none());
+
+    assert!(history.rollback(1));
+    assert_eq!(history.version(), 1);
+    assert_eq!(*history.current(), config("slow"));
+    assert!(!history.rollback(3));
+    assert_eq!(history.iter().map(|(v, _)| v).collect::<Vec<_>>(), vec![1]);
+}
+
+#[test]
+fn number_policy() {
+    use serde::Deserialize;
+
+    fn strict<'de, T: Deserialize<'de>>(v: Value) -> Result<T, DeserializerError> {
+        T::deserialize(v)
+    }
+    fn saturating<'de, T: Deserialize<'de>>(v: Value) -> Result<T, DeserializerError> {
+        T::deserialize(ValueDeserializer::new(v).with_number_policy(NumberPolicy::Saturating))
+    }
+
+    assert_eq!(strict::<u8>(Value::Number(Number::U64(200))).unwrap(), 200);
+    assert!(strict::<u8>(Value::Number(Number::U64(300))).is_err());
+    assert!(strict::<u32>(Value::Number(Number::I8(-1))).is_err());
+    assert!(strict::<i64>(Value::Number(Number::F64(1.5))).is_err());
+    assert_eq!(strict::<i64>(Value::Number(Number::F64(-3.0))).unwrap(), -3);
+    assert!(strict::<i64>(Value::Number(Number::F64(9.3e18))).is_err());
+    assert!(strict::<f32>(Value::Number(Number::F64(1e300))).is_err());
+    assert_eq!(strict::<f32>(Value::Number(Number::F64(0.1))).unwrap(), 0.1f32);
+    assert_eq!(strict::<f64>(Value::Number(Number::U64(u64::MAX))).unwrap(), u64::MAX as f64);
+    assert!(strict::<u8>(Value::string("1".to_owned())).is_err());
+
+    assert_eq!(saturating::<u8>(Value::Number(Number::U64(300))).unwrap(), 255);
+    assert_eq!(saturating::<u32>(Value::Number(Number::I8(-1))).unwrap(), 0);
+    assert_eq!(saturating::<i8>(Value::Number(Number::F32(-1e10))).unwrap(), -128);
+    assert_eq!(saturating::<i64>(Value::Number(Number::F64(1.9))).unwrap(), 1);
+    assert!(saturating::<f32>(Value::Number(Number::F64(1e300))).unwrap().is_infinite());
+
+    // the policy applies to nested values too
+    let v = Value::seq(vec![Value::Number(Number::U16(1)), Value::Number(Number::U16(1000))]);
+    assert!(strict::<Vec<u8>>(v.clone()).is_err());
+    assert_eq!(saturating::<Vec<u8>>(v).unwrap(), vec![1, 255]);
+}
+
+#[test]
+fn unique_and_dedup_adjacent() {
+    let a = Value::string("a".to_owned());
+    let b = Value::string("b".to_owned());
+    let input = Value::seq(vec![
+        a.clone(),
+        a.clone(),
+        b.clone(),
+        Value::Number(Number::F64(f64::NAN)),
+        a.clone(),
+        Value::Number(Number::F64(f64::NAN)),
+    ]);
+
+    let unique = input.unique();
+    let nan = Value::Number(Number::F64(f64::NAN));
+    assert_eq!(unique, Value::seq(vec![a.clone(), b.clone(), nan]));
+    let adjacent = input.dedup_adjacent();
+    assert_eq!(adjacent.as_shared_seq().unwrap().len(), 5);
+    match (&unique, &a) {
+        (Value::Seq(v), Value::String(a)) => match v[0] {
+            Value::String(ref s) => assert!(Arc::ptr_eq(s, a)),
+            _ => panic!("expected a string"),
+        },
+        _ => unreachable!(),
+    }
+
+    // nothing to remove keeps the vector itself
+    let seq = unique.as_shared_seq().unwrap();
+    assert!(Arc::ptr_eq(&seq, &unique.unique().as_shared_seq().unwrap()));
+    assert!(Arc::ptr_eq(&seq, &unique.dedup_adjacent().as_shared_seq().unwrap()));
+    assert_eq!(Value::Number(Number::U8(1)).unique(), Value::Number(Number::U8(1)));
+}
+
+#[test]
+fn tuple_values() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point(i32, i32);
+
+    let value = to_value((1u8, "a")).unwrap();
+    assert_eq!(
+        value,
+        Value::Tuple(Arc::new(vec![Value::Number(Number::U8(1)), Value::string("a".to_owned())]))
+    );
+    assert!(to_value(vec![1u8, 2]).unwrap().as_shared_seq().is_some());
+    assert!(matches!(to_value([1u8, 2]).unwrap(), Value::Tuple(..)));
+
+    let point = to_value(Point(1, 2)).unwrap();
+    assert_eq!(point.to_string(), "(1,2)");
+    assert_eq!(point.clone().deserialize_into::<Point>().unwrap(), Point(1, 2));
+    assert_eq!(point.clone().deserialize_into::<[i32; 2]>().unwrap(), [1, 2]);
+    assert_eq!(point.clone().deserialize_into::<Vec<i32>>().unwrap(), vec![1, 2]);
+    assert!(point.clone().deserialize_into::<(i32, i32, i32)>().is_err());
+    assert!(point.deserialize_into::<(i32,)>().is_err());
+
+    // sequences are still accepted for tuples
+    let seq = to_value(vec![1, 2]).unwrap();
+    assert_eq!(seq.deserialize_into::<(i32, i32)>().unwrap(), (1, 2));
+}
+
+#[test]
+fn unit_struct_names() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Marker;
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Other;
+
+    assert_eq!(to_value(Marker).unwrap(), Value::Unit);
+    assert!(to_value(Marker).unwrap().deserialize_into::<Other>().is_ok());
+
+    let options = ToValueOptions::new().unit_names(true);
+    let value = to_value_with(Marker, options).unwrap();
+    assert_eq!(value, Value::UnitStruct(Arc::from("Marker")));
+    assert_eq!(value.clone().deserialize_into::<Marker>().unwrap(), Marker);
+    assert_eq!(value.clone().deserialize_into::<()>().unwrap(), ());
+    assert!(value.clone().deserialize_into::<Other>().is_err());
+
+    // the name is allocated once and shared
+    let values = to_value_with(vec![Marker, Marker], options).unwrap();
+    match (&values.as_shared_seq().unwrap()[..], value) {
+        ([Value::UnitStruct(a), Value::UnitStruct(b)], Value::UnitStruct(c)) => {
+            assert!(Arc::ptr_eq(a, b) && Arc::ptr_eq(a, &c))
+        }
+        _ => panic!("expected unit structs"),
+    }
+}
+
+#[test]
+fn string_key_lookup() {
+    #[derive(Serialize)]
+    struct Record {
+        b: u8,
+        a: u8,
+        c: u8,
+    }
+
+    let value = to_value(Record { b: 2, a: 1, c: 3 }).unwrap();
+    assert_eq!(value.get("a"), Some(&Value::Number(Number::U8(1))));
+    assert_eq!(value.get("c"), Some(&Value::Number(Number::U8(3))));
+    assert_eq!(value.get("d"), None);
+    assert_eq!(Value::Number(Number::U8(0)).get("a"), None);
+
+    // other keys sort around the string keys
+    let mut map = BTreeMap::new();
+    map.insert(Value::Number(Number::U8(1)), Value::Number(Number::U8(1)));
+    map.insert(Value::string("x".to_owned()), Value::Number(Number::U8(2)));
+    map.insert(Value::Unit, Value::Number(Number::U8(3)));
+    let value = Value::map(map);
+    assert_eq!(value.get("x"), Some(&Value::Number(Number::U8(2))));
+    assert_eq!(value.get("1"), None);
+    assert_eq!(from_value_at::<u8>(&value, "/1").unwrap(), 1);
+}
+
+#[test]
+fn case_insensitive_keys() {
+    let mut headers = BTreeMap::new();
+    headers.insert("Content-Type", "text/plain");
+    headers.insert("content-type", "text/html");
+    headers.insert("X-Request-ID", "Abc");
+    let value = to_value(&headers).unwrap();
+    assert_eq!(
+        value.get_ignore_ascii_case("CONTENT-TYPE"),
+        Some(&Value::string("text/plain".to_owned()))
+    );
+    assert_eq!(value.get_ignore_ascii_case("x-request-id"), value.get("X-Request-ID"));
+    assert_eq!(value.get_ignore_ascii_case("missing"), None);
+
+    let folded = value.fold_keys_ascii_lowercase();
+    let mut expected = BTreeMap::new();
+    expected.insert("content-type", "text/html");
+    expected.insert("x-request-id", "Abc");
+    assert_eq!(folded, to_value(&expected).unwrap());
+
+    // nested maps are folded too, lowercase ones stay shared
+    let nested = Value::seq(vec![folded.clone(), value]);
+    match nested.fold_keys_ascii_lowercase() {
+        Value::Seq(ref v) => {
+            assert_eq!(v[1], folded);
+            match (&v[0], &folded) {
+                (Value::Map(a), Value::Map(b)) => assert!(Arc::ptr_eq(a, b)),
+                _ => panic!("expected maps"),
+            }
+        }
+        _ => panic!("expected a sequence"),
+    }
+}
+
+#[cfg(feature = "half")]
+#[test]
+fn half_floats() {
+    use half::f16;
+
+    let embedding = vec![f16::from_f32(0.5), f16::from_f32(-1.25), f16::NAN];
+    let value = to_value(&embedding).unwrap();
+    assert_eq!(
+        value,
+        Value::seq(vec![
+            Value::F16(f16::from_f32(0.5)),
+            Value::F16(f16::from_f32(-1.25)),
+            Value::F16(f16::NAN),
+        ])
+    );
+    let back: Vec<f16> = value.clone().deserialize_into().unwrap();
+    assert_eq!(back[..2], embedding[..2]);
+    assert!(back[2].is_nan());
+    assert_eq!(value.clone().deserialize_into::<Vec<f32>>().unwrap()[1], -1.25);
+    assert_eq!(testing::to_snapshot(&Value::F16(f16::ONE)), "1.0f16\n");
+    assert!(Value::F16(f16::from_f32(0.5)).canonical_eq(&Value::Number(Number::F64(0.5))));
+
+    // other formats see the same representation as for a plain `f16`
+    assert_eq!(
+        serde_json::to_string(&value).unwrap(),
+        serde_json::to_string(&embedding).unwrap()
+    );
+}
+
+#[cfg(feature = "unicode-normalization")]
+#[test]
+fn normalize_unicode() {
+    let composed = "caf\u{e9}";
+    let decomposed = "cafe\u{301}";
+    let mut input = BTreeMap::new();
+    input.insert(decomposed, vec![composed, decomposed]);
+    input.insert("other", vec!["plain"]);
+    let value = to_value(&input).unwrap();
+
+    let nfc = value.normalize_unicode(Form::NFC);
+    let mut expected = BTreeMap::new();
+    expected.insert(composed, vec![composed, composed]);
+    expected.insert("other", vec!["plain"]);
+    assert_eq!(nfc, to_value(&expected).unwrap());
+    // untouched subtrees stay shared
+    match (nfc.get("other"), value.get("other")) {
+        (Some(Value::Seq(a)), Some(Value::Seq(b))) => assert!(Arc::ptr_eq(a, b)),
+        _ => panic!("expected sequences"),
+    }
+    let again = nfc.normalize_unicode(Form::NFC);
+    match (&again, &nfc) {
+        (Value::Map(a), Value::Map(b)) => assert!(Arc::ptr_eq(a, b)),
+        _ => panic!("expected maps"),
+    }
+
+    let nfd = Value::string(composed.to_owned()).normalize_unicode(Form::NFD);
+    assert_eq!(nfd, Value::string(decomposed.to_owned()));
+    let nfkc = Value::string("\u{fb01}le".to_owned()).normalize_unicode(Form::NFKC);
+    assert_eq!(nfkc, Value::string("file".to_owned()));
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_roundtrip() {
+    use ffi::*;
+    use std::ptr;
+    use std::slice;
+
+    unsafe {
+        let key = serde_arc_value_string(b"name".as_ptr(), 4);
+        let name = serde_arc_value_string(b"abc".as_ptr(), 3);
+        let n = serde_arc_value_i64(-3);
+        let items = [name as *const _, n as *const _];
+        let seq = serde_arc_value_seq(items.as_ptr(), 2);
+        let keys = [key as *const _];
+        let values = [seq as *const _];
+        let map = serde_arc_value_map(keys.as_ptr(), values.as_ptr(), 1);
+        assert_eq!(serde_arc_value_kind(map), serde_arc_value_kind_t::SERDE_ARC_VALUE_MAP);
+        assert_eq!(serde_arc_value_len(map), 1);
+        assert!(serde_arc_value_string([0xff].as_ptr(), 1).is_null());
+
+        let found = serde_arc_value_pointer(map, b"/name/0".as_ptr(), 7);
+        let mut len = 0;
+        let data = serde_arc_value_as_str(found, &mut len);
+        assert_eq!(slice::from_raw_parts(data, len), b"abc");
+        let found_n = serde_arc_value_pointer(map, b"/name/1".as_ptr(), 7);
+        let mut i = 0;
+        assert!(serde_arc_value_as_i64(found_n, &mut i));
+        assert_eq!(i, -3);
+        let mut u = 0;
+        assert!(!serde_arc_value_as_u64(found_n, &mut u));
+        assert!(serde_arc_value_get(map, b"other".as_ptr(), 5).is_null());
+
+        let dedup = serde_arc_dedup_new();
+        let a = serde_arc_dedup_value(dedup, map);
+        let b = serde_arc_dedup_value(dedup, map);
+        assert!(serde_arc_value_eq(a, b));
+        match (&(*a).0, &(*b).0) {
+            (Value::Map(a), Value::Map(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected maps"),
+        }
+        serde_arc_dedup_free(dedup);
+
+        for v in [key, name, n, seq, map, found, found_n, a, b] {
+            serde_arc_value_free(v);
+        }
+        serde_arc_value_free(ptr::null_mut());
+    }
+}
+
+#[cfg(all(feature = "pyo3", feature = "dedup"))]
+#[test]
+fn python_conversion() {
+    use pyo3::prelude::*;
+    use pyo3::types::{PyDict, PyList, PyTuple};
+
+    Python::initialize();
+    Python::attach(|py| {
+        let records = PyList::empty(py);
+        for i in 0..3 {
+            let record = PyDict::new(py);
+            record.set_item("kind", "event").unwrap();
+            record.set_item("tags", vec!["a", "b"]).unwrap();
+            record.set_item("n", -i).unwrap();
+            record.set_item("pair", PyTuple::new(py, [1.5, 2.0]).unwrap()).unwrap();
+            record.set_item("none", py.None()).unwrap();
+            records.append(record).unwrap();
+        }
+        let mut dedup = Dedup::new();
+        let value = dedup.dedup(python::from_python(records.as_any(), 0).unwrap());
+        assert_eq!(value.pointer("/2/n"), Some(&Value::Number(Number::I64(-2))));
+        assert_eq!(value.pointer("/0/none"), Some(&Value::Unit));
+        match (value.pointer("/0/tags"), value.pointer("/2/tags")) {
+            (Some(Value::Seq(a)), Some(Value::Seq(b))) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected sequences"),
+        }
+        let back = python::to_python(py, &value).unwrap();
+        assert!(back.eq(&records).unwrap());
+
+        // self-referencing lists and unknown types are errors
+        let cycle = PyList::empty(py);
+        cycle.append(&cycle).unwrap();
+        assert!(python::from_python(cycle.as_any(), 0).is_err());
+        assert!(python::from_python(py.get_type::<PyList>().as_any(), 0).is_err());
+    });
+}
+
+#[cfg(feature = "wasm")]
+#[test]
+fn wasm_memory_stats() {
+    let record = r#"{"kind":"event","payload":{"x":1},"tags":["a","b"]}"#;
+    let json = format!("[{0},{0},{0}]", record);
+    let value = wasm::parse_json(&mut Dedup::new(), &json).unwrap();
+    let stats = wasm::value_stats(&value);
+    let plain = wasm::value_stats(&serde_json::from_str::<Value>(&json).unwrap());
+    assert_eq!(stats.expanded_bytes, plain.expanded_bytes);
+    assert_eq!(plain.stored_bytes, plain.expanded_bytes);
+    assert!(stats.stored_bytes * 2.0 < stats.expanded_bytes);
+    assert!(stats.ratio() > 2.0);
+
+    let value = wasm::parse_json_dedup(&json).unwrap();
+    assert_eq!(wasm::to_json(&value).unwrap(), json);
+
+    let mut dedup = wasm::WasmDedup::new();
+    dedup.parse_json(record).unwrap();
+    dedup.parse_json(record).unwrap();
+    let stats = dedup.memory_stats();
+    assert!(stats.saved_bytes() > 0.0);
+}
+
+#[cfg(all(feature = "serde_json", feature = "dedup"))]
+#[test]
+fn ndjson_pipeline() {
+    use pipeline::{NdjsonPipeline, PipelineErrorKind};
+
+    let mut input = String::new();
+    for i in 0..50 {
+        input.push_str(&format!("{{\"kind\":\"event\",\"id\":{}}}\n", i));
+        if i == 20 {
+            input.push_str("\nnot json\n");
+        }
+    }
+    let mut pipeline = NdjsonPipeline::new(input.as_bytes(), 3, Dedup::new()).with_batch_lines(4);
+    let (mut ids, mut kinds, mut errors) = (Vec::new(), Vec::new(), Vec::new());
+    for result in &mut pipeline {
+        match result {
+            Ok(value) => {
+                ids.push(value.get("id").cloned().unwrap());
+                kinds.push(value.get("kind").and_then(Value::as_shared_str).cloned().unwrap());
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    assert_eq!(ids, (0..50u64).map(Number::U64).map(Value::Number).collect::<Vec<_>>());
+    assert!(kinds.iter().all(|k| Arc::ptr_eq(k, &kinds[0])));
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 23);
+    match errors[0].kind {
+        PipelineErrorKind::Json(..) => {}
+        _ => panic!("expected a parse error"),
+    }
+    assert!(pipeline.into_dedup().iter_strings().any(|(s, _)| &*s == "event"));
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn json_conversion() {
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+
+    let mut inner = BTreeMap::new();
+    inner.insert(Value::string("id".to_owned()), Value::Number(Number::U64(1 << 60)));
+    inner.insert(Value::string("ok".to_owned()), Value::Bool(true));
+    let mut outer = BTreeMap::new();
+    outer.insert(
+        Value::string("items".to_owned()),
+        Value::seq(vec![Value::map(inner), Value::Char('x')]),
+    );
+    outer.insert(Value::string("raw".to_owned()), Value::bytes(vec![1, 2]));
+    outer.insert(Value::Number(Number::U8(7)), Value::Number(Number::F64(f64::NAN)));
+    let value = Value::map(outer);
+
+    let error = |value: Value| serde_json::Value::try_from(value).unwrap_err();
+    let e = error(value.clone());
+    assert_eq!(e.kind, JsonErrorKind::Key);
+    assert_eq!(e.pointer, "/7");
+    let items = value.pointer("/items").unwrap().clone();
+    let e = error(items.clone());
+    assert_eq!((e.kind, e.pointer.as_str()), (JsonErrorKind::Number, "/0/id"));
+    assert_eq!(error(Value::Char('x')).kind, JsonErrorKind::Char);
+    assert_eq!(error(Value::bytes(vec![])).kind, JsonErrorKind::Bytes);
+    assert_eq!(error(Value::Number(Number::I64(-(1 << 53)))).kind, JsonErrorKind::Number);
+    let ext = Value::Ext { tag: 1, value: Box::new(Value::Unit) };
+    assert_eq!(error(ext).kind, JsonErrorKind::Ext);
+    assert_eq!(e.to_string(), "a number out of range at '/0/id' has no JSON representation");
+
+    let json = serde_json::Value::try_from(Value::seq(vec![
+        Value::Option(Some(Box::new(Value::Number(Number::I32(-3))))),
+        Value::Unit,
+        Value::string("a".to_owned()),
+    ]))
+    .unwrap();
+    assert_eq!(json.to_string(), r#"[-3,null,"a"]"#);
+
+    assert_eq!(
+        value.to_json_lossy().to_string(),
+        r#"{"7":null,"items":[{"id":1152921504606846976,"ok":true},"x"],"raw":[1,2]}"#
+    );
+}
+
+#[test]
+fn hash_map_lookup() {
+    use std::collections::HashMap;
+
+    let names = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta"];
+    let map: HashMap<String, usize> = names.iter().map(|n| (n.to_string(), n.len())).collect();
+    let value = to_value(&map).unwrap();
+    for name in names.iter() {
+        assert_eq!(value.get(name), Some(&Value::Number(Number::U64(name.len() as u64))));
+        let path = format!("/{}", name);
+        assert_eq!(from_value_at::<usize>(&value, &path).unwrap(), name.len());
+    }
+    assert_eq!(value.get("iota"), None);
+    let sorted: BTreeMap<String, usize> = map.into_iter().collect();
+    assert_eq!(value, to_value(&sorted).unwrap());
+}
+
+#[test]
+fn query_strings() {
+    let s = |s: &str| Value::string(s.to_owned());
+    let value = from_query_str(
+        "?user[name]=Ada+L&user[tags][]=a&user[tags][]=b&ids[1]=y&ids[0]=x&flag&q=%E2%9C%93\
+         &dup=1&dup=2&rows[0][n]=1&rows[0][m]=2&rows[1][n]=3&raw=%FF&bad=%zz&&key[01]=v",
+    )
+    .unwrap();
+    assert_eq!(value.pointer("/user/name"), Some(&s("Ada L")));
+    assert_eq!(value.pointer("/user/tags"), Some(&Value::seq(vec![s("a"), s("b")])));
+    assert_eq!(value.pointer("/ids"), Some(&Value::seq(vec![s("x"), s("y")])));
+    assert_eq!(value.pointer("/flag"), Some(&s("")));
+    assert_eq!(value.pointer("/q"), Some(&s("\u{2713}")));
+    assert_eq!(value.pointer("/dup"), Some(&Value::seq(vec![s("1"), s("2")])));
+    assert_eq!(value.pointer("/rows/0/m"), Some(&s("2")));
+    assert_eq!(value.pointer("/rows/1/n"), Some(&s("3")));
+    assert_eq!(value.pointer("/raw"), Some(&Value::bytes(vec![0xff])));
+    assert_eq!(value.pointer("/bad"), Some(&s("%zz")));
+    assert_eq!(value.pointer("/key/01"), Some(&s("v")));
+
+    let query = to_query_string(&value).unwrap();
+    assert!(query.contains("user[name]=Ada+L&user[tags][0]=a&user[tags][1]=b"));
+    assert_eq!(from_query_str(&query).unwrap(), value);
+    assert_eq!(from_form_urlencoded(b"").unwrap(), Value::map(BTreeMap::new()));
+
+    let record = Value::map(
+        vec![
+            (s("n"), Value::Number(Number::U32(5))),
+            (s("none"), Value::Option(None)),
+            (s("a b&c"), s("x=y")),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(to_query_string(&record).unwrap(), "a+b%26c=x%3Dy&n=5");
+
+    assert_eq!(
+        from_query_str("a=1&a[b]=2"),
+        Err(QueryError::Conflict("/a".to_owned()))
+    );
+    assert_eq!(
+        from_query_str("a[]=1&a[b]=2"),
+        Err(QueryError::Conflict("/a".to_owned()))
+    );
+    assert_eq!(from_query_str("a[b=1"), Err(QueryError::Syntax("a[b".to_owned())));
+    assert_eq!(from_query_str("[a]=1"), Err(QueryError::Syntax("[a]".to_owned())));
+    assert!(from_query_str(&format!("a{}=1", "[]".repeat(100))).is_err());
+    let bracket = Value::map(vec![(s("a[b]"), s("c"))].into_iter().collect());
+    assert_eq!(to_query_string(&bracket), Err(QueryError::Unsupported("/a[b]".to_owned())));
+    assert_eq!(to_query_string(&s("x")), Err(QueryError::Unsupported(String::new())));
+}
+
+#[test]
+fn truncated_values() {
+    let s = |s: &str| Value::string(s.to_owned());
+    let limits = TruncateLimits::new().max_bytes(4).max_entries(2).max_depth(2);
+    let record = Value::map(
+        vec![
+            (s("a"), s("\u{e9}\u{e9}\u{e9}")),
+            (s("b"), Value::seq((0..5).map(Number::U32).map(Value::Number).collect())),
+            (s("c"), Value::bytes(vec![7; 10])),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let truncated = record.truncated(&limits);
+    assert_eq!(truncated.pointer("/a"), Some(&s("\u{e9}\u{e9}\u{2026}+2 more")));
+    let b = Value::seq(vec![Number::U32(0).into(), Number::U32(1).into(), s("\u{2026}+3 more")]);
+    assert_eq!(truncated.pointer("/b"), Some(&b));
+    assert_eq!(truncated.pointer("/\u{2026}+1 more"), Some(&Value::Unit));
+    assert!(truncated.get("c").is_none());
+
+    let blob = Value::bytes(vec![7; 10]).truncated(&limits);
+    assert_eq!(blob, Value::Tuple(Arc::new(vec![Value::bytes(vec![7; 4]), s("\u{2026}+6 more")])));
+    let nested = Value::seq(vec![Value::seq(vec![Value::seq(vec![Value::Unit; 3])])]);
+    let expected = Value::seq(vec![Value::seq(vec![s("\u{2026}+3 more")])]);
+    assert_eq!(nested.truncated(&limits), expected);
+
+    // values within the limits are returned as they are, sharing everything
+    let small = Value::seq(vec![s("ab"), Value::seq(vec![])]);
+    let same = small.truncated(&limits);
+    assert!(Arc::ptr_eq(&same.as_shared_seq().unwrap(), &small.as_shared_seq().unwrap()));
+    let long = Value::seq(vec![small.clone(), s("abcdef")]);
+    let kept = long.truncated(&limits);
+    assert!(Arc::ptr_eq(
+        &kept.pointer("/0").unwrap().as_shared_seq().unwrap(),
+        &small.as_shared_seq().unwrap()
+    ));
+    assert_eq!(kept.pointer("/1"), Some(&s("abcd\u{2026}+2 more")));
+}
+
+#[test]
+fn fingerprints() {
+    // the FNV-1a hashes of the encodings, which must never change
+    assert_eq!(Value::Unit.fingerprint(), 12638153115695167455);
+    assert_eq!(Value::Number(Number::U32(1)).fingerprint(), 11289045597036932164);
+    assert_eq!(Value::String("abc".into()).fingerprint(), 5941594983840848593);
+    let mut inner = BTreeMap::new();
+    inner.insert(Value::String("c".into()), Value::Number(Number::F64(1.5)));
+    let mut outer = BTreeMap::new();
+    let a = vec![Value::Number(Number::U8(1)), Value::String("x".into()), Value::Option(None)];
+    outer.insert(Value::String("a".into()), Value::seq(a));
+    outer.insert(Value::String("b".into()), Value::map(inner));
+    let value = Value::map(outer);
+    assert_eq!(value.fingerprint(), 5787428588151277528);
+
+    // equal values, however they are stored
+    let fingerprint = |n: Number| Value::Number(n).fingerprint();
+    assert_eq!(fingerprint(Number::F64(0.0)), fingerprint(Number::F64(-0.0)));
+    assert_eq!(fingerprint(Number::F64(f64::NAN)), fingerprint(Number::F32(-f32::NAN)));
+    assert_eq!(value.fingerprint(), value.clone().fingerprint());
+    // numbers of any width
+    assert_eq!(fingerprint(Number::U32(1)), fingerprint(Number::I32(1)));
+    assert_eq!(fingerprint(Number::U8(1)), fingerprint(Number::F64(1.0)));
+    assert_eq!(fingerprint(Number::F32(0.5)), fingerprint(Number::F64(0.5)));
+    assert_ne!(fingerprint(Number::F64(1.0)), fingerprint(Number::F64(1.5)));
+    // different variants
+    let empty = Arc::new(vec![]);
+    assert_ne!(Value::Seq(empty.clone()).fingerprint(), Value::Tuple(empty).fingerprint());
+    let a = Value::Seq(Arc::new(vec![Value::String("ab".into()), Value::String("".into())]));
+    let b = Value::Seq(Arc::new(vec![Value::String("a".into()), Value::String("b".into())]));
+    assert_ne!(a.fingerprint(), b.fingerprint());
+}
+
+#[test]
+fn keyed_fingerprints() {
+    use std::hash::Hasher;
+
+    // the 64 bit test vector of the SipHash paper
+    let key = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    let mut sip = fingerprint::Sip::new(&key);
+    sip.write(&(0..15).collect::<Vec<u8>>());
+    assert_eq!(sip.finish(), 0xa129_ca61_49be_45e5);
+
+    let value = Value::String("alice@example.com".into());
+    let other = [7; 16];
+    assert_eq!(value.fingerprint_keyed(&key), 11072466455894069779);
+    assert_eq!(value.fingerprint_keyed(&key), value.clone().fingerprint_keyed(&key));
+    assert_ne!(value.fingerprint_keyed(&key), value.fingerprint_keyed(&other));
+    assert_ne!(value.fingerprint_keyed(&key), value.fingerprint());
+}
+
+#[test]
+fn transactions() {
+    let s = |x: &str| Value::string(x.to_owned());
+    let original = Value::map(
+        vec![
+            (s("a"), Value::map(vec![(s("x"), Number::U32(1).into())].into_iter().collect())),
+            (s("b"), s("old")),
+        ]
+        .into_iter()
+        .collect(),
+    );
+
+    let mut value = original.clone();
+    {
+        let mut tx = value.transaction();
+        *tx.get_mut("b").unwrap() = s("new");
+        *tx.get_mut("a").unwrap().get_mut("x").unwrap() = Value::Number(Number::U32(2));
+        assert_eq!(tx.original(), &original);
+        tx.abort();
+    }
+    assert_eq!(value, original);
+    match (&value, &original) {
+        (Value::Map(x), Value::Map(y)) => assert!(Arc::ptr_eq(x, y)),
+        _ => panic!("expected maps"),
+    }
+
+    // a panic midway rolls back as well
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut tx = value.transaction();
+        *tx.get_mut("b").unwrap() = s("new");
+        tx.get_mut("missing").expect("no such key");
+    }));
+    assert!(result.is_err());
+    assert_eq!(value, original);
+
+    {
+        let mut tx = value.transaction();
+        *tx.get_mut("b").unwrap() = s("new");
+        tx.commit();
+    }
+    assert_eq!(value.get("b"), Some(&s("new")));
+    assert_eq!(original.get("b"), Some(&s("old")));
+    match (value.get("a"), original.get("a")) {
+        (Some(Value::Map(x)), Some(Value::Map(y))) => assert!(Arc::ptr_eq(x, y)),
+        _ => panic!("expected maps"),
+    }
+}
+
+#[test]
+fn float_total_order() {
+    use std::collections::hash_map::DefaultHasher;
+    let hash = |v: &Value| {
+        let mut hasher = DefaultHasher::new();
+        v.hash(&mut hasher);
+        hasher.finish()
+    };
+    let nan = Value::Number(Number::F64(f64::NAN));
+    let (zero, negative_zero) = (Value::Number(Number::F64(0.0)), Value::Number(Number::F64(-0.0)));
+    assert_eq!(nan, Value::Number(Number::F64(-f64::NAN)));
+    assert_eq!(hash(&nan), hash(&Value::Number(Number::F64(-f64::NAN))));
+    assert!(nan > Value::Number(Number::F64(f64::INFINITY)));
+    assert!(Value::Number(Number::F32(f32::NAN)) > Value::Number(Number::F32(f32::INFINITY)));
+    assert!(Value::Number(Number::F64(f64::NEG_INFINITY)) < Value::Number(Number::F64(-1e300)));
+    assert_eq!(zero, negative_zero);
+    assert_eq!(hash(&zero), hash(&negative_zero));
+    assert_ne!(hash(&Value::Number(Number::F32(1.5))), hash(&Value::Number(Number::F32(-1.5))));
+}
+
+#[test]
+fn set_values() {
+    use std::collections::HashSet;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Doc {
+        #[serde(with = "set")]
+        tags: HashSet<String>,
+        list: Vec<String>,
+    }
+
+    let doc = Doc {
+        tags: ["b", "a"].iter().map(|s| s.to_string()).collect(),
+        list: vec!["b".to_owned(), "a".to_owned()],
+    };
+    let value = to_value(&doc).unwrap();
+    let a = Value::string("a".to_owned());
+    let b = Value::string("b".to_owned());
+    let c = Value::string("c".to_owned());
+    let tags = match value {
+        Value::Map(ref kv) => kv.values()[1].clone(),
+        _ => panic!("expected a map"),
+    };
+    assert_eq!(tags, Value::Set(Arc::new(vec![a.clone(), b.clone()].into_iter().collect())));
+    assert!(tags.contains(&a) && !tags.contains(&c));
+    assert_eq!(value.clone().deserialize_into::<Doc>().unwrap(), doc);
+    assert_eq!(
+        testing::to_snapshot(&tags),
+        "Set([\n  \"a\",\n  \"b\",\n])\n"
+    );
+
+    let other = Value::Set(Arc::new(vec![b.clone(), c.clone()].into_iter().collect()));
+    let set = |v: Vec<&Value>| Value::Set(Arc::new(v.into_iter().cloned().collect()));
+    assert_eq!(tags.union(&other).unwrap(), set(vec![&a, &b, &c]));
+    assert_eq!(tags.intersection(&other).unwrap(), set(vec![&b]));
+    assert_eq!(tags.difference(&other).unwrap(), set(vec![&a]));
+    assert!(tags.union(&a).is_none());
+
+    // a subset leaves the larger set shared
+    let union = tags.union(&set(vec![&a])).unwrap();
+    match (&union, &tags) {
+        (Value::Set(x), Value::Set(y)) => assert!(Arc::ptr_eq(x, y)),
+        _ => panic!("expected sets"),
+    }
+}
+
+#[cfg(all(test, feature = "dedup"))]
+mod dedup_tests {
+    extern crate bincode;
+    #[cfg(any(
+        feature = "tokio",
+        feature = "codec",
+        feature = "axum-extractor",
+        feature = "actix-extractor"
+    ))]
+    extern crate futures;
+    #[cfg(feature = "metrics")]
+    extern crate metrics_util;
+    #[cfg(feature = "tracing")]
+    extern crate tracing_subscriber;
+    extern crate serde_json;
+
+    use self::serde_json::json;
+    use super::*;
+    use serde_value_derive::ValueSchema;
+
+    #[test]
+    fn dedup_simple() {
+        let input = Value::seq(vec![
+            Value::string("a".to_owned()),
+            Value::string("a".to_owned()),
+        ]);
+        let mut dedup = Dedup::new();
+        let result = dedup.dedup(input);
+        if let Value::Seq(x) = result {
+            if let Value::String(ref a) = x[0] {
+                if let Value::String(ref b) = x[1] {
+                    assert!(Arc::ptr_eq(a, b));
+                } else {
+                    panic!();
+                }
+            } else {
+                panic!();
+            }
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn shared_handles() {
+        let input = json!([{ "x": 0, "y": 0 }, { "x": 1, "y": 2 }]);
+        let value = Dedup::new().dedup(to_value(input).unwrap());
+        let seq = value.as_shared_seq().unwrap();
+        assert!(Arc::ptr_eq(&seq, &value.as_shared_seq().unwrap()));
+        match (&seq[0], &seq[1]) {
+            (Value::Map(a), Value::Map(b)) => {
+                assert!(Arc::ptr_eq(&a.keys_shared(), &b.keys_shared()));
+                assert_eq!(a.keys(), b.keys());
+                assert_ne!(a.values(), b.values());
+            }
+            _ => panic!("expected maps"),
+        }
+        assert!(Value::Number(Number::U8(0)).as_shared_seq().is_none());
+    }
+
+    #[test]
+    fn dedup_miss_filter() {
+        // enough distinct values to make the filters grow
+        let input = (0..10_000)
+            .map(|i| json!({ "id": i, "name": format!("name {}", i % 5000), "tags": ["a", "b"] }))
+            .collect::<Vec<_>>();
+        let value = to_value(&input).unwrap();
+        let mut plain = Dedup::new();
+        let mut filtered = Dedup::with_miss_filter();
+        let a = plain.dedup(value.clone());
+        let b = filtered.dedup(value);
+        assert_eq!(a, b);
+        assert_eq!(plain.strings.len(), filtered.strings.len());
+        assert_eq!(plain.vectors.len(), filtered.vectors.len());
+        assert_eq!(plain.objects.len(), filtered.objects.len());
+        assert_eq!(plain.strings.len(), 5000 + 5);
+
+        // repeated values are still found after the filter has grown
+        let again = filtered.dedup(to_value(&input).unwrap());
+        match (&b, &again) {
+            (Value::Seq(x), Value::Seq(y)) => assert!(Arc::ptr_eq(x, y)),
+            _ => panic!("expected sequences"),
+        }
+    }
+
+    #[test]
+    fn path_analysis() {
+        let records = (0..100).map(|i| {
+            json!({ "id": i, "user": { "agent": format!("agent {}", i % 4) }, "tags": ["x"] })
+        });
+        let mut analysis = PathAnalysis::new();
+        for record in records {
+            analysis.add(&to_value(record).unwrap());
+        }
+        let report = analysis.report();
+        let stats = |path: &str| report.iter().find(|s| s.path == path).unwrap().clone();
+
+        let agent = stats("/user/agent");
+        assert_eq!((agent.occurrences, agent.distinct), (100, 4));
+        assert_eq!(agent.bytes, 700);
+        assert_eq!(agent.distinct_bytes, 28);
+        assert_eq!(
+            agent.to_string(),
+            "/user/agent: 100 occurrences, 4 distinct values, 700 bytes -> 28 bytes"
+        );
+        assert_eq!(stats("/tags/*").distinct, 1);
+        assert_eq!(stats("/id").distinct, 100);
+        assert_eq!(stats("/id").saved_bytes(), 0);
+        assert_eq!(stats("").occurrences, 100);
+        // sorted by savings
+        assert!(report.windows(2).all(|w| w[0].saved_bytes() >= w[1].saved_bytes()));
+    }
+
+    #[test]
+    fn path_cardinality() {
+        let mut exact = PathAnalysis::new();
+        let mut approximate = PathAnalysis::approximate(12);
+        for i in 0..20000 {
+            let record = json!({ "id": i, "kind": format!("kind {}", i % 7), "user": i % 3000 });
+            let record = to_value(record).unwrap();
+            exact.add(&record);
+            approximate.add(&record);
+        }
+        let exact = exact.report();
+        let approximate = approximate.report();
+        assert_eq!(exact.len(), approximate.len());
+        for e in &exact {
+            let a = approximate.iter().find(|a| a.path == e.path).unwrap();
+            assert_eq!((a.occurrences, a.bytes), (e.occurrences, e.bytes));
+            let error = (a.distinct as f64 - e.distinct as f64).abs() / e.distinct as f64;
+            assert!(error < 0.05, "{}: {} vs {}", e.path, a.distinct, e.distinct);
+        }
+        let kind = approximate.iter().find(|a| a.path == "/kind").unwrap();
+        assert_eq!(kind.distinct, 7);
+        // same size strings, so the estimate of the distinct bytes is exact
+        assert_eq!(kind.distinct_bytes, 7 * 6);
+    }
+
+    #[test]
+    fn heavy_hitters() {
+        let mut hitters = HeavyHitters::new(10);
+        for i in 0..1000 {
+            // "hot" in half the records, "warm" in a quarter, the rest all different, so
+            // with 10 counters both are above the guaranteed 2000 / 10 strings
+            let name = match i % 4 {
+                0 | 1 => "hot".to_string(),
+                2 => "warm".to_string(),
+                _ => format!("cold {}", i),
+            };
+            hitters.add(&to_value(json!({ "name": name })).unwrap());
+        }
+        assert_eq!((hitters.total_records(), hitters.total_strings()), (1000, 2000));
+
+        let strings = hitters.strings();
+        assert_eq!(strings.len(), 10);
+        assert_eq!(strings[0].value, Value::String("name".into()));
+        assert_eq!((strings[0].count, strings[0].error), (1000, 0));
+        assert_eq!(strings[1].value, Value::String("hot".into()));
+        assert!(strings[1].guaranteed() <= 500 && strings[1].count >= 500);
+        assert_eq!(strings[2].value, Value::String("warm".into()));
+        assert!(strings[2].guaranteed() <= 250 && strings[2].count >= 250);
+
+        let records = hitters.records();
+        assert_eq!(records[0].value, to_value(json!({ "name": "hot" })).unwrap());
+        assert!(records[0].guaranteed() <= 500 && records[0].count >= 500);
+    }
+
+    #[test]
+    #[should_panic]
+    fn path_cardinality_precision() {
+        PathAnalysis::approximate(2);
+    }
+
+    // the fields of a protobuf message, with varints as numbers and everything else as bytes
+    fn protobuf_fields(mut buf: &[u8]) -> Vec<(u64, Result<u64, Vec<u8>>)> {
+        fn varint(buf: &mut &[u8]) -> u64 {
+            let mut v = 0;
+            for i in 0.. {
+                let b = buf[0];
+                *buf = &buf[1..];
+                v |= u64::from(b & 0x7f) << (7 * i);
+                if b < 0x80 {
+                    break;
+                }
+            }
+            v
+        }
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = varint(&mut buf);
+            let value = match key & 7 {
+                0 => Ok(varint(&mut buf)),
+                2 => {
+                    let len = varint(&mut buf) as usize;
+                    let (v, rest) = buf.split_at(len);
+                    buf = rest;
+                    Err(v.to_vec())
+                }
+                t => panic!("unexpected wire type {}", t),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    // stacks, by frame name and leaf first, and values
+    type Samples = Vec<(Vec<String>, Vec<u64>)>;
+
+    // the string table and the samples of a pprof profile
+    fn pprof_samples(profile: &[u8]) -> (Vec<String>, Samples) {
+        let fields = protobuf_fields(profile);
+        let bytes = |field| {
+            fields
+                .iter()
+                .filter(move |(f, _)| *f == field)
+                .map(|(_, v)| v.clone().unwrap_err())
+        };
+        let strings: Vec<String> = bytes(6).map(|s| String::from_utf8(s).unwrap()).collect();
+        assert_eq!(strings[0], "");
+        let mut names = BTreeMap::new();
+        for function in bytes(5) {
+            let function = protobuf_fields(&function);
+            names.insert(function[0].1.clone().unwrap(), function[1].1.clone().unwrap());
+        }
+        let samples = bytes(2)
+            .map(|sample| {
+                let sample = protobuf_fields(&sample);
+                let list = |v: &Result<u64, Vec<u8>>| {
+                    let v = v.clone().unwrap_err();
+                    let mut buf = &v[..];
+                    let mut res = Vec::new();
+                    while !buf.is_empty() {
+                        let end = buf.iter().position(|&b| b < 0x80).unwrap() + 1;
+                        let mut x = vec![8];
+                        x.extend_from_slice(&buf[..end]);
+                        res.push(protobuf_fields(&x)[0].1.clone().unwrap());
+                        buf = &buf[end..];
+                    }
+                    res
+                };
+                let stack = list(&sample[0].1)
+                    .iter()
+                    .map(|id| strings[names[id] as usize].clone())
+                    .collect();
+                (stack, list(&sample[1].1))
+            })
+            .collect();
+        (strings, samples)
+    }
+
+    #[test]
+    fn pprof_export() {
+        let records = (0..10)
+            .map(|i| json!({ "host": "example.com", "id": i, "tags": ["a", "b"] }))
+            .collect::<Vec<_>>();
+        let mut dedup = Dedup::new();
+        let value = dedup.dedup(to_value(&records).unwrap());
+
+        let (strings, samples) = pprof_samples(&dedup.to_pprof());
+        for s in &["space", "bytes", "references", "count", "saved_space", "string", "seq"] {
+            assert!(strings.iter().any(|x| x == s), "{}", s);
+        }
+        let host = samples.iter().find(|(stack, _)| stack[0] == "example.com").unwrap();
+        assert_eq!(host.0, vec!["example.com", "string"]);
+        // a single copy of "example.com"
+        assert_eq!(host.1[0], 11);
+        let stored: u64 = samples.iter().map(|(_, values)| values[0]).sum();
+        assert_eq!(stored, dedup.savings_report().total().stored_bytes);
+        drop(value);
+
+        let mut analysis = PathAnalysis::new();
+        for record in &records {
+            analysis.add(&to_value(record).unwrap());
+        }
+        let (_, samples) = pprof_samples(&analysis.to_pprof());
+        let stack = |path: &[&str]| {
+            let path: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+            samples.iter().find(|(stack, _)| *stack == path).unwrap().1.clone()
+        };
+        assert_eq!(stack(&["host", "(root)"]), vec![110, 10]);
+        assert_eq!(stack(&["*", "tags", "(root)"]), vec![20, 20]);
+        // the paths add up to the bytes of the whole records
+        let total: u64 = samples.iter().map(|(_, values)| values[0]).sum();
+        let root = analysis.report().into_iter().find(|s| s.path.is_empty()).unwrap();
+        assert_eq!(total, root.bytes);
+        let values: u64 = samples.iter().map(|(_, values)| values[1]).sum();
+        assert_eq!(values, 10 + 10 * 3 + 20);
+    }
+
+    #[test]
+    fn strict_leftovers() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Item {
+            id: u32,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        enum Kind {
+            Plain,
+            Nested { level: u8 },
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Record {
+            name: String,
+            items: Vec<Item>,
+            note: Option<Item>,
+            kinds: Vec<Kind>,
+        }
+
+        let input = to_value(json!({
+            "name": "a",
+            "legacy": { "deep": [1, 2] },
+            "items": [{ "id": 1 }, { "id": 2, "color": "red" }],
+            "note": { "id": 3, "a/b": true },
+            "kinds": ["Plain", { "Nested": { "level": 1, "extra": null } }],
+        }))
+        .unwrap();
+        let (record, unused) = from_value_strict::<Record>(&input).unwrap();
+        assert_eq!(record.name, "a");
+        assert_eq!(record.items, vec![Item { id: 1 }, Item { id: 2 }]);
+        assert_eq!(record.note, Some(Item { id: 3 }));
+        assert_eq!(record.kinds, vec![Kind::Plain, Kind::Nested { level: 1 }]);
+        assert_eq!(
+            unused,
+            to_value(json!({
+                "/legacy": { "deep": [1, 2] },
+                "/items/1/color": "red",
+                "/note/a~1b": true,
+                "/kinds/1/Nested/extra": null,
+            }))
+            .unwrap()
+        );
+        // the leftovers are found where they are
+        if let Value::Map(ref kv) = unused {
+            for (pointer, value) in kv.keys().iter().zip(kv.values()) {
+                if let Value::String(ref pointer) = *pointer {
+                    assert_eq!(input.pointer(pointer), Some(value));
+                }
+            }
+        }
+
+        let item = to_value(json!({ "id": 1 })).unwrap();
+        let (_, unused) = from_value_strict::<Item>(&item).unwrap();
+        assert_eq!(unused, Value::map(BTreeMap::new()));
+        let bad = to_value(json!({ "id": "x" })).unwrap();
+        assert!(from_value_strict::<Item>(&bad).is_err());
+    }
+
+    #[test]
+    fn merge_patch() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+        struct Address {
+            city: String,
+            zip: Option<String>,
+        }
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+        struct User {
+            name: String,
+            age: u32,
+            address: Option<Address>,
+            tags: Vec<String>,
+        }
+
+        let mut user = User {
+            name: "ann".to_owned(),
+            age: 30,
+            address: Some(Address {
+                city: "Berlin".to_owned(),
+                zip: Some("10115".to_owned()),
+            }),
+            tags: vec!["a".to_owned(), "b".to_owned()],
+        };
+        let patch = to_value(json!({ "age": 31, "address": { "zip": null }, "tags": ["c"] }));
+        update_from_value(&mut user, &patch.unwrap()).unwrap();
+        assert_eq!(
+            user,
+            User {
+                name: "ann".to_owned(),
+                age: 31,
+                address: Some(Address {
+                    city: "Berlin".to_owned(),
+                    zip: None,
+                }),
+                tags: vec!["c".to_owned()],
+            }
+        );
+
+        // a patch that does not fit leaves the target alone
+        let before = user.clone();
+        let bad = to_value(json!({ "age": "old", "name": "bob" })).unwrap();
+        assert!(update_from_value(&mut user, &bad).is_err());
+        assert_eq!(user, before);
+
+        let base = to_value(json!({ "a": { "x": 1, "y": [1, 2] }, "b": "keep" })).unwrap();
+        let merged = base.merge(&to_value(json!({ "a": { "x": 2, "z": true } })).unwrap());
+        assert_eq!(
+            merged,
+            to_value(json!({ "a": { "x": 2, "y": [1, 2], "z": true }, "b": "keep" })).unwrap()
+        );
+        // untouched parts stay shared
+        match (base.pointer("/a/y"), merged.pointer("/a/y")) {
+            (Some(Value::Seq(a)), Some(Value::Seq(b))) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected sequences"),
+        }
+        assert_eq!(base.merge(&Value::Number(Number::U8(1))), Value::Number(Number::U8(1)));
+    }
+
+    #[test]
+    fn value_schema() {
+        use self::bincode::Options;
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq, ValueSchema)]
+        enum Shape {
+            Empty,
+            Circle(f64),
+            Rect { w: u32, h: u32 },
+        }
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq, ValueSchema)]
+        struct Id(u64);
+
+        #[derive(Debug, Serialize, Deserialize, PartialEq, ValueSchema)]
+        struct Node {
+            id: Id,
+            label: Option<String>,
+            shapes: Vec<Shape>,
+            children: Vec<Node>,
+            pair: (u8, char),
+        }
+
+        let node = Node {
+            id: Id(1),
+            label: Some("root".to_owned()),
+            shapes: vec![Shape::Empty, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+            children: vec![Node {
+                id: Id(2),
+                label: None,
+                shapes: vec![],
+                children: vec![],
+                pair: (0, 'b'),
+            }],
+            pair: (7, 'a'),
+        };
+        let schema = Node::schema();
+        let value = to_value(&node).unwrap();
+        schema.validate(&value).unwrap();
+
+        // JSON without the missing option and with plain numbers fits as well
+        let json = to_value(json!({
+            "id": 1,
+            "shapes": ["Empty", { "Rect": { "w": 1, "h": 2 } }],
+            "children": [],
+            "pair": [1, "x"],
+        }))
+        .unwrap();
+        schema.validate(&json).unwrap();
+
+        let bad = to_value(json!({
+            "id": -1,
+            "label": 3,
+            "shapes": ["Square", { "Rect": { "w": 1 } }, { "Circle": "x" }],
+            "children": [{ "id": 2 }],
+            "pair": [1],
+        }))
+        .unwrap();
+        let errors = schema.validate(&bad).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "/id: invalid type: integer `-1`, expected u64",
+                "/label: invalid type: integer `3`, expected a string",
+                "/shapes/0: unknown variant `Square`",
+                "/shapes/1/Rect: missing field `h`",
+                "/shapes/2/Circle: invalid type: string \"x\", expected f64",
+                "/children/0: missing field `shapes`",
+                "/children/0: missing field `children`",
+                "/children/0: missing field `pair`",
+                "/pair: invalid length 1, expected 2",
+            ]
+        );
+        assert!(Node::deserialize(bad).is_err());
+
+        // bincode does not describe its contents, so it can only be read with a schema
+        let encoded = bincode::options().serialize(&node).unwrap();
+        let mut decoder = bincode::Deserializer::from_slice(&encoded, bincode::options());
+        assert!(Value::deserialize(&mut decoder).is_err());
+        let mut decoder = bincode::Deserializer::from_slice(&encoded, bincode::options());
+        let decoded = schema.decode(&mut decoder).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(Node::deserialize(decoded).unwrap(), node);
+    }
+
+    #[test]
+    fn value_ref() {
+        let value = to_value(json!({
+            "name": "a",
+            "tags": ["x", "y"],
+            "size": 1.5,
+        }))
+        .unwrap();
+        let kv = value.as_shared_map().unwrap().clone();
+        let name = kv.get("name").unwrap();
+        let count = Arc::strong_count(name.as_shared_str().unwrap());
+
+        // looking through a borrowed view does not touch reference counts
+        let names: Vec<&str> = match value.as_ref() {
+            ValueRef::Map(kv) => kv
+                .values()
+                .iter()
+                .filter_map(|v| match v.as_ref() {
+                    ValueRef::String(s) => Some(s),
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!(),
+        };
+        assert_eq!(names, vec!["a"]);
+        match kv.get("tags").unwrap().as_ref() {
+            ValueRef::Seq(tags) => assert_eq!(tags[1], ValueRef::String("y")),
+            _ => panic!(),
+        }
+        assert_eq!(Arc::strong_count(name.as_shared_str().unwrap()), count);
+
+        assert_eq!(*name, ValueRef::String("a"));
+        assert_eq!(ValueRef::String("a"), *name);
+        assert_ne!(*name, ValueRef::UnitStruct("a"));
+        assert_eq!(ValueRef::Number(Number::F64(f64::NAN)), Value::Number(Number::F64(f64::NAN)));
+        assert_eq!(value.as_ref(), value);
+        assert_eq!(value.as_ref().to_value(), value);
+        assert_eq!(ValueRef::from(name).to_value(), *name);
+    }
+
+    #[test]
+    fn rust_literal() {
+        let mut entries = BTreeMap::new();
+        entries.insert(Value::string("name".to_owned()), Value::string("a \"b\"".to_owned()));
+        entries.insert(Value::string("none".to_owned()), Value::Option(None));
+        entries.insert(Value::string("tags".to_owned()), Value::seq(vec![]));
+        entries.insert(
+            Value::string("data".to_owned()),
+            Value::seq(vec![
+                Value::Tuple(Arc::new(vec![Value::Char('\n'), Number::F32(f32::NAN).into()])),
+                Value::Newtype(Box::new(Value::Bytes(Arc::new(vec![1, 2])))),
+                Value::Option(Some(Box::new(Value::Number(Number::F64(0.1))))),
+                Value::Ext {
+                    tag: 7,
+                    value: Box::new(Value::Number(Number::I64(i64::MIN))),
+                },
+            ]),
+        );
+        let value = Value::map(entries);
+        let expected = r#"Value::map_from_sorted_iter(vec![
+    (Value::String("data".into()), Value::Seq(Arc::new(vec![
+        Value::Tuple(Arc::new(vec![
+            Value::Char('\n'),
+            Value::Number(Number::F32(f32::NAN)),
+        ])),
+        Value::Newtype(Box::new(Value::Bytes(Arc::new(vec![1, 2])))),
+        Value::Option(Some(Box::new(Value::Number(Number::F64(0.1))))),
+        Value::Ext { tag: 7, value: Box::new(Value::Number(Number::I64(-9223372036854775808))) },
+    ]))),
+    (Value::String("name".into()), Value::String("a \"b\"".into())),
+    (Value::String("none".into()), Value::Option(None)),
+    (Value::String("tags".into()), Value::Seq(Arc::new(vec![]))),
+])"#;
+        assert_eq!(value.to_rust_literal(), expected);
+
+        // the expected output, pasted as code
+        let pasted = Value::map_from_sorted_iter(vec![
+            (Value::String("data".into()), Value::Seq(Arc::new(vec![
+                Value::Tuple(Arc::new(vec![
+                    Value::Char('\n'),
+                    Value::Number(Number::F32(f32::NAN)),
+                ])),
+                Value::Newtype(Box::new(Value::Bytes(Arc::new(vec![1, 2])))),
+                Value::Option(Some(Box::new(Value::Number(Number::F64(0.1))))),
+                Value::Ext {
+                    tag: 7,
+                    value: Box::new(Value::Number(Number::I64(-9223372036854775808))),
+                },
+            ]))),
+            (Value::String("name".into()), Value::String("a \"b\"".into())),
+            (Value::String("none".into()), Value::Option(None)),
+            (Value::String("tags".into()), Value::Seq(Arc::new(vec![]))),
+        ]);
+        assert_eq!(pasted, value);
+        assert_eq!(Value::Number(Number::U8(3)).to_rust_literal(), "Value::Number(Number::U8(3))");
+    }
+
+    #[cfg(feature = "quick-xml")]
+    #[test]
+    fn xml_roundtrip() {
+        let xml = r#"<?xml version="1.0"?>
+<feed lang="en">
+  <!-- two items -->
+  <item id="1"><title>A &amp; B</title><tag>x</tag><tag>y</tag></item>
+  <item id="2"><title><![CDATA[<C>]]></title><tag>z</tag></item>
+  <note kind="info">text</note>
+  <empty/>
+</feed>"#;
+        let value = from_xml_reader(xml.as_bytes()).unwrap();
+        let expected = to_value(json!({
+            "feed": {
+                "@lang": "en",
+                "item": [
+                    { "@id": "1", "title": "A & B", "tag": ["x", "y"] },
+                    { "@id": "2", "title": "<C>", "tag": "z" },
+                ],
+                "note": { "@kind": "info", "#text": "text" },
+                "empty": "",
+            }
+        }))
+        .unwrap();
+        assert_eq!(value, expected);
+
+        // both items share their key vector
+        let items = value.pointer("/feed/item").unwrap().as_shared_seq().unwrap();
+        let keys: Vec<_> = items
+            .iter()
+            .map(|item| item.as_shared_map().unwrap().keys_shared())
+            .collect();
+        assert!(Arc::ptr_eq(&keys[0], &keys[1]));
+
+        let mut out = Vec::new();
+        to_xml_writer(&value, &mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert_eq!(
+            written,
+            "<feed lang=\"en\"><empty></empty>\
+             <item id=\"1\"><tag>x</tag><tag>y</tag><title>A &amp; B</title></item>\
+             <item id=\"2\"><tag>z</tag><title>&lt;C&gt;</title></item>\
+             <note kind=\"info\">text</note></feed>"
+        );
+        assert_eq!(from_xml_reader(written.as_bytes()).unwrap(), value);
+
+        let options = XmlOptions::new().attribute_prefix("-").text_key("$value");
+        let note = options.read(&b"<note kind='info'>text</note>"[..]).unwrap();
+        assert_eq!(
+            note,
+            to_value(json!({ "note": { "-kind": "info", "$value": "text" } })).unwrap()
+        );
+
+        assert!(matches!(from_xml_reader(&b"<a></b>"[..]), Err(XmlError::Syntax(_))));
+        assert!(matches!(from_xml_reader(&b"<a><b>"[..]), Err(XmlError::Syntax(_))));
+        assert!(matches!(from_xml_reader(&b"<!-- -->"[..]), Err(XmlError::NoRoot)));
+        let mut entries = BTreeMap::new();
+        entries.insert(Value::string("a".to_owned()), Value::Bytes(Arc::new(vec![1])));
+        match to_xml_writer(&Value::map(entries), Vec::new()) {
+            Err(XmlError::Unsupported(pointer)) => assert_eq!(pointer, "/a"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn csv_roundtrip() {
+        let csv = "name,id,score,active,note\n\
+                   alice,-1,2.5,true,\n\
+                   bob,18446744073709551615,-3,false,NaN\n";
+        let value = from_csv_reader(csv.as_bytes()).unwrap();
+        let expected = to_value(json!([
+            { "name": "alice", "id": -1, "score": 2.5, "active": true, "note": null },
+            {
+                "name": "bob",
+                "id": 18446744073709551615u64,
+                "score": -3,
+                "active": false,
+                "note": "NaN",
+            },
+        ]))
+        .unwrap();
+        assert_eq!(value, expected);
+        assert_eq!(value.pointer("/1/id"), Some(&Value::Number(Number::U64(u64::MAX))));
+
+        // all rows share the key vector built from the header
+        let rows = value.as_shared_seq().unwrap();
+        let first = rows[0].as_shared_map().unwrap().keys_shared();
+        assert!(Arc::ptr_eq(&first, &rows[1].as_shared_map().unwrap().keys_shared()));
+
+        let mut out = Vec::new();
+        to_csv_writer(&value, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "active,id,name,note,score\ntrue,-1,alice,,2.5\nfalse,18446744073709551615,bob,NaN,-3\n"
+        );
+
+        // missing columns are left empty
+        let sparse = to_value(json!([{ "a": 1.0 }, { "b": "x, y" }])).unwrap();
+        let mut out = Vec::new();
+        to_csv_writer(&sparse, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a,b\n1.0,\n,\"x, y\"\n");
+
+        match from_csv_reader("a,b,a\n1,2,3\n".as_bytes()) {
+            Err(CsvError::DuplicateHeader(name)) => assert_eq!(name, "a"),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(from_csv_reader("a,b\n1\n".as_bytes()), Err(CsvError::Csv(_))));
+        let nested = to_value(json!([{ "a": [1] }])).unwrap();
+        match to_csv_writer(&nested, Vec::new()) {
+            Err(CsvError::Unsupported(pointer)) => assert_eq!(pointer, "/0/a"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[cfg(feature = "prost-reflect")]
+    #[test]
+    fn protobuf_roundtrip() {
+        use prost_reflect::prost::Message;
+        use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+        use prost_reflect::prost_types::{
+            DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto,
+            FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet, MessageOptions,
+        };
+        use prost_reflect::{DescriptorPool, DynamicMessage};
+
+        let field = |name: &str, number, label: Label, ty: Type, type_name: &str| {
+            FieldDescriptorProto {
+                name: Some(name.to_owned()),
+                number: Some(number),
+                label: Some(label as i32),
+                r#type: Some(ty as i32),
+                type_name: if type_name.is_empty() { None } else { Some(type_name.to_owned()) },
+                ..Default::default()
+            }
+        };
+        let item = DescriptorProto {
+            name: Some("Item".to_owned()),
+            field: vec![
+                field("name", 1, Label::Optional, Type::String, ""),
+                field("count", 2, Label::Optional, Type::Int64, ""),
+                field("tags", 3, Label::Repeated, Type::String, ""),
+                field("color", 4, Label::Optional, Type::Enum, ".test.Color"),
+                field("child", 5, Label::Optional, Type::Message, ".test.Item"),
+                field("scores", 6, Label::Repeated, Type::Message, ".test.Item.ScoresEntry"),
+                field("data", 7, Label::Optional, Type::Bytes, ""),
+            ],
+            nested_type: vec![DescriptorProto {
+                name: Some("ScoresEntry".to_owned()),
+                field: vec![
+                    field("key", 1, Label::Optional, Type::String, ""),
+                    field("value", 2, Label::Optional, Type::Int32, ""),
+                ],
+                options: Some(MessageOptions {
+                    map_entry: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let color = EnumDescriptorProto {
+            name: Some("Color".to_owned()),
+            value: ["RED", "GREEN"]
+                .iter()
+                .zip(0..)
+                .map(|(name, number)| EnumValueDescriptorProto {
+                    name: Some(name.to_string()),
+                    number: Some(number),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("test.proto".to_owned()),
+            package: Some("test".to_owned()),
+            syntax: Some("proto3".to_owned()),
+            message_type: vec![item],
+            enum_type: vec![color],
+            ..Default::default()
+        };
+        let pool = DescriptorPool::from_file_descriptor_set(FileDescriptorSet { file: vec![file] })
+            .unwrap();
+        let descriptor = pool.get_message_by_name("test.Item").unwrap();
+
+        let value = to_value(json!({
+            "name": "root",
+            "count": 3u8,
+            "tags": ["a", "b"],
+            "color": "GREEN",
+            "child": { "name": "leaf", "color": 7 },
+            "scores": { "x": 1 },
+            "data": null,
+        }))
+        .unwrap();
+        let message = to_dynamic_message(&value, &descriptor).unwrap();
+        let encoded = message.encode_to_vec();
+        let decoded = DynamicMessage::decode(descriptor.clone(), &*encoded).unwrap();
+
+        let mut dedup = Dedup::new();
+        let back = from_dynamic_message(&decoded, &mut dedup);
+        let at = |pointer| back.pointer(pointer).unwrap().clone();
+        assert_eq!(at("/name"), Value::string("root".to_owned()));
+        assert_eq!(at("/count"), Value::Number(Number::I64(3)));
+        assert_eq!(at("/tags"), to_value(vec!["a", "b"]).unwrap());
+        assert_eq!(at("/color"), Value::string("GREEN".to_owned()));
+        assert_eq!(at("/scores/x"), Value::Number(Number::I32(1)));
+        assert_eq!(at("/data"), Value::Bytes(Arc::new(Vec::new())));
+        // unset fields of the child have their defaults, or none if they have presence
+        assert_eq!(at("/child/count"), Value::Number(Number::I64(0)));
+        assert_eq!(at("/child/tags"), Value::seq(Vec::new()));
+        assert_eq!(at("/child/child"), Value::Option(None));
+        // an open enum keeps numbers it has no name for
+        assert_eq!(at("/child/color"), Value::Number(Number::I32(7)));
+        assert_eq!(from_dynamic_message(&decoded, &mut dedup), back);
+
+        // both messages of type Item share one key vector
+        let root = back.as_shared_map().unwrap().keys_shared();
+        let child = back.pointer("/child").unwrap().as_shared_map().unwrap().keys_shared();
+        assert!(Arc::ptr_eq(&root, &child));
+
+        // and the result converts back to the same message, with defaults set explicitly;
+        // compared encoded, as the debug output of a recursive message type does not end
+        let again = to_dynamic_message(&back, &descriptor).unwrap();
+        assert!(again.encode_to_vec() == decoded.encode_to_vec());
+
+        let error = |value: serde_json::Value| {
+            let err = to_dynamic_message(&to_value(value).unwrap(), &descriptor).unwrap_err();
+            (err.pointer, err.kind)
+        };
+        assert_eq!(
+            error(json!({ "nope": 1 })),
+            ("/nope".to_owned(), ProtobufErrorKind::UnknownField)
+        );
+        assert_eq!(error(json!({ "count": "1" })), ("/count".to_owned(), ProtobufErrorKind::Type));
+        assert_eq!(
+            error(json!({ "scores": { "x": 1u64 << 40 } })),
+            ("/scores/x".to_owned(), ProtobufErrorKind::Type)
+        );
+        assert_eq!(
+            error(json!({ "child": { "color": "BLUE" } })),
+            ("/child/color".to_owned(), ProtobufErrorKind::UnknownEnumValue)
+        );
+    }
+
+    #[cfg(feature = "rusqlite")]
+    #[test]
+    fn sqlite_rows() {
+        use rusqlite::Connection;
+
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch("CREATE TABLE t (id INTEGER, name TEXT, score REAL, data BLOB)")
+            .unwrap();
+        let rows = to_value(json!([
+            { "id": 1, "name": "a", "score": 0.5 },
+            { "id": 2, "name": "b", "score": null },
+        ]))
+        .unwrap();
+        let mut insert = db
+            .prepare("INSERT INTO t (id, name, score, data) VALUES (:id, :name, :score, :data)")
+            .unwrap();
+        assert_eq!(execute_values(&mut insert, &rows).unwrap(), 2);
+        let tuple = Value::seq(vec![
+            Value::Number(Number::U8(3)),
+            Value::Option(Some(Box::new(Value::string("c".to_owned())))),
+            Value::Number(Number::F32(1.5)),
+            Value::Bytes(Arc::new(vec![1, 2])),
+        ]);
+        let mut positional = db.prepare("INSERT INTO t VALUES (?, ?, ?, ?)").unwrap();
+        assert_eq!(execute_values(&mut positional, &Value::seq(vec![tuple])).unwrap(), 1);
+
+        let mut select = db.prepare("SELECT * FROM t WHERE id >= ? ORDER BY id").unwrap();
+        let result = query_values(&mut select, [2]).unwrap();
+        let mut expected = vec![BTreeMap::new(), BTreeMap::new()];
+        for (row, (id, name, score, data)) in expected.iter_mut().zip(vec![
+            (2, "b", Value::Unit, Value::Unit),
+            (3, "c", Value::Number(Number::F64(1.5)), Value::Bytes(Arc::new(vec![1, 2]))),
+        ]) {
+            row.insert(Value::string("id".to_owned()), Value::Number(Number::I64(id)));
+            row.insert(Value::string("name".to_owned()), Value::string(name.to_owned()));
+            row.insert(Value::string("score".to_owned()), score);
+            row.insert(Value::string("data".to_owned()), data);
+        }
+        assert_eq!(result, Value::seq(expected.into_iter().map(Value::map).collect()));
+
+        // all rows share the key vector built from the column names
+        let rows = result.as_shared_seq().unwrap();
+        let first = rows[0].as_shared_map().unwrap().keys_shared();
+        assert!(Arc::ptr_eq(&first, &rows[1].as_shared_map().unwrap().keys_shared()));
+
+        let unknown = to_value(json!([{ "nope": 1 }])).unwrap();
+        assert!(execute_values(&mut insert, &unknown).is_err());
+        let nested = to_value(json!([{ "id": [1] }])).unwrap();
+        assert!(execute_values(&mut insert, &nested).is_err());
+        let big = Value::seq(vec![Value::map_from_sorted_iter(vec![(
+            Value::string("id".to_owned()),
+            Value::Number(Number::U64(u64::MAX)),
+        )])]);
+        assert!(execute_values(&mut insert, &big).is_err());
+    }
+
+    #[cfg(feature = "tokio-postgres")]
+    #[test]
+    fn postgres_cells() {
+        use tokio_postgres::types::Type;
+
+        // rows cannot be built without a server, so this checks the conversion of columns
+        let cell = |ty: &Type, raw: &[u8]| super::postgres::cell(ty, raw).unwrap();
+        let ext = |tag, value| Value::Ext {
+            tag,
+            value: Box::new(value),
+        };
+        assert_eq!(cell(&Type::INT4, &[0, 0, 1, 0]), Value::Number(Number::I32(256)));
+        assert_eq!(cell(&Type::TEXT, b"abc"), Value::string("abc".to_owned()));
+        assert_eq!(cell(&Type::BYTEA, &[0, 255]), Value::Bytes(Arc::new(vec![0, 255])));
+        assert_eq!(cell(&Type::JSONB, b"\x01{}"), Value::string("{}".to_owned()));
+        // 2000-01-01 plus one day, and plus half a second
+        let day = 86_400_000_000i64;
+        let expected = ext(1, Value::Number(Number::I64(946_771_200)));
+        assert_eq!(cell(&Type::TIMESTAMPTZ, &day.to_be_bytes()), expected);
+        assert_eq!(
+            cell(&Type::TIMESTAMP, &(-500_000i64).to_be_bytes()),
+            ext(1, Value::Number(Number::F64(946_684_799.5)))
+        );
+        assert_eq!(
+            cell(&Type::TIMESTAMP, &i64::MAX.to_be_bytes()),
+            ext(1, Value::Number(Number::F64(f64::INFINITY)))
+        );
+        let expected = ext(100, Value::Number(Number::I64(10_956)));
+        assert_eq!(cell(&Type::DATE, &(-1i32).to_be_bytes()), expected);
+        let uuid = (0..16).collect::<Vec<u8>>();
+        assert_eq!(
+            cell(&Type::UUID, &uuid),
+            Value::string("00010203-0405-0607-0809-0a0b0c0d0e0f".to_owned())
+        );
+        // numerics are base 10000 digits with a weight, sign and scale
+        let numeric = |words: &[u16]| {
+            let raw = words.iter().flat_map(|w| w.to_be_bytes().to_vec()).collect::<Vec<_>>();
+            cell(&Type::NUMERIC, &raw)
+        };
+        assert_eq!(numeric(&[2, 0, 0, 2, 12, 3400]), Value::string("12.34".to_owned()));
+        assert_eq!(numeric(&[1, 0xffff, 0x4000, 5, 50]), Value::string("-0.00500".to_owned()));
+        assert_eq!(numeric(&[1, 1, 0, 0, 7]), Value::string("70000".to_owned()));
+        assert_eq!(numeric(&[0, 0, 0xc000, 0]), Value::string("NaN".to_owned()));
+        // a one dimensional int4 array of 7 and null: dimensions, null flag, element type,
+        // length and lower bound, then length prefixed elements
+        let array = [1i32, 1, 23, 2, 1, 4, 7, -1]
+            .iter()
+            .flat_map(|w| w.to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cell(&Type::INT4_ARRAY, &array),
+            Value::seq(vec![Value::Number(Number::I32(7)), Value::Unit])
+        );
+        assert!(super::postgres::cell(&Type::INT8, &[1]).is_err());
+    }
+
+    #[test]
+    fn path_macro() {
+        let value = to_value(json!({
+            "results": [{ "user": { "name": "a" } }, { "user": { "name": "b", "a/b": 1 } }],
+            "type": "list",
+        }))
+        .unwrap();
+        assert_eq!(path!().as_str(), "");
+        assert_eq!(path!(results[1].user.name).as_str(), "/results/1/user/name");
+        assert_eq!(path!(results[1].user."a/b").as_str(), "/results/1/user/a~1b");
+        assert_eq!(path!([0][1 + 1]).as_str(), "/0/2");
+        assert_eq!(path!("~x".type).as_str(), "/~0x/type");
+        assert_eq!(path!(results[*].*).as_str(), "/results/*/*");
+
+        assert_eq!(
+            value.pointer(&path!(results[1].user.name)),
+            value.pointer("/results/1/user/name")
+        );
+        let one = Value::Number(Number::U64(1));
+        assert_eq!(value.pointer(&path!(results[1].user."a/b")), Some(&one));
+        let i = 0;
+        let updated = value
+            .update(&path!(results[i].user.name), |_| Value::string("c".to_owned()))
+            .unwrap();
+        let name = Value::string("c".to_owned());
+        assert_eq!(updated.pointer(&path!(results[0].user.name)), Some(&name));
+
+        let redactions = testing::Redactions::new().redact(&path!(results[*].user.name));
+        let redacted = redactions.apply(&value);
+        let marker = Value::string(testing::REDACTED.to_owned());
+        assert_eq!(redacted.pointer(&path!(results[1].user.name)), Some(&marker));
+        assert_eq!(String::from(path!(type)), "/type");
+    }
+
+    #[test]
+    fn custom_key_order() {
+        let value = to_value(json!({
+            "name": "a",
+            "id": 1,
+            "tags": [{ "z": 1, "id": 2, "a": 3 }],
+            "created": null,
+        }))
+        .unwrap();
+        let json = serde_json::to_string(&value.canonicalize_with(keys_first(&["id", "name"])));
+        assert_eq!(
+            json.unwrap(),
+            r#"{"id":1,"name":"a","created":null,"tags":[{"id":2,"a":3,"z":1}]}"#
+        );
+        let reversed = value.canonicalize_with(|a: &Value, b: &Value| b.cmp(a));
+        assert_eq!(
+            serde_json::to_string(&reversed).unwrap(),
+            r#"{"tags":[{"z":1,"id":2,"a":3}],"name":"a","id":1,"created":null}"#
+        );
+        // the value itself keeps its order
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            r#"{"created":null,"id":1,"name":"a","tags":[{"a":3,"id":2,"z":1}]}"#
+        );
+        let back: Value = serde_json::from_str(&serde_json::to_string(&reversed).unwrap()).unwrap();
+        assert_eq!(back.canonical_cmp(&value), Ordering::Equal);
+    }
+
+    #[test]
+    fn len_and_contains() {
+        let value = to_value(json!({ "a": [1, 2], "b": "hello", "c": {}, "d": null })).unwrap();
+        assert_eq!(value.len(), Some(4));
+        assert_eq!(value.pointer("/a").unwrap().len(), Some(2));
+        assert_eq!(value.pointer("/b").unwrap().len(), Some(5));
+        assert!(value.pointer("/c").unwrap().is_empty());
+        assert_eq!(value.pointer("/d").unwrap().len(), None);
+        assert!(!value.pointer("/d").unwrap().is_empty());
+        assert_eq!(Value::Bytes(Arc::new(vec![1, 2, 3])).len(), Some(3));
+        let set = Value::Set(Arc::new(vec![Value::Number(Number::U8(1))].into_iter().collect()));
+        assert_eq!(set.len(), Some(1));
+
+        assert!(value.contains(&Value::string("a".to_owned())));
+        assert!(!value.contains(&Value::string("e".to_owned())));
+        assert!(value.pointer("/a").unwrap().contains(&Value::Number(Number::U64(2))));
+        assert!(value.pointer("/a").unwrap().contains(&Value::Number(Number::U8(2))));
+        assert!(!value.pointer("/a").unwrap().contains(&Value::Number(Number::U8(3))));
+        assert!(value.pointer("/b").unwrap().contains(&Value::string("ell".to_owned())));
+        assert!(value.pointer("/b").unwrap().contains(&Value::Char('o')));
+        assert!(!value.pointer("/b").unwrap().contains(&Value::Number(Number::U8(1))));
+        assert!(set.contains(&Value::Number(Number::U8(1))));
+        assert!(!Value::Number(Number::U8(1)).contains(&Value::Number(Number::U8(1))));
+    }
+
+    #[test]
+    fn dedup_session() {
+        let session = DedupSession::default();
+        let document = |tenant: &str, body: &str| {
+            to_value(json!({ "service": "checkout", "tenant": tenant, "body": body })).unwrap()
+        };
+        let a = session.ingest(document("a", "the same long body text"));
+        let b = session.ingest(document("b", "the same long body text"));
+        let c = session.ingest(document("c", "a body only c has"));
+        assert_eq!((a.id(), b.id(), c.id()), (0, 1, 2));
+        assert_eq!(session.documents(), 3);
+        match (a.value().get("body"), b.value().get("body")) {
+            (Some(Value::String(x)), Some(Value::String(y))) => assert!(Arc::ptr_eq(x, y)),
+            _ => panic!("expected strings"),
+        }
+
+        let attribution = session.attribution();
+        assert_eq!(attribution.iter().map(|s| s.id).collect::<Vec<_>>(), vec![0, 1, 2]);
+        let (sa, sb, sc) = (attribution[0], attribution[1], attribution[2]);
+        // a and b share the body, c only the service and the keys
+        assert_eq!(sa.input_bytes, sb.input_bytes);
+        assert!(sa.saved_bytes() > sc.saved_bytes());
+        assert!(sc.exclusive_bytes > sa.exclusive_bytes);
+        // the shares add up to what the tables hold, but for rounding each of them down
+        let total = session.savings_report().total();
+        let stored = total.stored_bytes;
+        let attributed: u64 = attribution.iter().map(|s| s.stored_bytes).sum();
+        assert!(attributed <= stored && stored - attributed < 3 * total.entries as u64);
+
+        // dropping c frees what only it referenced, and nothing while it was alive
+        assert_eq!(session.reclaim(), 0);
+        drop(c);
+        assert_eq!(session.documents(), 2);
+        assert_eq!(session.reclaim() as u64, sc.exclusive_bytes);
+        // what c shared with the others is now split between fewer documents
+        let after = session.attribution();
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0].input_bytes, sa.input_bytes);
+        assert!(after[0].stored_bytes > sa.stored_bytes);
+        drop(a);
+        drop(b);
+        assert_eq!(session.reclaim() as u64, stored - sc.exclusive_bytes);
+        assert_eq!(session.into_inner().savings_report().total().entries, 0);
+    }
+
+    #[cfg(feature = "axum-extractor")]
+    #[test]
+    fn dedup_json_axum() {
+        use axum::body::Body;
+        use axum::extract::FromRequest;
+        use axum::http::Request;
+        use self::futures::executor::block_on;
+
+        let service = DedupService::spawn(Dedup::new(), 4);
+        let handle = service.handle();
+        let extract = |content_type: &str, body: &'static str| {
+            let req = Request::builder()
+                .header("content-type", content_type)
+                .body(Body::from(body))
+                .unwrap();
+            block_on(DedupJson::from_request(req, &handle))
+        };
+        let body = r#"{"service": "checkout", "tags": ["a", "b"]}"#;
+        let DedupJson(a) = extract("application/json", body).unwrap();
+        let DedupJson(b) = extract("application/problem+json; charset=utf-8", body).unwrap();
+        assert_eq!(a, to_value(json!({ "service": "checkout", "tags": ["a", "b"] })).unwrap());
+        let tags = |v: &Value| v.get("tags").and_then(Value::as_shared_seq).unwrap();
+        assert!(Arc::ptr_eq(&tags(&a), &tags(&b)));
+
+        let rejection = extract("text/plain", body).unwrap_err();
+        assert_eq!(rejection.status(), 415);
+        let rejection = extract("application/json", "{").unwrap_err();
+        assert_eq!(rejection.status(), 400);
+        service.shutdown();
+        let rejection = extract("application/json", body).unwrap_err();
+        assert_eq!(rejection.status(), 503);
+    }
+
+    #[cfg(feature = "actix-extractor")]
+    #[test]
+    fn dedup_json_actix() {
+        use actix_web::test::TestRequest;
+        use actix_web::web::Data;
+        use actix_web::FromRequest;
+        use self::futures::executor::block_on;
+
+        let service = DedupService::spawn(Dedup::new(), 4);
+        let body = r#"{"service": "checkout", "tags": ["a", "b"]}"#;
+        let extract = |req: TestRequest| {
+            let (req, mut payload) = req.set_payload(body).to_http_parts();
+            block_on(DedupJson::from_request(&req, &mut payload))
+        };
+        let json = || TestRequest::default().insert_header(("content-type", "application/json"));
+        let DedupJson(a) = extract(json().app_data(service.handle())).unwrap();
+        let DedupJson(b) = extract(json().app_data(Data::new(service.handle()))).unwrap();
+        assert_eq!(a, to_value(json!({ "service": "checkout", "tags": ["a", "b"] })).unwrap());
+        let tags = |v: &Value| v.get("tags").and_then(Value::as_shared_seq).unwrap();
+        assert!(Arc::ptr_eq(&tags(&a), &tags(&b)));
+
+        let rejection = extract(json()).unwrap_err();
+        assert_eq!(rejection.status(), 500);
+        let rejection = extract(TestRequest::default().app_data(service.handle())).unwrap_err();
+        assert_eq!(rejection.status(), 415);
+    }
+
+    #[cfg(feature = "codec")]
+    #[test]
+    fn value_codec() {
+        use bytes::BytesMut;
+        use self::futures::executor::block_on;
+        use self::futures::StreamExt;
+        use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+        let records: Vec<Value> = (0..3)
+            .map(|i| to_value(json!({ "service": "checkout", "id": i, "tags": ["a", "b"] })))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let mut sender = ValueCodec::new(Dedup::new());
+        let mut wire = BytesMut::new();
+        for record in records.iter() {
+            sender.encode(record, &mut wire).unwrap();
+        }
+        sender.encode(Value::Unit, &mut wire).unwrap();
+        let wire = wire.freeze();
+
+        // a frame split across reads is only decoded once complete
+        let mut receiver = ValueCodec::new(Dedup::new());
+        let mut buf = BytesMut::from(&wire[..3]);
+        assert_eq!(receiver.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&wire[3..10]);
+        assert_eq!(receiver.decode(&mut buf).unwrap(), None);
+
+        let framed = FramedRead::new(&wire[..], ValueCodec::new(Dedup::new()));
+        let decoded = block_on(framed.map(Result::unwrap).collect::<Vec<_>>());
+        assert_eq!(decoded[..3], records[..]);
+        assert_eq!(decoded[3], Value::Unit);
+        // the connection's dedup shares what the frames repeat
+        let tags = |v: &Value| v.get("tags").and_then(Value::as_shared_seq).unwrap();
+        assert!(Arc::ptr_eq(&tags(&decoded[0]), &tags(&decoded[2])));
+
+        let mut limited = ValueCodec::new(Dedup::new()).max_frame_length(8);
+        assert!(limited.encode(&records[0], &mut BytesMut::new()).is_err());
+        assert!(limited.decode(&mut BytesMut::from(&wire[..])).is_err());
+        // a frame with trailing bytes after its value
+        let mut trailing = BytesMut::from(&b"\0\0\0\x02\0\0"[..]);
+        assert!(receiver.decode(&mut trailing).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fast-hash")]
+    fn fast_hashing() {
+        let blob = |fill: u8| Value::bytes(vec![fill; 4096]);
+        let text = |fill: char| Value::string(std::iter::repeat_n(fill, 1000).collect());
+        let record = |fill: u8| {
+            Value::seq(vec![blob(fill), text(fill as char), Value::string("short".to_owned())])
+        };
+        let mut dedup = Dedup::new();
+        let before = dedup.dedup(record(b'a'));
+        // entries interned before are rehashed, and found again
+        let mut dedup = dedup.with_fast_hashing(256);
+        let after = dedup.dedup(record(b'a'));
+        assert!(Arc::ptr_eq(&before.as_shared_seq().unwrap(), &after.as_shared_seq().unwrap()));
+        let other = dedup.dedup(record(b'b'));
+        assert_ne!(other, after);
+        assert_eq!(dedup.iter_blobs().count(), 2);
+        assert_eq!(dedup.iter_strings().count(), 3);
+        assert_eq!(dedup.dedup(record(b'b')), other);
+        assert_eq!(dedup.iter_seqs().count(), 2);
+    }
+
+    #[test]
+    fn strings_shared_with_the_table() {
+        #[derive(Deserialize)]
+        struct Event {
+            #[serde(with = "shared::arc_str")]
+            host: Arc<str>,
+        }
+
+        let json = r#"[{"host": "example.org"}, {"host": "example.org"}]"#;
+        let parsed: Value = serde_json::from_str(json).unwrap();
+        let first = parsed.pointer("/0/host").and_then(Value::as_shared_str).unwrap().clone();
+        let mut dedup = Dedup::new();
+        let value = dedup.dedup(parsed);
+        // the string the deserializer allocated first goes into the table as it is
+        let (table, _) = dedup.iter_strings().find(|(s, _)| &**s == "example.org").unwrap();
+        assert!(Arc::ptr_eq(&table, &first));
+        let events: Vec<Event> = value.deserialize_into().unwrap();
+        assert!(events.iter().all(|e| Arc::ptr_eq(&e.host, &table)));
+    }
+
+    #[test]
+    fn numbers_of_any_width_interned_once() {
+        let mut dedup = Dedup::new();
+        let float = dedup.dedup(Value::seq(vec![Number::F64(1.0).into()]));
+        let int = dedup.dedup(Value::seq(vec![Number::U8(1).into()]));
+        // equal, so the sequence seen first stands for both
+        assert_eq!(int, float);
+        assert_eq!(serde_json::to_string(&int).unwrap(), "[1.0]");
+    }
+
+    #[test]
+    #[cfg(feature = "fast-hash")]
+    fn keyed_fast_hashing() {
+        let long = |n: usize| Value::string(format!("{:0>512}", n));
+        let mut dedup = Dedup::new().with_keyed_fast_hashing(256);
+        let values: Vec<Value> = (0..100).map(|n| dedup.dedup(long(n % 10))).collect();
+        assert_eq!(dedup.iter_strings().count(), 10);
+        let (a, b) = (values[3].as_shared_str(), values[93].as_shared_str());
+        assert!(Arc::ptr_eq(a.unwrap(), b.unwrap()));
+    }
+
+    #[test]
+    fn dedup_policy() {
+        let records = (0..10)
+            .map(|i| {
+                let body = format!("payload {}", i);
+                json!({ "headers": { "host": "example.com" }, "body": { "text": body } })
+            })
+            .collect::<Vec<_>>();
+        let value = to_value(&records).unwrap();
+
+        let mut dedup = Dedup::new().with_policy(DedupPolicy::new().exclude("/*/body"));
+        let result = dedup.dedup(value.clone());
+        assert_eq!(result, value);
+        let strings = dedup.iter_strings().map(|(s, _)| s.to_string()).collect::<BTreeSet<_>>();
+        assert!(strings.contains("example.com"));
+        assert!(strings.contains("body"));
+        assert!(!strings.contains("text"));
+        assert!(!strings.iter().any(|s| s.starts_with("payload")));
+        match result {
+            Value::Seq(ref v) => match (&v[0], &v[1]) {
+                (Value::Map(a), Value::Map(b)) => {
+                    assert!(Arc::ptr_eq(&a.keys_shared(), &b.keys_shared()));
+                    match (a.get("headers"), b.get("headers")) {
+                        (Some(Value::Map(x)), Some(Value::Map(y))) => assert!(Arc::ptr_eq(x, y)),
+                        _ => panic!("expected maps"),
+                    }
+                }
+                _ => panic!("expected maps"),
+            },
+            _ => panic!("expected a sequence"),
+        }
+
+        // the longest matching pattern wins
+        let policy = DedupPolicy::none().include("/*/headers").exclude("/*/headers/host");
+        let mut dedup = Dedup::new().with_policy(policy);
+        dedup.dedup(value);
+        let strings = dedup.iter_strings().map(|(s, _)| s.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(strings, vec!["host".to_owned()].into_iter().collect());
+    }
+
+    #[test]
+    fn dedup_sampling() {
+        let unique = (0..90).map(|i| Value::string(format!("unique {}", i)));
+        let hot = (0..10).map(|_| Value::string("hot".to_owned()));
+        let input = Value::seq(unique.chain(hot).collect());
+
+        let mut dedup = Dedup::new().with_sampling(Sampling::EveryNth(3));
+        let result = dedup.dedup(input.clone());
+        assert_eq!(result, input);
+        // every third unique string, and the hot one on its third occurrence
+        assert_eq!(dedup.strings.len(), 31);
+        match result {
+            Value::Seq(ref v) => match (&v[92], &v[99]) {
+                (Value::String(a), Value::String(b)) => assert!(Arc::ptr_eq(a, b)),
+                _ => panic!("expected strings"),
+            },
+            _ => panic!("expected a sequence"),
+        }
+        assert_eq!(dedup.savings_report().strings.seen, 100);
+
+        let mut dedup = Dedup::with_miss_filter().with_sampling(Sampling::Hash(4));
+        assert_eq!(dedup.dedup(input.clone()), input);
+        let sampled = dedup.strings.len();
+        assert!(sampled < 50);
+        // the same values are sampled again
+        dedup.dedup(input);
+        assert_eq!(dedup.strings.len(), sampled);
+    }
+
+    #[test]
+    fn dedup_recycles_buffers() {
+        let record = json!({ "tags": ["a", "b"], "user": { "name": "x" } });
+        let mut dedup = Dedup::new();
+        let first = dedup.dedup(to_value(&record).unwrap());
+        assert!(dedup.pool.values.is_empty());
+        // all table hits, so the tags, both key vectors and both value vectors are recycled
+        let second = dedup.dedup(to_value(&record).unwrap());
+        assert_eq!(dedup.pool.values.len(), 5);
+        match (&first, &second) {
+            (Value::Map(a), Value::Map(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected maps"),
+        }
+        // a shared input is copied into the recycled buffers
+        let input = to_value(&record).unwrap();
+        let third = dedup.dedup(input.clone());
+        assert_eq!(third, input);
+        assert_eq!(dedup.pool.values.len(), 5);
+    }
+
+    #[test]
+    fn string_dictionary() {
+        let mut dedup = Dedup::new();
+        let value = dedup.dedup(to_value(json!([{ "b": "x" }, { "a": "x" }])).unwrap());
+        let dictionary = dedup.string_dictionary();
+        let strings: Vec<&str> = dictionary.strings().iter().map(|s| &**s).collect();
+        assert_eq!(strings, vec!["a", "b", "x"]);
+        assert_eq!(dictionary.len(), 3);
+        assert_eq!(dictionary.id("x"), Some(2));
+        assert_eq!(dictionary.id("y"), None);
+        assert_eq!(dictionary.get(3), None);
+        // the dictionary hands out the interned strings
+        match value.as_shared_seq().unwrap()[0].get("b") {
+            Some(Value::String(s)) => assert!(Arc::ptr_eq(s, dictionary.get(2).unwrap())),
+            _ => panic!("expected a string"),
+        }
+
+        // interning the same strings in another order gives the same ids
+        let mut other = Dedup::new();
+        other.dedup(to_value(json!({ "x": ["b", "a"] })).unwrap());
+        assert_eq!(other.string_dictionary(), dictionary);
+    }
+
+    #[test]
+    fn dictionary_encoding() {
+        let mut dedup = Dedup::new();
+        let mut records: Vec<Value> = (0..20)
+            .map(|i| json!({ "service": "checkout", "level": "info", "id": i, "ok": true }))
+            .map(|r| dedup.dedup(to_value(r).unwrap()))
+            .collect();
+        let mut scalars = BTreeMap::new();
+        scalars.insert(Value::Number(Number::I64(-5)), Value::Number(Number::F64(0.25)));
+        scalars.insert(Value::Char('x'), Value::Option(None));
+        scalars.insert(Value::Number(Number::U16(300)), Value::bytes(vec![1, 2, 3]));
+        scalars.insert(
+            Value::UnitStruct(Arc::from("Marker")),
+            Value::Tuple(Arc::new(vec![
+                Number::I8(-1).into(),
+                Number::F32(1.5).into(),
+                Value::Unit,
+            ])),
+        );
+        scalars.insert(
+            Value::string("not interned".to_owned()),
+            Value::Ext {
+                tag: 7,
+                value: Box::new(Value::Newtype(Box::new(Value::Option(Some(Box::new(
+                    Value::Set(Arc::new(vec![Number::I32(-70000).into()].into_iter().collect())),
+                )))))),
+            },
+        );
+        records.push(Value::map(scalars));
+
+        let mut encoder = DictionaryEncoder::new(Vec::new(), dedup.string_dictionary()).unwrap();
+        for record in records.iter() {
+            encoder.encode(record).unwrap();
+        }
+        let bytes = encoder.finish().unwrap();
+        let expanded = serde_json::to_vec(&records[..20]).unwrap();
+        assert!(bytes.len() < expanded.len() / 2);
+
+        let decoder = DictionaryDecoder::new(&bytes[..]).unwrap();
+        assert_eq!(decoder.dictionary(), &dedup.string_dictionary());
+        let decoded = decoder.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded, records);
+        match (decoded[0].get("service"), decoded[1].get("service")) {
+            (Some(Value::String(a)), Some(Value::String(b))) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected strings"),
+        }
+
+        assert!(DictionaryDecoder::new(&b"JSON"[..]).is_err());
+        // an empty dictionary, then a reference to a string that is not in it
+        let mut decoder = DictionaryDecoder::new(&b"SVD\x01\x00\x13\x05"[..]).unwrap();
+        assert_eq!(
+            decoder.decode().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn dictionary_encoding_zstd() {
+        let mut dedup = Dedup::new();
+        let records: Vec<Value> = (0..2000)
+            .map(|i| {
+                let host = format!("host-{}.eu-west-1.compute.internal", i % 500);
+                let path = format!("/api/v1/orders/{}/items", i);
+                json!({ "host": host, "path": path, "status": 200, "id": i })
+            })
+            .map(|r| dedup.dedup(to_value(r).unwrap()))
+            .collect();
+        let encode = |compress: bool| {
+            let dictionary = dedup.string_dictionary();
+            let mut encoder = if compress {
+                DictionaryEncoder::with_zstd(Vec::new(), dictionary, 0).unwrap()
+            } else {
+                DictionaryEncoder::new(Vec::new(), dictionary).unwrap()
+            };
+            for record in records.iter() {
+                encoder.encode(record).unwrap();
+            }
+            encoder.finish().unwrap()
+        };
+        let (plain, compressed) = (encode(false), encode(true));
+        assert!(compressed.len() < plain.len() / 2);
+        // the trained dictionary is stored in front of the compressed stream
+        assert!(compressed.starts_with(b"SVZ\x01"));
+        assert_ne!(&compressed[4..6], &[0, 0]);
+
+        let decoder = DictionaryDecoder::new(&compressed[..]).unwrap();
+        assert_eq!(decoder.dictionary(), &dedup.string_dictionary());
+        let decoded = decoder.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded, records);
+        match (decoded[0].get("status"), decoded[1].get("status")) {
+            (Some(a), Some(b)) => assert_eq!(a, b),
+            _ => panic!("expected statuses"),
+        }
+
+        // too few strings to train on, so the stream is compressed without a dictionary
+        let mut encoder =
+            DictionaryEncoder::with_zstd(Vec::new(), StringDictionary::default(), 3).unwrap();
+        encoder.encode(&records[0]).unwrap();
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(bytes[4], 0);
+        let decoded = DictionaryDecoder::new(&bytes[..]).unwrap().collect::<Result<Vec<_>, _>>();
+        assert_eq!(decoded.unwrap(), &records[..1]);
+
+        // a truncated stream fails instead of ending early
+        let truncated = &compressed[..compressed.len() - 8];
+        let decoded =
+            DictionaryDecoder::new(truncated).and_then(|d| d.collect::<Result<Vec<_>, _>>());
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn cbor_stringref() {
+        let records = (0..10)
+            .map(|i| json!({ "service": "checkout", "id": -i, "ratio": 0.5, "tags": ["a", null] }))
+            .collect::<Vec<_>>();
+        let value = Dedup::new().dedup(to_value(&records).unwrap());
+
+        let mut plain = Vec::new();
+        value.write_cbor(&mut plain).unwrap();
+        let mut stringref = Vec::new();
+        value.write_cbor_stringref(&mut stringref).unwrap();
+        assert!(stringref.len() < plain.len() * 3 / 4);
+
+        let decoded = Value::from_cbor(&stringref).unwrap();
+        assert!(decoded.canonical_eq(&value));
+        assert!(Value::from_cbor(&plain).unwrap().canonical_eq(&value));
+        let decoded = decoded.as_shared_seq().unwrap();
+        match (decoded[0].get("service"), decoded[9].get("service")) {
+            (Some(Value::String(a)), Some(Value::String(b))) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected strings"),
+        }
+
+        // strings shorter than three bytes are never referenced
+        let short = to_value(json!(["aaa", "aaa", "a", "a"])).unwrap();
+        let mut bytes = Vec::new();
+        short.write_cbor_stringref(&mut bytes).unwrap();
+        assert_eq!(
+            bytes,
+            b"\xd9\x01\x00\x84\x63aaa\xd8\x19\x00\x61a\x61a".to_vec()
+        );
+        assert_eq!(Value::from_cbor(&bytes).unwrap(), short);
+
+        // half precision decodes the same with or without the `half` feature
+        assert_eq!(Value::from_cbor(b"\xf9\x3e\x00").unwrap(), Value::Number(Number::F32(1.5)));
+        // a reference outside of any namespace
+        assert!(Value::from_cbor(b"\xd8\x19\x00").is_err());
+        // other tags become extension values
+        assert_eq!(
+            Value::from_cbor(b"\xc1\x1a\x51\x4b\x67\xb0").unwrap(),
+            Value::Ext {
+                tag: 1,
+                value: Box::new(Value::Number(Number::U64(1_363_896_240))),
+            }
+        );
+    }
+
+    #[test]
+    fn ref_document() {
+        let address = json!({ "street": "Main St 1", "city": "Springfield", "zip": "12345" });
+        let people = (0..5)
+            .map(|i| json!({ "name": format!("p{}", i), "home": address, "tags": ["x"] }))
+            .collect::<Vec<_>>();
+        let value = Dedup::new().dedup(to_value(json!({ "people": people })).unwrap());
+
+        let document = value.to_ref_document(2).unwrap();
+        let defs = document.get("$defs").unwrap();
+        let reference = to_value(json!({ "$ref": "#/$defs/0" })).unwrap();
+        assert_eq!(defs.get("0"), to_value(&address).ok().as_ref());
+        assert_eq!(defs.get("1"), to_value(json!(["x"])).ok().as_ref());
+        assert_eq!(defs.get("2"), None);
+        match document.get("people") {
+            Some(Value::Seq(people)) => assert_eq!(people[4].get("home"), Some(&reference)),
+            _ => panic!("expected a sequence"),
+        }
+        let expanded = serde_json::to_string(&value).unwrap();
+        assert!(serde_json::to_string(&document).unwrap().len() < expanded.len());
+
+        // nothing shared often enough
+        assert_eq!(value.to_ref_document(5).unwrap(), value);
+        assert!(to_value(json!([1])).unwrap().to_ref_document(0).is_err());
+    }
+
+    #[test]
+    fn resolve_refs() {
+        let schema = to_value(json!({
+            "$defs": { "id": { "type": "integer" }, "user": { "id": { "$ref": "#/$defs/id" } } },
+            "properties": {
+                "author": { "$ref": "#/$defs/user" },
+                "editor": { "$ref": "#/$defs/user" },
+                "remote": { "$ref": "other.json#/x" }
+            }
+        }))
+        .unwrap();
+        let resolved = schema.resolve_refs().unwrap();
+        let user = to_value(json!({ "id": { "type": "integer" } })).unwrap();
+        let properties = resolved.get("properties").unwrap();
+        assert_eq!(properties.get("author"), Some(&user));
+        assert_eq!(properties.get("remote"), schema.get("properties").unwrap().get("remote"));
+        let defs = resolved.get("$defs").unwrap();
+        match (properties.get("author"), properties.get("editor"), defs.get("user")) {
+            (Some(Value::Map(a)), Some(Value::Map(b)), Some(Value::Map(c))) => {
+                assert!(Arc::ptr_eq(a, b));
+                assert!(Arc::ptr_eq(a, c));
+            }
+            _ => panic!("expected maps"),
+        }
+
+        // round trip through `to_ref_document`
+        let records = (0..3).map(|i| json!({ "n": i, "meta": { "v": 1 } })).collect::<Vec<_>>();
+        let value = Dedup::new().dedup(to_value(json!({ "records": records })).unwrap());
+        let document = value.to_ref_document(1).unwrap();
+        let resolved = document.resolve_refs().unwrap();
+        assert_eq!(resolved.get("records"), value.get("records"));
+
+        let cycle = to_value(json!({ "a": { "b": { "$ref": "#/a" } } })).unwrap();
+        assert_eq!(cycle.resolve_refs(), Err(RefError::Cycle("#/a".to_owned())));
+        let missing = to_value(json!({ "a": { "$ref": "#/b" } })).unwrap();
+        assert_eq!(missing.resolve_refs(), Err(RefError::Missing("#/b".to_owned())));
+    }
+
+    #[test]
+    fn custom_backend() {
+        // a backend that interns strings only
+        struct Strings(HashSet<Arc<str>>);
+
+        impl Deduplicator for Strings {
+            fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+                if let Some(s) = self.0.get(&value) {
+                    return s.clone();
+                }
+                self.0.insert(value.clone());
+                value
+            }
+        }
+
+        fn dedup_with<D: Deduplicator>(mut dedup: D, value: Value) -> Value {
+            dedup.dedup(value)
+        }
+
+        let mut strings = Strings(HashSet::new());
+        let document = json!({ "name": "x", "tags": ["x", "y"] });
+        let a = strings.dedup(to_value(&document).unwrap());
+        let b = dedup_with(&mut strings, to_value(&document).unwrap());
+        assert_eq!(a, b);
+        assert_eq!(strings.0.len(), 4);
+        match (a.pointer("/name"), b.pointer("/tags/0")) {
+            (Some(Value::String(x)), Some(Value::String(y))) => assert!(Arc::ptr_eq(x, y)),
+            _ => panic!("expected strings"),
+        }
+        match (a.pointer("/tags"), b.pointer("/tags")) {
+            (Some(Value::Seq(x)), Some(Value::Seq(y))) => assert!(!Arc::ptr_eq(x, y)),
+            _ => panic!("expected sequences"),
+        }
+    }
+
+    #[test]
+    fn middleware() {
+        let document = json!({ "id": "a", "name": "a longer string" });
+        let other = json!({ "id": "a", "name": "a longer string", "n": 1 });
+        let mut dedup = ThresholdFilter::new(StatsCollector::new(Dedup::new()), 4);
+        let a = dedup.dedup(to_value(&document).unwrap());
+        let b = dedup.dedup(to_value(&other).unwrap());
+        match (a.pointer("/name"), b.pointer("/name")) {
+            (Some(Value::String(x)), Some(Value::String(y))) => assert!(Arc::ptr_eq(x, y)),
+            _ => panic!("expected strings"),
+        }
+        // too short to be passed on
+        match (a.pointer("/id"), b.pointer("/id")) {
+            (Some(Value::String(x)), Some(Value::String(y))) => assert!(!Arc::ptr_eq(x, y)),
+            _ => panic!("expected strings"),
+        }
+        // the key "name" and the value of it
+        let stats = dedup.inner().stats(Category::String);
+        assert_eq!((stats.lookups, stats.hits, stats.saved_bytes), (4, 2, 19));
+        assert_eq!(dedup.inner().stats(Category::Object).lookups, 2);
+        assert_eq!(dedup.into_inner().into_inner().iter_strings().count(), 2);
+
+        let mut sampled = Sampled::new(Dedup::new(), Sampling::Hash(1));
+        let a = sampled.dedup(to_value(&document).unwrap());
+        let b = sampled.dedup(to_value(&document).unwrap());
+        match (a, b) {
+            (Value::Map(x), Value::Map(y)) => assert!(Arc::ptr_eq(&x, &y)),
+            _ => panic!("expected maps"),
+        }
+    }
+
+    #[test]
+    fn dedup_hooks() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Log(Mutex<Vec<String>>);
+
+        impl DedupHooks for Log {
+            fn on_insert(&self, value: &Value, size: usize) {
+                if let Value::String(..) = *value {
+                    self.0.lock().unwrap().push(format!("insert {} {}", value, size));
+                }
+            }
+
+            fn on_hit(&self, value: &Value, strong_count: usize) {
+                self.0.lock().unwrap().push(format!("hit {} {}", value, strong_count));
+            }
+        }
+
+        let log = Arc::new(Log::default());
+        let mut dedup = Dedup::new().with_hooks(log.clone());
+        let a = dedup.dedup(to_value(json!(["abc", "abc"])).unwrap());
+        let events = log.0.lock().unwrap().clone();
+        assert_eq!(events, vec!["insert abc 3", "hit abc 3"]);
+        let b = dedup.dedup(to_value(json!(["abc", "abc"])).unwrap());
+        assert_eq!(a, b);
+        assert_eq!(log.0.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut dedup = Dedup::new().with_memory_budget(10, BudgetMode::Freeze);
+        let value = dedup.dedup(to_value(json!(["aaaa", "bbbb", "cccc", "aaaa"])).unwrap());
+        assert_eq!(dedup.budget_used(), Some(8));
+        let mut strings = dedup.iter_strings().map(|(s, _)| s).collect::<Vec<_>>();
+        strings.sort();
+        assert_eq!(strings, vec![Arc::from("aaaa"), Arc::from("bbbb")]);
+        // found entries are still shared
+        match (value.pointer("/0"), value.pointer("/3")) {
+            (Some(Value::String(a)), Some(Value::String(b))) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("expected strings"),
+        }
+
+        let mut dedup = Dedup::new().with_memory_budget(8, BudgetMode::Evict);
+        drop(dedup.dedup(to_value(json!(["aaaa", "bbbb"])).unwrap()));
+        // evicting waits for as many refused entries as were kept by the last eviction
+        let _kept = (0..2)
+            .map(|_| dedup.dedup(to_value(json!(["cccc"])).unwrap()))
+            .collect::<Vec<_>>();
+        let strings = dedup.iter_strings().map(|(s, _)| s).collect::<Vec<_>>();
+        assert_eq!(strings, vec![Arc::from("cccc")]);
+        assert_eq!(dedup.budget_used(), Some(4));
+    }
+
+    #[test]
+    fn dedup_report() {
+        let mut dedup = Dedup::new();
+        dedup.dedup(to_value(json!(["shared", "shared", "shared"])).unwrap());
+        // computed one after the other, as the report holds on to the top entries
+        let text = dedup.to_string();
+        assert_eq!(text, dedup.report().to_string());
+        let report = dedup.report();
+        let strings = report.pointer("/categories/string").unwrap();
+        assert_eq!(strings.get("seen"), Some(&Value::Number(Number::U64(3))));
+        assert_eq!(strings.get("hits"), Some(&Value::Number(Number::U64(2))));
+        assert_eq!(strings.get("entries"), Some(&Value::Number(Number::U64(1))));
+        assert_eq!(report.pointer("/total/seen"), Some(&Value::Number(Number::U64(4))));
+        assert_eq!(report.pointer("/top/0/category"), Some(&Value::String("string".into())));
+        assert_eq!(report.pointer("/top/0/value"), Some(&Value::String("shared".into())));
+        assert!(serde_json::to_string(&report).is_ok());
+    }
+
+    #[test]
+    fn blob_checksums() {
+        let blob = |b: &[u8]| Value::bytes(b.to_vec());
+        let mut dedup = Dedup::new();
+        dedup.dedup(Value::seq(vec![blob(b"before")]));
+        let mut dedup = dedup.with_blob_checksums();
+        let value = dedup.dedup(Value::seq(vec![blob(b"after"), blob(b"before")]));
+        let after = match value.pointer("/0") {
+            Some(Value::Bytes(b)) => b.clone(),
+            _ => panic!("expected bytes"),
+        };
+        assert_eq!(dedup.recorded_checksum(&after), Some(blob_checksum(b"after")));
+        assert_eq!(dedup.checksums.as_ref().unwrap().0.len(), 2);
+        assert!(dedup.verify().is_ok());
+        // a stable hash, FNV-1a
+        assert_eq!(blob_checksum(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(blob_checksum(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        // as if the blob had changed since it was interned
+        let address = Arc::as_ptr(&after) as usize;
+        *dedup.checksums.as_mut().unwrap().0.get_mut(&address).unwrap() ^= 1;
+        let error = dedup.verify().unwrap_err();
+        assert_eq!(error.corrupt, vec![after]);
+        assert_eq!(error.to_string(), "1 interned blob(s) failed their checksum");
+        assert!(Dedup::new().verify().is_ok());
+    }
+
+    #[test]
+    fn deep_size() {
+        use std::mem::size_of;
+
+        let shared: Arc<str> = Arc::from("a string of some length");
+        let once = Value::seq(vec![Value::String(shared.clone()), Value::String(shared.clone())]);
+        let twice = Value::seq(vec![
+            Value::String(shared.clone()),
+            Value::String(Arc::from(&*shared)),
+        ]);
+        let string = 2 * size_of::<usize>() + shared.len();
+        assert_eq!(twice.deep_size(), once.deep_size() + string);
+        assert!(once.deep_size() > size_of::<Value>() + 2 * size_of::<Value>() + string);
+
+        let mut dedup = Dedup::new();
+        let empty = dedup.size();
+        let records = (0..10).map(|i| json!({ "id": i, "kind": "event" })).collect::<Vec<_>>();
+        let value = dedup.dedup(to_value(&records).unwrap());
+        // the tables hold all of the value
+        assert!(dedup.size() - empty >= value.deep_size() - size_of::<Value>());
+        assert!(value.deep_size() < to_value(&records).unwrap().deep_size());
+    }
+
+    #[test]
+    fn value_index() {
+        let value = to_value(json!({ "a": { "b": [1, 2], "c/d": true }, "e": "x" })).unwrap();
+        assert_eq!(value.pointer("/a/b/1"), Some(&Value::Number(Number::U64(2))));
+        assert_eq!(value.pointer("/a/c~1d"), Some(&Value::Bool(true)));
+        assert_eq!(value.pointer("/a/b/2"), None);
+        assert_eq!(value.pointer("a"), None);
+
+        let mut index = ValueIndex::new(value.clone());
+        assert_eq!(index.pointer("/a/b/0"), Some(&Value::Number(Number::U64(1))));
+        assert_eq!(index.pointer("/e"), value.pointer("/e"));
+        assert_eq!(index.cache.len(), 2);
+        *index.pointer_mut("/a/b/0").unwrap() = Value::Number(Number::U64(7));
+        assert_eq!(index.pointer("/a/b/0"), Some(&Value::Number(Number::U64(7))));
+        assert!(index.cache.contains_key("/e"));
+        // the original is not changed
+        assert_eq!(value.pointer("/a/b/0"), Some(&Value::Number(Number::U64(1))));
+
+        *index.value_mut() = Value::Unit;
+        assert_eq!(index.pointer("/e"), None);
+        assert_eq!(index.cache.len(), 1);
+    }
+
+    #[test]
+    fn edit_guard() {
+        let mut dedup = Dedup::new();
+        let original = dedup.dedup(to_value(json!({ "a": { "x": 1 }, "b": "old" })).unwrap());
+        let other = dedup.dedup(to_value(json!(["new"])).unwrap());
+
+        let mut value = original.clone();
+        {
+            let mut guard = value.edit(&mut dedup);
+            *guard.get_mut("b").unwrap() = Value::string("new".to_owned());
+            // copies `a`, but leaves it equal to the original
+            *guard.get_mut("a").unwrap().get_mut("x").unwrap() = Value::Number(Number::U64(1));
+        }
+        assert_eq!(value, to_value(json!({ "a": { "x": 1 }, "b": "new" })).unwrap());
+        assert_eq!(original.get("b"), Some(&Value::string("old".to_owned())));
+        match (value.get("a"), original.get("a")) {
+            (Some(Value::Map(x)), Some(Value::Map(y))) => assert!(Arc::ptr_eq(x, y)),
+            _ => panic!("expected maps"),
+        }
+        match (value.get("b"), &other) {
+            (Some(Value::String(x)), Value::Seq(y)) => match y[0] {
+                Value::String(ref y) => assert!(Arc::ptr_eq(x, y)),
+                _ => panic!("expected a string"),
+            },
+            _ => panic!("expected a string and a sequence"),
+        }
+
+        // reading through the guard does not dedup
+        let mut plain = Value::string("plain".to_owned());
+        assert_eq!(plain.edit(&mut dedup).get("x"), None);
+        assert!(dedup.iter_strings().all(|(s, _)| &*s != "plain"));
+    }
+
+    #[test]
+    fn dedup_record() {
+        let input = json!(
+            [{ "x": 0, "y":0},{ "x": 0, "y":1},{ "x": 1, "y":1},{ "x": 1, "y":0}]
+        );
+        let value: Value = to_value(input).unwrap();
+        let mut dedup = Dedup::new();
+        let result = dedup.dedup(value);
+        println!("{}", dedup);
+        println!("{}", result);
+
+        let mut strings: Vec<&str> = dedup.strings.iter().map(|x| x.as_ref()).collect();
+        strings.sort();
+        assert_eq!(strings, vec!["x", "y"]);
+
+        if let Value::Seq(x) = result {
+            if let Value::Map(ref a) = x[0] {
+                if let Value::Map(ref b) = x[1] {
+                    assert!(Arc::ptr_eq(&a.as_ref().0, &b.as_ref().0));
+                } else {
+                    panic!();
+                }
+            } else {
+                panic!();
+            }
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn savings_report() {
+        let input = json!(
+            [{ "name": "alice", "x": 0 }, { "name": "alice", "x": 0 }, { "name": "bob", "x": 1 }]
+        );
+        let mut dedup = Dedup::new();
+        let result = dedup.dedup(to_value(input).unwrap());
+        let report = dedup.savings_report();
+
+        assert_eq!(report.strings.seen, 9);
+        assert_eq!(report.strings.entries, 4);
+        assert_eq!(report.strings.input_bytes, 4 * 3 + 3 + 5 * 2 + 3);
+        assert_eq!(report.strings.stored_bytes, 4 + 1 + 5 + 3);
+        assert!(report.strings.ratio() > 2.0);
+        assert_eq!(report.objects.entries, 2);
+        assert_eq!(report.objects.seen, 3);
+        assert!(report.total().saved_bytes() > 0);
+
+        // the repeated record, and the key vector shared by both distinct records
+        assert_eq!(report.top[0].category, Category::Object);
+        assert_eq!(report.top[0].references, 2);
+        assert_eq!(report.top[1].category, Category::Seq);
+        assert_eq!(report.top[1].references, 2);
+        drop(result);
+    }
+
+    #[test]
+    fn hash_consing() {
+        let factory = HcFactory::new();
+        let a = factory.string("a");
+        let seq1 = factory.seq(vec![a.clone(), factory.unit()]);
+        let seq2 = factory.seq(vec![factory.string("a"), factory.unit()]);
+        assert!(a.ptr_eq(&factory.string("a")));
+        assert!(seq1.ptr_eq(&seq2));
+        assert_eq!(seq1.hash_code(), seq2.hash_code());
+        assert_eq!(factory.len(), 3);
+
+        let map = factory.map(vec![(a.clone(), seq1.clone())]);
+        let mut set = HashSet::new();
+        set.insert(map.clone());
+        assert!(set.contains(&factory.intern(map.value().clone())));
+
+        // values from another factory compare by content
+        let other = HcFactory::new();
+        assert_eq!(other.string("a"), a);
+        assert!(!other.string("a").ptr_eq(&a));
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn dedup_metrics() {
+        use self::metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mut dedup = Dedup::new();
+            dedup.dedup(to_value(json!(["x", "x", "x", "y"])).unwrap());
+        });
+        let find = |name: &str| {
+            snapshotter
+                .snapshot()
+                .into_vec()
+                .into_iter()
+                .find(|(key, _, _, _)| {
+                    key.key().name() == name
+                        && key.key().labels().any(|l| l.value() == "string")
+                })
+                .map(|(_, _, _, value)| value)
+        };
+        assert_eq!(find("serde_value_dedup_lookups_total"), Some(DebugValue::Counter(4)));
+        assert_eq!(find("serde_value_dedup_hits_total"), Some(DebugValue::Counter(2)));
+        assert_eq!(find("serde_value_dedup_bytes_saved_total"), Some(DebugValue::Counter(2)));
+        assert_eq!(find("serde_value_dedup_entries"), Some(DebugValue::Gauge(2.0.into())));
+        assert_eq!(find("serde_value_dedup_hit_ratio"), Some(DebugValue::Gauge(0.5.into())));
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn dedup_tracing() {
+        use self::tracing_subscriber::fmt::MakeWriter;
+        use std::io;
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl<'a> MakeWriter<'a> for Buffer {
+            type Writer = Buffer;
+            fn make_writer(&'a self) -> Buffer {
+                self.clone()
+            }
+        }
+
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(buffer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let value = to_value(json!(["x", "x"])).unwrap();
+            let value = Dedup::new().dedup(value);
+            assert!(value.deserialize_into::<Vec<u32>>().is_err());
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        for expected in &[
+            "to_value{ty=\"serde_json::value::Value\"}",
+            "serialized nodes=3",
+            "deduplicated lookups=3 hits=1 added=2",
+            "deserialize_into{ty=\"alloc::vec::Vec<u32>\" nodes=3}",
+            "deserialization failed",
+        ] {
+            assert!(output.contains(expected), "{:?} not in {}", expected, output);
+        }
+    }
+
+    #[test]
+    fn iterate_tables() {
+        let input = json!([{ "a": "x" }, { "a": "x" }, [1, 2], b"xy".as_ref()]);
+        let mut dedup = Dedup::new();
+        let result = dedup.dedup(to_value(input).unwrap());
+
+        let mut strings: Vec<(String, usize)> = dedup
+            .iter_strings()
+            .map(|(s, n)| (s.to_string(), n))
+            .collect();
+        strings.sort();
+        // one reference from the table, one from the shared object
+        assert_eq!(strings, vec![("a".to_owned(), 2), ("x".to_owned(), 2)]);
+        assert_eq!(dedup.iter_objects().map(|x| x.1).collect::<Vec<_>>(), vec![3]);
+        // the outer seq, the map keys, [1, 2] and the serialized byte string
+        assert_eq!(dedup.iter_seqs().count(), 4);
+        assert_eq!(dedup.iter_blobs().count(), 0);
+        drop(result);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn dedup_stream_and_sink() {
+        use self::futures::executor::block_on;
+        use self::futures::{stream, SinkExt, StreamExt};
+
+        fn assert_shared(values: &[Value]) {
+            match (&values[0], &values[1]) {
+                (Value::String(a), Value::String(b)) => assert!(Arc::ptr_eq(a, b)),
+                _ => panic!(),
+            }
+        }
+
+        let values = vec![Value::string("a".into()), Value::string("a".into())];
+        let mut dedup = Dedup::new();
+        let out: Vec<Value> =
+            block_on(DedupStream::new(stream::iter(values.clone()), &mut dedup).collect());
+        assert_shared(&out);
+
+        let mut sink = DedupSink::new(Vec::<Value>::new(), Dedup::new());
+        let mut input = stream::iter(values.into_iter().map(Ok));
+        block_on(sink.send_all(&mut input)).unwrap();
+        let (out, _) = sink.into_inner();
+        assert_shared(&out);
     }
 
     use std::io::BufRead;
@@ -734,8 +5327,11 @@ mod dedup_tests {
             value
         }).collect();
         drop(lines);
-        let mut strings = dedup.strings();
-        strings = strings.iter().cloned().filter(|x| x.1 > 10).collect::<Vec<_>>();
+        let mut strings = dedup
+            .iter_strings()
+            .filter(|x| x.1 > 10)
+            .map(|(s, n)| (s.to_string(), n))
+            .collect::<Vec<_>>();
         strings.sort_by_key(|x| x.1);
         println!("{:?}", strings);
         println!("{}", dedup.size());
@@ -0,0 +1,165 @@
+//! Rust source code that rebuilds a value, for freezing observed data into tests.
+
+use std::fmt::{self, Write};
+
+use {Number, Value};
+
+const INDENT: &str = "    ";
+
+macro_rules! float_literal {
+    ($out:expr, $ty:ident, $v:expr) => {
+        if $v.is_nan() {
+            $out.write_str(concat!(stringify!($ty), "::NAN"))
+        } else if $v == $ty::INFINITY {
+            $out.write_str(concat!(stringify!($ty), "::INFINITY"))
+        } else if $v == $ty::NEG_INFINITY {
+            $out.write_str(concat!(stringify!($ty), "::NEG_INFINITY"))
+        } else {
+            // the debug format is the shortest literal that reads back exactly
+            write!($out, "{:?}", $v)
+        }
+    };
+}
+
+fn newline(out: &mut String, depth: usize) -> fmt::Result {
+    out.write_char('\n')?;
+    for _ in 0..depth {
+        out.write_str(INDENT)?;
+    }
+    Ok(())
+}
+
+fn elements<'a, I: ExactSizeIterator<Item = &'a Value>>(
+    out: &mut String,
+    values: I,
+    depth: usize,
+) -> fmt::Result {
+    if values.len() == 0 {
+        return out.write_str("vec![]");
+    }
+    out.write_str("vec![")?;
+    for value in values {
+        newline(out, depth + 1)?;
+        literal(out, value, depth + 1)?;
+        out.write_char(',')?;
+    }
+    newline(out, depth)?;
+    out.write_char(']')
+}
+
+fn number(out: &mut String, n: Number) -> fmt::Result {
+    match n {
+        Number::U8(v) => write!(out, "Number::U8({})", v),
+        Number::U16(v) => write!(out, "Number::U16({})", v),
+        Number::U32(v) => write!(out, "Number::U32({})", v),
+        Number::U64(v) => write!(out, "Number::U64({})", v),
+        Number::I8(v) => write!(out, "Number::I8({})", v),
+        Number::I16(v) => write!(out, "Number::I16({})", v),
+        Number::I32(v) => write!(out, "Number::I32({})", v),
+        Number::I64(v) => write!(out, "Number::I64({})", v),
+        Number::F32(v) => {
+            out.write_str("Number::F32(")?;
+            float_literal!(out, f32, v)?;
+            out.write_char(')')
+        }
+        Number::F64(v) => {
+            out.write_str("Number::F64(")?;
+            float_literal!(out, f64, v)?;
+            out.write_char(')')
+        }
+    }
+}
+
+fn literal(out: &mut String, value: &Value, depth: usize) -> fmt::Result {
+    match *value {
+        Value::Unit => out.write_str("Value::Unit"),
+        Value::Bool(v) => write!(out, "Value::Bool({})", v),
+        Value::Number(n) => {
+            out.write_str("Value::Number(")?;
+            number(out, n)?;
+            out.write_char(')')
+        }
+        #[cfg(feature = "half")]
+        Value::F16(v) => {
+            out.write_str("Value::F16(half::f16::from_f32(")?;
+            float_literal!(out, f32, v.to_f32())?;
+            out.write_str("))")
+        }
+        Value::Char(v) => write!(out, "Value::Char({:?})", v),
+        Value::Option(None) => out.write_str("Value::Option(None)"),
+        Value::Option(Some(ref v)) => {
+            out.write_str("Value::Option(Some(Box::new(")?;
+            literal(out, v, depth)?;
+            out.write_str(")))")
+        }
+        Value::Newtype(ref v) => {
+            out.write_str("Value::Newtype(Box::new(")?;
+            literal(out, v, depth)?;
+            out.write_str("))")
+        }
+        Value::UnitStruct(ref v) => write!(out, "Value::UnitStruct({:?}.into())", v),
+        Value::String(ref v) => write!(out, "Value::String({:?}.into())", v),
+        Value::Bytes(ref v) => write!(out, "Value::Bytes(Arc::new(vec!{:?}))", v),
+        Value::Seq(ref v) => {
+            out.write_str("Value::Seq(Arc::new(")?;
+            elements(out, v.iter(), depth)?;
+            out.write_str("))")
+        }
+        Value::Tuple(ref v) => {
+            out.write_str("Value::Tuple(Arc::new(")?;
+            elements(out, v.iter(), depth)?;
+            out.write_str("))")
+        }
+        Value::Map(ref kv) if kv.keys().is_empty() => {
+            out.write_str("Value::map_from_sorted_iter(vec![])")
+        }
+        Value::Map(ref kv) => {
+            out.write_str("Value::map_from_sorted_iter(vec![")?;
+            for (key, value) in kv.keys().iter().zip(kv.values()) {
+                newline(out, depth + 1)?;
+                out.write_char('(')?;
+                literal(out, key, depth + 1)?;
+                out.write_str(", ")?;
+                literal(out, value, depth + 1)?;
+                out.write_str("),")?;
+            }
+            newline(out, depth)?;
+            out.write_str("])")
+        }
+        Value::Set(ref v) if v.is_empty() => out.write_str("Value::Set(Default::default())"),
+        Value::Set(ref v) => {
+            out.write_str("Value::Set(Arc::new(")?;
+            elements(out, v.iter(), depth)?;
+            out.write_str(".into_iter().collect()))")
+        }
+        Value::Ext { tag, ref value } => {
+            write!(out, "Value::Ext {{ tag: {}, value: Box::new(", tag)?;
+            literal(out, value, depth)?;
+            out.write_str(") }")
+        }
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { codec, ref data } => write!(
+            out,
+            "Value::CompressedBytes {{ codec: Codec::{:?}, data: Arc::new(vec!{:?}) }}",
+            codec, data
+        ),
+        // a literal cannot map a file, but the bytes behave the same
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(ref v) => write!(out, "Value::Bytes(Arc::new(vec!{:?}))", &v[..]),
+    }
+}
+
+impl Value {
+    /// Rust source code for an expression that evaluates to this value.
+    ///
+    /// Meant for pasting observed payloads into unit tests or emitting them from build
+    /// scripts. The expression names `Value`, `Number`, `Arc` and, for compressed bytes,
+    /// `Codec`, which need to be in scope where it is used. Nested sequences and maps are
+    /// spread over indented lines, floats are written so that they read back exactly, and
+    /// mapped bytes become plain `Bytes`.
+    pub fn to_rust_literal(&self) -> String {
+        let mut out = String::new();
+        literal(&mut out, self, 0).expect("writing to a string");
+        out
+    }
+}
@@ -0,0 +1,89 @@
+//! Deep merging of values, and partial updates of typed values through it.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use serde::de::{self, DeserializeOwned};
+use serde::Serialize;
+
+use path::transparent;
+use {to_value, DeserializerError, Value, KV};
+
+fn merge_maps(target: &Arc<KV>, patch: &KV) -> Value {
+    let (keys, values) = (target.keys(), target.values());
+    let mut merged_keys = Vec::with_capacity(keys.len() + patch.keys().len());
+    let mut merged_values = Vec::with_capacity(merged_keys.capacity());
+    let (mut i, mut j) = (0, 0);
+    while i < keys.len() || j < patch.keys().len() {
+        let order = match (keys.get(i), patch.keys().get(j)) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        match order {
+            Ordering::Less => {
+                merged_keys.push(keys[i].clone());
+                merged_values.push(values[i].clone());
+                i += 1;
+            }
+            Ordering::Equal => {
+                merged_keys.push(keys[i].clone());
+                merged_values.push(values[i].merge(&patch.values()[j]));
+                i += 1;
+                j += 1;
+            }
+            Ordering::Greater => {
+                merged_keys.push(patch.keys()[j].clone());
+                merged_values.push(patch.values()[j].clone());
+                j += 1;
+            }
+        }
+    }
+    if merged_keys.len() == keys.len() {
+        // no new keys, so the key vector stays shared
+        Value::Map(target.with_values(merged_values))
+    } else {
+        Value::Map(Arc::new(KV::from_sorted_vecs(merged_keys, merged_values)))
+    }
+}
+
+impl Value {
+    /// This value with `patch` merged into it.
+    ///
+    /// Where both are maps, the entries of `patch` are merged into those of `self` key by
+    /// key, and keys only in `patch` are added. Anywhere else, the value from `patch`
+    /// replaces the one in `self`, so a patch can set a field to `None` but cannot remove
+    /// it. Options, newtypes and extension values around a map in `self` are kept.
+    ///
+    /// Parts of `self` that the patch does not touch stay shared with it.
+    pub fn merge(&self, patch: &Value) -> Value {
+        let patch_map = match *transparent(patch) {
+            Value::Map(ref kv) => kv,
+            _ => return patch.clone(),
+        };
+        match *self {
+            Value::Map(ref kv) => merge_maps(kv, patch_map),
+            Value::Option(Some(ref v)) => Value::Option(Some(Box::new(v.merge(patch)))),
+            Value::Newtype(ref v) => Value::Newtype(Box::new(v.merge(patch))),
+            Value::Ext { tag, ref value } => Value::Ext {
+                tag,
+                value: Box::new(value.merge(patch)),
+            },
+            _ => patch.clone(),
+        }
+    }
+}
+
+/// Applies a partial update to `target`: serializes it, merges `patch` into the result with
+/// `Value::merge` and deserializes it back.
+///
+/// This is the usual semantics of PATCH requests: fields missing from the patch keep their
+/// values, nested structs are updated field by field. `target` is left unchanged on error.
+pub fn update_from_value<T>(target: &mut T, patch: &Value) -> Result<(), DeserializerError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let current = to_value(&*target).map_err(<DeserializerError as de::Error>::custom)?;
+    *target = T::deserialize(current.merge(patch))?;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! `Deduplicator`s wrapping another one, so that policies compose with any backend.
+//!
+//! Each wrapper implements only the per-category methods and relies on the provided
+//! `Deduplicator::dedup`, so the `dedup` of the wrapped deduplicator is not called. A wrapped
+//! `Dedup` therefore ignores its `DedupPolicy` and does not reuse buffers.
+
+use std::sync::Arc;
+
+use report::{blob_size, object_size, seq_size, string_size, Category};
+use sampling::Sampler;
+use {Deduplicator, Sampling, Value, KV};
+
+/// Leaves entries smaller than a number of bytes alone, as sharing them saves little.
+///
+/// Sizes are measured as in `Dedup::savings_report`.
+#[derive(Clone, Debug)]
+pub struct ThresholdFilter<D> {
+    inner: D,
+    min_bytes: usize,
+}
+
+impl<D: Deduplicator> ThresholdFilter<D> {
+    pub fn new(inner: D, min_bytes: usize) -> ThresholdFilter<D> {
+        ThresholdFilter { inner, min_bytes }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Deduplicator> Deduplicator for ThresholdFilter<D> {
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        if string_size(&value) < self.min_bytes {
+            return value;
+        }
+        self.inner.dedup_string(value)
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        if blob_size(&value) < self.min_bytes {
+            return value;
+        }
+        self.inner.dedup_blob(value)
+    }
+
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        if seq_size(&value) < self.min_bytes {
+            return value;
+        }
+        self.inner.dedup_seq(value)
+    }
+
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        if object_size(&value) < self.min_bytes {
+            return value;
+        }
+        self.inner.dedup_map(value)
+    }
+}
+
+/// Passes only a sample of the entries on, leaving the others unshared.
+///
+/// With `Sampling::Hash`, an entry is either always or never passed on, so equal entries
+/// end up shared whenever they are sampled at all.
+#[derive(Clone, Debug)]
+pub struct Sampled<D> {
+    inner: D,
+    sampler: Sampler,
+}
+
+impl<D: Deduplicator> Sampled<D> {
+    /// Panics if the rate is zero.
+    pub fn new(inner: D, sampling: Sampling) -> Sampled<D> {
+        Sampled {
+            inner,
+            sampler: Sampler::new(sampling),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Deduplicator> Deduplicator for Sampled<D> {
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        if !self.sampler.admit(&*value) {
+            return value;
+        }
+        self.inner.dedup_string(value)
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        if !self.sampler.admit(&*value) {
+            return value;
+        }
+        self.inner.dedup_blob(value)
+    }
+
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        if !self.sampler.admit(&*value) {
+            return value;
+        }
+        self.inner.dedup_seq(value)
+    }
+
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        if !self.sampler.admit(&*value) {
+            return value;
+        }
+        self.inner.dedup_map(value)
+    }
+}
+
+/// Lookups of one category seen by a `StatsCollector`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LookupStats {
+    /// number of entries passed on
+    pub lookups: u64,
+    /// number of entries replaced by an equal one that was already there
+    pub hits: u64,
+    /// bytes of the entries that were replaced
+    pub saved_bytes: u64,
+}
+
+/// Counts the lookups and hits of the deduplicator it wraps.
+#[derive(Clone, Debug, Default)]
+pub struct StatsCollector<D> {
+    inner: D,
+    blobs: LookupStats,
+    strings: LookupStats,
+    seqs: LookupStats,
+    objects: LookupStats,
+}
+
+fn count<T: ?Sized>(stats: &mut LookupStats, candidate: &Arc<T>, result: &Arc<T>, size: usize) {
+    stats.lookups += 1;
+    if !Arc::ptr_eq(candidate, result) {
+        stats.hits += 1;
+        stats.saved_bytes += size as u64;
+    }
+}
+
+impl<D: Deduplicator> StatsCollector<D> {
+    pub fn new(inner: D) -> StatsCollector<D> {
+        StatsCollector {
+            inner,
+            blobs: LookupStats::default(),
+            strings: LookupStats::default(),
+            seqs: LookupStats::default(),
+            objects: LookupStats::default(),
+        }
+    }
+
+    pub fn stats(&self, category: Category) -> LookupStats {
+        match category {
+            Category::Blob => self.blobs,
+            Category::String => self.strings,
+            Category::Seq => self.seqs,
+            Category::Object => self.objects,
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: Deduplicator> Deduplicator for StatsCollector<D> {
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        let size = string_size(&value);
+        let result = self.inner.dedup_string(value.clone());
+        count(&mut self.strings, &value, &result, size);
+        result
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        let size = blob_size(&value);
+        let result = self.inner.dedup_blob(value.clone());
+        count(&mut self.blobs, &value, &result, size);
+        result
+    }
+
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        let size = seq_size(&value);
+        let result = self.inner.dedup_seq(value.clone());
+        count(&mut self.seqs, &value, &result, size);
+        result
+    }
+
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        let size = object_size(&value);
+        let result = self.inner.dedup_map(value.clone());
+        count(&mut self.objects, &value, &result, size);
+        result
+    }
+}
@@ -0,0 +1,133 @@
+//! Byte blobs backed by memory mapped files, behind the `mmap` feature.
+//!
+//! A `Value::MappedBytes` node refers to a region of a file instead of holding its bytes, so
+//! metadata can reference large binary attachments that are paged in by the operating system
+//! only when read. It serializes and deserializes exactly like `Bytes`.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::path::Path;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+/// A region of a read only memory mapped file, cheap to clone and narrow.
+///
+/// Compared and hashed by content, like `Bytes`. `Dedup` never interns mapped blobs, as that
+/// would read them in, but clones share the same mapping.
+#[derive(Clone)]
+pub struct MappedBytes {
+    map: Arc<Mmap>,
+    start: usize,
+    end: usize,
+}
+
+impl MappedBytes {
+    /// Maps the whole file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other process, while any
+    /// value referring to the mapping exists. See `memmap2::Mmap::map`.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedBytes> {
+        let file = File::open(path)?;
+        Mmap::map(&file).map(MappedBytes::from)
+    }
+
+    /// A narrower region, with `range` relative to this one, or `None` if it is out of
+    /// bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Option<MappedBytes> {
+        let start = match range.start_bound() {
+            Bound::Included(&i) => i,
+            Bound::Excluded(&i) => i.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&i) => i.checked_add(1)?,
+            Bound::Excluded(&i) => i,
+            Bound::Unbounded => self.len(),
+        };
+        if start > end || end > self.len() {
+            return None;
+        }
+        Some(MappedBytes {
+            map: self.map.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        })
+    }
+
+    pub(crate) fn from_parts(map: Arc<Mmap>, offset: usize, len: usize) -> MappedBytes {
+        MappedBytes {
+            map,
+            start: offset,
+            end: offset + len,
+        }
+    }
+
+    /// The mapping this is a region of.
+    pub fn shared(&self) -> &Arc<Mmap> {
+        &self.map
+    }
+
+    /// Where this region starts in the mapping.
+    pub fn offset(&self) -> usize {
+        self.start
+    }
+}
+
+impl From<Mmap> for MappedBytes {
+    fn from(map: Mmap) -> MappedBytes {
+        let end = map.len();
+        MappedBytes {
+            map: Arc::new(map),
+            start: 0,
+            end,
+        }
+    }
+}
+
+impl Deref for MappedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[self.start..self.end]
+    }
+}
+
+impl PartialEq for MappedBytes {
+    fn eq(&self, rhs: &MappedBytes) -> bool {
+        (Arc::ptr_eq(&self.map, &rhs.map) && self.start == rhs.start && self.end == rhs.end)
+            || **self == **rhs
+    }
+}
+
+impl Eq for MappedBytes {}
+
+impl PartialOrd for MappedBytes {
+    fn partial_cmp(&self, rhs: &MappedBytes) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl Ord for MappedBytes {
+    fn cmp(&self, rhs: &MappedBytes) -> Ordering {
+        (**self).cmp(&**rhs)
+    }
+}
+
+impl Hash for MappedBytes {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        (**self).hash(hasher)
+    }
+}
+
+impl fmt::Debug for MappedBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedBytes({}..{})", self.start, self.end)
+    }
+}
@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use float::OrderedFloat;
+use Value;
+
+/// A numeric scalar that remembers its original width, but compares and hashes by value.
+///
+/// This is the content of `Value::Number`, so `Value::Number(Number::U8(1))` equals
+/// `Value::Number(Number::F64(1.0))` and both hash alike, while serializing each one still
+/// produces its own width. As `Dedup` interns equal values once, a sequence of `[1u8]` may
+/// come back as the `[1.0f64]` it saw first.
+#[derive(Clone, Copy, Debug)]
+pub enum Number {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+/// A `Number` without its width.
+pub(crate) enum Num {
+    Int(i128),
+    Float(f64),
+}
+
+/// Compares an integer with a float by exact numeric value. NaN sorts above everything.
+fn cmp_int_float(i: i128, f: f64) -> Ordering {
+    const LIMIT: f64 = 170_141_183_460_469_231_731_687_303_715_884_105_728.0; // 2^127
+    if f.is_nan() || f >= LIMIT {
+        return Ordering::Less;
+    }
+    if f < -LIMIT {
+        return Ordering::Greater;
+    }
+    let trunc = f.trunc();
+    i.cmp(&(trunc as i128)).then_with(|| 0f64.partial_cmp(&(f - trunc)).unwrap())
+}
+
+impl Number {
+    pub(crate) fn widen(self) -> Num {
+        match self {
+            Number::U8(v) => Num::Int(v.into()),
+            Number::U16(v) => Num::Int(v.into()),
+            Number::U32(v) => Num::Int(v.into()),
+            Number::U64(v) => Num::Int(v.into()),
+            Number::I8(v) => Num::Int(v.into()),
+            Number::I16(v) => Num::Int(v.into()),
+            Number::I32(v) => Num::Int(v.into()),
+            Number::I64(v) => Num::Int(v.into()),
+            Number::F32(v) => Num::Float(v.into()),
+            Number::F64(v) => Num::Float(v),
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Number::F32(..) | Number::F64(..))
+    }
+
+    /// The value as an `i64`, if it is an integer (of any width) that fits.
+    pub fn as_i64(self) -> Option<i64> {
+        match self.widen() {
+            Num::Int(v) if v >= i64::MIN.into() && v <= i64::MAX.into() => Some(v as i64),
+            _ => None,
+        }
+    }
+
+    /// The value as a `u64`, if it is an integer (of any width) that fits.
+    pub fn as_u64(self) -> Option<u64> {
+        match self.widen() {
+            Num::Int(v) if v >= 0 && v <= u64::MAX.into() => Some(v as u64),
+            _ => None,
+        }
+    }
+
+    /// The value as an `f64`, possibly rounded.
+    pub fn as_f64(self) -> f64 {
+        match self.widen() {
+            Num::Int(v) => v as f64,
+            Num::Float(v) => v,
+        }
+    }
+
+    /// The value as an `i128`, if it is an integer or a float with an integral value.
+    pub(crate) fn integral(self) -> Option<i128> {
+        match self.widen() {
+            Num::Int(v) => Some(v),
+            Num::Float(v) if v.fract() == 0.0 && cmp_int_float(v as i128, v).is_eq() => {
+                Some(v as i128)
+            }
+            Num::Float(_) => None,
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Value {
+        n.into_value()
+    }
+}
+
+impl Value {
+    /// Returns the numeric content of this value, if it is a `Number` (or a half float).
+    pub fn as_number(&self) -> Option<Number> {
+        match *self {
+            Value::Number(n) => Some(n),
+            #[cfg(feature = "half")]
+            Value::F16(v) => Some(Number::F32(v.to_f32())),
+            _ => None,
+        }
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, rhs: &Self) -> Ordering {
+        match (self.widen(), rhs.widen()) {
+            (Num::Int(a), Num::Int(b)) => a.cmp(&b),
+            (Num::Float(a), Num::Float(b)) => OrderedFloat(a).cmp(&OrderedFloat(b)),
+            (Num::Int(a), Num::Float(b)) => cmp_int_float(a, b),
+            (Num::Float(a), Num::Int(b)) => cmp_int_float(b, a).reverse(),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.cmp(rhs) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        // integral floats must hash like the equal integer
+        match self.integral() {
+            Some(v) => v.hash(hasher),
+            None => OrderedFloat(self.as_f64()).hash(hasher),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::U8(v) => v.fmt(f),
+            Number::U16(v) => v.fmt(f),
+            Number::U32(v) => v.fmt(f),
+            Number::U64(v) => v.fmt(f),
+            Number::I8(v) => v.fmt(f),
+            Number::I16(v) => v.fmt(f),
+            Number::I32(v) => v.fmt(f),
+            Number::I64(v) => v.fmt(f),
+            Number::F32(v) => v.fmt(f),
+            Number::F64(v) => v.fmt(f),
+        }
+    }
+}
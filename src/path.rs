@@ -0,0 +1,296 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use {Value, KV};
+
+/// Appends a segment to a JSON pointer style path, escaping `~` and `/`.
+pub(crate) fn join(path: &str, segment: &str) -> String {
+    let mut res = String::with_capacity(path.len() + segment.len() + 1);
+    res.push_str(path);
+    res.push('/');
+    for c in segment.chars() {
+        match c {
+            '~' => res.push_str("~0"),
+            '/' => res.push_str("~1"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// The path segment naming a map key. String keys are used verbatim.
+pub(crate) fn key_segment(key: &Value) -> String {
+    match *key {
+        Value::String(ref s) => s.to_string(),
+        ref other => other.to_string(),
+    }
+}
+
+/// Splits a JSON pointer into unescaped segments. The empty pointer addresses the root;
+/// anything else must start with `/`.
+pub(crate) fn split(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(Vec::new());
+    }
+    if !pointer.starts_with('/') {
+        return None;
+    }
+    Some(
+        pointer[1..]
+            .split('/')
+            .map(|s| s.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+    )
+}
+
+/// The node addressed by already split path segments. Options, newtypes and extension
+/// wrappers are looked through, as they do not appear in paths.
+pub(crate) fn lookup<'a>(mut value: &'a Value, segments: &[String]) -> Option<&'a Value> {
+    for segment in segments {
+        value = match *transparent(value) {
+            Value::Seq(ref v) | Value::Tuple(ref v) => v.get(segment.parse::<usize>().ok()?)?,
+            Value::Map(ref kv) => &kv.1[key_index(kv, segment)?],
+            _ => return None,
+        };
+    }
+    Some(transparent(value))
+}
+
+/// Like `lookup`, but unsharing the containers on the way with `Arc::make_mut`.
+pub(crate) fn lookup_mut<'a>(value: &'a mut Value, segments: &[String]) -> Option<&'a mut Value> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Some(transparent_mut(value)),
+    };
+    let child = match *transparent_mut(value) {
+        Value::Seq(ref mut v) | Value::Tuple(ref mut v) => {
+            let i = segment.parse::<usize>().ok().filter(|&i| i < v.len())?;
+            &mut Arc::make_mut(v)[i]
+        }
+        Value::Map(ref mut kv) => {
+            let i = key_index(kv, segment)?;
+            &mut Arc::make_mut(kv).1[i]
+        }
+        _ => return None,
+    };
+    lookup_mut(child, rest)
+}
+
+fn transparent_mut(value: &mut Value) -> &mut Value {
+    match *value {
+        Value::Option(Some(ref mut v)) | Value::Newtype(ref mut v) => transparent_mut(v),
+        Value::Ext { ref mut value, .. } => transparent_mut(value),
+        ref mut other => other,
+    }
+}
+
+pub(crate) fn transparent(mut value: &Value) -> &Value {
+    loop {
+        value = match *value {
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => v,
+            Value::Ext { ref value, .. } => value,
+            _ => return value,
+        }
+    }
+}
+
+fn key_index(kv: &KV, segment: &str) -> Option<usize> {
+    if let Some(i) = kv.string_key_index(segment) {
+        return Some(i);
+    }
+    kv.0.iter().position(|k| match *k {
+        Value::String(..) => false,
+        ref k => key_segment(k) == segment,
+    })
+}
+
+/// Replaces the node at `segments` by `f` applied to it, rebuilding only the nodes on the way.
+fn update<F: FnOnce(Value) -> Value>(value: &Value, segments: &[String], f: F) -> Option<Value> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Some(f(value.clone())),
+    };
+    Some(match *value {
+        Value::Option(Some(ref v)) => Value::Option(Some(Box::new(update(v, segments, f)?))),
+        Value::Newtype(ref v) => Value::Newtype(Box::new(update(v, segments, f)?)),
+        Value::Ext { tag, ref value } => Value::Ext {
+            tag,
+            value: Box::new(update(value, segments, f)?),
+        },
+        Value::Seq(ref v) => Value::Seq(update_element(v, segment, rest, f)?),
+        Value::Tuple(ref v) => Value::Tuple(update_element(v, segment, rest, f)?),
+        Value::Map(ref kv) => {
+            let i = key_index(kv, segment)?;
+            let child = update(&kv.1[i], rest, f)?;
+            let mut values = kv.1.clone();
+            values[i] = child;
+            Value::Map(kv.with_values(values))
+        }
+        _ => return None,
+    })
+}
+
+fn update_element<F: FnOnce(Value) -> Value>(
+    elements: &[Value],
+    segment: &str,
+    rest: &[String],
+    f: F,
+) -> Option<Arc<Vec<Value>>> {
+    let i = segment.parse::<usize>().ok()?;
+    let child = update(elements.get(i)?, rest, f)?;
+    let mut elements = elements.to_vec();
+    elements[i] = child;
+    Some(Arc::new(elements))
+}
+
+/// A JSON pointer built segment by segment, so keys are always escaped. Usually written
+/// with the `path!` macro.
+///
+/// It dereferences to the pointer string, so it can be passed wherever a pointer is taken,
+/// as in `value.pointer(&path!(results[3].user.name))`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Path {
+    pointer: String,
+}
+
+impl Path {
+    /// The empty path, addressing the root.
+    pub fn root() -> Path {
+        Path::default()
+    }
+
+    /// Appends the map key `key`.
+    pub fn key(self, key: &str) -> Path {
+        Path {
+            pointer: join(&self.pointer, key),
+        }
+    }
+
+    /// Appends the sequence index `index`.
+    pub fn index(self, index: usize) -> Path {
+        self.key(&index.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pointer
+    }
+}
+
+impl Deref for Path {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.pointer
+    }
+}
+
+impl AsRef<str> for Path {
+    fn as_ref(&self) -> &str {
+        &self.pointer
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pointer)
+    }
+}
+
+impl From<Path> for String {
+    fn from(path: Path) -> String {
+        path.pointer
+    }
+}
+
+/// Builds a `Path` from Rust-like accessors, checking their syntax at compile time.
+///
+/// Keys are identifiers or string literals, after a `.` except for the first one, and
+/// indices are expressions in brackets. `*`, as a key or index, is the wildcard segment of
+/// `Redactions` and `DedupPolicy` patterns.
+///
+/// ```ignore
+/// let name = value.pointer(&path!(results[3].user.name));
+/// let redactions = Redactions::new().redact(&path!(headers."x-request-id"));
+/// let ids = Redactions::new().redact(&path!(items[*].id));
+/// let root = path!();
+/// ```
+#[macro_export]
+macro_rules! path {
+    (@ $path:expr;) => {
+        $path
+    };
+    (@ $path:expr; . * $($rest:tt)*) => {
+        $crate::path!(@ $path.key("*"); $($rest)*)
+    };
+    (@ $path:expr; . $key:ident $($rest:tt)*) => {
+        $crate::path!(@ $path.key(stringify!($key)); $($rest)*)
+    };
+    (@ $path:expr; . $key:literal $($rest:tt)*) => {
+        $crate::path!(@ $path.key($key); $($rest)*)
+    };
+    (@ $path:expr; [*] $($rest:tt)*) => {
+        $crate::path!(@ $path.key("*"); $($rest)*)
+    };
+    (@ $path:expr; [$index:expr] $($rest:tt)*) => {
+        $crate::path!(@ $path.index($index); $($rest)*)
+    };
+    () => {
+        $crate::Path::root()
+    };
+    ([$($index:tt)*] $($rest:tt)*) => {
+        $crate::path!(@ $crate::Path::root(); [$($index)*] $($rest)*)
+    };
+    ($($rest:tt)+) => {
+        $crate::path!(@ $crate::Path::root(); . $($rest)+)
+    };
+}
+
+/// A JSON pointer that does not address a node of a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathError {
+    pub pointer: String,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No value at {}", self.pointer)
+    }
+}
+
+impl Error for PathError {}
+
+impl Value {
+    /// The node at the JSON pointer `pointer`, looking through options, newtypes and
+    /// extension values. Sequence and tuple elements are addressed by index, and map keys
+    /// that are not strings by their `Display` form.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        lookup(self, &split(pointer)?)
+    }
+
+    /// Like `pointer`, but mutable. The maps and sequences from the root to the target are
+    /// copied if they are shared, so the change is not seen through other handles to them.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        let segments = split(pointer)?;
+        lookup(self, &segments)?;
+        lookup_mut(self, &segments)
+    }
+
+    /// Returns a copy of this value with the node at the JSON pointer `pointer` replaced by
+    /// `f` applied to it.
+    ///
+    /// Only the maps and sequences from the root to the target are copied, everything else
+    /// stays shared with `self`. Options, newtypes and extension values along the way are
+    /// kept, but the target itself is passed to `f` as is.
+    pub fn update<F>(&self, pointer: &str, f: F) -> Result<Value, PathError>
+    where
+        F: FnOnce(Value) -> Value,
+    {
+        split(pointer)
+            .and_then(|segments| update(self, &segments, f))
+            .ok_or_else(|| PathError {
+                pointer: pointer.to_owned(),
+            })
+    }
+}
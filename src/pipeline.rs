@@ -0,0 +1,239 @@
+//! Parallel ingestion of newline delimited JSON, behind the `serde_json` feature.
+//!
+//! `NdjsonPipeline` reads lines on the calling thread and hands them to worker threads in
+//! batches. Each worker parses a batch with a `Dedup` of its own, so repeats within a batch
+//! are already shared when it is sent back, and the results are then deduplicated against
+//! the shared table in input order.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use serde_json;
+
+use {Dedup, Deduplicator, Value};
+
+/// Why a line could not be ingested.
+#[derive(Debug)]
+pub enum PipelineErrorKind {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+/// An error reading or parsing the line with the given number, counting from 1.
+#[derive(Debug)]
+pub struct PipelineError {
+    pub line: usize,
+    pub kind: PipelineErrorKind,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            PipelineErrorKind::Io(ref e) => write!(f, "reading line {}: {}", self.line, e),
+            PipelineErrorKind::Json(ref e) => write!(f, "parsing line {}: {}", self.line, e),
+        }
+    }
+}
+
+impl Error for PipelineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.kind {
+            PipelineErrorKind::Io(ref e) => Some(e),
+            PipelineErrorKind::Json(ref e) => Some(e),
+        }
+    }
+}
+
+type Parsed = Vec<Result<Value, PipelineError>>;
+
+struct Batch {
+    first_line: usize,
+    lines: Vec<String>,
+}
+
+struct Worker {
+    jobs: Option<Sender<Batch>>,
+    results: Receiver<Parsed>,
+    handle: Option<JoinHandle<()>>,
+}
+
+fn parse(batch: Batch) -> Parsed {
+    let mut local = Dedup::new();
+    let first = batch.first_line;
+    batch
+        .lines
+        .into_iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(&line)
+                .map(|value| local.dedup(value))
+                .map_err(|e| PipelineError {
+                    line: first + i,
+                    kind: PipelineErrorKind::Json(e),
+                })
+        })
+        .collect()
+}
+
+impl Worker {
+    fn spawn() -> Worker {
+        let (jobs, inbox) = channel::<Batch>();
+        let (outbox, results) = channel();
+        let handle = thread::spawn(move || {
+            for batch in inbox {
+                if outbox.send(parse(batch)).is_err() {
+                    break;
+                }
+            }
+        });
+        Worker {
+            jobs: Some(jobs),
+            results,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// An iterator over the documents of an NDJSON stream, parsed on several threads and
+/// deduplicated against a shared `Dedup`, in input order.
+///
+/// Blank lines are skipped. A line that is not valid JSON yields an error and ingestion
+/// continues with the next one, a read error ends the stream after the lines before it.
+pub struct NdjsonPipeline<R> {
+    reader: R,
+    dedup: Dedup,
+    workers: Vec<Worker>,
+    batch_lines: usize,
+    // batches sent and received so far, which pick the worker round robin
+    sent: usize,
+    received: usize,
+    line: usize,
+    eof: bool,
+    error: Option<PipelineError>,
+    ready: VecDeque<Result<Value, PipelineError>>,
+}
+
+impl<R: BufRead> NdjsonPipeline<R> {
+    /// Starts `threads` workers, which must be at least 1, to parse the lines of `reader`,
+    /// interning the documents in `dedup`.
+    pub fn new(reader: R, threads: usize, dedup: Dedup) -> NdjsonPipeline<R> {
+        assert!(threads > 0, "a pipeline needs at least one worker");
+        NdjsonPipeline {
+            reader,
+            dedup,
+            workers: (0..threads).map(|_| Worker::spawn()).collect(),
+            batch_lines: 1024,
+            sent: 0,
+            received: 0,
+            line: 1,
+            eof: false,
+            error: None,
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// How many lines are sent to a worker at once, 1024 by default.
+    pub fn with_batch_lines(mut self, lines: usize) -> NdjsonPipeline<R> {
+        self.batch_lines = lines.max(1);
+        self
+    }
+
+    /// The shared table, with everything ingested so far.
+    pub fn dedup(&self) -> &Dedup {
+        &self.dedup
+    }
+
+    /// Stops the workers and returns the shared table.
+    pub fn into_dedup(mut self) -> Dedup {
+        mem::take(&mut self.dedup)
+    }
+
+    fn read_batch(&mut self) -> Option<Batch> {
+        let mut batch = Batch {
+            first_line: self.line,
+            lines: Vec::with_capacity(self.batch_lines),
+        };
+        while batch.lines.len() < self.batch_lines {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(_) => {
+                    self.line += 1;
+                    batch.lines.push(line);
+                }
+                Err(e) => {
+                    self.eof = true;
+                    self.error = Some(PipelineError {
+                        line: self.line,
+                        kind: PipelineErrorKind::Io(e),
+                    });
+                    break;
+                }
+            }
+        }
+        if batch.lines.is_empty() {
+            None
+        } else {
+            Some(batch)
+        }
+    }
+
+    // keeps every worker busy, then takes the oldest batch
+    fn fill(&mut self) -> bool {
+        while !self.eof && self.sent - self.received < self.workers.len() {
+            if let Some(batch) = self.read_batch() {
+                let worker = &self.workers[self.sent % self.workers.len()];
+                worker.jobs.as_ref().unwrap().send(batch).expect("pipeline worker stopped");
+                self.sent += 1;
+            }
+        }
+        if self.sent == self.received {
+            return false;
+        }
+        let worker = &self.workers[self.received % self.workers.len()];
+        let parsed = worker.results.recv().expect("pipeline worker panicked");
+        self.received += 1;
+        for result in parsed {
+            let result = result.map(|value| self.dedup.dedup(value));
+            self.ready.push_back(result);
+        }
+        true
+    }
+}
+
+impl<R: BufRead> Iterator for NdjsonPipeline<R> {
+    type Item = Result<Value, PipelineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.ready.pop_front() {
+                return Some(result);
+            }
+            if !self.fill() {
+                return self.error.take().map(Err);
+            }
+        }
+    }
+}
+
+impl<R> Drop for NdjsonPipeline<R> {
+    fn drop(&mut self) {
+        for worker in &mut self.workers {
+            worker.jobs.take();
+        }
+        for worker in &mut self.workers {
+            if let Some(handle) = worker.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use path;
+use {Dedup, Deduplicator, Value, KV};
+
+/// Which parts of a value `Dedup` interns, by JSON pointer pattern.
+///
+/// A rule applies to the node its pattern matches and everything below it. Where several
+/// rules apply, the one with the longest pattern wins, and among those the one added last.
+/// A `*` segment matches any map key or sequence index, as for `testing::Redactions`.
+///
+/// ```ignore
+/// // intern the headers, but not the unique payloads
+/// let policy = DedupPolicy::new().exclude("/body").include("/headers");
+/// let mut dedup = Dedup::new().with_policy(policy);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DedupPolicy {
+    default: bool,
+    rules: Vec<(Vec<String>, bool)>,
+}
+
+impl Default for DedupPolicy {
+    fn default() -> DedupPolicy {
+        DedupPolicy::new()
+    }
+}
+
+impl DedupPolicy {
+    /// A policy that interns everything not excluded.
+    pub fn new() -> DedupPolicy {
+        DedupPolicy {
+            default: true,
+            rules: Vec::new(),
+        }
+    }
+
+    /// A policy that interns nothing but what is included.
+    pub fn none() -> DedupPolicy {
+        DedupPolicy {
+            default: false,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Interns the subtrees matching `pattern`. Panics if it is not a valid JSON pointer.
+    pub fn include(self, pattern: &str) -> DedupPolicy {
+        self.rule(pattern, true)
+    }
+
+    /// Leaves the subtrees matching `pattern` alone. Panics if it is not a valid JSON pointer.
+    pub fn exclude(self, pattern: &str) -> DedupPolicy {
+        self.rule(pattern, false)
+    }
+
+    fn rule(mut self, pattern: &str, intern: bool) -> DedupPolicy {
+        let segments = path::split(pattern)
+            .unwrap_or_else(|| panic!("invalid dedup policy pattern {:?}", pattern));
+        self.rules.push((segments, intern));
+        self
+    }
+
+    fn interns(&self, segments: &[String]) -> bool {
+        let mut best: Option<(usize, bool)> = None;
+        for (pattern, intern) in self.rules.iter() {
+            let applies = pattern.len() <= segments.len()
+                && pattern
+                    .iter()
+                    .zip(segments.iter())
+                    .all(|(p, s)| p == "*" || p == s);
+            if applies && best.map_or(0, |(len, _)| len) <= pattern.len() {
+                best = Some((pattern.len(), *intern));
+            }
+        }
+        best.map_or(self.default, |(_, intern)| intern)
+    }
+}
+
+impl Dedup {
+    /// Restricts interning to the parts of each value selected by `policy`.
+    ///
+    /// Interning unique payloads costs time and table memory for no benefit, so excluding
+    /// them can make deduplication considerably cheaper.
+    pub fn with_policy(mut self, policy: DedupPolicy) -> Dedup {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    pub(crate) fn dedup_with_policy(&mut self, value: Value, policy: &DedupPolicy) -> Value {
+        self.dedup_at(value, policy, &mut Vec::new())
+    }
+
+    fn dedup_elements(
+        &mut self,
+        elements: &[Value],
+        policy: &DedupPolicy,
+        segments: &mut Vec<String>,
+    ) -> Vec<Value> {
+        elements
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                segments.push(i.to_string());
+                let x = self.dedup_at(x.clone(), policy, segments);
+                segments.pop();
+                x
+            })
+            .collect()
+    }
+
+    // like `dedup_value`, but with the path of `value` in `segments`
+    fn dedup_at(
+        &mut self,
+        value: Value,
+        policy: &DedupPolicy,
+        segments: &mut Vec<String>,
+    ) -> Value {
+        let intern = policy.interns(segments);
+        match value {
+            Value::Bytes(v) if intern => Value::Bytes(self.dedup_blob(v)),
+            Value::String(v) if intern => Value::String(self.dedup_string(v)),
+            Value::UnitStruct(v) if intern => Value::UnitStruct(self.dedup_string(v)),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, data } if intern => Value::CompressedBytes {
+                codec,
+                data: self.dedup_blob(data),
+            },
+            Value::Ext { tag, value } => Value::Ext {
+                tag,
+                value: Box::new(self.dedup_at(*value, policy, segments)),
+            },
+            Value::Seq(elements) => {
+                let elements = Arc::new(self.dedup_elements(&elements, policy, segments));
+                Value::Seq(if intern { self.dedup_seq(elements) } else { elements })
+            }
+            Value::Tuple(elements) => {
+                let elements = Arc::new(self.dedup_elements(&elements, policy, segments));
+                Value::Tuple(if intern { self.dedup_seq(elements) } else { elements })
+            }
+            Value::Set(elements) => {
+                segments.push("*".to_owned());
+                let elements: BTreeSet<Value> = elements
+                    .iter()
+                    .map(|x| self.dedup_at(x.clone(), policy, segments))
+                    .collect();
+                segments.pop();
+                // sets themselves are not interned, as in `dedup_value`
+                Value::Set(Arc::new(elements))
+            }
+            Value::Map(object) => {
+                let KV(k, v) = object.as_ref();
+                let values = k
+                    .iter()
+                    .zip(v.iter())
+                    .map(|(k, v)| {
+                        segments.push(path::key_segment(k));
+                        let v = self.dedup_at(v.clone(), policy, segments);
+                        segments.pop();
+                        v
+                    })
+                    .collect();
+                if intern {
+                    let keys = Arc::new(self.dedup_value_vec(k.as_ref().clone()));
+                    let keys = self.dedup_seq(keys);
+                    Value::Map(self.dedup_map(Arc::new(KV(keys, values))))
+                } else {
+                    Value::Map(Arc::new(KV(k.clone(), values)))
+                }
+            }
+            x => x,
+        }
+    }
+}
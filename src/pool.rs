@@ -0,0 +1,63 @@
+//! Recycling of element buffers inside `Dedup`.
+//!
+//! Deduplicating a record rebuilds every sequence and map in it, and every table hit throws
+//! the rebuilt buffer away again. When ingesting many small records, this allocation and
+//! free traffic dominates, so the buffers of discarded candidates are kept here and reused
+//! for the next record.
+
+use std::sync::Arc;
+
+use {Value, KV};
+
+// bounds on what is kept, so one unusually large record does not pin its memory
+const MAX_BUFFERS: usize = 64;
+const MAX_CAPACITY: usize = 4096;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Pool {
+    pub(crate) values: Vec<Vec<Value>>,
+}
+
+impl Pool {
+    /// A copy of `elements` in a recycled buffer if there is one.
+    pub(crate) fn copy(&mut self, elements: &[Value]) -> Vec<Value> {
+        match self.values.pop() {
+            Some(mut buffer) => {
+                buffer.extend(elements.iter().cloned());
+                buffer
+            }
+            None => elements.to_vec(),
+        }
+    }
+
+    /// The elements of `elements`, without copying them if the vector is not shared.
+    pub(crate) fn take(&mut self, elements: Arc<Vec<Value>>) -> Vec<Value> {
+        Arc::try_unwrap(elements).unwrap_or_else(|elements| self.copy(&elements))
+    }
+
+    /// The keys and values of `object`, see `take`.
+    pub(crate) fn take_object(&mut self, object: Arc<KV>) -> KV {
+        Arc::try_unwrap(object).unwrap_or_else(|object| KV(object.0.clone(), self.copy(&object.1)))
+    }
+
+    /// Keeps the buffer of `elements` for reuse, unless something else still refers to it.
+    pub(crate) fn recycle(&mut self, elements: Arc<Vec<Value>>) {
+        if let Ok(buffer) = Arc::try_unwrap(elements) {
+            self.recycle_buffer(buffer);
+        }
+    }
+
+    /// Keeps the value buffer of `object` for reuse, see `recycle`.
+    pub(crate) fn recycle_object(&mut self, object: Arc<KV>) {
+        if let Ok(KV(_, buffer)) = Arc::try_unwrap(object) {
+            self.recycle_buffer(buffer);
+        }
+    }
+
+    fn recycle_buffer(&mut self, mut buffer: Vec<Value>) {
+        if self.values.len() < MAX_BUFFERS && buffer.capacity() <= MAX_CAPACITY {
+            buffer.clear();
+            self.values.push(buffer);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! PostgreSQL rows as deduplicated records, behind the `tokio-postgres` feature.
+//!
+//! Columns convert by type: booleans, integers and floats to the variant of their width,
+//! text, names, enums, JSON and `numeric` to strings, `bytea` to bytes, `uuid` to its
+//! hyphenated string, and one dimensional arrays to sequences. Timestamps become
+//! `Ext { tag: 1 }` with the seconds since the Unix epoch, as an `I64`, or an `F64` if
+//! there are microseconds, and dates `Ext { tag: 100 }` with the days since the epoch, as
+//! the CBOR tags for epoch based times and dates do. `NULL` is `Unit`. Other types keep
+//! their binary wire format as bytes.
+
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use tokio_postgres::types::{FromSql, Kind, Type};
+use tokio_postgres::Row;
+
+use {Deduplicator, Number, Value, KV};
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+/// Seconds between the Unix epoch and the PostgreSQL epoch, 2000-01-01.
+const POSTGRES_EPOCH: i64 = 946_684_800;
+
+/// A column of any type, see the module documentation.
+struct Cell(Value);
+
+impl<'a> FromSql<'a> for Cell {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Cell, BoxError> {
+        cell(ty, raw).map(Cell)
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Cell, BoxError> {
+        Ok(Cell(Value::Unit))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+fn be_bytes<T: Default + AsMut<[u8]>>(raw: &[u8]) -> Result<T, BoxError> {
+    let mut bytes = T::default();
+    if raw.len() != bytes.as_mut().len() {
+        return Err("unexpected length of a fixed size value".into());
+    }
+    bytes.as_mut().copy_from_slice(raw);
+    Ok(bytes)
+}
+
+fn timestamp(micros: i64) -> Value {
+    let seconds = match micros {
+        i64::MAX => Value::Number(Number::F64(f64::INFINITY)),
+        i64::MIN => Value::Number(Number::F64(f64::NEG_INFINITY)),
+        _ if micros % 1_000_000 == 0 => {
+            Value::Number(Number::I64(micros / 1_000_000 + POSTGRES_EPOCH))
+        }
+        _ => Value::Number(Number::F64(micros as f64 / 1e6 + POSTGRES_EPOCH as f64)),
+    };
+    Value::Ext {
+        tag: 1,
+        value: Box::new(seconds),
+    }
+}
+
+fn date(days: i32) -> Value {
+    let days = match days {
+        i32::MAX => Value::Number(Number::F64(f64::INFINITY)),
+        i32::MIN => Value::Number(Number::F64(f64::NEG_INFINITY)),
+        _ => Value::Number(Number::I64(i64::from(days) + POSTGRES_EPOCH / 86_400)),
+    };
+    Value::Ext {
+        tag: 100,
+        value: Box::new(days),
+    }
+}
+
+fn uuid(raw: &[u8]) -> Result<Value, BoxError> {
+    let bytes: [u8; 16] = be_bytes(raw)?;
+    let mut text = String::with_capacity(36);
+    for (i, byte) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            text.push('-');
+        }
+        let _ = write!(text, "{:02x}", byte);
+    }
+    Ok(Value::String(text.into()))
+}
+
+/// The decimal text of a `numeric` in binary format: a header of digit count, weight of
+/// the first digit, sign and scale, followed by digits in base 10000.
+fn numeric(raw: &[u8]) -> Result<Value, BoxError> {
+    if raw.len() < 8 {
+        return Err("truncated numeric".into());
+    }
+    let word = |i: usize| u16::from_be_bytes([raw[2 * i], raw[2 * i + 1]]);
+    let (count, weight, sign, scale) = (word(0) as usize, word(1) as i16, word(2), word(3));
+    if raw.len() != 8 + 2 * count {
+        return Err("truncated numeric".into());
+    }
+    let special = match sign {
+        0x0000 | 0x4000 => None,
+        0xc000 => Some("NaN"),
+        0xd000 => Some("Infinity"),
+        0xf000 => Some("-Infinity"),
+        _ => return Err("invalid numeric sign".into()),
+    };
+    if let Some(special) = special {
+        return Ok(Value::String(special.into()));
+    }
+    let digit = |i: i32| {
+        if i >= 0 && (i as usize) < count {
+            word(4 + i as usize)
+        } else {
+            0
+        }
+    };
+    let mut text = String::new();
+    if sign == 0x4000 {
+        text.push('-');
+    }
+    // the integer part, from the first digit group down to the one of weight 0
+    if weight < 0 {
+        text.push('0');
+    } else {
+        let _ = write!(text, "{}", digit(0));
+        for i in 1..=i32::from(weight) {
+            let _ = write!(text, "{:04}", digit(i));
+        }
+    }
+    if scale > 0 {
+        text.push('.');
+        let mut fraction = String::new();
+        let mut i = i32::from(weight) + 1;
+        while fraction.len() < scale as usize {
+            let _ = write!(fraction, "{:04}", digit(i));
+            i += 1;
+        }
+        text.push_str(&fraction[..scale as usize]);
+    }
+    Ok(Value::String(text.into()))
+}
+
+/// Converts the binary wire format of a non-null column of type `ty`.
+pub(crate) fn cell(ty: &Type, raw: &[u8]) -> Result<Value, BoxError> {
+    Ok(match *ty {
+        Type::BOOL => Value::Bool(bool::from_sql(ty, raw)?),
+        Type::CHAR => Value::Number(Number::I8(i8::from_sql(ty, raw)?)),
+        Type::INT2 => Value::Number(Number::I16(i16::from_sql(ty, raw)?)),
+        Type::INT4 => Value::Number(Number::I32(i32::from_sql(ty, raw)?)),
+        Type::INT8 => Value::Number(Number::I64(i64::from_sql(ty, raw)?)),
+        Type::OID => Value::Number(Number::U32(u32::from_sql(ty, raw)?)),
+        Type::FLOAT4 => Value::Number(Number::F32(f32::from_sql(ty, raw)?)),
+        Type::FLOAT8 => Value::Number(Number::F64(f64::from_sql(ty, raw)?)),
+        Type::BYTEA => Value::Bytes(Arc::new(raw.to_vec())),
+        Type::JSON => Value::String(std::str::from_utf8(raw)?.into()),
+        // jsonb is its text after a version byte
+        Type::JSONB => match raw.split_first() {
+            Some((1, text)) => Value::String(std::str::from_utf8(text)?.into()),
+            _ => return Err("unsupported jsonb version".into()),
+        },
+        Type::TIMESTAMP | Type::TIMESTAMPTZ => timestamp(i64::from_be_bytes(be_bytes(raw)?)),
+        Type::DATE => date(i32::from_be_bytes(be_bytes(raw)?)),
+        Type::UUID => uuid(raw)?,
+        Type::NUMERIC => numeric(raw)?,
+        ref ty if <&str as FromSql>::accepts(ty) => {
+            Value::String(<&str as FromSql>::from_sql(ty, raw)?.into())
+        }
+        ref ty => match *ty.kind() {
+            Kind::Enum(_) => Value::String(std::str::from_utf8(raw)?.into()),
+            Kind::Domain(ref inner) => cell(inner, raw)?,
+            Kind::Array(_) => {
+                let cells = Vec::<Cell>::from_sql(ty, raw)?;
+                Value::Seq(Arc::new(cells.into_iter().map(|c| c.0).collect()))
+            }
+            _ => Value::Bytes(Arc::new(raw.to_vec())),
+        },
+    })
+}
+
+/// Converts rows into records, sharing their key vector while the columns stay the same,
+/// and deduplicating each record.
+///
+/// This is meant for the rows of a `RowStream` or of `Client::query`, one by one.
+pub struct RowConverter<D> {
+    dedup: D,
+    // the column names of the last row, and the position of each column in key order
+    names: Vec<String>,
+    order: Vec<usize>,
+    keys: Arc<Vec<Value>>,
+}
+
+impl<D: Deduplicator> RowConverter<D> {
+    pub fn new(dedup: D) -> RowConverter<D> {
+        RowConverter {
+            dedup,
+            names: Vec::new(),
+            order: Vec::new(),
+            keys: Arc::new(Vec::new()),
+        }
+    }
+
+    fn update_columns(&mut self, row: &Row) {
+        let columns = row.columns();
+        if columns.len() == self.names.len()
+            && columns.iter().zip(&self.names).all(|(c, n)| c.name() == n)
+        {
+            return;
+        }
+        self.names = columns.iter().map(|c| c.name().to_owned()).collect();
+        // columns with the same name keep the last of them
+        let mut order: Vec<(&str, usize)> = self.names.iter().map(|n| &**n).zip(0..).collect();
+        order.sort();
+        order.reverse();
+        order.dedup_by(|a, b| a.0 == b.0);
+        order.reverse();
+        let keys = order.iter().map(|&(name, _)| Value::String(name.into())).collect();
+        self.keys = self.dedup.dedup_seq(Arc::new(keys));
+        self.order = order.into_iter().map(|(_, i)| i).collect();
+    }
+
+    /// Converts one row into a map from column names to values.
+    pub fn convert(&mut self, row: &Row) -> Result<Value, tokio_postgres::Error> {
+        self.update_columns(row);
+        let values = self
+            .order
+            .iter()
+            .map(|&i| row.try_get::<_, Cell>(i).map(|c| c.0))
+            .collect::<Result<_, _>>()?;
+        let record = Value::Map(Arc::new(KV(self.keys.clone(), values)));
+        Ok(self.dedup.dedup(record))
+    }
+
+    pub fn dedup(&self) -> &D {
+        &self.dedup
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dedup
+    }
+}
+
+/// Converts the rows of a query into a `Seq` of records, see `RowConverter`.
+pub fn rows_to_value<D: Deduplicator>(
+    rows: &[Row],
+    dedup: D,
+) -> Result<Value, tokio_postgres::Error> {
+    let mut converter = RowConverter::new(dedup);
+    let records = rows
+        .iter()
+        .map(|row| converter.convert(row))
+        .collect::<Result<_, _>>()?;
+    Ok(Value::Seq(Arc::new(records)))
+}
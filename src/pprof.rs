@@ -0,0 +1,229 @@
+//! Export of memory profiles in the pprof protobuf format, for `go tool pprof`, speedscope
+//! and other profile viewers.
+//!
+//! The profiles are written uncompressed, which pprof accepts as well as gzipped ones. See
+//! <https://github.com/google/pprof/blob/main/proto/profile.proto> for the format.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::Arc;
+
+use report::{blob_size, object_size, seq_size, string_size, Category};
+use {Dedup, Value};
+
+/// Longest frame name made from a value, in chars.
+const MAX_LABEL: usize = 64;
+
+fn varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn key(out: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(out, u64::from(field) << 3 | u64::from(wire_type));
+}
+
+fn uint(out: &mut Vec<u8>, field: u32, v: u64) {
+    // zero is the default, which protobuf leaves out
+    if v != 0 {
+        key(out, field, 0);
+        varint(out, v);
+    }
+}
+
+fn bytes(out: &mut Vec<u8>, field: u32, v: &[u8]) {
+    key(out, field, 2);
+    varint(out, v.len() as u64);
+    out.extend_from_slice(v);
+}
+
+fn packed(out: &mut Vec<u8>, field: u32, vs: &[u64]) {
+    let mut buf = Vec::new();
+    for &v in vs {
+        varint(&mut buf, v);
+    }
+    bytes(out, field, &buf);
+}
+
+/// Collects samples and writes them as a pprof `Profile` message.
+///
+/// Every distinct frame name becomes one function with one location of the same id.
+pub(crate) struct ProfileBuilder {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u64>,
+    sample_types: Vec<(u64, u64)>,
+    // function and location ids by name id, counting from 1
+    frames: HashMap<u64, u64>,
+    frame_names: Vec<u64>,
+    samples: Vec<(Vec<u64>, Vec<u64>)>,
+}
+
+impl ProfileBuilder {
+    /// A profile with the given (type, unit) sample types, e.g. `("space", "bytes")`.
+    pub(crate) fn new(sample_types: &[(&str, &str)]) -> ProfileBuilder {
+        let mut builder = ProfileBuilder {
+            // the string table must start with the empty string
+            strings: vec![String::new()],
+            string_ids: HashMap::new(),
+            sample_types: Vec::new(),
+            frames: HashMap::new(),
+            frame_names: Vec::new(),
+            samples: Vec::new(),
+        };
+        builder.sample_types = sample_types
+            .iter()
+            .map(|&(ty, unit)| (builder.string(ty), builder.string(unit)))
+            .collect();
+        builder
+    }
+
+    fn string(&mut self, s: &str) -> u64 {
+        if s.is_empty() {
+            return 0;
+        }
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u64;
+        self.strings.push(s.to_owned());
+        self.string_ids.insert(s.to_owned(), id);
+        id
+    }
+
+    fn frame(&mut self, name: &str) -> u64 {
+        let name = self.string(name);
+        let next = self.frame_names.len() as u64 + 1;
+        let id = *self.frames.entry(name).or_insert(next);
+        if id == next {
+            self.frame_names.push(name);
+        }
+        id
+    }
+
+    /// Adds a sample with one value per sample type. The stack is leaf first.
+    pub(crate) fn add<S: AsRef<str>>(&mut self, stack: &[S], values: &[u64]) {
+        debug_assert_eq!(values.len(), self.sample_types.len());
+        let locations = stack.iter().map(|name| self.frame(name.as_ref())).collect();
+        self.samples.push((locations, values.to_vec()));
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &(ty, unit) in &self.sample_types {
+            let mut msg = Vec::new();
+            uint(&mut msg, 1, ty);
+            uint(&mut msg, 2, unit);
+            bytes(&mut out, 1, &msg);
+        }
+        for (locations, values) in &self.samples {
+            let mut msg = Vec::new();
+            packed(&mut msg, 1, locations);
+            packed(&mut msg, 2, values);
+            bytes(&mut out, 2, &msg);
+        }
+        for id in 1..=self.frame_names.len() as u64 {
+            let mut line = Vec::new();
+            uint(&mut line, 1, id);
+            let mut location = Vec::new();
+            uint(&mut location, 1, id);
+            bytes(&mut location, 4, &line);
+            bytes(&mut out, 4, &location);
+        }
+        for (i, &name) in self.frame_names.iter().enumerate() {
+            let mut function = Vec::new();
+            uint(&mut function, 1, i as u64 + 1);
+            uint(&mut function, 2, name);
+            uint(&mut function, 3, name);
+            bytes(&mut out, 5, &function);
+        }
+        for s in &self.strings {
+            bytes(&mut out, 6, s.as_bytes());
+        }
+        out
+    }
+}
+
+// stops formatting once the label is long enough, so large values are not printed in full
+struct Truncated {
+    label: String,
+    chars: usize,
+}
+
+impl Write for Truncated {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.chars == MAX_LABEL {
+                self.label.push('…');
+                return Err(fmt::Error);
+            }
+            self.label.push(c);
+            self.chars += 1;
+        }
+        Ok(())
+    }
+}
+
+/// A frame name for an interned value.
+fn label(value: &Value) -> String {
+    let mut out = Truncated {
+        label: String::new(),
+        chars: 0,
+    };
+    match *value {
+        Value::Bytes(ref v) => {
+            let _ = write!(out, "<{} bytes>", v.len());
+        }
+        ref v => {
+            let _ = write!(out, "{}", v);
+        }
+    }
+    out.label
+}
+
+fn push_entries<'a, T: ?Sized + 'a, I, F, W>(
+    profile: &mut ProfileBuilder,
+    category: Category,
+    entries: I,
+    size: F,
+    wrap: W,
+) where
+    I: Iterator<Item = &'a Arc<T>>,
+    F: Fn(&T) -> usize,
+    W: Fn(Arc<T>) -> Value,
+{
+    for entry in entries {
+        let bytes = size(entry) as u64;
+        // one reference is held by the table itself
+        let references = Arc::strong_count(entry) as u64 - 1;
+        let saved = bytes * references.saturating_sub(1);
+        let name = label(&wrap(entry.clone()));
+        profile.add(&[name.as_str(), category.label()], &[bytes, references, saved]);
+    }
+}
+
+impl Dedup {
+    /// The memory held by the interned entries, as a pprof profile.
+    ///
+    /// There is one sample per entry, below a frame for its `Category`, with the sample
+    /// types `space/bytes` for the size of the entry, `references/count` for the live
+    /// references outside of the table and `saved_space/bytes` for what sharing it saves,
+    /// as in `savings_report`. Entries are named by their value, cut to 64 characters.
+    ///
+    /// Write the result to a file and open it with e.g. `go tool pprof -http=: file`.
+    pub fn to_pprof(&self) -> Vec<u8> {
+        let mut profile = ProfileBuilder::new(&[
+            ("space", "bytes"),
+            ("references", "count"),
+            ("saved_space", "bytes"),
+        ]);
+        let p = &mut profile;
+        push_entries(p, Category::Blob, self.blobs.iter(), |x| blob_size(x), Value::Bytes);
+        push_entries(p, Category::String, self.strings.iter(), string_size, Value::String);
+        push_entries(p, Category::Seq, self.vectors.iter(), |x| seq_size(x), Value::Seq);
+        push_entries(p, Category::Object, self.objects.iter(), object_size, Value::Map);
+        profile.finish()
+    }
+}
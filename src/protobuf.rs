@@ -0,0 +1,320 @@
+//! Conversion between dynamic protobuf messages and values, behind the `prost-reflect`
+//! feature.
+//!
+//! A message becomes a map from the names of all fields of its type to their values, so
+//! messages of one type always have the same keys. Unset fields with presence, such as
+//! submessages and `optional` fields, are `None`, enums are the names of their values,
+//! repeated fields are sequences and map fields are maps.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use prost_reflect::{
+    self, DynamicMessage, FieldDescriptor, Kind, MapKey, MessageDescriptor, ReflectMessage,
+};
+
+use path::{self, transparent};
+use {Deduplicator, Number, Value, KV};
+
+/// What made a value not convertible to a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtobufErrorKind {
+    /// a key that is not the name of a field of the message
+    UnknownField,
+    /// a value that does not fit the type of its field, including integers out of range
+    Type,
+    /// a string that is not the name of a value of the enum of its field
+    UnknownEnumValue,
+}
+
+/// A node that does not fit the message type, with its JSON pointer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtobufError {
+    pub pointer: String,
+    pub kind: ProtobufErrorKind,
+}
+
+impl fmt::Display for ProtobufError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            ProtobufErrorKind::UnknownField => "an unknown field",
+            ProtobufErrorKind::Type => "a value of the wrong type",
+            ProtobufErrorKind::UnknownEnumValue => "an unknown enum value",
+        };
+        write!(f, "{} at '{}'", what, self.pointer)
+    }
+}
+
+impl Error for ProtobufError {}
+
+/// The keys of the maps of one message type, and its fields in key order.
+struct MessageSchema {
+    keys: Arc<Vec<Value>>,
+    fields: Vec<FieldDescriptor>,
+}
+
+struct Reader<'d, D: ?Sized> {
+    dedup: &'d mut D,
+    schemas: HashMap<String, MessageSchema>,
+}
+
+impl<'d, D: Deduplicator + ?Sized> Reader<'d, D> {
+    fn string(&mut self, value: &str) -> Value {
+        Value::String(self.dedup.dedup_string(value.into()))
+    }
+
+    fn message(&mut self, message: &DynamicMessage) -> Value {
+        let descriptor = message.descriptor();
+        if !self.schemas.contains_key(descriptor.full_name()) {
+            let mut fields: Vec<FieldDescriptor> = descriptor.fields().collect();
+            fields.sort_by(|a, b| a.name().cmp(b.name()));
+            let keys = fields.iter().map(|f| self.string(f.name())).collect();
+            let keys = self.dedup.dedup_seq(Arc::new(keys));
+            self.schemas
+                .insert(descriptor.full_name().to_owned(), MessageSchema { keys, fields });
+        }
+        let schema = &self.schemas[descriptor.full_name()];
+        let (keys, fields) = (schema.keys.clone(), schema.fields.clone());
+        let values = fields
+            .iter()
+            .map(|field| {
+                if field.supports_presence() && !message.has_field(field) {
+                    Value::Option(None)
+                } else {
+                    self.field(field, &message.get_field(field))
+                }
+            })
+            .collect();
+        Value::Map(Arc::new(KV(keys, values)))
+    }
+
+    fn field(&mut self, field: &FieldDescriptor, value: &prost_reflect::Value) -> Value {
+        match *value {
+            prost_reflect::Value::List(ref values) => {
+                let kind = field.kind();
+                Value::Seq(Arc::new(values.iter().map(|v| self.single(&kind, v)).collect()))
+            }
+            prost_reflect::Value::Map(ref entries) => {
+                let kind = match field.kind() {
+                    Kind::Message(entry) => entry.map_entry_value_field().kind(),
+                    kind => kind,
+                };
+                let map: BTreeMap<Value, Value> = entries
+                    .iter()
+                    .map(|(key, value)| (self.key(key), self.single(&kind, value)))
+                    .collect();
+                Value::map(map)
+            }
+            ref value => self.single(&field.kind(), value),
+        }
+    }
+
+    fn key(&mut self, key: &MapKey) -> Value {
+        match *key {
+            MapKey::Bool(v) => Value::Bool(v),
+            MapKey::I32(v) => Value::Number(Number::I32(v)),
+            MapKey::I64(v) => Value::Number(Number::I64(v)),
+            MapKey::U32(v) => Value::Number(Number::U32(v)),
+            MapKey::U64(v) => Value::Number(Number::U64(v)),
+            MapKey::String(ref v) => self.string(v),
+        }
+    }
+
+    fn single(&mut self, kind: &Kind, value: &prost_reflect::Value) -> Value {
+        match *value {
+            prost_reflect::Value::Bool(v) => Value::Bool(v),
+            prost_reflect::Value::I32(v) => Value::Number(Number::I32(v)),
+            prost_reflect::Value::I64(v) => Value::Number(Number::I64(v)),
+            prost_reflect::Value::U32(v) => Value::Number(Number::U32(v)),
+            prost_reflect::Value::U64(v) => Value::Number(Number::U64(v)),
+            prost_reflect::Value::F32(v) => Value::Number(Number::F32(v)),
+            prost_reflect::Value::F64(v) => Value::Number(Number::F64(v)),
+            prost_reflect::Value::String(ref v) => Value::String(v.as_str().into()),
+            prost_reflect::Value::Bytes(ref v) => Value::Bytes(Arc::new(v.to_vec())),
+            prost_reflect::Value::EnumNumber(v) => {
+                let name = match *kind {
+                    Kind::Enum(ref e) => e.get_value(v),
+                    _ => None,
+                };
+                match name {
+                    Some(name) => self.string(name.name()),
+                    // an open enum can hold numbers it does not name
+                    None => Value::Number(Number::I32(v)),
+                }
+            }
+            prost_reflect::Value::Message(ref v) => self.message(v),
+            prost_reflect::Value::List(..) | prost_reflect::Value::Map(..) => {
+                unreachable!("nested repeated fields")
+            }
+        }
+    }
+}
+
+/// Converts a message into a map from field names to values, see the module
+/// documentation.
+///
+/// Field names and enum value names go through `dedup`, as does one key vector for each
+/// message type, which all messages of that type share. The other strings and blobs are
+/// left alone, so pass the result to `dedup` as well to share those.
+pub fn from_dynamic_message<D: Deduplicator + ?Sized>(
+    message: &DynamicMessage,
+    dedup: &mut D,
+) -> Value {
+    Reader {
+        dedup,
+        schemas: HashMap::new(),
+    }
+    .message(message)
+}
+
+fn error(pointer: &str, kind: ProtobufErrorKind) -> ProtobufError {
+    ProtobufError {
+        pointer: pointer.to_owned(),
+        kind,
+    }
+}
+
+fn is_null(value: &Value) -> bool {
+    matches!(*value, Value::Unit | Value::Option(None))
+}
+
+fn message(
+    value: &Value,
+    descriptor: &MessageDescriptor,
+    pointer: &str,
+) -> Result<DynamicMessage, ProtobufError> {
+    let kv = match *transparent(value) {
+        Value::Map(ref kv) => kv,
+        _ => return Err(error(pointer, ProtobufErrorKind::Type)),
+    };
+    let mut message = DynamicMessage::new(descriptor.clone());
+    for (key, value) in kv.keys().iter().zip(kv.values()) {
+        let pointer = path::join(pointer, &path::key_segment(key));
+        let field = match *key {
+            Value::String(ref name) => descriptor.get_field_by_name(name),
+            _ => None,
+        };
+        let field = field.ok_or_else(|| error(&pointer, ProtobufErrorKind::UnknownField))?;
+        if !is_null(value) {
+            let value = field_value(value, &field, &pointer)?;
+            message.set_field(&field, value);
+        }
+    }
+    Ok(message)
+}
+
+fn field_value(
+    value: &Value,
+    field: &FieldDescriptor,
+    pointer: &str,
+) -> Result<prost_reflect::Value, ProtobufError> {
+    let value = transparent(value);
+    if field.is_map() {
+        let entry = match field.kind() {
+            Kind::Message(entry) => entry,
+            _ => unreachable!("map field without entry type"),
+        };
+        let (key_kind, value_kind) = (
+            entry.map_entry_key_field().kind(),
+            entry.map_entry_value_field().kind(),
+        );
+        let kv = match *value {
+            Value::Map(ref kv) => kv,
+            _ => return Err(error(pointer, ProtobufErrorKind::Type)),
+        };
+        let mut map = HashMap::with_capacity(kv.keys().len());
+        for (key, value) in kv.keys().iter().zip(kv.values()) {
+            let pointer = path::join(pointer, &path::key_segment(key));
+            let key = match single(key, &key_kind, &pointer)? {
+                prost_reflect::Value::Bool(v) => MapKey::Bool(v),
+                prost_reflect::Value::I32(v) => MapKey::I32(v),
+                prost_reflect::Value::I64(v) => MapKey::I64(v),
+                prost_reflect::Value::U32(v) => MapKey::U32(v),
+                prost_reflect::Value::U64(v) => MapKey::U64(v),
+                prost_reflect::Value::String(v) => MapKey::String(v),
+                _ => return Err(error(&pointer, ProtobufErrorKind::Type)),
+            };
+            map.insert(key, single(value, &value_kind, &pointer)?);
+        }
+        return Ok(prost_reflect::Value::Map(map));
+    }
+    if field.is_list() {
+        let elements: Vec<&Value> = match *value {
+            Value::Seq(ref v) | Value::Tuple(ref v) => v.iter().collect(),
+            Value::Set(ref v) => v.iter().collect(),
+            _ => return Err(error(pointer, ProtobufErrorKind::Type)),
+        };
+        let kind = field.kind();
+        let list = elements
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| single(v, &kind, &path::join(pointer, &i.to_string())))
+            .collect::<Result<_, _>>()?;
+        return Ok(prost_reflect::Value::List(list));
+    }
+    single(value, &field.kind(), pointer)
+}
+
+fn single(
+    value: &Value,
+    kind: &Kind,
+    pointer: &str,
+) -> Result<prost_reflect::Value, ProtobufError> {
+    let value = transparent(value);
+    let type_error = || error(pointer, ProtobufErrorKind::Type);
+    let number = value.as_number();
+    let int = || number.and_then(|n| n.as_i64()).ok_or_else(type_error);
+    let uint = || number.and_then(|n| n.as_u64()).ok_or_else(type_error);
+    Ok(match *kind {
+        Kind::Double => prost_reflect::Value::F64(number.ok_or_else(type_error)?.as_f64()),
+        Kind::Float => prost_reflect::Value::F32(number.ok_or_else(type_error)?.as_f64() as f32),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => {
+            prost_reflect::Value::I32(i32::try_from(int()?).map_err(|_| type_error())?)
+        }
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => prost_reflect::Value::I64(int()?),
+        Kind::Uint32 | Kind::Fixed32 => {
+            prost_reflect::Value::U32(u32::try_from(uint()?).map_err(|_| type_error())?)
+        }
+        Kind::Uint64 | Kind::Fixed64 => prost_reflect::Value::U64(uint()?),
+        Kind::Bool => match *value {
+            Value::Bool(v) => prost_reflect::Value::Bool(v),
+            _ => return Err(type_error()),
+        },
+        Kind::String => match *value {
+            Value::String(ref v) => prost_reflect::Value::String(v.to_string()),
+            _ => return Err(type_error()),
+        },
+        Kind::Bytes => match *value {
+            Value::Bytes(ref v) => prost_reflect::Value::Bytes(v.to_vec().into()),
+            _ => return Err(type_error()),
+        },
+        Kind::Enum(ref e) => match *value {
+            Value::String(ref name) => {
+                let variant = e
+                    .get_value_by_name(name)
+                    .ok_or_else(|| error(pointer, ProtobufErrorKind::UnknownEnumValue))?;
+                prost_reflect::Value::EnumNumber(variant.number())
+            }
+            // numbers are kept as they are, as open enums can hold any
+            _ => prost_reflect::Value::EnumNumber(i32::try_from(int()?).map_err(|_| type_error())?),
+        },
+        Kind::Message(ref m) => prost_reflect::Value::Message(message(value, m, pointer)?),
+    })
+}
+
+/// Converts a map from field names to values into a message of type `descriptor`, the
+/// inverse of `from_dynamic_message`.
+///
+/// Fields that are missing, unit or `None` are left unset. Numbers convert between
+/// widths if they fit, and enums can be given by name or number. Numbers are
+/// not checked against the enum, as open enums can hold any. Options, newtypes and
+/// extension values are looked through.
+pub fn to_dynamic_message(
+    value: &Value,
+    descriptor: &MessageDescriptor,
+) -> Result<DynamicMessage, ProtobufError> {
+    message(value, descriptor, "")
+}
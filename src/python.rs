@@ -0,0 +1,229 @@
+//! Python bindings, behind the `pyo3` feature.
+//!
+//! `serde_value.Value` wraps a value converted from `None`, `bool`, `int`, `float`, `str`,
+//! `bytes`, `list`, `tuple`, `dict`, `set` and `frozenset`, and `Value.to_python` converts it
+//! back. With the `dedup` feature, `serde_value.Dedup` deduplicates such values, so that a
+//! corpus of JSON documents loaded with `json.loads` shares all repeated strings and
+//! subtrees. Build the extension module with maturin, or
+//! `cargo rustc --features pyo3/extension-module --crate-type cdylib`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use pyo3::exceptions::{PyOverflowError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyBool, PyBytes, PyDict, PyFloat, PyFrozenSet, PyInt, PyList, PySet, PyString, PyTuple,
+};
+
+use path;
+use {Number, Value};
+#[cfg(feature = "dedup")]
+use {Dedup, Deduplicator};
+
+// how deep Python objects are converted, which also catches self-referencing lists
+const MAX_DEPTH: usize = 128;
+
+/// Converts a Python object to a value, keeping `Value` objects as they are.
+pub(crate) fn from_python(obj: &Bound<'_, PyAny>, depth: usize) -> PyResult<Value> {
+    if depth > MAX_DEPTH {
+        return Err(PyValueError::new_err("object is nested too deeply"));
+    }
+    let convert = |x: Bound<'_, PyAny>| from_python(&x, depth + 1);
+    if let Ok(v) = obj.cast::<PyValue>() {
+        return Ok(v.get().0.clone());
+    }
+    if obj.is_none() {
+        return Ok(Value::Unit);
+    }
+    // before ints, as bools are ints in Python
+    if let Ok(b) = obj.cast::<PyBool>() {
+        return Ok(Value::Bool(b.is_true()));
+    }
+    if obj.is_instance_of::<PyInt>() {
+        if let Ok(n) = obj.extract::<u64>() {
+            return Ok(Value::Number(Number::U64(n)));
+        }
+        return obj
+            .extract::<i64>()
+            .map(|n| Value::Number(Number::I64(n)))
+            .map_err(|_| PyOverflowError::new_err("int does not fit 64 bits"));
+    }
+    if let Ok(f) = obj.cast::<PyFloat>() {
+        return Ok(Value::Number(Number::F64(f.value())));
+    }
+    if let Ok(s) = obj.cast::<PyString>() {
+        return Ok(Value::string(s.to_str()?.to_owned()));
+    }
+    if let Ok(b) = obj.cast::<PyBytes>() {
+        return Ok(Value::bytes(b.as_bytes().to_vec()));
+    }
+    if let Ok(list) = obj.cast::<PyList>() {
+        return Ok(Value::seq(list.iter().map(convert).collect::<PyResult<_>>()?));
+    }
+    if let Ok(tuple) = obj.cast::<PyTuple>() {
+        let items = tuple.iter().map(convert).collect::<PyResult<_>>()?;
+        return Ok(Value::Tuple(Arc::new(items)));
+    }
+    if let Ok(dict) = obj.cast::<PyDict>() {
+        let mut map = BTreeMap::new();
+        for (k, v) in dict.iter() {
+            map.insert(convert(k)?, convert(v)?);
+        }
+        return Ok(Value::map(map));
+    }
+    let set: Option<PyResult<BTreeSet<Value>>> = if let Ok(set) = obj.cast::<PySet>() {
+        Some(set.iter().map(convert).collect())
+    } else if let Ok(set) = obj.cast::<PyFrozenSet>() {
+        Some(set.iter().map(convert).collect())
+    } else {
+        None
+    };
+    match set {
+        Some(set) => Ok(Value::Set(Arc::new(set?))),
+        None => Err(PyTypeError::new_err(format!(
+            "cannot convert {} to a value",
+            obj.get_type().name()?
+        ))),
+    }
+}
+
+/// Converts a value to plain Python objects. Options, newtypes and extension values become
+/// their content, sequences lists, tuples tuples and sets frozensets.
+pub(crate) fn to_python<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match *path::transparent(value) {
+        Value::Unit | Value::Option(None) => py.None().into_bound(py),
+        Value::Bool(v) => PyBool::new(py, v).to_owned().into_any(),
+        Value::Number(Number::U8(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::U16(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::U32(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::U64(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::I8(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::I16(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::I32(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::I64(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::F32(v)) => v.into_pyobject(py)?.into_any(),
+        Value::Number(Number::F64(v)) => v.into_pyobject(py)?.into_any(),
+        #[cfg(feature = "half")]
+        Value::F16(v) => v.to_f32().into_pyobject(py)?.into_any(),
+        Value::Char(v) => v.into_pyobject(py)?.into_any(),
+        Value::String(ref s) | Value::UnitStruct(ref s) => PyString::new(py, s).into_any(),
+        Value::Bytes(ref b) => PyBytes::new(py, b).into_any(),
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { .. } => {
+            let bytes = value.byte_content().unwrap()?;
+            PyBytes::new(py, &bytes).into_any()
+        }
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(ref b) => PyBytes::new(py, b).into_any(),
+        Value::Seq(ref v) => {
+            let items = v.iter().map(|x| to_python(py, x)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any()
+        }
+        Value::Tuple(ref v) => {
+            let items = v.iter().map(|x| to_python(py, x)).collect::<PyResult<Vec<_>>>()?;
+            PyTuple::new(py, items)?.into_any()
+        }
+        Value::Set(ref v) => {
+            let items = v.iter().map(|x| to_python(py, x)).collect::<PyResult<Vec<_>>>()?;
+            PyFrozenSet::new(py, items)?.into_any()
+        }
+        Value::Map(ref kv) => {
+            let dict = PyDict::new(py);
+            for (k, v) in kv.keys().iter().zip(kv.values().iter()) {
+                dict.set_item(to_python(py, k)?, to_python(py, v)?)?;
+            }
+            dict.into_any()
+        }
+        Value::Option(Some(..)) | Value::Newtype(..) | Value::Ext { .. } => unreachable!(),
+    })
+}
+
+/// An immutable value, shared with all values it was deduplicated with.
+#[pyclass(name = "Value", module = "serde_value", frozen)]
+pub struct PyValue(pub Value);
+
+#[pymethods]
+impl PyValue {
+    #[new]
+    fn new(obj: &Bound<'_, PyAny>) -> PyResult<PyValue> {
+        from_python(obj, 0).map(PyValue)
+    }
+
+    fn to_python<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        to_python(py, &self.0)
+    }
+
+    /// The value for a string key of a map.
+    fn get(&self, key: &str) -> Option<PyValue> {
+        self.0.get(key).cloned().map(PyValue)
+    }
+
+    /// The node at a JSON pointer.
+    fn pointer(&self, pointer: &str) -> Option<PyValue> {
+        self.0.pointer(pointer).cloned().map(PyValue)
+    }
+
+    fn __len__(&self) -> usize {
+        match *path::transparent(&self.0) {
+            Value::Seq(ref v) | Value::Tuple(ref v) => v.len(),
+            Value::Map(ref kv) => kv.keys().len(),
+            Value::Set(ref v) => v.len(),
+            _ => 0,
+        }
+    }
+
+    fn __eq__(&self, other: &PyValue) -> bool {
+        self.0 == other.0
+    }
+
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.0.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Value({})", self.0)
+    }
+}
+
+/// A dedup table, see `Dedup`.
+#[cfg(feature = "dedup")]
+#[pyclass(name = "Dedup", module = "serde_value")]
+pub struct PyDedup(pub Dedup);
+
+#[cfg(feature = "dedup")]
+#[pymethods]
+impl PyDedup {
+    #[new]
+    fn new() -> PyDedup {
+        PyDedup(Dedup::new())
+    }
+
+    /// Converts `obj` like `Value(obj)` and deduplicates it against everything before.
+    fn dedup(&mut self, obj: &Bound<'_, PyAny>) -> PyResult<PyValue> {
+        let value = from_python(obj, 0)?;
+        Ok(PyValue(self.0.dedup(value)))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Dedup(strings={}, blobs={}, seqs={}, objects={})",
+            self.0.iter_strings().count(),
+            self.0.iter_blobs().count(),
+            self.0.iter_seqs().count(),
+            self.0.iter_objects().count()
+        )
+    }
+}
+
+#[pymodule]
+fn serde_value(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyValue>()?;
+    #[cfg(feature = "dedup")]
+    m.add_class::<PyDedup>()?;
+    Ok(())
+}
@@ -0,0 +1,350 @@
+//! Conversion between query strings, or `application/x-www-form-urlencoded` bodies, and
+//! maps.
+//!
+//! Keys nest with the bracket syntax of Rails and serde_qs: `user[name]=x` sets the key
+//! `name` of the map at `user`, `tags[]=a&tags[]=b` appends to the sequence at `tags`, and
+//! `ids[1]=y&ids[0]=x` fills a sequence in the order of the indices. Every `[]` starts a
+//! new element. A key given more than once collects its values in a sequence.
+//!
+//! Values are strings, as the format has no other types, and a key without `=` has the
+//! empty string. Values that are not valid UTF-8 after percent decoding are bytes, and
+//! keys are decoded lossily. As in browsers, `+` is a space and malformed escapes are kept
+//! as they are.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::sync::Arc;
+
+use path;
+use Value;
+
+// how deep keys nest, which also bounds the recursion on crafted keys
+const MAX_DEPTH: usize = 32;
+
+/// Why a query string could not be read or written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    /// a key with unbalanced brackets, or nested too deeply
+    Syntax(String),
+    /// a key used for a string as well as nested keys, or for a sequence as well as a map,
+    /// with its JSON pointer
+    Conflict(String),
+    /// a node with no query string representation, with its JSON pointer
+    Unsupported(String),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryError::Syntax(ref key) => write!(f, "the key '{}' is malformed", key),
+            QueryError::Conflict(ref pointer) => {
+                write!(f, "the key at '{}' is used for different types", pointer)
+            }
+            QueryError::Unsupported(ref pointer) => {
+                write!(f, "the value at '{}' has no query string representation", pointer)
+            }
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+fn hex(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|d| d as u8)
+}
+
+fn decode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let escaped = match input[i] {
+            b'%' if i + 2 < input.len() => {
+                match (hex(input[i + 1]), hex(input[i + 2])) {
+                    (Some(high), Some(low)) => Some(high << 4 | low),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                output.push(byte);
+                i += 3;
+            }
+            None => {
+                output.push(if input[i] == b'+' { b' ' } else { input[i] });
+                i += 1;
+            }
+        }
+    }
+    output
+}
+
+fn encode(output: &mut String, input: &[u8]) {
+    for &byte in input {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => {
+                output.push(byte as char)
+            }
+            b' ' => output.push('+'),
+            _ => {
+                let _ = write!(output, "%{:02X}", byte);
+            }
+        }
+    }
+}
+
+enum Segment {
+    Key(String),
+    Index(usize),
+    Append,
+}
+
+/// Splits a decoded key into its name and the contents of its brackets.
+fn segments(key: &str) -> Result<Vec<Segment>, QueryError> {
+    let syntax = || QueryError::Syntax(key.to_owned());
+    let (name, mut rest) = match key.find('[') {
+        Some(0) => return Err(syntax()),
+        Some(i) => key.split_at(i),
+        None => (key, ""),
+    };
+    if name.contains(']') {
+        return Err(syntax());
+    }
+    let mut segments = vec![Segment::Key(name.to_owned())];
+    while !rest.is_empty() {
+        let end = match rest.find(']') {
+            Some(end) if rest.starts_with('[') => end,
+            _ => return Err(syntax()),
+        };
+        let inner = &rest[1..end];
+        if inner.contains('[') || segments.len() == MAX_DEPTH {
+            return Err(syntax());
+        }
+        segments.push(match inner.parse::<usize>() {
+            _ if inner.is_empty() => Segment::Append,
+            // only canonical numbers are indices, so `[01]` stays a key
+            Ok(i) if i.to_string() == inner => Segment::Index(i),
+            _ => Segment::Key(inner.to_owned()),
+        });
+        rest = &rest[end + 1..];
+    }
+    Ok(segments)
+}
+
+/// A node of the tree of keys being read.
+enum Node {
+    Empty,
+    Leaf(Vec<Value>),
+    Map(BTreeMap<String, Node>),
+    // elements with their index, if given, and the position of each index
+    Seq(Vec<(Option<usize>, Node)>, HashMap<usize, usize>),
+}
+
+impl Node {
+    fn insert(
+        &mut self,
+        segments: &[Segment],
+        value: Value,
+        pointer: &str,
+    ) -> Result<(), QueryError> {
+        let conflict = || QueryError::Conflict(pointer.to_owned());
+        let (segment, rest) = match segments.split_first() {
+            None => {
+                return match *self {
+                    Node::Empty => {
+                        *self = Node::Leaf(vec![value]);
+                        Ok(())
+                    }
+                    Node::Leaf(ref mut values) => {
+                        values.push(value);
+                        Ok(())
+                    }
+                    _ => Err(conflict()),
+                }
+            }
+            Some(split) => split,
+        };
+        if let Node::Empty = *self {
+            *self = match *segment {
+                Segment::Key(_) => Node::Map(BTreeMap::new()),
+                Segment::Index(_) | Segment::Append => Node::Seq(Vec::new(), HashMap::new()),
+            };
+        }
+        let (key, child) = match (self, segment) {
+            (&mut Node::Map(ref mut map), segment) => {
+                let key = match *segment {
+                    Segment::Key(ref key) => key.clone(),
+                    // indices into a map are keys
+                    Segment::Index(i) => i.to_string(),
+                    Segment::Append => return Err(conflict()),
+                };
+                let child = map.entry(key.clone()).or_insert(Node::Empty);
+                (key, child)
+            }
+            (&mut Node::Seq(ref mut elements, ref mut positions), segment) => {
+                let (key, position) = match *segment {
+                    Segment::Index(i) => {
+                        let position = *positions.entry(i).or_insert_with(|| {
+                            elements.push((Some(i), Node::Empty));
+                            elements.len() - 1
+                        });
+                        (i, position)
+                    }
+                    Segment::Append => {
+                        elements.push((None, Node::Empty));
+                        (elements.len() - 1, elements.len() - 1)
+                    }
+                    Segment::Key(_) => return Err(conflict()),
+                };
+                (key.to_string(), &mut elements[position].1)
+            }
+            _ => return Err(conflict()),
+        };
+        child.insert(rest, value, &path::join(pointer, &key))
+    }
+
+    fn finish(self) -> Value {
+        match self {
+            Node::Empty => Value::Unit,
+            Node::Leaf(mut values) => {
+                if values.len() == 1 {
+                    values.pop().unwrap()
+                } else {
+                    Value::Seq(Arc::new(values))
+                }
+            }
+            Node::Map(map) => Value::map(
+                map.into_iter()
+                    .map(|(key, node)| (Value::String(key.into()), node.finish()))
+                    .collect(),
+            ),
+            Node::Seq(mut elements, _) => {
+                // by index, with the appended ones after the indexed ones
+                elements.sort_by_key(|&(i, _)| i.map_or((1, 0), |i| (0, i)));
+                Value::Seq(Arc::new(elements.into_iter().map(|(_, n)| n.finish()).collect()))
+            }
+        }
+    }
+}
+
+/// Reads an `application/x-www-form-urlencoded` body into a map, see the module
+/// documentation.
+pub fn from_form_urlencoded(input: &[u8]) -> Result<Value, QueryError> {
+    let mut root = Node::Map(BTreeMap::new());
+    for pair in input.split(|&b| b == b'&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = match pair.iter().position(|&b| b == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &b""[..]),
+        };
+        let key = String::from_utf8_lossy(&decode(key)).into_owned();
+        let value = match String::from_utf8(decode(value)) {
+            Ok(value) => Value::String(value.into()),
+            Err(e) => Value::Bytes(Arc::new(e.into_bytes())),
+        };
+        root.insert(&segments(&key)?, value, "")?;
+    }
+    Ok(root.finish())
+}
+
+/// Reads a query string into a map, ignoring a leading `?`, see `from_form_urlencoded`.
+pub fn from_query_str(query: &str) -> Result<Value, QueryError> {
+    let query = query.strip_prefix('?').unwrap_or(query);
+    from_form_urlencoded(query.as_bytes())
+}
+
+/// The text of a scalar or a key, `None` for units and `None`.
+fn text(value: &Value) -> Option<Result<String, ()>> {
+    Some(Ok(match *value {
+        Value::Unit | Value::Option(None) => return None,
+        Value::Bool(v) => v.to_string(),
+        Value::Number(v) => v.to_string(),
+        #[cfg(feature = "half")]
+        Value::F16(v) => v.to_string(),
+        Value::Char(v) => v.to_string(),
+        Value::String(ref v) | Value::UnitStruct(ref v) => v.to_string(),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) | Value::Ext { value: ref v, .. } => {
+            return text(v)
+        }
+        _ => return Some(Err(())),
+    }))
+}
+
+fn write_pairs(
+    output: &mut String,
+    key: &str,
+    value: &Value,
+    pointer: &str,
+) -> Result<(), QueryError> {
+    let nested = |segment: &str| {
+        let mut nested = key.to_owned();
+        if key.is_empty() {
+            encode(&mut nested, segment.as_bytes());
+        } else {
+            nested.push('[');
+            encode(&mut nested, segment.as_bytes());
+            nested.push(']');
+        }
+        nested
+    };
+    let unsupported = || QueryError::Unsupported(pointer.to_owned());
+    match *path::transparent(value) {
+        Value::Map(ref kv) => {
+            for (k, v) in kv.keys().iter().zip(kv.values()) {
+                let pointer = path::join(pointer, &path::key_segment(k));
+                let k = match text(k) {
+                    Some(Ok(ref k)) if !k.contains(['[', ']']) => nested(k),
+                    _ => return Err(QueryError::Unsupported(pointer)),
+                };
+                write_pairs(output, &k, v, &pointer)?;
+            }
+        }
+        Value::Seq(ref elements) | Value::Tuple(ref elements) if !key.is_empty() => {
+            for (i, element) in elements.iter().enumerate() {
+                let pointer = path::join(pointer, &i.to_string());
+                write_pairs(output, &nested(&i.to_string()), element, &pointer)?;
+            }
+        }
+        Value::Set(ref elements) if !key.is_empty() => {
+            for (i, element) in elements.iter().enumerate() {
+                let pointer = path::join(pointer, &i.to_string());
+                write_pairs(output, &nested(&i.to_string()), element, &pointer)?;
+            }
+        }
+        _ if key.is_empty() => return Err(unsupported()),
+        Value::Bytes(ref bytes) => {
+            if !output.is_empty() {
+                output.push('&');
+            }
+            output.push_str(key);
+            output.push('=');
+            encode(output, bytes);
+        }
+        ref scalar => match text(scalar) {
+            None => {}
+            Some(Ok(text)) => {
+                if !output.is_empty() {
+                    output.push('&');
+                }
+                output.push_str(key);
+                output.push('=');
+                encode(output, text.as_bytes());
+            }
+            Some(Err(())) => return Err(unsupported()),
+        },
+    }
+    Ok(())
+}
+
+/// Writes a map as a query string or form body, the inverse of `from_form_urlencoded`.
+///
+/// Nested maps and sequences use brackets, with the index of every element, so sequences
+/// of maps read back the same. Units and `None` are left out, as are empty maps and
+/// sequences, which have no pairs. Map keys with brackets, and keys and values that are
+/// not scalars, have no representation.
+pub fn to_query_string(value: &Value) -> Result<String, QueryError> {
+    let mut output = String::new();
+    write_pairs(&mut output, "", value, "")?;
+    Ok(output)
+}
@@ -0,0 +1,280 @@
+//! JSON style `$ref` references between shared subtrees.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use path;
+use ser::SerializerError;
+use Value;
+
+const DEFS: &str = "$defs";
+const REF: &str = "$ref";
+
+fn address<T: ?Sized>(v: &Arc<T>) -> usize {
+    Arc::as_ptr(v) as *const u8 as usize
+}
+
+/// Identity of a non-empty container, which is what can be replaced by a reference.
+fn container(value: &Value) -> Option<usize> {
+    match *value {
+        Value::Seq(ref v) | Value::Tuple(ref v) if !v.is_empty() => Some(address(v)),
+        Value::Set(ref v) if !v.is_empty() => Some(address(v)),
+        Value::Map(ref kv) if !kv.keys().is_empty() => Some(address(kv)),
+        _ => None,
+    }
+}
+
+/// Counts how often each container occurs, looking into each of them only once, so that
+/// deeply shared trees are not expanded.
+fn count(value: &Value, counts: &mut HashMap<usize, usize>) {
+    if let Some(id) = container(value) {
+        let n = counts.entry(id).or_insert(0);
+        *n += 1;
+        if *n > 1 {
+            return;
+        }
+    }
+    match *value {
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => count(v, counts),
+        Value::Ext { ref value, .. } => count(value, counts),
+        Value::Seq(ref v) | Value::Tuple(ref v) => v.iter().for_each(|x| count(x, counts)),
+        Value::Set(ref v) => v.iter().for_each(|x| count(x, counts)),
+        Value::Map(ref kv) => kv.values().iter().for_each(|x| count(x, counts)),
+        _ => {}
+    }
+}
+
+struct Extractor {
+    counts: HashMap<usize, usize>,
+    threshold: usize,
+    // index in `defs` of every container that was extracted
+    names: HashMap<usize, usize>,
+    defs: Vec<Value>,
+}
+
+impl Extractor {
+    fn reference(index: usize) -> Value {
+        let mut map = BTreeMap::new();
+        map.insert(
+            Value::string(REF.to_owned()),
+            Value::string(format!("#/{}/{}", DEFS, index)),
+        );
+        Value::map(map)
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        if let Some(id) = container(value) {
+            if self.counts[&id] > self.threshold {
+                if let Some(&index) = self.names.get(&id) {
+                    return Extractor::reference(index);
+                }
+                let index = self.defs.len();
+                self.names.insert(id, index);
+                self.defs.push(Value::Unit);
+                self.defs[index] = self.children(value);
+                return Extractor::reference(index);
+            }
+        }
+        self.children(value)
+    }
+
+    fn children(&mut self, value: &Value) -> Value {
+        match *value {
+            Value::Option(Some(ref v)) => Value::Option(Some(Box::new(self.value(v)))),
+            Value::Newtype(ref v) => Value::Newtype(Box::new(self.value(v))),
+            Value::Ext { tag, ref value } => Value::Ext {
+                tag,
+                value: Box::new(self.value(value)),
+            },
+            Value::Seq(ref v) => Value::seq(v.iter().map(|x| self.value(x)).collect()),
+            Value::Tuple(ref v) => {
+                Value::Tuple(Arc::new(v.iter().map(|x| self.value(x)).collect()))
+            }
+            Value::Set(ref v) => Value::Set(Arc::new(v.iter().map(|x| self.value(x)).collect())),
+            Value::Map(ref kv) => {
+                let values = kv.values().iter().map(|x| self.value(x)).collect();
+                Value::Map(kv.with_values(values))
+            }
+            ref other => other.clone(),
+        }
+    }
+}
+
+impl Value {
+    /// Returns a copy of this map where subtrees occurring more than `threshold` times are
+    /// written once under a `$defs` key and replaced by `{"$ref": "#/$defs/<n>"}` elsewhere,
+    /// like in JSON Schema. Serialized as JSON, this can be a lot smaller than the expanded
+    /// document, while staying standard JSON.
+    ///
+    /// Subtrees are identified by their `Arc`, so this finds what `Dedup` shared. A subtree
+    /// is counted once for every occurrence of the container it is in, and empty ones are
+    /// never extracted. Definitions may refer to each other.
+    ///
+    /// Fails unless this is a map, or if it already has a `$defs` key and there is something
+    /// to extract. Maps with a `$ref` key are not escaped, so data containing them will not
+    /// round trip.
+    pub fn to_ref_document(&self, threshold: usize) -> Result<Value, SerializerError> {
+        let kv = match *self {
+            Value::Map(ref kv) => kv,
+            _ => return Err(SerializerError::Custom("expected a map".to_owned())),
+        };
+        let mut counts = HashMap::new();
+        count(self, &mut counts);
+        let mut extractor = Extractor {
+            counts,
+            threshold,
+            names: HashMap::new(),
+            defs: Vec::new(),
+        };
+        let document = extractor.children(self);
+        if extractor.defs.is_empty() {
+            return Ok(self.clone());
+        }
+        if kv.get(DEFS).is_some() {
+            return Err(SerializerError::Custom(format!("map already has a {} key", DEFS)));
+        }
+        let defs = extractor
+            .defs
+            .into_iter()
+            .enumerate()
+            .map(|(i, def)| (Value::string(i.to_string()), def))
+            .collect();
+        let mut map = match document {
+            Value::Map(ref kv) => kv.as_map(),
+            _ => unreachable!(),
+        };
+        map.insert(Value::string(DEFS.to_owned()), Value::map(defs));
+        Ok(Value::map(map))
+    }
+}
+
+/// Why `Value::resolve_refs` failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefError {
+    /// a `$ref` pointing at nothing
+    Missing(String),
+    /// a `$ref` pointing at a node containing it
+    Cycle(String),
+}
+
+impl fmt::Display for RefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RefError::Missing(ref target) => write!(f, "$ref {} points at nothing", target),
+            RefError::Cycle(ref target) => write!(f, "$ref {} refers to itself", target),
+        }
+    }
+}
+
+impl Error for RefError {}
+
+/// The local pointer of a `{"$ref": "#..."}` map.
+fn ref_target(value: &Value) -> Option<&str> {
+    match *value {
+        Value::Map(ref kv) if kv.keys().len() == 1 => match kv.get(REF) {
+            Some(Value::String(ref target)) if target.starts_with('#') => Some(target),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct Resolver<'a> {
+    root: &'a Value,
+    // resolved maps and sequences by their normalized pointer
+    resolved: HashMap<String, Value>,
+    // pointers of the containers being resolved, to detect cycles
+    active: Vec<String>,
+}
+
+impl<'a> Resolver<'a> {
+    fn pointer(&mut self, target: &str) -> Result<Value, RefError> {
+        let segments =
+            path::split(&target[1..]).ok_or_else(|| RefError::Missing(target.to_owned()))?;
+        let key = segments.iter().fold(String::new(), |p, s| path::join(&p, s));
+        if let Some(value) = self.resolved.get(&key) {
+            return Ok(value.clone());
+        }
+        if self.active.contains(&key) {
+            return Err(RefError::Cycle(target.to_owned()));
+        }
+        let root = self.root;
+        let value =
+            path::lookup(root, &segments).ok_or_else(|| RefError::Missing(target.to_owned()))?;
+        self.value(value, key)
+    }
+
+    fn value(&mut self, value: &Value, key: String) -> Result<Value, RefError> {
+        if let Some(target) = ref_target(value) {
+            return self.pointer(target);
+        }
+        let resolved = match *value {
+            Value::Option(Some(ref v)) => Value::Option(Some(Box::new(self.value(v, key)?))),
+            Value::Newtype(ref v) => Value::Newtype(Box::new(self.value(v, key)?)),
+            Value::Ext { tag, ref value } => Value::Ext {
+                tag,
+                value: Box::new(self.value(value, key)?),
+            },
+            Value::Seq(..) | Value::Tuple(..) | Value::Map(..) => {
+                if let Some(value) = self.resolved.get(&key) {
+                    return Ok(value.clone());
+                }
+                self.active.push(key.clone());
+                let resolved = self.container(value, &key);
+                self.active.pop();
+                let resolved = resolved?;
+                self.resolved.insert(key, resolved.clone());
+                resolved
+            }
+            ref other => other.clone(),
+        };
+        Ok(resolved)
+    }
+
+    fn container(&mut self, value: &Value, key: &str) -> Result<Value, RefError> {
+        Ok(match *value {
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                let elements = v
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| self.value(x, path::join(key, &i.to_string())))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Value::Tuple(..) = *value {
+                    Value::Tuple(Arc::new(elements))
+                } else {
+                    Value::seq(elements)
+                }
+            }
+            Value::Map(ref kv) => {
+                let values = kv
+                    .keys()
+                    .iter()
+                    .zip(kv.values().iter())
+                    .map(|(k, x)| self.value(x, path::join(key, &path::key_segment(k))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Value::Map(kv.with_values(values))
+            }
+            ref other => other.clone(),
+        })
+    }
+}
+
+impl Value {
+    /// Returns a copy of this value with every `{"$ref": "#/..."}` map replaced by the node
+    /// its JSON pointer addresses, the inverse of `to_ref_document`.
+    ///
+    /// All references to a node, and the node itself, end up as the same `Arc`, so documents
+    /// like JSON Schemas or OpenAPI specs share their definitions as `Dedup` would. Only maps
+    /// whose single key is `$ref` count as references, and other references are left alone.
+    /// The `$defs` section itself is kept. Sets are not looked into.
+    pub fn resolve_refs(&self) -> Result<Value, RefError> {
+        Resolver {
+            root: self,
+            resolved: HashMap::new(),
+            active: Vec::new(),
+        }
+        .value(self, String::new())
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::mem::{size_of, size_of_val};
+use std::sync::Arc;
+
+use {Dedup, Number, Value, KV};
+
+/// Number of entries listed in `SavingsReport::top`.
+const TOP_CONTRIBUTORS: usize = 10;
+
+pub(crate) fn blob_size(blob: &[u8]) -> usize {
+    blob.len()
+}
+
+pub(crate) fn string_size(string: &str) -> usize {
+    string.len()
+}
+
+pub(crate) fn seq_size(seq: &[Value]) -> usize {
+    size_of_val(seq)
+}
+
+pub(crate) fn object_size(object: &KV) -> usize {
+    size_of::<KV>() + size_of_val(&object.1[..])
+}
+
+/// Running totals of what was passed through one of the `Dedup` tables.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Seen {
+    pub(crate) count: u64,
+    pub(crate) hits: u64,
+    pub(crate) bytes: u64,
+}
+
+impl Seen {
+    pub(crate) fn record(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// Publishes the outcome of a table lookup through the `metrics` facade.
+///
+/// Per category, this maintains the counters `serde_value_dedup_lookups_total`,
+/// `serde_value_dedup_hits_total` and `serde_value_dedup_bytes_saved_total`, and the gauges
+/// `serde_value_dedup_entries` and `serde_value_dedup_hit_ratio`.
+#[cfg(feature = "metrics")]
+pub(crate) fn observe(category: Category, hit: bool, bytes: usize, entries: usize, seen: &Seen) {
+    let label = category.label();
+    metrics::counter!("serde_value_dedup_lookups_total", "category" => label).increment(1);
+    if hit {
+        metrics::counter!("serde_value_dedup_hits_total", "category" => label).increment(1);
+        metrics::counter!("serde_value_dedup_bytes_saved_total", "category" => label)
+            .increment(bytes as u64);
+    }
+    metrics::gauge!("serde_value_dedup_entries", "category" => label).set(entries as f64);
+    metrics::gauge!("serde_value_dedup_hit_ratio", "category" => label)
+        .set(seen.hits as f64 / seen.count as f64);
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn observe(_category: Category, _hit: bool, _bytes: usize, _entries: usize, _seen: &Seen) {}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SeenTables {
+    pub(crate) blobs: Seen,
+    pub(crate) strings: Seen,
+    pub(crate) vectors: Seen,
+    pub(crate) objects: Seen,
+}
+
+/// The kind of table an interned entry lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    Blob,
+    String,
+    Seq,
+    Object,
+}
+
+impl Category {
+    /// Lowercase name, used as metric label.
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Blob => "blob",
+            Category::String => "string",
+            Category::Seq => "seq",
+            Category::Object => "object",
+        }
+    }
+}
+
+/// Savings of a single `Dedup` table.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CategorySavings {
+    /// number of values passed through the table
+    pub seen: u64,
+    /// number of values found in the table
+    pub hits: u64,
+    /// number of distinct entries kept in the table
+    pub entries: usize,
+    /// bytes of all values passed through the table, as if none were shared
+    pub input_bytes: u64,
+    /// bytes actually held by the table
+    pub stored_bytes: u64,
+}
+
+impl CategorySavings {
+    /// `input_bytes / stored_bytes`, or 1 if nothing is stored.
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.input_bytes as f64 / self.stored_bytes as f64
+        }
+    }
+
+    pub fn saved_bytes(&self) -> u64 {
+        self.input_bytes.saturating_sub(self.stored_bytes)
+    }
+}
+
+/// An interned entry that is currently shared by many values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contributor {
+    pub category: Category,
+    pub value: Value,
+    /// size of a single copy of the entry
+    pub bytes: usize,
+    /// number of live references outside of the table
+    pub references: usize,
+    /// `bytes` times the number of references beyond the first
+    pub saved_bytes: usize,
+}
+
+/// Numbers describing how much a `Dedup` instance saved, see `Dedup::savings_report`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavingsReport {
+    pub blobs: CategorySavings,
+    pub strings: CategorySavings,
+    pub seqs: CategorySavings,
+    pub objects: CategorySavings,
+    /// entries saving the most bytes right now, largest first
+    pub top: Vec<Contributor>,
+}
+
+impl SavingsReport {
+    pub fn total(&self) -> CategorySavings {
+        let categories = [self.blobs, self.strings, self.seqs, self.objects];
+        categories.iter().fold(CategorySavings::default(), |acc, c| CategorySavings {
+            seen: acc.seen + c.seen,
+            hits: acc.hits + c.hits,
+            entries: acc.entries + c.entries,
+            input_bytes: acc.input_bytes + c.input_bytes,
+            stored_bytes: acc.stored_bytes + c.stored_bytes,
+        })
+    }
+}
+
+fn category<'a, T: ?Sized + 'a, I, F>(seen: Seen, entries: I, size: F) -> CategorySavings
+where
+    I: ExactSizeIterator<Item = &'a Arc<T>>,
+    F: Fn(&T) -> usize,
+{
+    CategorySavings {
+        seen: seen.count,
+        hits: seen.hits,
+        entries: entries.len(),
+        input_bytes: seen.bytes,
+        stored_bytes: entries.map(|x| size(x) as u64).sum(),
+    }
+}
+
+fn push_contributor<T: ?Sized, F>(
+    top: &mut Vec<Contributor>,
+    category: Category,
+    entry: &Arc<T>,
+    bytes: usize,
+    wrap: F,
+) where
+    F: FnOnce(Arc<T>) -> Value,
+{
+    // one reference is held by the table itself
+    let references = Arc::strong_count(entry) - 1;
+    let saved_bytes = bytes * references.saturating_sub(1);
+    if saved_bytes > 0 {
+        top.push(Contributor {
+            category,
+            value: wrap(entry.clone()),
+            bytes,
+            references,
+            saved_bytes,
+        });
+    }
+}
+
+impl Dedup {
+    /// Reports the bytes seen and stored by each table and the entries that save the most.
+    ///
+    /// Input bytes accumulate over the lifetime of this instance. Stored bytes and the top
+    /// contributors reflect the current state of the tables.
+    pub fn savings_report(&self) -> SavingsReport {
+        let mut top: Vec<Contributor> = Vec::new();
+        for x in self.blobs.iter() {
+            push_contributor(&mut top, Category::Blob, x, blob_size(x), Value::Bytes);
+        }
+        for x in self.strings.iter() {
+            push_contributor(&mut top, Category::String, x, string_size(x), Value::String);
+        }
+        for x in self.vectors.iter() {
+            push_contributor(&mut top, Category::Seq, x, seq_size(x), Value::Seq);
+        }
+        for x in self.objects.iter() {
+            push_contributor(&mut top, Category::Object, x, object_size(x), Value::Map);
+        }
+        top.sort_by(|a, b| b.saved_bytes.cmp(&a.saved_bytes).then_with(|| a.value.cmp(&b.value)));
+        top.truncate(TOP_CONTRIBUTORS);
+
+        SavingsReport {
+            blobs: category(self.seen.blobs, self.blobs.iter(), |x| blob_size(x)),
+            strings: category(self.seen.strings, self.strings.iter(), string_size),
+            seqs: category(self.seen.vectors, self.vectors.iter(), |x| seq_size(x)),
+            objects: category(self.seen.objects, self.objects.iter(), object_size),
+            top,
+        }
+    }
+}
+
+fn record(fields: Vec<(&str, Value)>) -> Value {
+    let map: BTreeMap<Value, Value> = fields
+        .into_iter()
+        .map(|(k, v)| (Value::string(k.to_owned()), v))
+        .collect();
+    Value::map(map)
+}
+
+impl CategorySavings {
+    fn to_value(self) -> Value {
+        record(vec![
+            ("seen", Value::Number(Number::U64(self.seen))),
+            ("hits", Value::Number(Number::U64(self.hits))),
+            ("entries", Value::Number(Number::U64(self.entries as u64))),
+            ("input_bytes", Value::Number(Number::U64(self.input_bytes))),
+            ("stored_bytes", Value::Number(Number::U64(self.stored_bytes))),
+            ("saved_bytes", Value::Number(Number::U64(self.saved_bytes()))),
+            ("ratio", Value::Number(Number::F64(self.ratio()))),
+        ])
+    }
+}
+
+impl Contributor {
+    fn to_value(&self) -> Value {
+        record(vec![
+            ("category", Value::string(self.category.label().to_owned())),
+            ("value", self.value.clone()),
+            ("bytes", Value::Number(Number::U64(self.bytes as u64))),
+            ("references", Value::Number(Number::U64(self.references as u64))),
+            ("saved_bytes", Value::Number(Number::U64(self.saved_bytes as u64))),
+        ])
+    }
+}
+
+impl SavingsReport {
+    /// The report as a map, to serialize it as JSON or compare it between runs.
+    ///
+    /// It has the numbers of all tables under `total`, those of each table under
+    /// `categories`, keyed by `Category::label`, and the top contributors in `top`.
+    pub fn to_value(&self) -> Value {
+        let categories = [
+            (Category::Blob, self.blobs),
+            (Category::String, self.strings),
+            (Category::Seq, self.seqs),
+            (Category::Object, self.objects),
+        ];
+        let categories = categories
+            .iter()
+            .map(|&(category, savings)| (category.label(), savings.to_value()))
+            .collect();
+        let top = self.top.iter().map(Contributor::to_value).collect();
+        record(vec![
+            ("total", self.total().to_value()),
+            ("categories", record(categories)),
+            ("top", Value::seq(top)),
+        ])
+    }
+}
+
+impl Dedup {
+    /// `savings_report` as a map, see `SavingsReport::to_value`. This is also what
+    /// `Display` prints.
+    ///
+    /// The report shares the top entries with the tables, so while it is alive they count as
+    /// referenced once more in later reports.
+    pub fn report(&self) -> Value {
+        self.savings_report().to_value()
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use {Value, KV};
+
+/// Which strings `rewrite_strings` passes to its function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Strings {
+    /// every string, map keys included
+    All,
+    /// only map keys that are strings
+    Keys,
+}
+
+/// Applies `f` to the strings selected by `scope`, where `f` returns `None` for strings it
+/// leaves alone. Returns `None` if nothing changed, so untouched subtrees stay shared.
+///
+/// A map whose keys change is rebuilt in key order. If two keys become equal, the value of
+/// the one that sorted last is kept.
+pub(crate) fn rewrite_strings<F>(value: &Value, scope: Strings, f: &mut F) -> Option<Value>
+where
+    F: FnMut(&Arc<str>) -> Option<Arc<str>>,
+{
+    match *value {
+        Value::String(ref s) if scope == Strings::All => f(s).map(Value::String),
+        Value::Option(Some(ref v)) => {
+            rewrite_strings(v, scope, f).map(|v| Value::Option(Some(Box::new(v))))
+        }
+        Value::Newtype(ref v) => rewrite_strings(v, scope, f).map(|v| Value::Newtype(Box::new(v))),
+        Value::Ext { tag, ref value } => rewrite_strings(value, scope, f).map(|value| Value::Ext {
+            tag,
+            value: Box::new(value),
+        }),
+        Value::Seq(ref v) => rewrite_elements(v, scope, f).map(Value::seq),
+        Value::Tuple(ref v) => rewrite_elements(v, scope, f).map(|v| Value::Tuple(Arc::new(v))),
+        Value::Set(ref v) => {
+            let mut changed = false;
+            let set: BTreeSet<Value> = v
+                .iter()
+                .map(|x| match rewrite_strings(x, scope, f) {
+                    Some(x) => {
+                        changed = true;
+                        x
+                    }
+                    None => x.clone(),
+                })
+                .collect();
+            if changed {
+                Some(Value::Set(Arc::new(set)))
+            } else {
+                None
+            }
+        }
+        Value::Map(ref kv) => rewrite_map(kv, scope, f),
+        _ => None,
+    }
+}
+
+fn rewrite_elements<F>(v: &[Value], scope: Strings, f: &mut F) -> Option<Vec<Value>>
+where
+    F: FnMut(&Arc<str>) -> Option<Arc<str>>,
+{
+    let rewritten: Vec<Option<Value>> = v.iter().map(|x| rewrite_strings(x, scope, f)).collect();
+    if rewritten.iter().all(Option::is_none) {
+        return None;
+    }
+    Some(merge(v, rewritten))
+}
+
+fn merge(old: &[Value], new: Vec<Option<Value>>) -> Vec<Value> {
+    new.into_iter()
+        .zip(old.iter())
+        .map(|(new, old)| new.unwrap_or_else(|| old.clone()))
+        .collect()
+}
+
+fn rewrite_map<F>(kv: &KV, scope: Strings, f: &mut F) -> Option<Value>
+where
+    F: FnMut(&Arc<str>) -> Option<Arc<str>>,
+{
+    let keys: Vec<Option<Value>> = kv
+        .0
+        .iter()
+        .map(|k| match *k {
+            Value::String(ref s) => f(s).map(Value::String),
+            ref k => rewrite_strings(k, scope, f),
+        })
+        .collect();
+    let values: Vec<Option<Value>> = kv.1.iter().map(|v| rewrite_strings(v, scope, f)).collect();
+    if keys.iter().all(Option::is_none) {
+        if values.iter().all(Option::is_none) {
+            return None;
+        }
+        return Some(Value::Map(kv.with_values(merge(&kv.1, values))));
+    }
+    // changed keys may sort differently or collide
+    let map: BTreeMap<Value, Value> = merge(&kv.0, keys)
+        .into_iter()
+        .zip(merge(&kv.1, values))
+        .collect();
+    Some(Value::map(map))
+}
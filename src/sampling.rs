@@ -0,0 +1,58 @@
+use filter::Fingerprint;
+use Dedup;
+
+/// Which new values a sampling `Dedup` adds to its tables, see `Dedup::with_sampling`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// Every `n`th value not found in a table, counted over all tables.
+    EveryNth(u32),
+    /// Values whose fingerprint is a multiple of `n`, about one in `n`. Unlike `EveryNth`,
+    /// whether a value is added does not depend on the order of the input.
+    Hash(u32),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sampler {
+    sampling: Sampling,
+    skipped: u32,
+}
+
+impl Sampler {
+    /// Panics if the rate is zero.
+    pub(crate) fn new(sampling: Sampling) -> Sampler {
+        match sampling {
+            Sampling::EveryNth(n) | Sampling::Hash(n) => assert!(n > 0, "sampling rate is zero"),
+        }
+        Sampler {
+            sampling,
+            skipped: 0,
+        }
+    }
+
+    /// Whether a value that was not found should be added to the table.
+    pub(crate) fn admit<T: Fingerprint + ?Sized>(&mut self, value: &T) -> bool {
+        match self.sampling {
+            Sampling::EveryNth(n) => {
+                self.skipped += 1;
+                if self.skipped < n {
+                    return false;
+                }
+                self.skipped = 0;
+                true
+            }
+            Sampling::Hash(n) => value.fingerprint().is_multiple_of(u64::from(n)),
+        }
+    }
+}
+
+impl Dedup {
+    /// Only adds a sample of the values it does not know yet to the tables.
+    ///
+    /// Values already in a table are still found, so a value repeated often enough gets
+    /// interned soon after it first appears. This bounds the size of the tables and the
+    /// cost of inserting on streams of mostly unique values. Panics if `n` is zero.
+    pub fn with_sampling(mut self, sampling: Sampling) -> Dedup {
+        self.sampler = Some(Sampler::new(sampling));
+        self
+    }
+}
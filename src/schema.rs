@@ -0,0 +1,813 @@
+//! Static descriptions of the `Value` shape of types, see `ValueSchema`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use serde::de::{self, DeserializeSeed, Deserializer, Visitor};
+
+use de::{num, Num};
+use path;
+use {Unexpected, Value, ValueVisitor};
+
+/// A schema computed on demand, so that recursive types have finite schemas.
+pub type SchemaFn = fn() -> Schema;
+
+/// The shape of the values a type serializes to and deserializes from.
+///
+/// Names are those passed to serde, so a schema can drive the `deserialize_*` calls that
+/// formats without type information need, see `Schema::decode`.
+#[derive(Clone, Debug)]
+pub enum Schema {
+    /// anything, e.g. the schema of `Value` itself
+    Any,
+    Unit,
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Char,
+    String,
+    Bytes,
+    Option(SchemaFn),
+    Seq(SchemaFn),
+    Tuple(Vec<SchemaFn>),
+    Map(SchemaFn, SchemaFn),
+    UnitStruct(&'static str),
+    Newtype(&'static str, SchemaFn),
+    TupleStruct(&'static str, Vec<SchemaFn>),
+    Struct {
+        name: &'static str,
+        fields: &'static [&'static str],
+        /// one per field
+        schemas: Vec<SchemaFn>,
+    },
+    Enum {
+        name: &'static str,
+        variants: &'static [&'static str],
+        /// one per variant
+        kinds: Vec<VariantSchema>,
+    },
+}
+
+/// The data of an enum variant.
+#[derive(Clone, Debug)]
+pub enum VariantSchema {
+    Unit,
+    Newtype(SchemaFn),
+    Tuple(Vec<SchemaFn>),
+    Struct {
+        fields: &'static [&'static str],
+        schemas: Vec<SchemaFn>,
+    },
+}
+
+/// Types that can describe the shape of their values.
+///
+/// With the `derive` feature, `#[derive(ValueSchema)]` implements this for structs and
+/// enums. The derive does not read serde attributes, so it only describes types whose serde
+/// derives do not change the shape with renames, defaults, flattening or tagging.
+pub trait ValueSchema {
+    fn schema() -> Schema;
+}
+
+/// Why a value does not fit a schema.
+#[derive(Debug)]
+pub enum SchemaErrorKind {
+    InvalidType(Unexpected, String),
+    /// the number of elements found and expected
+    InvalidLength(usize, usize),
+    MissingField(&'static str),
+    UnknownVariant(String),
+}
+
+/// A place where a value does not fit a schema, at the JSON pointer `pointer`.
+#[derive(Debug)]
+pub struct SchemaError {
+    pub pointer: String,
+    pub kind: SchemaErrorKind,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() { "(root)" } else { &self.pointer };
+        match self.kind {
+            SchemaErrorKind::InvalidType(ref found, ref expected) => write!(
+                f,
+                "{}: invalid type: {}, expected {}",
+                pointer,
+                found.to_unexpected(),
+                expected
+            ),
+            SchemaErrorKind::InvalidLength(found, expected) => write!(
+                f,
+                "{}: invalid length {}, expected {}",
+                pointer, found, expected
+            ),
+            SchemaErrorKind::MissingField(field) => {
+                write!(f, "{}: missing field `{}`", pointer, field)
+            }
+            SchemaErrorKind::UnknownVariant(ref variant) => {
+                write!(f, "{}: unknown variant `{}`", pointer, variant)
+            }
+        }
+    }
+}
+
+impl Error for SchemaError {}
+
+// everything that deserializes as a sequence
+fn elements(value: &Value) -> Option<Vec<&Value>> {
+    match *value {
+        Value::Seq(ref v) | Value::Tuple(ref v) => Some(v.iter().collect()),
+        Value::Set(ref v) => Some(v.iter().collect()),
+        _ => None,
+    }
+}
+
+fn fits_int(value: &Value, min: i128, max: i128) -> bool {
+    match num(value) {
+        Some(Num::Int(i)) => i >= min && i <= max,
+        Some(Num::Float(f)) => f.fract() == 0.0 && f >= min as f64 && f < max as f64 + 1.0,
+        None => false,
+    }
+}
+
+struct Checker {
+    errors: Vec<SchemaError>,
+}
+
+impl Checker {
+    fn invalid(&mut self, pointer: &str, value: &Value, expected: String) {
+        self.errors.push(SchemaError {
+            pointer: pointer.to_owned(),
+            kind: SchemaErrorKind::InvalidType(value.unexpected().into(), expected),
+        });
+    }
+
+    fn length(&mut self, pointer: &str, found: usize, expected: usize) {
+        self.errors.push(SchemaError {
+            pointer: pointer.to_owned(),
+            kind: SchemaErrorKind::InvalidLength(found, expected),
+        });
+    }
+
+    fn elements(&mut self, schemas: &[SchemaFn], values: &[&Value], pointer: &str) {
+        if values.len() != schemas.len() {
+            return self.length(pointer, values.len(), schemas.len());
+        }
+        for (i, (schema, value)) in schemas.iter().zip(values).enumerate() {
+            self.check(&schema(), value, &path::join(pointer, &i.to_string()));
+        }
+    }
+
+    fn fields(
+        &mut self,
+        fields: &'static [&'static str],
+        schemas: &[SchemaFn],
+        value: &Value,
+        pointer: &str,
+        expected: String,
+    ) {
+        if let Some(values) = elements(value) {
+            return self.elements(schemas, &values, pointer);
+        }
+        let kv = match *value {
+            Value::Map(ref kv) => kv,
+            _ => return self.invalid(pointer, value, expected),
+        };
+        for (&field, schema) in fields.iter().zip(schemas) {
+            let schema = schema();
+            match kv.get(field) {
+                Some(v) => self.check(&schema, v, &path::join(pointer, field)),
+                // serde fills in missing options with `None`
+                None if matches!(schema, Schema::Option(..)) => {}
+                None => self.errors.push(SchemaError {
+                    pointer: pointer.to_owned(),
+                    kind: SchemaErrorKind::MissingField(field),
+                }),
+            }
+        }
+    }
+
+    fn variant(&mut self, kind: &VariantSchema, value: &Value, pointer: &str) {
+        match *kind {
+            VariantSchema::Unit => {
+                if !matches!(*value, Value::Unit) {
+                    self.invalid(pointer, value, "unit variant".to_owned());
+                }
+            }
+            VariantSchema::Newtype(schema) => self.check(&schema(), value, pointer),
+            VariantSchema::Tuple(ref schemas) => match elements(value) {
+                Some(values) => self.elements(schemas, &values, pointer),
+                None => self.invalid(pointer, value, "tuple variant".to_owned()),
+            },
+            VariantSchema::Struct { fields, ref schemas } => {
+                self.fields(fields, schemas, value, pointer, "struct variant".to_owned())
+            }
+        }
+    }
+
+    fn check(&mut self, schema: &Schema, value: &Value, pointer: &str) {
+        let value = match *value {
+            Value::Ext { ref value, .. } if !matches!(*schema, Schema::Any) => {
+                return self.check(schema, value, pointer)
+            }
+            ref value => value,
+        };
+        let ok = match *schema {
+            Schema::Any => true,
+            Schema::Unit => matches!(*value, Value::Unit | Value::UnitStruct(..)),
+            Schema::Bool => matches!(*value, Value::Bool(..)),
+            Schema::U8 => fits_int(value, 0, u8::MAX.into()),
+            Schema::U16 => fits_int(value, 0, u16::MAX.into()),
+            Schema::U32 => fits_int(value, 0, u32::MAX.into()),
+            Schema::U64 => fits_int(value, 0, u64::MAX.into()),
+            Schema::I8 => fits_int(value, i8::MIN.into(), i8::MAX.into()),
+            Schema::I16 => fits_int(value, i16::MIN.into(), i16::MAX.into()),
+            Schema::I32 => fits_int(value, i32::MIN.into(), i32::MAX.into()),
+            Schema::I64 => fits_int(value, i64::MIN.into(), i64::MAX.into()),
+            Schema::F32 => match num(value) {
+                Some(Num::Float(f)) => !f.is_finite() || f.abs() <= f64::from(f32::MAX),
+                Some(Num::Int(..)) => true,
+                None => false,
+            },
+            Schema::F64 => num(value).is_some(),
+            Schema::Char => match *value {
+                Value::Char(..) => true,
+                Value::String(ref s) => s.chars().count() == 1,
+                _ => false,
+            },
+            Schema::String => matches!(*value, Value::String(..) | Value::Char(..)),
+            Schema::Bytes => match *value {
+                Value::Bytes(..) | Value::String(..) => true,
+                #[cfg(feature = "compression")]
+                Value::CompressedBytes { .. } => true,
+                #[cfg(feature = "mmap")]
+                Value::MappedBytes(..) => true,
+                _ => false,
+            },
+            Schema::Option(schema) => {
+                match *value {
+                    Value::Option(None) | Value::Unit => {}
+                    Value::Option(Some(ref v)) => self.check(&schema(), v, pointer),
+                    ref v => self.check(&schema(), v, pointer),
+                }
+                return;
+            }
+            Schema::Seq(schema) => match elements(value) {
+                Some(values) => {
+                    let schema = schema();
+                    for (i, v) in values.into_iter().enumerate() {
+                        self.check(&schema, v, &path::join(pointer, &i.to_string()));
+                    }
+                    return;
+                }
+                None => false,
+            },
+            Schema::Tuple(ref schemas) | Schema::TupleStruct(_, ref schemas) => {
+                match elements(value) {
+                    Some(values) => return self.elements(schemas, &values, pointer),
+                    None => false,
+                }
+            }
+            Schema::Map(keys, values) => match *value {
+                Value::Map(ref kv) => {
+                    let (keys, values) = (keys(), values());
+                    for (k, v) in kv.keys().iter().zip(kv.values()) {
+                        let pointer = path::join(pointer, &path::key_segment(k));
+                        self.check(&keys, k, &pointer);
+                        self.check(&values, v, &pointer);
+                    }
+                    return;
+                }
+                _ => false,
+            },
+            Schema::UnitStruct(name) => match *value {
+                Value::UnitStruct(ref found) => **found == *name,
+                Value::Unit => true,
+                _ => false,
+            },
+            Schema::Newtype(_, schema) => {
+                match *value {
+                    Value::Newtype(ref v) => self.check(&schema(), v, pointer),
+                    ref v => self.check(&schema(), v, pointer),
+                }
+                return;
+            }
+            Schema::Struct {
+                fields,
+                ref schemas,
+                ..
+            } => {
+                return self.fields(fields, schemas, value, pointer, schema.describe());
+            }
+            Schema::Enum {
+                variants,
+                ref kinds,
+                ..
+            } => {
+                let (variant, data) = match *value {
+                    Value::String(ref v) => (&**v, None),
+                    Value::Map(ref kv) if kv.keys().len() == 1 => match kv.keys()[0] {
+                        Value::String(ref v) => (&**v, Some(&kv.values()[0])),
+                        _ => return self.invalid(pointer, value, schema.describe()),
+                    },
+                    _ => return self.invalid(pointer, value, schema.describe()),
+                };
+                let kind = match variants.iter().position(|&v| v == variant) {
+                    Some(i) => &kinds[i],
+                    None => {
+                        return self.errors.push(SchemaError {
+                            pointer: pointer.to_owned(),
+                            kind: SchemaErrorKind::UnknownVariant(variant.to_owned()),
+                        })
+                    }
+                };
+                match data {
+                    Some(data) => self.variant(kind, data, &path::join(pointer, variant)),
+                    None if matches!(*kind, VariantSchema::Unit) => {}
+                    None => self.invalid(pointer, value, "variant with data".to_owned()),
+                }
+                return;
+            }
+        };
+        if !ok {
+            self.invalid(pointer, value, schema.describe());
+        }
+    }
+}
+
+impl Schema {
+    /// A short description of what this schema expects, for error messages.
+    pub fn describe(&self) -> String {
+        match *self {
+            Schema::Any => "any value".to_owned(),
+            Schema::Unit => "unit".to_owned(),
+            Schema::Bool => "a boolean".to_owned(),
+            Schema::U8 => "u8".to_owned(),
+            Schema::U16 => "u16".to_owned(),
+            Schema::U32 => "u32".to_owned(),
+            Schema::U64 => "u64".to_owned(),
+            Schema::I8 => "i8".to_owned(),
+            Schema::I16 => "i16".to_owned(),
+            Schema::I32 => "i32".to_owned(),
+            Schema::I64 => "i64".to_owned(),
+            Schema::F32 => "f32".to_owned(),
+            Schema::F64 => "f64".to_owned(),
+            Schema::Char => "a character".to_owned(),
+            Schema::String => "a string".to_owned(),
+            Schema::Bytes => "bytes".to_owned(),
+            Schema::Option(..) => "an option".to_owned(),
+            Schema::Seq(..) => "a sequence".to_owned(),
+            Schema::Tuple(ref v) => format!("a tuple of size {}", v.len()),
+            Schema::Map(..) => "a map".to_owned(),
+            Schema::UnitStruct(name) => format!("unit struct {}", name),
+            Schema::Newtype(name, _) => format!("newtype struct {}", name),
+            Schema::TupleStruct(name, _) => format!("tuple struct {}", name),
+            Schema::Struct { name, .. } => format!("struct {}", name),
+            Schema::Enum { name, .. } => format!("enum {}", name),
+        }
+    }
+
+    /// Checks whether `value` fits this schema, listing every place where it does not.
+    ///
+    /// This follows what deserializing with `from_value` accepts, so a value that passes
+    /// deserializes into the type the schema is for, unless that has custom checks. Unlike
+    /// deserialization, it does not stop at the first error.
+    pub fn validate(&self, value: &Value) -> Result<(), Vec<SchemaError>> {
+        let mut checker = Checker { errors: Vec::new() };
+        checker.check(self, value, "");
+        if checker.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(checker.errors)
+        }
+    }
+
+    /// Reads a value of this shape from `deserializer`.
+    ///
+    /// Unlike `Value::deserialize`, this calls the `deserialize_*` method for the expected
+    /// type at every node instead of `deserialize_any`, so it works with formats that do
+    /// not describe their contents, such as bincode. The result has the same shape as
+    /// `to_value` of the original.
+    pub fn decode<'de, D: Deserializer<'de>>(&self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+}
+
+fn variant_value(variant: &str, value: Value) -> Value {
+    let mut map = BTreeMap::new();
+    map.insert(Value::string(variant.to_owned()), value);
+    Value::map(map)
+}
+
+impl<'de> DeserializeSeed<'de> for &Schema {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        match *self {
+            Schema::Any => d.deserialize_any(ValueVisitor),
+            Schema::Unit => d.deserialize_unit(ValueVisitor),
+            Schema::Bool => d.deserialize_bool(ValueVisitor),
+            Schema::U8 => d.deserialize_u8(ValueVisitor),
+            Schema::U16 => d.deserialize_u16(ValueVisitor),
+            Schema::U32 => d.deserialize_u32(ValueVisitor),
+            Schema::U64 => d.deserialize_u64(ValueVisitor),
+            Schema::I8 => d.deserialize_i8(ValueVisitor),
+            Schema::I16 => d.deserialize_i16(ValueVisitor),
+            Schema::I32 => d.deserialize_i32(ValueVisitor),
+            Schema::I64 => d.deserialize_i64(ValueVisitor),
+            Schema::F32 => d.deserialize_f32(ValueVisitor),
+            Schema::F64 => d.deserialize_f64(ValueVisitor),
+            Schema::Char => d.deserialize_char(ValueVisitor),
+            Schema::String => d.deserialize_string(ValueVisitor),
+            Schema::Bytes => d.deserialize_byte_buf(ValueVisitor),
+            Schema::Option(schema) => d.deserialize_option(OptionVisitor(schema())),
+            Schema::Seq(schema) => d.deserialize_seq(SeqVisitor(schema())),
+            Schema::Tuple(ref schemas) => {
+                d.deserialize_tuple(schemas.len(), TupleVisitor(schemas))
+            }
+            Schema::Map(keys, values) => d.deserialize_map(MapVisitor(keys(), values())),
+            Schema::UnitStruct(name) => d.deserialize_unit_struct(name, ValueVisitor),
+            Schema::Newtype(name, schema) => {
+                d.deserialize_newtype_struct(name, NewtypeVisitor(schema()))
+            }
+            Schema::TupleStruct(name, ref schemas) => {
+                d.deserialize_tuple_struct(name, schemas.len(), TupleVisitor(schemas))
+            }
+            Schema::Struct {
+                name,
+                fields,
+                ref schemas,
+            } => d.deserialize_struct(name, fields, StructVisitor { fields, schemas }),
+            Schema::Enum {
+                name,
+                variants,
+                ref kinds,
+            } => d.deserialize_enum(name, variants, EnumVisitor { variants, kinds }),
+        }
+    }
+}
+
+struct OptionVisitor(Schema);
+
+impl<'de> Visitor<'de> for OptionVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an option")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Option(None))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Option(None))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        self.0.deserialize(d).map(|v| Value::Option(Some(Box::new(v))))
+    }
+}
+
+struct NewtypeVisitor(Schema);
+
+impl<'de> Visitor<'de> for NewtypeVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a newtype struct")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        self.0.deserialize(d).map(|v| Value::Newtype(Box::new(v)))
+    }
+}
+
+struct SeqVisitor(Schema);
+
+impl<'de> Visitor<'de> for SeqVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(v) = seq.next_element_seed(&self.0)? {
+            values.push(v);
+        }
+        Ok(Value::seq(values))
+    }
+}
+
+struct TupleVisitor<'a>(&'a [SchemaFn]);
+
+impl<'a> TupleVisitor<'a> {
+    fn elements<'de, A: de::SeqAccess<'de>>(&self, mut seq: A) -> Result<Vec<Value>, A::Error> {
+        let mut values = Vec::with_capacity(self.0.len());
+        for (i, schema) in self.0.iter().enumerate() {
+            match seq.next_element_seed(&schema())? {
+                Some(v) => values.push(v),
+                None => return Err(de::Error::invalid_length(i, self)),
+            }
+        }
+        Ok(values)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for TupleVisitor<'a> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a tuple of size {}", self.0.len())
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Value, A::Error> {
+        self.elements(seq).map(|v| Value::Tuple(Arc::new(v)))
+    }
+}
+
+struct MapVisitor(Schema, Schema);
+
+impl<'de> Visitor<'de> for MapVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let mut map = BTreeMap::new();
+        while let Some((k, v)) = access.next_entry_seed(&self.0, &self.1)? {
+            map.insert(k, v);
+        }
+        Ok(Value::map(map))
+    }
+}
+
+struct StructVisitor<'a> {
+    fields: &'static [&'static str],
+    schemas: &'a [SchemaFn],
+}
+
+impl<'a, 'de> Visitor<'de> for StructVisitor<'a> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a struct with {} fields", self.fields.len())
+    }
+
+    // formats without field names send the fields in order
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Value, A::Error> {
+        let values = TupleVisitor(self.schemas).elements(seq)?;
+        let names = self.fields.iter().map(|&f| Value::string(f.to_owned()));
+        Ok(Value::map(names.zip(values).collect()))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let mut map = BTreeMap::new();
+        while let Some(key) = access.next_key::<Value>()? {
+            let index = match key {
+                Value::String(ref s) => self.fields.iter().position(|&f| f == &**s),
+                ref k => num(k).and_then(|n| match n {
+                    Num::Int(i) if (i as usize) < self.fields.len() => Some(i as usize),
+                    _ => None,
+                }),
+            };
+            match index {
+                Some(i) => {
+                    let value = access.next_value_seed(&(self.schemas[i])())?;
+                    map.insert(Value::string(self.fields[i].to_owned()), value);
+                }
+                None => {
+                    access.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Value::map(map))
+    }
+}
+
+struct VariantIndex(&'static [&'static str]);
+
+impl<'de> DeserializeSeed<'de> for VariantIndex {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<usize, D::Error> {
+        d.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for VariantIndex {
+    type Value = usize;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a variant name or index")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<usize, E> {
+        if v < self.0.len() as u64 {
+            Ok(v as usize)
+        } else {
+            Err(E::invalid_value(de::Unexpected::Unsigned(v), &self))
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<usize, E> {
+        self.0
+            .iter()
+            .position(|&variant| variant == v)
+            .ok_or_else(|| E::unknown_variant(v, self.0))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<usize, E> {
+        match ::std::str::from_utf8(v) {
+            Ok(v) => self.visit_str(v),
+            Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(v), &self)),
+        }
+    }
+}
+
+struct EnumVisitor<'a> {
+    variants: &'static [&'static str],
+    kinds: &'a [VariantSchema],
+}
+
+impl<'a, 'de> Visitor<'de> for EnumVisitor<'a> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an enum")
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<Value, A::Error> {
+        use serde::de::VariantAccess;
+
+        let (index, access) = data.variant_seed(VariantIndex(self.variants))?;
+        let variant = self.variants[index];
+        let value = match self.kinds[index] {
+            VariantSchema::Unit => {
+                access.unit_variant()?;
+                return Ok(Value::string(variant.to_owned()));
+            }
+            VariantSchema::Newtype(schema) => access.newtype_variant_seed(&schema())?,
+            VariantSchema::Tuple(ref schemas) => {
+                access.tuple_variant(schemas.len(), TupleVisitor(schemas))?
+            }
+            VariantSchema::Struct {
+                fields,
+                ref schemas,
+            } => access.struct_variant(fields, StructVisitor { fields, schemas })?,
+        };
+        Ok(variant_value(variant, value))
+    }
+}
+
+macro_rules! leaf_schemas {
+    ($($ty:ty => $schema:ident,)*) => {
+        $(
+            impl ValueSchema for $ty {
+                fn schema() -> Schema {
+                    Schema::$schema
+                }
+            }
+        )*
+    };
+}
+
+leaf_schemas! {
+    () => Unit,
+    bool => Bool,
+    u8 => U8,
+    u16 => U16,
+    u32 => U32,
+    u64 => U64,
+    usize => U64,
+    i8 => I8,
+    i16 => I16,
+    i32 => I32,
+    i64 => I64,
+    isize => I64,
+    f32 => F32,
+    f64 => F64,
+    char => Char,
+    String => String,
+    str => String,
+    Value => Any,
+}
+
+impl<T: ValueSchema> ValueSchema for Option<T> {
+    fn schema() -> Schema {
+        Schema::Option(T::schema)
+    }
+}
+
+macro_rules! transparent_schemas {
+    ($($ty:ident,)*) => {
+        $(
+            impl<T: ValueSchema + ?Sized> ValueSchema for $ty<T> {
+                fn schema() -> Schema {
+                    T::schema()
+                }
+            }
+        )*
+    };
+}
+
+transparent_schemas! {
+    Box,
+    Rc,
+    Arc,
+}
+
+impl<T: ValueSchema + ?Sized> ValueSchema for &T {
+    fn schema() -> Schema {
+        T::schema()
+    }
+}
+
+impl<T: ValueSchema> ValueSchema for [T] {
+    fn schema() -> Schema {
+        Schema::Seq(T::schema)
+    }
+}
+
+macro_rules! seq_schemas {
+    ($($ty:ident,)*) => {
+        $(
+            impl<T: ValueSchema> ValueSchema for $ty<T> {
+                fn schema() -> Schema {
+                    Schema::Seq(T::schema)
+                }
+            }
+        )*
+    };
+}
+
+seq_schemas! {
+    Vec,
+    VecDeque,
+    BTreeSet,
+}
+
+impl<T: ValueSchema, S: BuildHasher> ValueSchema for HashSet<T, S> {
+    fn schema() -> Schema {
+        Schema::Seq(T::schema)
+    }
+}
+
+impl<K: ValueSchema, V: ValueSchema> ValueSchema for BTreeMap<K, V> {
+    fn schema() -> Schema {
+        Schema::Map(K::schema, V::schema)
+    }
+}
+
+impl<K: ValueSchema, V: ValueSchema, S: BuildHasher> ValueSchema for HashMap<K, V, S> {
+    fn schema() -> Schema {
+        Schema::Map(K::schema, V::schema)
+    }
+}
+
+// serde encodes arrays as tuples
+impl<T: ValueSchema, const N: usize> ValueSchema for [T; N] {
+    fn schema() -> Schema {
+        Schema::Tuple(vec![T::schema as SchemaFn; N])
+    }
+}
+
+macro_rules! tuple_schemas {
+    ($(($($name:ident)+),)*) => {
+        $(
+            impl<$($name: ValueSchema),+> ValueSchema for ($($name,)+) {
+                fn schema() -> Schema {
+                    Schema::Tuple(vec![$($name::schema as SchemaFn),+])
+                }
+            }
+        )*
+    };
+}
+
+tuple_schemas! {
+    (A),
+    (A B),
+    (A B C),
+    (A B C D),
+    (A B C D E),
+    (A B C D E F),
+    (A B C D E F G),
+    (A B C D E F G H),
+}
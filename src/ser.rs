@@ -1,11 +1,31 @@
 use serde::ser;
-use std::collections::BTreeMap;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::sync::Arc;
 
-use Value;
+use set;
+use {Number, Value};
 use KV;
+#[cfg(feature = "tracing")]
+use instrument;
+
+/// Newtype struct name of `Value::Ext`, recognized by the value serializer.
+pub(crate) const EXT_TOKEN: &str = "$serde_value::private::Ext";
+
+thread_local! {
+    // the tag of the `Value::Ext` being serialized, for the value serializer to take
+    static EXT_TAG: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Offers `tag` to the value serializer for the duration of `f`.
+fn offer_ext_tag<R, F: FnOnce() -> R>(tag: u64, f: F) -> R {
+    EXT_TAG.with(|slot| slot.set(Some(tag)));
+    let res = f();
+    EXT_TAG.with(|slot| slot.set(None));
+    res
+}
 
 #[derive(Debug)]
 pub enum SerializerError {
@@ -36,34 +56,126 @@ impl ser::Serialize for Value {
     fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
         match *self {
             Value::Bool(v) => s.serialize_bool(v),
-            Value::U8(v) => s.serialize_u8(v),
-            Value::U16(v) => s.serialize_u16(v),
-            Value::U32(v) => s.serialize_u32(v),
-            Value::U64(v) => s.serialize_u64(v),
-            Value::I8(v) => s.serialize_i8(v),
-            Value::I16(v) => s.serialize_i16(v),
-            Value::I32(v) => s.serialize_i32(v),
-            Value::I64(v) => s.serialize_i64(v),
-            Value::F32(v) => s.serialize_f32(v),
-            Value::F64(v) => s.serialize_f64(v),
+            Value::Number(ref v) => v.serialize(s),
+            #[cfg(feature = "half")]
+            Value::F16(v) => v.serialize(s),
             Value::Char(v) => s.serialize_char(v),
             Value::String(ref v) => s.serialize_str(v),
-            Value::Unit => s.serialize_unit(),
+            // other serializers need a `&'static str` for the name
+            Value::Unit | Value::UnitStruct(..) => s.serialize_unit(),
             Value::Option(None) => s.serialize_none(),
             Value::Option(Some(ref v)) => s.serialize_some(v),
             Value::Newtype(ref v) => s.serialize_newtype_struct("", v),
             Value::Seq(ref v) => v.serialize(s),
+            Value::Tuple(ref v) => {
+                let mut tuple = s.serialize_tuple(v.len())?;
+                for element in v.iter() {
+                    ser::SerializeTuple::serialize_element(&mut tuple, element)?;
+                }
+                ser::SerializeTuple::end(tuple)
+            }
             Value::Map(ref v) => v.as_ref().as_map().serialize(s),
+            Value::Set(ref v) => v.serialize(s),
             Value::Bytes(ref v) => s.serialize_bytes(v),
+            // other serializers see the bare value in a newtype struct, only the value
+            // serializer takes the tag and turns it back into an `Ext`
+            Value::Ext { tag, ref value } => {
+                offer_ext_tag(tag, || s.serialize_newtype_struct(EXT_TOKEN, value))
+            }
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => {
+                let data = codec.decompress(data).map_err(ser::Error::custom)?;
+                s.serialize_bytes(&data)
+            }
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => s.serialize_bytes(v),
+        }
+    }
+}
+
+impl ser::Serialize for Number {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Number::U8(v) => s.serialize_u8(v),
+            Number::U16(v) => s.serialize_u16(v),
+            Number::U32(v) => s.serialize_u32(v),
+            Number::U64(v) => s.serialize_u64(v),
+            Number::I8(v) => s.serialize_i8(v),
+            Number::I16(v) => s.serialize_i16(v),
+            Number::I32(v) => s.serialize_i32(v),
+            Number::I64(v) => s.serialize_i64(v),
+            Number::F32(v) => s.serialize_f32(v),
+                Number::F64(v) => s.serialize_f64(v),
         }
     }
 }
 
 pub fn to_value<T: ser::Serialize>(value: T) -> Result<Value, SerializerError> {
-    value.serialize(Serializer)
+    to_value_with(value, ToValueOptions::default())
+}
+
+/// Like `to_value`, with non-default options.
+pub fn to_value_with<T: ser::Serialize>(
+    value: T,
+    options: ToValueOptions,
+) -> Result<Value, SerializerError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("to_value", ty = std::any::type_name::<T>()).entered();
+    let result = value.serialize(Serializer { options });
+    #[cfg(feature = "tracing")]
+    instrument::serialized(&result);
+    result
+}
+
+/// Options for `to_value_with`, to keep information the default conversion drops.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ToValueOptions {
+    unit_names: bool,
+}
+
+impl ToValueOptions {
+    pub fn new() -> Self {
+        ToValueOptions::default()
+    }
+
+    /// Records the name of unit structs as `Value::UnitStruct` instead of `Value::Unit`, so
+    /// that deserializing into a different unit struct fails.
+    pub fn unit_names(mut self, unit_names: bool) -> Self {
+        self.unit_names = unit_names;
+        self
+    }
 }
 
-struct Serializer;
+#[derive(Clone, Copy)]
+struct Serializer {
+    options: ToValueOptions,
+}
+
+thread_local! {
+    // field names are `&'static str`, so they can be keyed by address and length
+    static FIELD_NAMES: RefCell<HashMap<(usize, usize), Arc<str>>> = RefCell::new(HashMap::new());
+}
+
+/// The string for a type or field name, allocated once per name and thread.
+fn shared_name(name: &'static str) -> Arc<str> {
+    FIELD_NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        names
+            .entry((name.as_ptr() as usize, name.len()))
+            .or_insert_with(|| Arc::from(name))
+            .clone()
+    })
+}
+
+/// The key for a struct field.
+fn field_name(name: &'static str) -> Value {
+    Value::String(shared_name(name))
+}
+
+/// Externally tagged encoding of a variant with data, a map with the variant name as the only key.
+fn variant_value(variant: &'static str, value: Value) -> Value {
+    Value::Map(Arc::new(KV(Arc::new(vec![field_name(variant)]), vec![value])))
+}
 
 impl ser::Serializer for Serializer {
     type Ok = Value;
@@ -81,43 +193,43 @@ impl ser::Serializer for Serializer {
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::I8(v))
+        Ok(Value::Number(Number::I8(v)))
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::I16(v))
+        Ok(Value::Number(Number::I16(v)))
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::I32(v))
+        Ok(Value::Number(Number::I32(v)))
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::I64(v))
+        Ok(Value::Number(Number::I64(v)))
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::U8(v))
+        Ok(Value::Number(Number::U8(v)))
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::U16(v))
+        Ok(Value::Number(Number::U16(v)))
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::U32(v))
+        Ok(Value::Number(Number::U32(v)))
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::U64(v))
+        Ok(Value::Number(Number::U64(v)))
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::F32(v))
+        Ok(Value::Number(Number::F32(v)))
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::F64(v))
+        Ok(Value::Number(Number::F64(v)))
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
@@ -136,12 +248,12 @@ impl ser::Serializer for Serializer {
         Ok(Value::Option(None))
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
         value
-            .serialize(Serializer)
+            .serialize(self)
             .map(|v| Value::Option(Some(Box::new(v))))
     }
 
@@ -149,75 +261,101 @@ impl ser::Serializer for Serializer {
         Ok(Value::Unit)
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Unit)
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
+        if self.options.unit_names {
+            Ok(Value::UnitStruct(shared_name(name)))
+        } else {
+            Ok(Value::Unit)
+        }
     }
 
     fn serialize_unit_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Unit)
+        Ok(field_name(variant))
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
+        if name == set::SET_TOKEN {
+            return value.serialize(self).map(set::from_serialized);
+        }
+        if name == EXT_TOKEN {
+            // taken before the value, which may hold an `Ext` of its own
+            let tag = EXT_TAG.with(Cell::take);
+            let value = Box::new(value.serialize(self)?);
+            return Ok(match tag {
+                Some(tag) => Value::Ext { tag, value },
+                None => Value::Newtype(value),
+            });
+        }
+        // `half::f16` serializes as a newtype around its bits
+        #[cfg(feature = "half")]
+        {
+            if name == "f16" {
+                if let Value::Number(Number::U16(bits)) = value.serialize(self)? {
+                    return Ok(Value::F16(half::f16::from_bits(bits)));
+                }
+            }
+        }
         value
-            .serialize(Serializer)
+            .serialize(self)
             .map(|v| Value::Newtype(Box::new(v)))
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
         value
-            .serialize(Serializer)
-            .map(|v| Value::Newtype(Box::new(v)))
+            .serialize(self)
+            .map(|v| variant_value(variant, v))
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        Ok(SerializeSeq(vec![]))
+        Ok(SerializeSeq(self, vec![]))
     }
 
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        Ok(SerializeTuple(vec![]))
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(SerializeTuple(self, Vec::with_capacity(len)))
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        Ok(SerializeTupleStruct(vec![]))
+        Ok(SerializeTupleStruct(self, Vec::with_capacity(len)))
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
+        variant: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        Ok(SerializeTupleVariant(vec![]))
+        Ok(SerializeTupleVariant(self, variant, Vec::with_capacity(len)))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
         Ok(SerializeMap {
+            ser: self,
             keys: Vec::new(),
             values: Vec::new(),
         })
@@ -225,104 +363,105 @@ impl ser::Serializer for Serializer {
 
     fn serialize_struct(
         self,
-        _name: &'static str,
-        _len: usize,
+        name: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        Ok(SerializeStruct(BTreeMap::new()))
+        Ok(SerializeStruct(StructFields::new(self, name, len)))
     }
 
     fn serialize_struct_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
+        variant: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        Ok(SerializeStructVariant(BTreeMap::new()))
+        Ok(SerializeStructVariant(variant, StructFields::new(self, variant, len)))
     }
 }
 
-struct SerializeSeq(Vec<Value>);
+struct SerializeSeq(Serializer, Vec<Value>);
 
 impl ser::SerializeSeq for SerializeSeq {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let value = value.serialize(Serializer)?;
-        self.0.push(value);
+        let value = value.serialize(self.0)?;
+        self.1.push(value);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Seq(Arc::new(self.0)))
+        Ok(Value::Seq(Arc::new(self.1)))
     }
 }
 
-struct SerializeTuple(Vec<Value>);
+struct SerializeTuple(Serializer, Vec<Value>);
 
 impl ser::SerializeTuple for SerializeTuple {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let value = value.serialize(Serializer)?;
-        self.0.push(value);
+        let value = value.serialize(self.0)?;
+        self.1.push(value);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Seq(Arc::new(self.0)))
+        Ok(Value::Tuple(Arc::new(self.1)))
     }
 }
 
-struct SerializeTupleStruct(Vec<Value>);
+struct SerializeTupleStruct(Serializer, Vec<Value>);
 
 impl ser::SerializeTupleStruct for SerializeTupleStruct {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let value = value.serialize(Serializer)?;
-        self.0.push(value);
+        let value = value.serialize(self.0)?;
+        self.1.push(value);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Seq(Arc::new(self.0)))
+        Ok(Value::Tuple(Arc::new(self.1)))
     }
 }
 
-struct SerializeTupleVariant(Vec<Value>);
+struct SerializeTupleVariant(Serializer, &'static str, Vec<Value>);
 
 impl ser::SerializeTupleVariant for SerializeTupleVariant {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let value = value.serialize(Serializer)?;
-        self.0.push(value);
+        let value = value.serialize(self.0)?;
+        self.2.push(value);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Seq(Arc::new(self.0)))
+        Ok(variant_value(self.1, Value::Tuple(Arc::new(self.2))))
     }
 }
 
 struct SerializeMap {
+    ser: Serializer,
     keys: Vec<Value>,
     values: Vec<Value>,
 }
@@ -331,75 +470,178 @@ impl ser::SerializeMap for SerializeMap {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let key = key.serialize(Serializer)?;
+        let key = key.serialize(self.ser)?;
         self.keys.push(key);
         Ok(())
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let value = value.serialize(Serializer)?;
+        let value = value.serialize(self.ser)?;
         self.values.push(value);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::Map(Arc::new(KV(Arc::new(self.keys), self.values))))
+        let (mut keys, mut values) = (self.keys, self.values);
+        // maps like `HashMap` come in any order, and lookups need the keys sorted
+        if !keys.windows(2).all(|w| w[0] < w[1]) {
+            let mut entries: Vec<(Value, Value)> = keys.into_iter().zip(values).collect();
+            // the sort is stable, so reversed, the last of equal keys comes first
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries.reverse();
+            entries.dedup_by(|a, b| a.0 == b.0);
+            entries.reverse();
+            let (k, v) = entries.into_iter().unzip();
+            keys = k;
+            values = v;
+        }
+        Ok(Value::Map(Arc::new(KV(Arc::new(keys), values))))
     }
 }
 
-struct SerializeStruct(BTreeMap<Value, Value>);
+/// The sorted keys of one struct layout, shared by every instance serialized with it.
+struct StructKeys {
+    fields: Vec<&'static str>,
+    keys: Arc<Vec<Value>>,
+    // index into `fields` for each entry of `keys`
+    order: Vec<usize>,
+}
+
+impl StructKeys {
+    fn new(fields: &[&'static str]) -> StructKeys {
+        let mut order: Vec<usize> = (0..fields.len()).collect();
+        order.sort_by_key(|&i| fields[i]);
+        // like a map, a repeated field keeps the last value
+        order.reverse();
+        order.dedup_by_key(|i| fields[*i]);
+        order.reverse();
+        StructKeys {
+            fields: fields.to_vec(),
+            keys: Arc::new(order.iter().map(|&i| field_name(fields[i])).collect()),
+            order,
+        }
+    }
+
+    fn matches(&self, fields: &[&'static str]) -> bool {
+        self.fields.len() == fields.len()
+            && self
+                .fields
+                .iter()
+                .zip(fields.iter())
+                .all(|(a, b)| a.as_ptr() == b.as_ptr() && a.len() == b.len())
+    }
+}
+
+thread_local! {
+    // all layouts seen for a struct name, usually just one unless fields are skipped
+    static STRUCT_KEYS: RefCell<HashMap<(usize, usize), Vec<StructKeys>>> = RefCell::new(HashMap::new());
+}
+
+struct StructFields {
+    ser: Serializer,
+    name: &'static str,
+    fields: Vec<&'static str>,
+    values: Vec<Value>,
+}
+
+impl StructFields {
+    fn new(ser: Serializer, name: &'static str, len: usize) -> StructFields {
+        StructFields {
+            ser,
+            name,
+            fields: Vec::with_capacity(len),
+            values: Vec::with_capacity(len),
+        }
+    }
+
+    fn push<T>(&mut self, key: &'static str, value: &T) -> Result<(), SerializerError>
+    where
+        T: ser::Serialize + ?Sized,
+    {
+        let value = value.serialize(self.ser)?;
+        self.fields.push(key);
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn into_value(self) -> Value {
+        let StructFields {
+            ser: _,
+            name,
+            fields,
+            values,
+        } = self;
+        STRUCT_KEYS.with(|layouts| {
+            let mut layouts = layouts.borrow_mut();
+            let layouts = layouts
+                .entry((name.as_ptr() as usize, name.len()))
+                .or_default();
+            let index = match layouts.iter().position(|l| l.matches(&fields)) {
+                Some(index) => index,
+                None => {
+                    layouts.push(StructKeys::new(&fields));
+                    layouts.len() - 1
+                }
+            };
+            let layout = &layouts[index];
+            let mut values: Vec<Option<Value>> = values.into_iter().map(Some).collect();
+            let values = layout
+                .order
+                .iter()
+                .map(|&i| values[i].take().unwrap())
+                .collect();
+            Value::Map(Arc::new(KV(layout.keys.clone(), values)))
+        })
+    }
+}
+
+struct SerializeStruct(StructFields);
 
 impl ser::SerializeStruct for SerializeStruct {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_field<T: ?Sized>(
+    fn serialize_field<T>(
         &mut self,
         key: &'static str,
         value: &T,
     ) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let key = Value::string(key.to_string());
-        let value = value.serialize(Serializer)?;
-        self.0.insert(key, value);
-        Ok(())
+        self.0.push(key, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::map(self.0))
+        Ok(self.0.into_value())
     }
 }
 
-struct SerializeStructVariant(BTreeMap<Value, Value>);
+struct SerializeStructVariant(&'static str, StructFields);
 
 impl ser::SerializeStructVariant for SerializeStructVariant {
     type Ok = Value;
     type Error = SerializerError;
 
-    fn serialize_field<T: ?Sized>(
+    fn serialize_field<T>(
         &mut self,
         key: &'static str,
         value: &T,
     ) -> Result<(), Self::Error>
     where
-        T: ser::Serialize,
+        T: ser::Serialize + ?Sized,
     {
-        let key = Value::string(key.to_string());
-        let value = value.serialize(Serializer)?;
-        self.0.insert(key, value);
-        Ok(())
+        self.1.push(key, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(Value::map(self.0))
+        Ok(variant_value(self.0, self.1.into_value()))
     }
 }
@@ -0,0 +1,342 @@
+//! A `Dedup` owned by a thread of its own, shared through cloneable handles.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+use {Dedup, Deduplicator, SharedDeduplicator, Value, KV};
+
+/// The service was shut down before the request was handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServiceStopped;
+
+impl fmt::Display for ServiceStopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the dedup service has stopped")
+    }
+}
+
+impl Error for ServiceStopped {}
+
+#[derive(Default)]
+struct ReplyState {
+    value: Option<Value>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct Reply {
+    state: Mutex<ReplyState>,
+    ready: Condvar,
+}
+
+impl Reply {
+    fn finish(&self, value: Option<Value>) {
+        let mut state = self.state.lock().unwrap();
+        if state.done {
+            return;
+        }
+        state.value = value;
+        state.done = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+
+    fn take(state: &mut ReplyState) -> Result<Value, ServiceStopped> {
+        state.value.take().ok_or(ServiceStopped)
+    }
+}
+
+struct Request {
+    value: Option<Value>,
+    reply: Arc<Reply>,
+}
+
+// a request that is dropped unanswered, e.g. when the service panics, fails its caller
+impl Drop for Request {
+    fn drop(&mut self) {
+        self.reply.finish(None);
+    }
+}
+
+struct Queue {
+    requests: VecDeque<Request>,
+    capacity: usize,
+    closed: bool,
+    // async callers waiting for room in the queue
+    waiting: Vec<Waker>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // signals the service that there are requests or it was closed
+    work: Condvar,
+    // signals blocked callers that there is room
+    room: Condvar,
+}
+
+impl Shared {
+    /// Enqueues `value` if there is room, otherwise gives it back.
+    fn try_push(&self, queue: &mut Queue, value: Value) -> Result<Arc<Reply>, Value> {
+        if queue.requests.len() >= queue.capacity {
+            return Err(value);
+        }
+        let reply = Arc::new(Reply::default());
+        queue.requests.push_back(Request {
+            value: Some(value),
+            reply: reply.clone(),
+        });
+        self.work.notify_one();
+        Ok(reply)
+    }
+
+    fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        for waker in queue.waiting.drain(..) {
+            waker.wake();
+        }
+        self.work.notify_all();
+        self.room.notify_all();
+    }
+
+    fn run(&self, mut dedup: Dedup) -> Dedup {
+        loop {
+            let mut request = {
+                let mut queue = self.queue.lock().unwrap();
+                while queue.requests.is_empty() && !queue.closed {
+                    queue = self.work.wait(queue).unwrap();
+                }
+                match queue.requests.pop_front() {
+                    Some(request) => {
+                        for waker in queue.waiting.drain(..) {
+                            waker.wake();
+                        }
+                        self.room.notify_one();
+                        request
+                    }
+                    // closed, and every request before that was answered
+                    None => return dedup,
+                }
+            };
+            let value = dedup.dedup(request.value.take().unwrap());
+            request.reply.finish(Some(value));
+        }
+    }
+}
+
+/// A `Dedup` running on a thread of its own, for sharing one table between threads or
+/// async tasks without exposing a lock.
+///
+/// Requests go through a queue of bounded capacity. When it is full, `DedupHandle::intern`
+/// blocks and the future of `DedupHandle::intern_async` waits, so producers cannot run
+/// ahead of the table. `shutdown` answers all queued requests before returning the table.
+pub struct DedupService {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<Dedup>>,
+}
+
+impl DedupService {
+    /// Starts a thread owning `dedup`, queueing at most `capacity` requests, at least 1.
+    pub fn spawn(dedup: Dedup, capacity: usize) -> DedupService {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                requests: VecDeque::new(),
+                capacity: capacity.max(1),
+                closed: false,
+                waiting: Vec::new(),
+            }),
+            work: Condvar::new(),
+            room: Condvar::new(),
+        });
+        let service = shared.clone();
+        let thread = thread::Builder::new()
+            .name("dedup-service".into())
+            .spawn(move || service.run(dedup))
+            .expect("spawning the dedup service");
+        DedupService {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn handle(&self) -> DedupHandle {
+        DedupHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Stops taking requests, answers the queued ones and returns the table.
+    ///
+    /// Handles fail with `ServiceStopped` from then on.
+    pub fn shutdown(mut self) -> Dedup {
+        self.shared.close();
+        let thread = self.thread.take().unwrap();
+        thread.join().expect("the dedup service panicked")
+    }
+}
+
+impl Drop for DedupService {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shared.close();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for DedupService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DedupService").finish()
+    }
+}
+
+/// A cheap to clone handle for sending values to a `DedupService`.
+#[derive(Clone)]
+pub struct DedupHandle {
+    shared: Arc<Shared>,
+}
+
+impl DedupHandle {
+    /// Deduplicates `value` on the service, blocking while the queue is full and until it is
+    /// done.
+    pub fn intern(&self, value: Value) -> Result<Value, ServiceStopped> {
+        let reply = self.push(value).map_err(|_| ServiceStopped)?;
+        DedupHandle::wait(&reply)
+    }
+
+    /// Enqueues `value`, blocking while the queue is full, or gives it back if the service
+    /// has stopped.
+    fn push(&self, mut value: Value) -> Result<Arc<Reply>, Value> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if queue.closed {
+                return Err(value);
+            }
+            match self.shared.try_push(&mut queue, value) {
+                Ok(reply) => return Ok(reply),
+                Err(v) => value = v,
+            }
+            queue = self.shared.room.wait(queue).unwrap();
+        }
+    }
+
+    fn wait(reply: &Reply) -> Result<Value, ServiceStopped> {
+        let mut state = reply.state.lock().unwrap();
+        while !state.done {
+            state = reply.ready.wait(state).unwrap();
+        }
+        Reply::take(&mut state)
+    }
+
+    /// Like `intern`, as a future that waits instead of blocking. It works with any
+    /// executor.
+    pub fn intern_async(&self, value: Value) -> Intern {
+        Intern {
+            shared: self.shared.clone(),
+            value: Some(value),
+            reply: None,
+        }
+    }
+}
+
+/// Interns through the service, blocking like `intern`, so that a handle can be used
+/// wherever a `Deduplicator` is expected.
+///
+/// Once the service has stopped, values are returned as they are. Panics if the service
+/// panicked while handling the value.
+impl Deduplicator for DedupHandle {
+    fn dedup(&mut self, value: Value) -> Value {
+        match self.push(value) {
+            Ok(reply) => DedupHandle::wait(&reply).expect("the dedup service panicked"),
+            Err(value) => value,
+        }
+    }
+
+    fn dedup_string(&mut self, value: Arc<str>) -> Arc<str> {
+        match self.dedup(Value::String(value)) {
+            Value::String(v) => v,
+            _ => unreachable!("interning keeps the variant"),
+        }
+    }
+
+    fn dedup_blob(&mut self, value: Arc<Vec<u8>>) -> Arc<Vec<u8>> {
+        match self.dedup(Value::Bytes(value)) {
+            Value::Bytes(v) => v,
+            _ => unreachable!("interning keeps the variant"),
+        }
+    }
+
+    fn dedup_seq(&mut self, value: Arc<Vec<Value>>) -> Arc<Vec<Value>> {
+        match self.dedup(Value::Seq(value)) {
+            Value::Seq(v) => v,
+            _ => unreachable!("interning keeps the variant"),
+        }
+    }
+
+    fn dedup_map(&mut self, value: Arc<KV>) -> Arc<KV> {
+        match self.dedup(Value::Map(value)) {
+            Value::Map(v) => v,
+            _ => unreachable!("interning keeps the variant"),
+        }
+    }
+}
+
+impl SharedDeduplicator for DedupService {
+    type Handle = DedupHandle;
+
+    fn handle(&self) -> DedupHandle {
+        DedupService::handle(self)
+    }
+}
+
+impl fmt::Debug for DedupHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DedupHandle").finish()
+    }
+}
+
+/// The future returned by `DedupHandle::intern_async`.
+pub struct Intern {
+    shared: Arc<Shared>,
+    value: Option<Value>,
+    reply: Option<Arc<Reply>>,
+}
+
+impl Future for Intern {
+    type Output = Result<Value, ServiceStopped>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if this.reply.is_none() {
+            let mut queue = this.shared.queue.lock().unwrap();
+            if queue.closed {
+                return Poll::Ready(Err(ServiceStopped));
+            }
+            match this.shared.try_push(&mut queue, this.value.take().unwrap()) {
+                Ok(reply) => this.reply = Some(reply),
+                Err(value) => {
+                    this.value = Some(value);
+                    queue.waiting.push(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+        let reply = this.reply.as_ref().unwrap();
+        let mut state = reply.state.lock().unwrap();
+        if state.done {
+            Poll::Ready(Reply::take(&mut state))
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
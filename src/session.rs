@@ -0,0 +1,264 @@
+//! A `Dedup` shared by many documents, with savings attributed to each of them.
+//!
+//! Each document ingested into a `DedupSession` is held by a `Document` handle. The values
+//! it references stay alive as long as the handle, and dropping it removes the document
+//! from the session, so `DedupSession::reclaim` can drop the entries only it referenced.
+//!
+//! ```ignore
+//! let session = DedupSession::new(Dedup::new());
+//! let a = session.ingest(to_value(&request_a)?);
+//! let b = session.ingest(to_value(&request_b)?);
+//! for savings in session.attribution() {
+//!     println!("{}: {} bytes saved", savings.id, savings.saved_bytes());
+//! }
+//! drop(b);
+//! let freed = session.reclaim();
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, Weak};
+
+use report::{self, SavingsReport};
+use {Dedup, Deduplicator, Table, Value};
+
+#[derive(Debug)]
+struct State {
+    dedup: Dedup,
+    documents: BTreeMap<u64, Value>,
+    next_id: u64,
+}
+
+/// A `Dedup` whose values are handed out as `Document`s, see the module documentation.
+///
+/// All methods take `&self`, so a session can be shared between threads. They lock the
+/// session for their duration.
+#[derive(Debug)]
+pub struct DedupSession {
+    state: Arc<Mutex<State>>,
+}
+
+/// A value ingested into a `DedupSession`. Dropping it removes it from the session.
+#[derive(Debug)]
+pub struct Document {
+    id: u64,
+    value: Value,
+    state: Weak<Mutex<State>>,
+}
+
+/// The part of the savings of a session attributed to one document.
+///
+/// Each table entry is counted once for every reference to it from the document, looking
+/// into each shared map or sequence once per document. The bytes of an entry are split
+/// between the documents in proportion to their references, rounding down.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DocumentSavings {
+    pub id: u64,
+    /// bytes of the entries the document references, as if none were shared
+    pub input_bytes: u64,
+    /// the share of the document in the bytes of the entries it references
+    pub stored_bytes: u64,
+    /// bytes of the entries no other document of the session references, which dropping
+    /// the document and reclaiming frees unless something outside the session holds them
+    pub exclusive_bytes: u64,
+}
+
+impl DocumentSavings {
+    pub fn saved_bytes(&self) -> u64 {
+        self.input_bytes.saturating_sub(self.stored_bytes)
+    }
+}
+
+impl DedupSession {
+    pub fn new(dedup: Dedup) -> DedupSession {
+        DedupSession {
+            state: Arc::new(Mutex::new(State {
+                dedup,
+                documents: BTreeMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Deduplicates `value` against the session and returns the handle holding it. Ids are
+    /// assigned in order of ingestion, starting at 0.
+    pub fn ingest(&self, value: Value) -> Document {
+        let mut state = self.state.lock().unwrap();
+        let value = state.dedup.dedup(value);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.documents.insert(id, value.clone());
+        Document {
+            id,
+            value,
+            state: Arc::downgrade(&self.state),
+        }
+    }
+
+    /// The number of documents whose handles are alive.
+    pub fn documents(&self) -> usize {
+        self.state.lock().unwrap().documents.len()
+    }
+
+    /// The savings attributed to each live document, in order of id.
+    pub fn attribution(&self) -> Vec<DocumentSavings> {
+        let state = self.state.lock().unwrap();
+        let references: Vec<(u64, HashMap<usize, Reference>)> = state
+            .documents
+            .iter()
+            .map(|(&id, value)| {
+                let mut walk = Walk {
+                    dedup: &state.dedup,
+                    visited: HashSet::new(),
+                    references: HashMap::new(),
+                };
+                walk.value(value);
+                (id, walk.references)
+            })
+            .collect();
+        let mut totals: HashMap<usize, u64> = HashMap::new();
+        for (_, document) in references.iter() {
+            for (&entry, reference) in document.iter() {
+                *totals.entry(entry).or_insert(0) += reference.count;
+            }
+        }
+        references
+            .iter()
+            .map(|(id, document)| {
+                let mut savings = DocumentSavings {
+                    id: *id,
+                    ..DocumentSavings::default()
+                };
+                for (entry, reference) in document.iter() {
+                    let (bytes, total) = (reference.bytes as u64, totals[entry]);
+                    savings.input_bytes += bytes * reference.count;
+                    savings.stored_bytes += bytes * reference.count / total;
+                    if reference.count == total {
+                        savings.exclusive_bytes += bytes;
+                    }
+                }
+                savings
+            })
+            .collect()
+    }
+
+    /// Drops the entries no longer referenced outside of the tables, see `Dedup::reclaim`.
+    pub fn reclaim(&self) -> usize {
+        self.state.lock().unwrap().dedup.reclaim()
+    }
+
+    /// The report of the underlying `Dedup`, see `Dedup::savings_report`.
+    pub fn savings_report(&self) -> SavingsReport {
+        self.state.lock().unwrap().dedup.savings_report()
+    }
+
+    /// The underlying `Dedup`. Documents still alive keep their values, but no longer take
+    /// part in a session.
+    pub fn into_inner(self) -> Dedup {
+        let state = match Arc::try_unwrap(self.state) {
+            Ok(state) => state.into_inner().unwrap(),
+            // only documents hold the state otherwise, and only weakly
+            Err(_) => unreachable!("session state shared"),
+        };
+        state.dedup
+    }
+}
+
+impl Default for DedupSession {
+    fn default() -> DedupSession {
+        DedupSession::new(Dedup::new())
+    }
+}
+
+impl Document {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The deduplicated value.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+impl Drop for Document {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.upgrade() {
+            // a poisoned session has nothing left to account for
+            if let Ok(mut state) = state.lock() {
+                state.documents.remove(&self.id);
+            }
+        }
+    }
+}
+
+/// References from one document to one table entry.
+struct Reference {
+    bytes: usize,
+    count: u64,
+}
+
+/// Collects the references from a document to the tables of `dedup`.
+struct Walk<'a> {
+    dedup: &'a Dedup,
+    // maps and sequences already looked into
+    visited: HashSet<usize>,
+    references: HashMap<usize, Reference>,
+}
+
+fn address<T: ?Sized>(x: &Arc<T>) -> usize {
+    Arc::as_ptr(x) as *const u8 as usize
+}
+
+impl<'a> Walk<'a> {
+    /// Counts a reference to `x` if it is the entry of `table`, and returns whether `x` is
+    /// seen for the first time.
+    fn entry<T>(&mut self, table: &Table<T>, x: &Arc<T>, bytes: usize) -> bool
+    where
+        T: Hash + Eq + ?Sized,
+    {
+        if table.get(x).is_some_and(|e| Arc::ptr_eq(e, x)) {
+            let reference = self
+                .references
+                .entry(address(x))
+                .or_insert(Reference { bytes, count: 0 });
+            reference.count += 1;
+        }
+        self.visited.insert(address(x))
+    }
+
+    fn value(&mut self, value: &Value) {
+        let dedup = self.dedup;
+        match *value {
+            Value::String(ref v) | Value::UnitStruct(ref v) => {
+                self.entry(&dedup.strings, v, report::string_size(v));
+            }
+            Value::Bytes(ref v) => {
+                self.entry(&dedup.blobs, v, report::blob_size(v));
+            }
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { ref data, .. } => {
+                self.entry(&dedup.blobs, data, report::blob_size(data));
+            }
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                let first = self.entry(&dedup.vectors, v, report::seq_size(v));
+                if first {
+                    v.iter().for_each(|x| self.value(x));
+                }
+            }
+            Value::Map(ref kv) => {
+                let first = self.entry(&dedup.objects, kv, report::object_size(kv));
+                if first && self.entry(&dedup.vectors, &kv.0, report::seq_size(&kv.0)) {
+                    kv.0.iter().for_each(|x| self.value(x));
+                }
+                if first {
+                    kv.1.iter().for_each(|x| self.value(x));
+                }
+            }
+            Value::Set(ref v) => v.iter().for_each(|x| self.value(x)),
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => self.value(v),
+            Value::Ext { ref value, .. } => self.value(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Opt-in `Value::Set` for set-typed fields.
+//!
+//! Serde serializes sets as plain sequences, so `to_value` can not tell a `HashSet` from a
+//! `Vec`. Fields using this module become a `Value::Set` instead, which keeps set semantics
+//! for the operations below. With any other serializer they behave like the plain types,
+//! and a `Value::Set` deserializes as a sequence.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Doc {
+//!     #[serde(with = "serde_value::set")]
+//!     tags: BTreeSet<String>,
+//! }
+//! ```
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use Value;
+
+/// Newtype struct name recognized by the value serializer to produce a `Value::Set`.
+pub(crate) const SET_TOKEN: &str = "$serde_value::private::Set";
+
+pub fn serialize<T, S>(value: &T, s: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    s.serialize_newtype_struct(SET_TOKEN, value)
+}
+
+pub fn deserialize<'de, T, D>(d: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(d)
+}
+
+/// Converts the result of serializing a set-typed field.
+pub(crate) fn from_serialized(value: Value) -> Value {
+    match value {
+        Value::Seq(v) => Value::Set(Arc::new(
+            Arc::try_unwrap(v)
+                .map(|v| v.into_iter().collect())
+                .unwrap_or_else(|v| v.iter().cloned().collect()),
+        )),
+        other => Value::Newtype(Box::new(other)),
+    }
+}
+
+impl Value {
+    /// The union of two sets, or `None` unless both are a `Set`.
+    ///
+    /// If one set already contains the other, it is returned as is, keeping it shared.
+    pub fn union(&self, other: &Value) -> Option<Value> {
+        let (a, b) = sets(self, other)?;
+        Some(if b.is_subset(a) {
+            self.clone()
+        } else if a.is_subset(b) {
+            other.clone()
+        } else {
+            Value::Set(Arc::new(a.union(b).cloned().collect()))
+        })
+    }
+
+    /// The intersection of two sets, or `None` unless both are a `Set`.
+    pub fn intersection(&self, other: &Value) -> Option<Value> {
+        let (a, b) = sets(self, other)?;
+        Some(if a.is_subset(b) {
+            self.clone()
+        } else if b.is_subset(a) {
+            other.clone()
+        } else {
+            Value::Set(Arc::new(a.intersection(b).cloned().collect()))
+        })
+    }
+
+    /// The elements of this set that are not in `other`, or `None` unless both are a `Set`.
+    pub fn difference(&self, other: &Value) -> Option<Value> {
+        let (a, b) = sets(self, other)?;
+        Some(if a.is_disjoint(b) {
+            self.clone()
+        } else {
+            Value::Set(Arc::new(a.difference(b).cloned().collect()))
+        })
+    }
+}
+
+fn sets<'a>(a: &'a Value, b: &'a Value) -> Option<(&'a BTreeSet<Value>, &'a BTreeSet<Value>)> {
+    match (a, b) {
+        (Value::Set(a), Value::Set(b)) => Some((a, b)),
+        _ => None,
+    }
+}
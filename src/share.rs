@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use {Value, KV};
+
+/// Whether `new` is `old` or a clone of it, without looking at the contents.
+pub(crate) fn is_shared(new: &Value, old: &Value) -> bool {
+    match (new, old) {
+        (Value::String(a), Value::String(b)) | (Value::UnitStruct(a), Value::UnitStruct(b)) => {
+            Arc::ptr_eq(a, b)
+        }
+        (Value::Bytes(a), Value::Bytes(b)) => Arc::ptr_eq(a, b),
+        (Value::Seq(a), Value::Seq(b)) | (Value::Tuple(a), Value::Tuple(b)) => Arc::ptr_eq(a, b),
+        (Value::Map(a), Value::Map(b)) => Arc::ptr_eq(a, b),
+        (Value::Set(a), Value::Set(b)) => Arc::ptr_eq(a, b),
+        #[cfg(feature = "compression")]
+        (Value::CompressedBytes { data: a, .. }, Value::CompressedBytes { data: b, .. }) => {
+            Arc::ptr_eq(a, b)
+        }
+        #[cfg(feature = "mmap")]
+        (Value::MappedBytes(a), Value::MappedBytes(b)) => {
+            Arc::ptr_eq(a.shared(), b.shared()) && a.offset() == b.offset() && a.len() == b.len()
+        }
+        (Value::Option(Some(a)), Value::Option(Some(b))) | (Value::Newtype(a), Value::Newtype(b)) => {
+            is_shared(a, b)
+        }
+        (Value::Ext { tag: ta, value: a }, Value::Ext { tag: tb, value: b }) => {
+            ta == tb && is_shared(a, b)
+        }
+        (a, b) => a == b && !a.is_shared_container(),
+    }
+}
+
+impl Value {
+    fn is_shared_container(&self) -> bool {
+        match *self {
+            Value::String(..)
+            | Value::UnitStruct(..)
+            | Value::Bytes(..)
+            | Value::Seq(..)
+            | Value::Tuple(..)
+            | Value::Map(..)
+            | Value::Set(..) => true,
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { .. } => true,
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(..) => true,
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => v.is_shared_container(),
+            Value::Ext { ref value, .. } => value.is_shared_container(),
+            _ => false,
+        }
+    }
+
+    /// Returns a copy of this value that reuses the storage of `old` wherever the two are
+    /// equal.
+    ///
+    /// Sequences are matched by index and maps by key, so a new revision of a document
+    /// only allocates the parts that changed. Unlike `Dedup`, this needs no table and only
+    /// finds sharing at the same position in both trees.
+    pub fn share_with(&self, old: &Value) -> Value {
+        match (self, old) {
+            (Value::String(a), Value::String(b)) if a == b => old.clone(),
+            (Value::UnitStruct(a), Value::UnitStruct(b)) if a == b => old.clone(),
+            (Value::Bytes(a), Value::Bytes(b)) if a == b => old.clone(),
+            (Value::Set(a), Value::Set(b)) if a == b => old.clone(),
+            #[cfg(feature = "compression")]
+            (Value::CompressedBytes { .. }, Value::CompressedBytes { .. }) if self == old => {
+                old.clone()
+            }
+            #[cfg(feature = "mmap")]
+            (Value::MappedBytes(a), Value::MappedBytes(b)) if a == b => old.clone(),
+            (Value::Option(Some(a)), Value::Option(Some(b))) => {
+                rewrap(old, a.share_with(b), |v| Value::Option(Some(Box::new(v))))
+            }
+            (Value::Newtype(a), Value::Newtype(b)) => {
+                rewrap(old, a.share_with(b), |v| Value::Newtype(Box::new(v)))
+            }
+            (Value::Ext { tag, value: a }, Value::Ext { tag: old_tag, value: b })
+                if tag == old_tag =>
+            {
+                let tag = *tag;
+                rewrap(old, a.share_with(b), |v| Value::Ext {
+                    tag,
+                    value: Box::new(v),
+                })
+            }
+            (Value::Seq(a), Value::Seq(b)) => {
+                share_elements(a, b).map_or_else(|| old.clone(), Value::Seq)
+            }
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                share_elements(a, b).map_or_else(|| old.clone(), Value::Tuple)
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                if Arc::ptr_eq(a, b) {
+                    return old.clone();
+                }
+                share_map(a, b).map_or_else(|| old.clone(), Value::Map)
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// The new elements matched by index, or `None` if they turned out equal to `old`.
+fn share_elements(new: &Arc<Vec<Value>>, old: &Arc<Vec<Value>>) -> Option<Arc<Vec<Value>>> {
+    if Arc::ptr_eq(new, old) {
+        return None;
+    }
+    let values: Vec<Value> = new
+        .iter()
+        .enumerate()
+        .map(|(i, x)| match old.get(i) {
+            Some(y) => x.share_with(y),
+            None => x.clone(),
+        })
+        .collect();
+    let unchanged =
+        values.len() == old.len() && values.iter().zip(old.iter()).all(|(x, y)| is_shared(x, y));
+    if unchanged {
+        None
+    } else {
+        Some(Arc::new(values))
+    }
+}
+
+/// Wraps a shared inner value again, reusing `old` if nothing below it changed.
+fn rewrap<F: FnOnce(Value) -> Value>(old: &Value, inner: Value, wrap: F) -> Value {
+    let unchanged = match *old {
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) => is_shared(&inner, v),
+        Value::Ext { ref value, .. } => is_shared(&inner, value),
+        _ => false,
+    };
+    if unchanged {
+        old.clone()
+    } else {
+        wrap(inner)
+    }
+}
+
+/// The new map with values matched by key, or `None` if it turned out equal to `old`.
+fn share_map(new: &Arc<KV>, old: &Arc<KV>) -> Option<Arc<KV>> {
+    let keys = if Arc::ptr_eq(&new.0, &old.0) || new.0 == old.0 {
+        old.0.clone()
+    } else {
+        Arc::new(
+            new.0
+                .iter()
+                .map(|k| match old.0.binary_search(k) {
+                    Ok(i) => k.share_with(&old.0[i]),
+                    Err(_) => k.clone(),
+                })
+                .collect(),
+        )
+    };
+    // both key vectors are sorted, so walk them like a merge
+    let mut values = Vec::with_capacity(new.1.len());
+    let mut j = 0;
+    for (k, v) in new.0.iter().zip(new.1.iter()) {
+        while j < old.0.len() && old.0[j].cmp(k) == Ordering::Less {
+            j += 1;
+        }
+        if j < old.0.len() && old.0[j] == *k {
+            values.push(v.share_with(&old.1[j]));
+        } else {
+            values.push(v.clone());
+        }
+    }
+    let unchanged = Arc::ptr_eq(&keys, &old.0)
+        && values.iter().zip(old.1.iter()).all(|(x, y)| is_shared(x, y));
+    if unchanged {
+        None
+    } else {
+        Some(Arc::new(KV(keys, values)))
+    }
+}
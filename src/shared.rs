@@ -0,0 +1,150 @@
+//! `#[serde(with = "...")]` helpers that keep `Arc` sharing when deserializing from a `Value`.
+//!
+//! Serde's own `Arc<T>` support deserializes a fresh `T` and wraps it, so a struct pulled out
+//! of a deduplicated `Value` loses all sharing. Fields using these helpers instead receive a
+//! clone of the `Arc` already stored in the value. With any other deserializer they behave
+//! like the plain types.
+//!
+//! There is no helper for `Arc<[u8]>`: `Value::Bytes` holds an `Arc<Vec<u8>>`, and an
+//! `Arc<[u8]>` needs its own allocation, so it could only be a copy. Use `arc_bytes` and an
+//! `Arc<Vec<u8>>` field to share blobs.
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Event {
+//!     #[serde(with = "serde_value::shared::arc_str")]
+//!     host: Arc<str>,
+//! }
+//! ```
+
+use serde::de;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+/// Newtype struct name recognized by `ValueDeserializer` to hand over shared contents.
+pub(crate) const SHARED_TOKEN: &str = "$serde_value::private::Shared";
+
+thread_local! {
+    static STRING: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+    static BYTES: RefCell<Option<Arc<Vec<u8>>>> = const { RefCell::new(None) };
+}
+
+/// Offers `value` to a shared-aware visitor for the duration of `f`.
+pub(crate) fn offer_str<R, F: FnOnce(&str) -> R>(value: Arc<str>, f: F) -> R {
+    STRING.with(|slot| *slot.borrow_mut() = Some(value.clone()));
+    let res = f(&value);
+    STRING.with(|slot| slot.borrow_mut().take());
+    res
+}
+
+pub(crate) fn offer_bytes<R, F: FnOnce(&[u8]) -> R>(value: Arc<Vec<u8>>, f: F) -> R {
+    BYTES.with(|slot| *slot.borrow_mut() = Some(value.clone()));
+    let res = f(&value);
+    BYTES.with(|slot| slot.borrow_mut().take());
+    res
+}
+
+// only hand out the offered Arc if the visitor was given exactly its contents
+fn take_str(v: &str) -> Option<Arc<str>> {
+    STRING.with(|slot| {
+        let slot = slot.borrow();
+        match *slot {
+            Some(ref s) if s.as_ptr() == v.as_ptr() && s.len() == v.len() => Some(s.clone()),
+            _ => None,
+        }
+    })
+}
+
+fn take_bytes(v: &[u8]) -> Option<Arc<Vec<u8>>> {
+    BYTES.with(|slot| {
+        let slot = slot.borrow();
+        match *slot {
+            Some(ref b) if b.as_ptr() == v.as_ptr() && b.len() == v.len() => Some(b.clone()),
+            _ => None,
+        }
+    })
+}
+
+/// `Arc<str>` fields that share the string of a `Value::String`.
+pub mod arc_str {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    struct Visitor;
+
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = Arc<str>;
+
+        fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str("a string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Arc<str>, E> {
+            Ok(take_str(v).unwrap_or_else(|| Arc::from(v)))
+        }
+
+        fn visit_string<E: de::Error>(self, v: String) -> Result<Arc<str>, E> {
+            Ok(Arc::from(v))
+        }
+
+        fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Arc<str>, D::Error> {
+            d.deserialize_string(self)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<str>, D::Error> {
+        d.deserialize_newtype_struct(SHARED_TOKEN, Visitor)
+    }
+
+    pub fn serialize<S: Serializer>(value: &Arc<str>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(value)
+    }
+}
+
+/// `Arc<Vec<u8>>` fields that share the buffer of a `Value::Bytes`.
+pub mod arc_bytes {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    struct Visitor;
+
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = Arc<Vec<u8>>;
+
+        fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str("a byte array")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Arc<Vec<u8>>, E> {
+            Ok(take_bytes(v).unwrap_or_else(|| Arc::new(v.to_vec())))
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Arc<Vec<u8>>, E> {
+            Ok(Arc::from(v))
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Arc<Vec<u8>>, A::Error> {
+            let mut res = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                res.push(b);
+            }
+            Ok(Arc::new(res))
+        }
+
+        fn visit_newtype_struct<D: Deserializer<'de>>(
+            self,
+            d: D,
+        ) -> Result<Arc<Vec<u8>>, D::Error> {
+            d.deserialize_byte_buf(self)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<Vec<u8>>, D::Error> {
+        d.deserialize_newtype_struct(SHARED_TOKEN, Visitor)
+    }
+
+    pub fn serialize<S: Serializer>(value: &Arc<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(value)
+    }
+}
@@ -0,0 +1,112 @@
+//! Memory use of values, counting every shared allocation once.
+
+use std::collections::{BTreeSet, HashSet};
+use std::mem::size_of;
+use std::sync::Arc;
+
+#[cfg(feature = "dedup")]
+use Dedup;
+use {Value, KV};
+
+// the strong and weak counts in front of the data of every `Arc`
+const ARC_COUNTS: usize = 2 * size_of::<usize>();
+
+/// Adds up the heap allocations below values, skipping those it has seen before.
+#[derive(Default)]
+struct Sizer {
+    seen: HashSet<usize>,
+}
+
+impl Sizer {
+    fn first<T: ?Sized>(&mut self, arc: &Arc<T>) -> bool {
+        self.seen.insert(Arc::as_ptr(arc) as *const u8 as usize)
+    }
+
+    fn heap(&mut self, value: &Value) -> usize {
+        match *value {
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => size_of::<Value>() + self.heap(v),
+            Value::Ext { ref value, .. } => size_of::<Value>() + self.heap(value),
+            Value::String(ref s) | Value::UnitStruct(ref s) => self.string(s),
+            Value::Bytes(ref b) => self.blob(b),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { ref data, .. } => self.blob(data),
+            Value::Seq(ref v) | Value::Tuple(ref v) => self.seq(v),
+            Value::Map(ref kv) => self.object(kv),
+            Value::Set(ref v) if self.first(v) => {
+                // the nodes of the tree hold the elements, their overhead is not counted
+                let own = ARC_COUNTS + size_of::<BTreeSet<Value>>() + v.len() * size_of::<Value>();
+                own + v.iter().map(|x| self.heap(x)).sum::<usize>()
+            }
+            _ => 0,
+        }
+    }
+
+    fn string(&mut self, s: &Arc<str>) -> usize {
+        if !self.first(s) {
+            return 0;
+        }
+        ARC_COUNTS + s.len()
+    }
+
+    fn blob(&mut self, b: &Arc<Vec<u8>>) -> usize {
+        if !self.first(b) {
+            return 0;
+        }
+        ARC_COUNTS + size_of::<Vec<u8>>() + b.capacity()
+    }
+
+    fn seq(&mut self, v: &Arc<Vec<Value>>) -> usize {
+        if !self.first(v) {
+            return 0;
+        }
+        let own = ARC_COUNTS + size_of::<Vec<Value>>() + v.capacity() * size_of::<Value>();
+        own + v.iter().map(|x| self.heap(x)).sum::<usize>()
+    }
+
+    fn object(&mut self, kv: &Arc<KV>) -> usize {
+        if !self.first(kv) {
+            return 0;
+        }
+        let own = ARC_COUNTS + size_of::<KV>() + kv.1.capacity() * size_of::<Value>();
+        own + self.seq(&kv.0) + kv.1.iter().map(|x| self.heap(x)).sum::<usize>()
+    }
+}
+
+/// Bytes of the slots of a hash table, assuming one control byte per slot.
+#[cfg(feature = "dedup")]
+fn table_size<T>(capacity: usize) -> usize {
+    capacity * (size_of::<T>() + 1)
+}
+
+impl Value {
+    /// The bytes this value takes in memory, including everything it references.
+    ///
+    /// Allocations shared within the value, as after `Dedup`, are counted once. The counts
+    /// and headers of `Arc`s and `Vec`s are included, but not the rounding and bookkeeping
+    /// of the allocator, nor the nodes of the trees behind sets.
+    pub fn deep_size(&self) -> usize {
+        size_of::<Value>() + Sizer::default().heap(self)
+    }
+}
+
+#[cfg(feature = "dedup")]
+impl Dedup {
+    /// The bytes the tables take in memory, including the entries and everything they
+    /// reference, each counted once, as in `Value::deep_size`.
+    ///
+    /// Values deduplicated earlier share most of this. Unlike `savings_report`, this counts
+    /// headers and the elements of sequences and maps.
+    pub fn size(&self) -> usize {
+        let mut sizer = Sizer::default();
+        let mut res = size_of::<Dedup>();
+        res += table_size::<Arc<Vec<u8>>>(self.blobs.capacity());
+        res += table_size::<Arc<str>>(self.strings.capacity());
+        res += table_size::<Arc<Vec<Value>>>(self.vectors.capacity());
+        res += table_size::<Arc<KV>>(self.objects.capacity());
+        res += self.blobs.iter().map(|x| sizer.blob(x)).sum::<usize>();
+        res += self.strings.iter().map(|x| sizer.string(x)).sum::<usize>();
+        res += self.vectors.iter().map(|x| sizer.seq(x)).sum::<usize>();
+        res += self.objects.iter().map(|x| sizer.object(x)).sum::<usize>();
+        res
+    }
+}
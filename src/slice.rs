@@ -0,0 +1,237 @@
+//! Views into sequences, and sequences of chunks, that share vectors instead of copying
+//! elements.
+
+use std::fmt;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::Arc;
+
+use serde::ser::{Serialize, Serializer};
+
+use Value;
+
+/// A range of the elements of a `Seq` or `Tuple`, holding on to the whole vector.
+///
+/// Creating, cloning and narrowing a slice costs the same no matter how many elements it
+/// covers, so paging through a large deduplicated sequence copies nothing. The whole vector
+/// is kept alive as long as any slice of it is.
+#[derive(Clone)]
+pub struct SeqSlice {
+    seq: Arc<Vec<Value>>,
+    start: usize,
+    end: usize,
+}
+
+/// `start..end` for `range` within `len` elements, or `None` if it is out of bounds.
+fn bounds<R: RangeBounds<usize>>(range: R, len: usize) -> Option<(usize, usize)> {
+    let start = match range.start_bound() {
+        Bound::Included(&i) => i,
+        Bound::Excluded(&i) => i.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&i) => i.checked_add(1)?,
+        Bound::Excluded(&i) => i,
+        Bound::Unbounded => len,
+    };
+    if start <= end && end <= len {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+impl SeqSlice {
+    /// A slice of all of `seq`.
+    pub fn new(seq: Arc<Vec<Value>>) -> SeqSlice {
+        let end = seq.len();
+        SeqSlice { seq, start: 0, end }
+    }
+
+    /// A narrower slice, with `range` relative to this one, or `None` if it is out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Option<SeqSlice> {
+        let (start, end) = bounds(range, self.len())?;
+        Some(SeqSlice {
+            seq: self.seq.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        })
+    }
+
+    /// The vector this is a slice of.
+    pub fn shared(&self) -> &Arc<Vec<Value>> {
+        &self.seq
+    }
+
+    /// Where this slice starts in `shared()`.
+    pub fn offset(&self) -> usize {
+        self.start
+    }
+
+    /// The elements as a `Seq`, which only copies them if this does not cover the whole
+    /// vector.
+    pub fn to_value(&self) -> Value {
+        if self.start == 0 && self.end == self.seq.len() {
+            Value::Seq(self.seq.clone())
+        } else {
+            Value::seq(self.to_vec())
+        }
+    }
+}
+
+impl Deref for SeqSlice {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        &self.seq[self.start..self.end]
+    }
+}
+
+impl PartialEq for SeqSlice {
+    fn eq(&self, rhs: &SeqSlice) -> bool {
+        **self == **rhs
+    }
+}
+
+impl Eq for SeqSlice {}
+
+impl fmt::Debug for SeqSlice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Serialized like a `Seq` of the elements it covers.
+impl Serialize for SeqSlice {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(self.iter())
+    }
+}
+
+impl Value {
+    /// A view of `range` of the elements of a `Seq` or `Tuple`, sharing its vector.
+    ///
+    /// `None` if this is not a sequence or the range is out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Option<SeqSlice> {
+        match *self {
+            Value::Seq(ref v) | Value::Tuple(ref v) => SeqSlice::new(v.clone()).slice(range),
+            _ => None,
+        }
+    }
+}
+
+/// A sequence made of chunks that are shared rather than copied, for cheap appends and
+/// concatenation of large sequences.
+///
+/// Appending a chunk or concatenating two chunked sequences copies no elements, only the
+/// chunk handles. Pushing single values fills the last chunk while no one else shares it.
+/// Indexing takes a binary search over the chunks. Use `to_value` to get a `Seq` for
+/// serialization or deduplication.
+#[derive(Clone, Default)]
+pub struct ChunkedSeq {
+    chunks: Vec<SeqSlice>,
+    // where each chunk ends, counting from the start of the first one
+    ends: Vec<usize>,
+}
+
+impl ChunkedSeq {
+    pub fn new() -> ChunkedSeq {
+        ChunkedSeq::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.last().cloned().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends a chunk without copying it. Empty chunks are dropped.
+    pub fn push_chunk(&mut self, chunk: SeqSlice) {
+        if !chunk.is_empty() {
+            let end = self.len() + chunk.len();
+            self.chunks.push(chunk);
+            self.ends.push(end);
+        }
+    }
+
+    /// Appends a value, to the last chunk if that is not shared and ends its vector, or to a
+    /// new chunk otherwise.
+    pub fn push(&mut self, value: Value) {
+        if let Some(last) = self.chunks.last_mut() {
+            if last.end == last.seq.len() {
+                if let Some(seq) = Arc::get_mut(&mut last.seq) {
+                    seq.push(value);
+                    last.end += 1;
+                    *self.ends.last_mut().unwrap() += 1;
+                    return;
+                }
+            }
+        }
+        self.push_chunk(SeqSlice::new(Arc::new(vec![value])));
+    }
+
+    /// Appends all chunks of `other`, sharing them.
+    pub fn append(&mut self, other: &ChunkedSeq) {
+        for chunk in &other.chunks {
+            self.push_chunk(chunk.clone());
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        if index >= self.len() {
+            return None;
+        }
+        let chunk = self.ends.partition_point(|&end| end <= index);
+        let start = if chunk == 0 { 0 } else { self.ends[chunk - 1] };
+        Some(&self.chunks[chunk][index - start])
+    }
+
+    pub fn chunks(&self) -> &[SeqSlice] {
+        &self.chunks
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    /// The elements as a single `Seq`, sharing the vector if there is only one chunk
+    /// covering all of it.
+    pub fn to_value(&self) -> Value {
+        match self.chunks.len() {
+            0 => Value::seq(Vec::new()),
+            1 => self.chunks[0].to_value(),
+            _ => Value::seq(self.iter().cloned().collect()),
+        }
+    }
+}
+
+impl From<SeqSlice> for ChunkedSeq {
+    fn from(chunk: SeqSlice) -> ChunkedSeq {
+        let mut seq = ChunkedSeq::new();
+        seq.push_chunk(chunk);
+        seq
+    }
+}
+
+/// Equal if the elements are, no matter how they are chunked.
+impl PartialEq for ChunkedSeq {
+    fn eq(&self, rhs: &ChunkedSeq) -> bool {
+        self.len() == rhs.len() && self.iter().eq(rhs.iter())
+    }
+}
+
+impl Eq for ChunkedSeq {}
+
+impl fmt::Debug for ChunkedSeq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Serialized like a `Seq` of all elements.
+impl Serialize for ChunkedSeq {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(self.iter())
+    }
+}
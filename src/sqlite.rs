@@ -0,0 +1,167 @@
+//! SQLite result sets as values and values as SQLite parameters, behind the `rusqlite`
+//! feature.
+
+use std::convert::TryFrom;
+use std::str;
+use std::sync::Arc;
+
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{self, Statement};
+
+use {Number, Value, KV};
+
+/// `NULL` is `Unit`, integers are `I64`, reals are `F64`, text is a string, or bytes if it
+/// is not valid UTF-8, and blobs are bytes.
+impl FromSql for Value {
+    fn column_result(value: ValueRef) -> FromSqlResult<Value> {
+        Ok(match value {
+            ValueRef::Null => Value::Unit,
+            ValueRef::Integer(v) => Value::Number(Number::I64(v)),
+            ValueRef::Real(v) => Value::Number(Number::F64(v)),
+            ValueRef::Text(v) => match str::from_utf8(v) {
+                Ok(text) => Value::String(text.into()),
+                Err(_) => Value::Bytes(Arc::new(v.to_vec())),
+            },
+            ValueRef::Blob(v) => Value::Bytes(Arc::new(v.to_vec())),
+        })
+    }
+}
+
+/// Binds scalars: units and `None` as `NULL`, booleans and integers as integers, floats
+/// as reals, strings and chars as text and bytes as blobs. Options, newtypes and
+/// extension values bind their content. Anything else, and unsigned integers beyond the
+/// range of `i64`, fail to bind.
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match *self {
+            Value::Unit | Value::Option(None) => ToSqlOutput::from(rusqlite::types::Null),
+            Value::Bool(v) => ToSqlOutput::from(v),
+            Value::Number(Number::U8(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::U16(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::U32(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::U64(v)) => match i64::try_from(v) {
+                Ok(v) => ToSqlOutput::from(v),
+                Err(e) => return Err(rusqlite::Error::ToSqlConversionFailure(e.into())),
+            },
+            Value::Number(Number::I8(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::I16(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::I32(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::I64(v)) => ToSqlOutput::from(v),
+            Value::Number(Number::F32(v)) => ToSqlOutput::from(f64::from(v)),
+            Value::Number(Number::F64(v)) => ToSqlOutput::from(v),
+            #[cfg(feature = "half")]
+            Value::F16(v) => ToSqlOutput::from(f64::from(v)),
+            Value::Char(v) => ToSqlOutput::from(v.to_string()),
+            Value::String(ref v) => ToSqlOutput::from(&**v),
+            Value::Bytes(ref v) => ToSqlOutput::from(&v[..]),
+            Value::Option(Some(ref v))
+            | Value::Newtype(ref v)
+            | Value::Ext { value: ref v, .. } => v.to_sql()?,
+            ref other => {
+                let message = format!("{} has no SQLite representation", other.unexpected());
+                return Err(rusqlite::Error::ToSqlConversionFailure(message.into()));
+            }
+        })
+    }
+}
+
+/// Runs a query and returns its rows as a `Seq` of `Map`s from column names to values.
+///
+/// All rows share one key vector, so the column names are stored once for the whole
+/// result. Columns with the same name, as in a join without aliases, keep the value of
+/// the last of them. Cells convert as in the `FromSql` implementation of `Value`.
+pub fn query_values<P: rusqlite::Params>(
+    statement: &mut Statement,
+    params: P,
+) -> rusqlite::Result<Value> {
+    // the position of each column in the sorted keys, keeping the last of equal names
+    let mut columns: Vec<(String, usize)> = statement
+        .column_names()
+        .into_iter()
+        .map(str::to_owned)
+        .zip(0..)
+        .collect();
+    columns.sort();
+    columns.reverse();
+    columns.dedup_by(|a, b| a.0 == b.0);
+    columns.reverse();
+    let keys: Arc<Vec<Value>> = Arc::new(
+        columns
+            .iter()
+            .map(|(name, _)| Value::String(name.as_str().into()))
+            .collect(),
+    );
+    let mut rows = statement.query(params)?;
+    let mut records = Vec::new();
+    while let Some(row) = rows.next()? {
+        let values = columns
+            .iter()
+            .map(|&(_, column)| row.get(column))
+            .collect::<rusqlite::Result<_>>()?;
+        records.push(Value::Map(Arc::new(KV(keys.clone(), values))));
+    }
+    Ok(Value::Seq(Arc::new(records)))
+}
+
+fn bind_record(statement: &mut Statement, record: &Value) -> rusqlite::Result<()> {
+    match *record {
+        Value::Map(ref kv) => {
+            for (key, value) in kv.keys().iter().zip(kv.values()) {
+                let name = match *key {
+                    Value::String(ref name) => name.to_string(),
+                    ref other => other.to_string(),
+                };
+                let mut index = None;
+                for prefix in &[":", "@", "$"] {
+                    let parameter = format!("{}{}", prefix, name);
+                    index = index.or(statement.parameter_index(&parameter)?);
+                }
+                let index = index.ok_or(rusqlite::Error::InvalidParameterName(name))?;
+                statement.raw_bind_parameter(index, value)?;
+            }
+        }
+        Value::Seq(ref values) | Value::Tuple(ref values) => {
+            if values.len() != statement.parameter_count() {
+                return Err(rusqlite::Error::InvalidParameterCount(
+                    values.len(),
+                    statement.parameter_count(),
+                ));
+            }
+            for (i, value) in values.iter().enumerate() {
+                statement.raw_bind_parameter(i + 1, value)?;
+            }
+        }
+        Value::Option(Some(ref v))
+        | Value::Newtype(ref v)
+        | Value::Ext { value: ref v, .. } => bind_record(statement, v)?,
+        ref other => {
+            let message = format!("{} is not a record", other.unexpected());
+            return Err(rusqlite::Error::ToSqlConversionFailure(message.into()));
+        }
+    }
+    Ok(())
+}
+
+/// Executes `statement` once for each record in the sequence `records`, and returns the
+/// total number of changed rows.
+///
+/// A map binds each of its values to the parameter named like its key with a `:`, `@` or
+/// `$` prefix, so the rows of `query_values` can be inserted with
+/// `INSERT INTO t (a, b) VALUES (:a, :b)`. A key without a parameter is an error, and
+/// parameters without a key are `NULL`. A sequence binds its values by position.
+pub fn execute_values(statement: &mut Statement, records: &Value) -> rusqlite::Result<usize> {
+    let records = match *records {
+        Value::Seq(ref records) | Value::Tuple(ref records) => records,
+        ref other => {
+            let message = format!("{} is not a sequence of records", other.unexpected());
+            return Err(rusqlite::Error::ToSqlConversionFailure(message.into()));
+        }
+    };
+    let mut changed = 0;
+    for record in records.iter() {
+        statement.clear_bindings();
+        bind_record(statement, record)?;
+        changed += statement.raw_execute()?;
+    }
+    Ok(changed)
+}
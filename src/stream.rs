@@ -0,0 +1,94 @@
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use {Deduplicator, Value};
+
+/// A `Stream` adapter that passes every value through a `Deduplicator`.
+#[derive(Debug)]
+pub struct DedupStream<S, D> {
+    inner: S,
+    dedup: D,
+}
+
+impl<S, D> DedupStream<S, D> {
+    pub fn new(inner: S, dedup: D) -> Self {
+        DedupStream { inner, dedup }
+    }
+
+    pub fn dedup(&self) -> &D {
+        &self.dedup
+    }
+
+    pub fn into_inner(self) -> (S, D) {
+        (self.inner, self.dedup)
+    }
+}
+
+impl<S, D> Stream for DedupStream<S, D>
+where
+    S: Stream<Item = Value> + Unpin,
+    D: Deduplicator + Unpin,
+{
+    type Item = Value;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Value>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(value)) => Poll::Ready(Some(this.dedup.dedup(value))),
+            other => other,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// A `Sink` adapter that passes every value through a `Deduplicator` before forwarding it.
+#[derive(Debug)]
+pub struct DedupSink<S, D> {
+    inner: S,
+    dedup: D,
+}
+
+impl<S, D> DedupSink<S, D> {
+    pub fn new(inner: S, dedup: D) -> Self {
+        DedupSink { inner, dedup }
+    }
+
+    pub fn dedup(&self) -> &D {
+        &self.dedup
+    }
+
+    pub fn into_inner(self) -> (S, D) {
+        (self.inner, self.dedup)
+    }
+}
+
+impl<S, D> Sink<Value> for DedupSink<S, D>
+where
+    S: Sink<Value> + Unpin,
+    D: Deduplicator + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Value) -> Result<(), S::Error> {
+        let this = &mut *self;
+        let item = this.dedup.dedup(item);
+        Pin::new(&mut this.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
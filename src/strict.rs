@@ -0,0 +1,332 @@
+//! Deserialization that reports what the target type did not consume.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use serde::de;
+
+use de::Elements;
+use path;
+use {DeserializerError, Value, ValueDeserializer};
+
+type Unused = RefCell<BTreeMap<Value, Value>>;
+
+/// Deserializes `T` from `value`, and also returns what `T` skipped, as a map from the JSON
+/// pointers of the skipped subtrees to the subtrees.
+///
+/// These are typically fields a struct does not know, which `from_value` drops silently,
+/// e.g. to find out what a migration to a new type would lose. Pointers address sequence
+/// elements by index, and look through options, newtypes and extension values, as in
+/// `from_value_at`. Elements of sets are not tracked.
+pub fn from_value_strict<'de, T: de::Deserialize<'de>>(
+    value: &Value,
+) -> Result<(T, Value), DeserializerError> {
+    let unused = RefCell::new(BTreeMap::new());
+    let result = T::deserialize(Tracked {
+        value: value.clone(),
+        path: String::new(),
+        unused: &unused,
+    })?;
+    Ok((result, Value::map(unused.into_inner())))
+}
+
+/// Deserializes like `ValueDeserializer`, but records the subtrees the visitor ignores.
+struct Tracked<'u> {
+    value: Value,
+    path: String,
+    unused: &'u Unused,
+}
+
+impl<'u> Tracked<'u> {
+    fn nested(&self, value: Value) -> Tracked<'u> {
+        Tracked {
+            value,
+            path: self.path.clone(),
+            unused: self.unused,
+        }
+    }
+
+    fn plain(self) -> ValueDeserializer<DeserializerError> {
+        ValueDeserializer::new(self.value)
+    }
+}
+
+macro_rules! forward_to_plain {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.plain().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'u> de::Deserializer<'de> for Tracked<'u> {
+    type Error = DeserializerError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Seq(v) | Value::Tuple(v) => visitor.visit_seq(TrackedSeq {
+                elements: Elements::seq(v),
+                index: 0,
+                path: self.path,
+                unused: self.unused,
+            }),
+            Value::Map(kv) => {
+                let (keys, values) = Elements::map(kv);
+                visitor.visit_map(TrackedMap {
+                    keys,
+                    values,
+                    segment: None,
+                    path: self.path,
+                    unused: self.unused,
+                })
+            }
+            Value::Option(Some(ref v)) => visitor.visit_some(self.nested((**v).clone())),
+            Value::Newtype(ref v) => visitor.visit_newtype_struct(self.nested((**v).clone())),
+            Value::Ext { ref value, .. } => self.nested((**value).clone()).deserialize_any(visitor),
+            _ => self.plain().deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Option(Some(ref v)) => visitor.visit_some(self.nested((**v).clone())),
+            Value::Ext { ref value, .. } => {
+                self.nested((**value).clone()).deserialize_option(visitor)
+            }
+            Value::Option(None) | Value::Unit => self.plain().deserialize_option(visitor),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Newtype(ref v) => visitor.visit_newtype_struct(self.nested((**v).clone())),
+            Value::Ext { ref value, .. } => {
+                self.nested((**value).clone()).deserialize_newtype_struct(name, visitor)
+            }
+            Value::Seq(..) | Value::Tuple(..) | Value::Map(..) | Value::Option(Some(..)) => {
+                visitor.visit_newtype_struct(self)
+            }
+            _ => self.plain().deserialize_newtype_struct(name, visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            // leaves the arity error to the plain deserializer
+            Value::Tuple(ref v) if v.len() != len => self.plain().deserialize_tuple(len, visitor),
+            Value::Ext { ref value, .. } => {
+                self.nested((**value).clone()).deserialize_tuple(len, visitor)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Map(ref kv) if kv.values().len() == 1 => visitor.visit_enum(TrackedEnum {
+                variant: kv.keys()[0].clone(),
+                value: kv.values()[0].clone(),
+                path: self.path.clone(),
+                unused: self.unused,
+            }),
+            Value::Ext { ref value, .. } => {
+                self.nested((**value).clone()).deserialize_enum(name, variants, visitor)
+            }
+            _ => self.plain().deserialize_enum(name, variants, visitor),
+        }
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.unused
+            .borrow_mut()
+            .insert(Value::string(self.path), self.value);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.plain().deserialize_unit_struct(name, visitor)
+    }
+
+    forward_to_plain! {
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_f32 deserialize_f64 deserialize_byte_buf
+    }
+
+    forward_to_deserialize_any! {
+        bool char str string unit
+        seq bytes map
+        struct identifier
+    }
+}
+
+struct TrackedSeq<'u> {
+    elements: Elements,
+    index: usize,
+    path: String,
+    unused: &'u Unused,
+}
+
+impl<'de, 'u> de::SeqAccess<'de> for TrackedSeq<'u> {
+    type Error = DeserializerError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        match self.elements.next() {
+            Some(value) => {
+                let path = path::join(&self.path, &self.index.to_string());
+                self.index += 1;
+                seed.deserialize(Tracked {
+                    value,
+                    path,
+                    unused: self.unused,
+                })
+                .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.remaining())
+    }
+}
+
+struct TrackedMap<'u> {
+    keys: Elements,
+    values: Elements,
+    // the path segment of the last key, for the value after it
+    segment: Option<String>,
+    path: String,
+    unused: &'u Unused,
+}
+
+impl<'de, 'u> de::MapAccess<'de> for TrackedMap<'u> {
+    type Error = DeserializerError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        match self.keys.next() {
+            Some(key) => {
+                self.segment = Some(path::key_segment(&key));
+                seed.deserialize(ValueDeserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let value = self
+            .values
+            .next()
+            .expect("MapAccess::next_value called before next_key");
+        let segment = self.segment.take().unwrap_or_default();
+        seed.deserialize(Tracked {
+            value,
+            path: path::join(&self.path, &segment),
+            unused: self.unused,
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.keys.remaining())
+    }
+}
+
+struct TrackedEnum<'u> {
+    variant: Value,
+    value: Value,
+    path: String,
+    unused: &'u Unused,
+}
+
+impl<'de, 'u> de::EnumAccess<'de> for TrackedEnum<'u> {
+    type Error = DeserializerError;
+    type Variant = Tracked<'u>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Tracked<'u>), Self::Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let content = Tracked {
+            path: path::join(&self.path, &path::key_segment(&self.variant)),
+            value: self.value,
+            unused: self.unused,
+        };
+        seed.deserialize(ValueDeserializer::new(self.variant))
+            .map(|v| (v, content))
+    }
+}
+
+// the content of a variant given as a single entry map
+impl<'de, 'u> de::VariantAccess<'de> for Tracked<'u> {
+    type Error = DeserializerError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
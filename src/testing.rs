@@ -0,0 +1,281 @@
+//! Helpers for snapshot tests of `Value`s, behind the `testing` feature.
+//!
+//! A snapshot is a canonical, line oriented rendering of a value that keeps the exact
+//! variant of every node, so that it can be checked into the repository as a golden file.
+//! Volatile fields such as timestamps or ids can be replaced by a marker before comparing.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use path;
+use {Number, Value};
+
+/// Marker that replaces redacted nodes.
+pub const REDACTED: &str = "[redacted]";
+
+/// Set of JSON pointer patterns whose nodes are replaced by `REDACTED` before comparison.
+///
+/// A `*` segment matches any single map key or sequence index, so `/items/*/id` redacts the
+/// `id` of every element of `items`.
+#[derive(Clone, Debug, Default)]
+pub struct Redactions {
+    patterns: Vec<Vec<String>>,
+}
+
+impl Redactions {
+    pub fn new() -> Redactions {
+        Redactions::default()
+    }
+
+    /// Adds a pattern. Panics if it is not a valid JSON pointer.
+    pub fn redact(mut self, pattern: &str) -> Redactions {
+        let segments = path::split(pattern)
+            .unwrap_or_else(|| panic!("invalid redaction pattern {:?}", pattern));
+        self.patterns.push(segments);
+        self
+    }
+
+    fn matches(&self, segments: &[String]) -> bool {
+        self.patterns.iter().any(|pattern| {
+            pattern.len() == segments.len()
+                && pattern
+                    .iter()
+                    .zip(segments.iter())
+                    .all(|(p, s)| p == "*" || p == s)
+        })
+    }
+
+    /// Returns a copy of `value` with all matching nodes replaced by `REDACTED`.
+    pub fn apply(&self, value: &Value) -> Value {
+        if self.patterns.is_empty() {
+            return value.clone();
+        }
+        self.apply_at(&mut Vec::new(), value)
+    }
+
+    fn apply_elements(&self, segments: &mut Vec<String>, elements: &[Value]) -> Vec<Value> {
+        elements
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                segments.push(i.to_string());
+                let x = self.apply_at(segments, x);
+                segments.pop();
+                x
+            })
+            .collect()
+    }
+
+    fn apply_at(&self, segments: &mut Vec<String>, value: &Value) -> Value {
+        if self.matches(segments) {
+            return Value::string(REDACTED.to_owned());
+        }
+        match *value {
+            Value::Option(Some(ref v)) => Value::Option(Some(Box::new(self.apply_at(segments, v)))),
+            Value::Newtype(ref v) => Value::Newtype(Box::new(self.apply_at(segments, v))),
+            Value::Ext { tag, ref value } => Value::Ext {
+                tag,
+                value: Box::new(self.apply_at(segments, value)),
+            },
+            Value::Seq(ref v) => Value::seq(self.apply_elements(segments, v)),
+            Value::Tuple(ref v) => Value::Tuple(Arc::new(self.apply_elements(segments, v))),
+            Value::Map(ref kv) => {
+                let values = kv
+                    .0
+                    .iter()
+                    .zip(kv.1.iter())
+                    .map(|(k, v)| {
+                        segments.push(path::key_segment(k));
+                        let v = self.apply_at(segments, v);
+                        segments.pop();
+                        v
+                    })
+                    .collect();
+                Value::Map(kv.with_values(values))
+            }
+            ref other => other.clone(),
+        }
+    }
+}
+
+fn write_indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+fn write_bytes(out: &mut String, v: &[u8]) {
+    out.push_str("b\"");
+    for &b in v {
+        out.extend(std::ascii::escape_default(b).map(char::from));
+    }
+    out.push('"');
+}
+
+fn write_elements<'a, I: ExactSizeIterator<Item = &'a Value>>(
+    out: &mut String,
+    elements: I,
+    depth: usize,
+) {
+    if elements.len() == 0 {
+        out.push_str("[]");
+        return;
+    }
+    out.push_str("[\n");
+    for x in elements {
+        write_indent(out, depth + 1);
+        write_node(out, x, depth + 1);
+        out.push_str(",\n");
+    }
+    write_indent(out, depth);
+    out.push(']');
+}
+
+fn write_node(out: &mut String, value: &Value, depth: usize) {
+    match *value {
+        Value::Unit => out.push_str("()"),
+        Value::UnitStruct(ref v) => write!(out, "UnitStruct({:?})", v).unwrap(),
+        Value::Bool(v) => write!(out, "{}", v).unwrap(),
+        Value::Number(Number::U8(v)) => write!(out, "{}u8", v).unwrap(),
+        Value::Number(Number::U16(v)) => write!(out, "{}u16", v).unwrap(),
+        Value::Number(Number::U32(v)) => write!(out, "{}u32", v).unwrap(),
+        Value::Number(Number::U64(v)) => write!(out, "{}u64", v).unwrap(),
+        Value::Number(Number::I8(v)) => write!(out, "{}i8", v).unwrap(),
+        Value::Number(Number::I16(v)) => write!(out, "{}i16", v).unwrap(),
+        Value::Number(Number::I32(v)) => write!(out, "{}i32", v).unwrap(),
+        Value::Number(Number::I64(v)) => write!(out, "{}i64", v).unwrap(),
+        Value::Number(Number::F32(v)) => write!(out, "{:?}f32", v).unwrap(),
+        Value::Number(Number::F64(v)) => write!(out, "{:?}f64", v).unwrap(),
+        #[cfg(feature = "half")]
+        Value::F16(v) => write!(out, "{:?}f16", v).unwrap(),
+        Value::Char(v) => write!(out, "{:?}", v).unwrap(),
+        Value::String(ref v) => write!(out, "{:?}", v).unwrap(),
+        Value::Bytes(ref v) => write_bytes(out, v),
+        #[cfg(feature = "compression")]
+        Value::CompressedBytes { codec, ref data } => {
+            write!(out, "Compressed({:?}, ", codec).unwrap();
+            write_bytes(out, data);
+            out.push(')');
+        }
+        #[cfg(feature = "mmap")]
+        Value::MappedBytes(ref v) => {
+            out.push_str("Mapped(");
+            write_bytes(out, v);
+            out.push(')');
+        }
+        Value::Option(None) => out.push_str("None"),
+        Value::Option(Some(ref v)) => {
+            out.push_str("Some(");
+            write_node(out, v, depth);
+            out.push(')');
+        }
+        Value::Newtype(ref v) => {
+            out.push_str("Newtype(");
+            write_node(out, v, depth);
+            out.push(')');
+        }
+        Value::Ext { tag, ref value } => {
+            write!(out, "Ext({}, ", tag).unwrap();
+            write_node(out, value, depth);
+            out.push(')');
+        }
+        Value::Seq(ref v) => write_elements(out, v.iter(), depth),
+        Value::Tuple(ref v) => {
+            out.push_str("Tuple(");
+            write_elements(out, v.iter(), depth);
+            out.push(')');
+        }
+        Value::Set(ref v) => {
+            out.push_str("Set(");
+            write_elements(out, v.iter(), depth);
+            out.push(')');
+        }
+        Value::Map(ref kv) if kv.0.is_empty() => out.push_str("{}"),
+        Value::Map(ref kv) => {
+            out.push_str("{\n");
+            for (k, v) in kv.0.iter().zip(kv.1.iter()) {
+                write_indent(out, depth + 1);
+                write_node(out, k, depth + 1);
+                out.push_str(": ");
+                write_node(out, v, depth + 1);
+                out.push_str(",\n");
+            }
+            write_indent(out, depth);
+            out.push('}');
+        }
+    }
+}
+
+/// Renders a value in the canonical snapshot format.
+///
+/// Map entries appear in key order and every scalar carries its exact variant, so two
+/// values have the same snapshot if and only if they are equal.
+pub fn to_snapshot(value: &Value) -> String {
+    let mut out = String::new();
+    write_node(&mut out, value, 0);
+    out.push('\n');
+    out
+}
+
+/// Compares `value`, after redaction, with the snapshot stored at `file`.
+///
+/// If the `UPDATE_SNAPSHOTS` environment variable is set, the snapshot is written instead.
+/// On mismatch, panics listing the differing lines. A missing file is a mismatch too, so
+/// that a forgotten or misspelled snapshot does not pass.
+#[track_caller]
+pub fn assert_snapshot<P: AsRef<Path>>(file: P, value: &Value, redactions: &Redactions) {
+    let file = file.as_ref();
+    let actual = to_snapshot(&redactions.apply(value));
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(file, &actual).unwrap();
+        return;
+    }
+    if !file.exists() {
+        panic!(
+            "snapshot {} does not exist, set UPDATE_SNAPSHOTS=1 to create it",
+            file.display()
+        );
+    }
+    let expected = fs::read_to_string(file).unwrap();
+    if expected != actual {
+        let mut message = format!("snapshot {} does not match:\n", file.display());
+        let (e, a): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+        for i in 0..e.len().max(a.len()) {
+            match (e.get(i), a.get(i)) {
+                (Some(e), Some(a)) if e == a => {}
+                (e, a) => {
+                    writeln!(message, "  line {}:", i + 1).unwrap();
+                    writeln!(message, "  - {}", e.unwrap_or(&"")).unwrap();
+                    writeln!(message, "  + {}", a.unwrap_or(&"")).unwrap();
+                }
+            }
+        }
+        message.push_str("set UPDATE_SNAPSHOTS=1 to accept the new value");
+        panic!("{}", message);
+    }
+}
+
+/// Asserts that a `Value` matches the snapshot `snapshots/<name>.snap` of the calling crate.
+///
+/// An optional third argument gives the `Redactions` to apply. See `testing::assert_snapshot`.
+#[macro_export]
+macro_rules! assert_value_snapshot {
+    ($name:expr, $value:expr) => {
+        $crate::assert_value_snapshot!($name, $value, $crate::testing::Redactions::new())
+    };
+    ($name:expr, $value:expr, $redactions:expr) => {
+        $crate::testing::assert_snapshot(
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("snapshots")
+                .join(format!("{}.snap", $name)),
+            &$value,
+            &$redactions,
+        )
+    };
+}
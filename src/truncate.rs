@@ -0,0 +1,175 @@
+//! Display safe copies of values, for logging large documents.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use Value;
+
+/// How much of a value `Value::truncated` keeps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TruncateLimits {
+    max_bytes: usize,
+    max_entries: usize,
+    max_depth: usize,
+}
+
+/// 256 bytes, 32 entries and 8 levels.
+impl Default for TruncateLimits {
+    fn default() -> TruncateLimits {
+        TruncateLimits {
+            max_bytes: 256,
+            max_entries: 32,
+            max_depth: 8,
+        }
+    }
+}
+
+impl TruncateLimits {
+    pub fn new() -> TruncateLimits {
+        TruncateLimits::default()
+    }
+
+    /// Sets the bytes kept of each string or blob.
+    pub fn max_bytes(mut self, max_bytes: usize) -> TruncateLimits {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Sets the elements kept of each sequence, tuple or set, and the entries of each map.
+    pub fn max_entries(mut self, max_entries: usize) -> TruncateLimits {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the levels of sequences, sets and maps kept, counting the outermost as 1.
+    /// Deeper ones are replaced by a marker as a whole.
+    pub fn max_depth(mut self, max_depth: usize) -> TruncateLimits {
+        self.max_depth = max_depth;
+        self
+    }
+
+    fn elements<'a, I>(&self, elements: I, len: usize, depth: usize) -> Option<Vec<Value>>
+    where
+        I: Iterator<Item = &'a Value>,
+    {
+        let mut changed = len > self.max_entries;
+        let mut kept: Vec<Value> = elements
+            .take(self.max_entries)
+            .map(|x| match self.node(x, depth + 1) {
+                Some(x) => {
+                    changed = true;
+                    x
+                }
+                None => x.clone(),
+            })
+            .collect();
+        if len > self.max_entries {
+            kept.push(marker(len - self.max_entries));
+        }
+        if changed {
+            Some(kept)
+        } else {
+            None
+        }
+    }
+
+    /// The truncated copy of `value`, or `None` if it is within the limits.
+    fn node(&self, value: &Value, depth: usize) -> Option<Value> {
+        let len = match *value {
+            Value::Seq(ref v) | Value::Tuple(ref v) => Some(v.len()),
+            Value::Set(ref v) => Some(v.len()),
+            Value::Map(ref kv) => Some(kv.0.len()),
+            _ => None,
+        };
+        match len {
+            Some(len) if depth >= self.max_depth && len > 0 => return Some(marker(len)),
+            _ => {}
+        }
+        match *value {
+            Value::String(ref v) | Value::UnitStruct(ref v) if v.len() > self.max_bytes => {
+                let mut end = self.max_bytes;
+                while !v.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let text = format!("{}{}", &v[..end], marker_text(v.len() - end));
+                Some(Value::String(text.into()))
+            }
+            Value::Bytes(ref v) => self.bytes(v),
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => self.bytes(v),
+            // the size of the content is not known without decompressing it
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { ref data, .. } => {
+                Some(Value::String(format!("\u{2026}{} compressed bytes", data.len()).into()))
+            }
+            Value::Seq(ref v) => self.elements(v.iter(), v.len(), depth).map(Value::seq),
+            Value::Tuple(ref v) => {
+                let elements = self.elements(v.iter(), v.len(), depth)?;
+                Some(Value::Tuple(Arc::new(elements)))
+            }
+            // a sequence, so the marker stays at the end
+            Value::Set(ref v) => self.elements(v.iter(), v.len(), depth).map(Value::seq),
+            Value::Map(ref kv) => {
+                let len = kv.0.len();
+                if len <= self.max_entries {
+                    let values = self.elements(kv.1.iter(), len, depth)?;
+                    return Some(Value::Map(kv.with_values(values)));
+                }
+                let mut map: BTreeMap<Value, Value> = kv
+                    .0
+                    .iter()
+                    .zip(kv.1.iter())
+                    .take(self.max_entries)
+                    .map(|(k, v)| {
+                        let v = self.node(v, depth + 1).unwrap_or_else(|| v.clone());
+                        (k.clone(), v)
+                    })
+                    .collect();
+                map.insert(marker(len - self.max_entries), Value::Unit);
+                Some(Value::map(map))
+            }
+            Value::Option(Some(ref v)) => {
+                Some(Value::Option(Some(Box::new(self.node(v, depth)?))))
+            }
+            Value::Newtype(ref v) => Some(Value::Newtype(Box::new(self.node(v, depth)?))),
+            Value::Ext { tag, ref value } => Some(Value::Ext {
+                tag,
+                value: Box::new(self.node(value, depth)?),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The first bytes of a long blob, followed by a marker.
+    fn bytes(&self, v: &[u8]) -> Option<Value> {
+        if v.len() <= self.max_bytes {
+            return None;
+        }
+        let kept = Value::bytes(v[..self.max_bytes].to_vec());
+        Some(Value::Tuple(Arc::new(vec![kept, marker(v.len() - self.max_bytes)])))
+    }
+}
+
+fn marker_text(more: usize) -> String {
+    format!("\u{2026}+{} more", more)
+}
+
+fn marker(more: usize) -> Value {
+    Value::String(marker_text(more).into())
+}
+
+impl Value {
+    /// A copy of this value that is safe to log, see `TruncateLimits`.
+    ///
+    /// Strings longer than the limit are cut at a character boundary and end with a marker
+    /// like `…+4321 more`, counting the bytes left out. Sequences, tuples and sets keep
+    /// their first elements and end with such a marker, counting the elements left out,
+    /// and maps keep their first entries, with the marker as an extra key. Sets become
+    /// sequences when truncated, long blobs become a tuple of their first bytes and a
+    /// marker, and containers nested too deeply become a marker counting their entries.
+    ///
+    /// Parts within the limits are shared with this value.
+    pub fn truncated(&self, limits: &TruncateLimits) -> Value {
+        limits.node(self, 0).unwrap_or_else(|| self.clone())
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use unicode_normalization::{is_nfc_quick, is_nfd_quick, is_nfkc_quick, is_nfkd_quick};
+use unicode_normalization::{IsNormalized, UnicodeNormalization};
+
+use rewrite::{rewrite_strings, Strings};
+use Value;
+
+/// A Unicode normalization form, see `Value::normalize_unicode`.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Form {
+    NFC,
+    NFD,
+    NFKC,
+    NFKD,
+}
+
+impl Form {
+    fn is_normalized(self, s: &str) -> IsNormalized {
+        match self {
+            Form::NFC => is_nfc_quick(s.chars()),
+            Form::NFD => is_nfd_quick(s.chars()),
+            Form::NFKC => is_nfkc_quick(s.chars()),
+            Form::NFKD => is_nfkd_quick(s.chars()),
+        }
+    }
+
+    fn normalize(self, s: &str) -> String {
+        match self {
+            Form::NFC => s.nfc().collect(),
+            Form::NFD => s.nfd().collect(),
+            Form::NFKC => s.nfkc().collect(),
+            Form::NFKD => s.nfkd().collect(),
+        }
+    }
+
+    /// The normalized string, or `None` if it already is.
+    fn apply(self, s: &Arc<str>) -> Option<Arc<str>> {
+        if self.is_normalized(s) == IsNormalized::Yes {
+            return None;
+        }
+        let normalized = self.normalize(s);
+        if *normalized == **s {
+            None
+        } else {
+            Some(Arc::from(normalized))
+        }
+    }
+}
+
+impl Value {
+    /// Returns a copy of this value with all strings, map keys included, in the Unicode
+    /// normalization form `form`.
+    ///
+    /// Strings that look the same but were composed differently, say by different operating
+    /// systems, become equal and can share storage after `Dedup`. Strings and subtrees that
+    /// are already normalized stay shared with `self`. If two keys of a map become equal,
+    /// the value of the one that sorted last is kept.
+    pub fn normalize_unicode(&self, form: Form) -> Value {
+        rewrite_strings(self, Strings::All, &mut |s| form.apply(s)).unwrap_or_else(|| self.clone())
+    }
+}
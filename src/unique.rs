@@ -0,0 +1,48 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use Value;
+
+// keeps the original vector if nothing was dropped
+fn filter_seq<'a, F: FnMut(&'a Value) -> bool>(v: &'a Arc<Vec<Value>>, mut keep: F) -> Value {
+    let kept: Vec<Value> = v.iter().filter(|&x| keep(x)).cloned().collect();
+    if kept.len() == v.len() {
+        Value::Seq(v.clone())
+    } else {
+        Value::seq(kept)
+    }
+}
+
+impl Value {
+    /// Removes repeated elements from a `Seq`, keeping the first occurrence of each.
+    ///
+    /// Elements are compared with the total `Ord` of `Value`, so `1u8` and `1u16` are
+    /// distinct and NaNs are equal to each other. Retained elements are cloned, so they keep
+    /// sharing with the input. Anything that is not a `Seq` is returned unchanged.
+    pub fn unique(&self) -> Value {
+        match *self {
+            Value::Seq(ref v) => {
+                let mut seen = BTreeSet::new();
+                filter_seq(v, |x| seen.insert(x.clone()))
+            }
+            ref other => other.clone(),
+        }
+    }
+
+    /// Collapses runs of equal elements in a `Seq` into a single element, like `Vec::dedup`.
+    ///
+    /// Uses the same equality as [`unique`](#method.unique) and the same sharing rules.
+    pub fn dedup_adjacent(&self) -> Value {
+        match *self {
+            Value::Seq(ref v) => {
+                let mut prev: Option<&Value> = None;
+                filter_seq(v, |x| {
+                    let keep = prev != Some(x);
+                    prev = Some(x);
+                    keep
+                })
+            }
+            ref other => other.clone(),
+        }
+    }
+}
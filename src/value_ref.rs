@@ -0,0 +1,171 @@
+//! A borrowed view of a value, for looking at data without touching reference counts.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+#[cfg(feature = "half")]
+use float::OrderedFloat;
+#[cfg(feature = "compression")]
+use Codec;
+#[cfg(feature = "mmap")]
+use MappedBytes;
+use {Number, Value, KV};
+
+/// A value borrowed from a `Value`, see `Value::as_ref`.
+///
+/// Mirrors the variants of `Value`, with shared contents as plain references, so matching on
+/// it gives `&str` and `&[Value]` directly. Compares equal to a `Value` exactly when the
+/// value it borrows from would.
+#[derive(Clone, Copy, Debug)]
+pub enum ValueRef<'a> {
+    Unit,
+
+    Bool(bool),
+
+    Number(Number),
+    #[cfg(feature = "half")]
+    F16(half::f16),
+
+    Char(char),
+
+    Option(Option<&'a Value>),
+    Newtype(&'a Value),
+    UnitStruct(&'a str),
+
+    String(&'a str),
+    Bytes(&'a [u8]),
+    Seq(&'a [Value]),
+    Tuple(&'a [Value]),
+    Map(&'a KV),
+    Set(&'a BTreeSet<Value>),
+
+    Ext { tag: u64, value: &'a Value },
+
+    #[cfg(feature = "compression")]
+    CompressedBytes { codec: Codec, data: &'a [u8] },
+
+    #[cfg(feature = "mmap")]
+    MappedBytes(&'a MappedBytes),
+}
+
+impl Value {
+    /// A borrowed view of this value.
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_ref(&self) -> ValueRef<'_> {
+        match *self {
+            Value::Unit => ValueRef::Unit,
+            Value::Bool(v) => ValueRef::Bool(v),
+            Value::Number(v) => ValueRef::Number(v),
+            #[cfg(feature = "half")]
+            Value::F16(v) => ValueRef::F16(v),
+            Value::Char(v) => ValueRef::Char(v),
+            Value::Option(ref v) => ValueRef::Option(v.as_deref()),
+            Value::Newtype(ref v) => ValueRef::Newtype(v),
+            Value::UnitStruct(ref v) => ValueRef::UnitStruct(v),
+            Value::String(ref v) => ValueRef::String(v),
+            Value::Bytes(ref v) => ValueRef::Bytes(v),
+            Value::Seq(ref v) => ValueRef::Seq(v),
+            Value::Tuple(ref v) => ValueRef::Tuple(v),
+            Value::Map(ref v) => ValueRef::Map(v),
+            Value::Set(ref v) => ValueRef::Set(v),
+            Value::Ext { tag, ref value } => ValueRef::Ext { tag, value },
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => ValueRef::CompressedBytes { codec, data },
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => ValueRef::MappedBytes(v),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> ValueRef<'a> {
+        value.as_ref()
+    }
+}
+
+impl<'a> ValueRef<'a> {
+    /// An owned copy of the borrowed value.
+    ///
+    /// Strings, blobs and sequences are copied into new allocations, since a reference does
+    /// not lead back to the `Arc` it points into. Clone the original `Value` to share them.
+    pub fn to_value(&self) -> Value {
+        match *self {
+            ValueRef::Unit => Value::Unit,
+            ValueRef::Bool(v) => Value::Bool(v),
+            ValueRef::Number(v) => Value::Number(v),
+            #[cfg(feature = "half")]
+            ValueRef::F16(v) => Value::F16(v),
+            ValueRef::Char(v) => Value::Char(v),
+            ValueRef::Option(v) => Value::Option(v.map(|v| Box::new(v.clone()))),
+            ValueRef::Newtype(v) => Value::Newtype(Box::new(v.clone())),
+            ValueRef::UnitStruct(v) => Value::UnitStruct(v.into()),
+            ValueRef::String(v) => Value::String(v.into()),
+            ValueRef::Bytes(v) => Value::Bytes(Arc::new(v.to_vec())),
+            ValueRef::Seq(v) => Value::Seq(Arc::new(v.to_vec())),
+            ValueRef::Tuple(v) => Value::Tuple(Arc::new(v.to_vec())),
+            ValueRef::Map(v) => Value::Map(Arc::new(v.clone())),
+            ValueRef::Set(v) => Value::Set(Arc::new(v.clone())),
+            ValueRef::Ext { tag, value } => Value::Ext {
+                tag,
+                value: Box::new(value.clone()),
+            },
+            #[cfg(feature = "compression")]
+            ValueRef::CompressedBytes { codec, data } => Value::CompressedBytes {
+                codec,
+                data: Arc::new(data.to_vec()),
+            },
+            #[cfg(feature = "mmap")]
+            ValueRef::MappedBytes(v) => Value::MappedBytes(v.clone()),
+        }
+    }
+}
+
+impl<'a, 'b> PartialEq<ValueRef<'b>> for ValueRef<'a> {
+    fn eq(&self, rhs: &ValueRef<'b>) -> bool {
+        match (*self, *rhs) {
+            (ValueRef::Bool(v0), ValueRef::Bool(v1)) => v0 == v1,
+            (ValueRef::Number(v0), ValueRef::Number(v1)) => v0 == v1,
+            #[cfg(feature = "half")]
+            (ValueRef::F16(v0), ValueRef::F16(v1)) => {
+                OrderedFloat(v0.to_f32()) == OrderedFloat(v1.to_f32())
+            }
+            (ValueRef::Char(v0), ValueRef::Char(v1)) => v0 == v1,
+            (ValueRef::String(v0), ValueRef::String(v1)) => v0 == v1,
+            (ValueRef::Unit, ValueRef::Unit) => true,
+            (ValueRef::UnitStruct(v0), ValueRef::UnitStruct(v1)) => v0 == v1,
+            (ValueRef::Option(v0), ValueRef::Option(v1)) => v0 == v1,
+            (ValueRef::Newtype(v0), ValueRef::Newtype(v1)) => v0 == v1,
+            (ValueRef::Seq(v0), ValueRef::Seq(v1)) => v0 == v1,
+            (ValueRef::Tuple(v0), ValueRef::Tuple(v1)) => v0 == v1,
+            (ValueRef::Map(v0), ValueRef::Map(v1)) => v0 == v1,
+            (ValueRef::Set(v0), ValueRef::Set(v1)) => v0 == v1,
+            (
+                ValueRef::Ext { tag: t0, value: v0 },
+                ValueRef::Ext { tag: t1, value: v1 },
+            ) => t0 == t1 && v0 == v1,
+            (ValueRef::Bytes(v0), ValueRef::Bytes(v1)) => v0 == v1,
+            #[cfg(feature = "compression")]
+            (
+                ValueRef::CompressedBytes { codec: c0, data: d0 },
+                ValueRef::CompressedBytes { codec: c1, data: d1 },
+            ) => c0 == c1 && d0 == d1,
+            #[cfg(feature = "mmap")]
+            (ValueRef::MappedBytes(v0), ValueRef::MappedBytes(v1)) => v0 == v1,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Eq for ValueRef<'a> {}
+
+impl<'a> PartialEq<Value> for ValueRef<'a> {
+    fn eq(&self, rhs: &Value) -> bool {
+        *self == rhs.as_ref()
+    }
+}
+
+impl<'a> PartialEq<ValueRef<'a>> for Value {
+    fn eq(&self, rhs: &ValueRef<'a>) -> bool {
+        self.as_ref() == *rhs
+    }
+}
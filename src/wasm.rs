@@ -0,0 +1,170 @@
+//! WebAssembly bindings, behind the `wasm` feature.
+//!
+//! Built with `wasm-pack build -- --features wasm` (or `cargo build --target
+//! wasm32-unknown-unknown --features wasm` and `wasm-bindgen`), JavaScript gets
+//! `parse_json_dedup` and `to_json`, a `Dedup` class for deduplicating many documents
+//! against each other, and `memory_stats` to see what sharing saved.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use serde_json;
+use wasm_bindgen::prelude::*;
+
+use report::{blob_size, object_size, seq_size, string_size};
+use {Dedup, Deduplicator, Value};
+
+/// A parsed JSON document, opaque to JavaScript.
+#[wasm_bindgen(js_name = Value)]
+pub struct WasmValue(Value);
+
+/// How many bytes a value or a dedup table takes, and how many it would without sharing.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryStats {
+    /// bytes as if nothing were shared
+    pub expanded_bytes: f64,
+    /// bytes actually held, counting every shared node once
+    pub stored_bytes: f64,
+}
+
+#[wasm_bindgen]
+impl MemoryStats {
+    #[wasm_bindgen(getter)]
+    pub fn saved_bytes(&self) -> f64 {
+        self.expanded_bytes - self.stored_bytes
+    }
+
+    /// `expanded_bytes / stored_bytes`, or 1 if nothing is stored.
+    #[wasm_bindgen(getter)]
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0.0 {
+            1.0
+        } else {
+            self.expanded_bytes / self.stored_bytes
+        }
+    }
+}
+
+fn address<T: ?Sized>(v: &Arc<T>) -> usize {
+    Arc::as_ptr(v) as *const u8 as usize
+}
+
+/// Heap bytes below a value, by the sizes `Dedup::savings_report` uses.
+#[derive(Default)]
+struct Footprint {
+    // expanded bytes below every shared node seen so far
+    expanded: HashMap<usize, u64>,
+    stored: u64,
+}
+
+impl Footprint {
+    fn shared<F>(&mut self, id: usize, own: usize, children: F) -> u64
+    where
+        F: FnOnce(&mut Footprint) -> u64,
+    {
+        if let Some(&bytes) = self.expanded.get(&id) {
+            return bytes;
+        }
+        self.stored += own as u64;
+        let bytes = own as u64 + children(self);
+        self.expanded.insert(id, bytes);
+        bytes
+    }
+
+    fn all<'a, I: Iterator<Item = &'a Value>>(&mut self, values: I) -> u64 {
+        values.map(|x| self.value(x)).sum()
+    }
+
+    fn value(&mut self, value: &Value) -> u64 {
+        match *value {
+            Value::Option(Some(ref v)) | Value::Newtype(ref v) => self.boxed(v),
+            Value::Ext { ref value, .. } => self.boxed(value),
+            Value::String(ref s) | Value::UnitStruct(ref s) => {
+                self.shared(address(s), string_size(s), |_| 0)
+            }
+            Value::Bytes(ref b) => self.shared(address(b), blob_size(b), |_| 0),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { ref data, .. } => {
+                self.shared(address(data), blob_size(data), |_| 0)
+            }
+            Value::Seq(ref v) | Value::Tuple(ref v) => {
+                self.shared(address(v), seq_size(v), |f| f.all(v.iter()))
+            }
+            Value::Set(ref v) => {
+                let own = v.len() * size_of::<Value>();
+                self.shared(address(v), own, |f| f.all(v.iter()))
+            }
+            Value::Map(ref kv) => self.shared(address(kv), object_size(kv), |f| {
+                let keys = &kv.0;
+                let keys = f.shared(address(keys), seq_size(keys), |f| f.all(keys.iter()));
+                keys + f.all(kv.values().iter())
+            }),
+            _ => 0,
+        }
+    }
+
+    // boxes are never shared
+    fn boxed(&mut self, value: &Value) -> u64 {
+        self.stored += size_of::<Value>() as u64;
+        size_of::<Value>() as u64 + self.value(value)
+    }
+}
+
+pub(crate) fn value_stats(value: &Value) -> MemoryStats {
+    let mut footprint = Footprint::default();
+    let expanded = size_of::<Value>() as u64 + footprint.value(value);
+    MemoryStats {
+        expanded_bytes: expanded as f64,
+        stored_bytes: (size_of::<Value>() as u64 + footprint.stored) as f64,
+    }
+}
+
+pub(crate) fn parse_json(dedup: &mut Dedup, json: &str) -> Result<Value, serde_json::Error> {
+    serde_json::from_str(json).map(|value| dedup.dedup(value))
+}
+
+/// Parses a JSON document, sharing all repeated strings and subtrees within it.
+#[wasm_bindgen]
+pub fn parse_json_dedup(json: &str) -> Result<WasmValue, JsError> {
+    Ok(WasmValue(parse_json(&mut Dedup::new(), json)?))
+}
+
+#[wasm_bindgen]
+pub fn to_json(value: &WasmValue) -> Result<String, JsError> {
+    Ok(serde_json::to_string(&value.0)?)
+}
+
+/// The bytes `value` takes in memory, and would take if it shared nothing.
+#[wasm_bindgen]
+pub fn memory_stats(value: &WasmValue) -> MemoryStats {
+    value_stats(&value.0)
+}
+
+/// A dedup table for parsing many documents sharing their contents.
+#[wasm_bindgen(js_name = Dedup)]
+pub struct WasmDedup(Dedup);
+
+#[wasm_bindgen(js_class = Dedup)]
+impl WasmDedup {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> WasmDedup {
+        WasmDedup(Dedup::new())
+    }
+
+    /// Parses a JSON document, sharing everything it has in common with earlier ones.
+    pub fn parse_json(&mut self, json: &str) -> Result<WasmValue, JsError> {
+        Ok(WasmValue(parse_json(&mut self.0, json)?))
+    }
+
+    /// The bytes seen and held by the tables so far, see `Dedup::savings_report`.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let total = self.0.savings_report().total();
+        MemoryStats {
+            expanded_bytes: total.input_bytes as f64,
+            stored_bytes: total.stored_bytes as f64,
+        }
+    }
+}
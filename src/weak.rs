@@ -0,0 +1,131 @@
+//! Weak references to shared values, for caches that should not keep them alive.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::{Arc, Weak};
+
+#[cfg(feature = "compression")]
+use Codec;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+#[cfg(feature = "mmap")]
+use MappedBytes;
+use {Value, KV};
+
+#[derive(Clone)]
+enum Inner {
+    String(Weak<str>),
+    UnitStruct(Weak<str>),
+    Bytes(Weak<Vec<u8>>),
+    Seq(Weak<Vec<Value>>),
+    Tuple(Weak<Vec<Value>>),
+    Map(Weak<KV>),
+    Set(Weak<BTreeSet<Value>>),
+    #[cfg(feature = "compression")]
+    CompressedBytes(Codec, Weak<Vec<u8>>),
+    #[cfg(feature = "mmap")]
+    MappedBytes(Weak<Mmap>, usize, usize),
+}
+
+/// A value that does not keep its allocation alive, see `Value::downgrade`.
+#[derive(Clone)]
+pub struct WeakValue(Inner);
+
+impl Value {
+    /// A weak reference to this value if it is a string, blob, sequence, tuple, map or set.
+    ///
+    /// `None` for scalars, which have nothing to share, and for options, newtypes and
+    /// extension values, whose box is never shared. Downgrade their content instead.
+    pub fn downgrade(&self) -> Option<WeakValue> {
+        Some(WeakValue(match *self {
+            Value::String(ref v) => Inner::String(Arc::downgrade(v)),
+            Value::UnitStruct(ref v) => Inner::UnitStruct(Arc::downgrade(v)),
+            Value::Bytes(ref v) => Inner::Bytes(Arc::downgrade(v)),
+            Value::Seq(ref v) => Inner::Seq(Arc::downgrade(v)),
+            Value::Tuple(ref v) => Inner::Tuple(Arc::downgrade(v)),
+            Value::Map(ref v) => Inner::Map(Arc::downgrade(v)),
+            Value::Set(ref v) => Inner::Set(Arc::downgrade(v)),
+            #[cfg(feature = "compression")]
+            Value::CompressedBytes { codec, ref data } => {
+                Inner::CompressedBytes(codec, Arc::downgrade(data))
+            }
+            #[cfg(feature = "mmap")]
+            Value::MappedBytes(ref v) => {
+                Inner::MappedBytes(Arc::downgrade(v.shared()), v.offset(), v.len())
+            }
+            _ => return None,
+        }))
+    }
+}
+
+impl WeakValue {
+    /// The value, if it is still referenced elsewhere.
+    pub fn upgrade(&self) -> Option<Value> {
+        Some(match self.0 {
+            Inner::String(ref v) => Value::String(v.upgrade()?),
+            Inner::UnitStruct(ref v) => Value::UnitStruct(v.upgrade()?),
+            Inner::Bytes(ref v) => Value::Bytes(v.upgrade()?),
+            Inner::Seq(ref v) => Value::Seq(v.upgrade()?),
+            Inner::Tuple(ref v) => Value::Tuple(v.upgrade()?),
+            Inner::Map(ref v) => Value::Map(v.upgrade()?),
+            Inner::Set(ref v) => Value::Set(v.upgrade()?),
+            #[cfg(feature = "compression")]
+            Inner::CompressedBytes(codec, ref data) => Value::CompressedBytes {
+                codec,
+                data: data.upgrade()?,
+            },
+            #[cfg(feature = "mmap")]
+            Inner::MappedBytes(ref map, offset, len) => {
+                Value::MappedBytes(MappedBytes::from_parts(map.upgrade()?, offset, len))
+            }
+        })
+    }
+
+    /// How many values reference the allocation, 0 once it is freed.
+    pub fn strong_count(&self) -> usize {
+        match self.0 {
+            Inner::String(ref v) | Inner::UnitStruct(ref v) => v.strong_count(),
+            Inner::Bytes(ref v) => v.strong_count(),
+            Inner::Seq(ref v) | Inner::Tuple(ref v) => v.strong_count(),
+            Inner::Map(ref v) => v.strong_count(),
+            Inner::Set(ref v) => v.strong_count(),
+            #[cfg(feature = "compression")]
+            Inner::CompressedBytes(_, ref v) => v.strong_count(),
+            #[cfg(feature = "mmap")]
+            Inner::MappedBytes(ref v, ..) => v.strong_count(),
+        }
+    }
+
+    /// Whether `value` is the value this was downgraded from, or a clone of it.
+    pub fn ptr_eq(&self, value: &Value) -> bool {
+        match (&self.0, value) {
+            (Inner::String(a), Value::String(b)) | (Inner::UnitStruct(a), Value::UnitStruct(b)) => {
+                a.ptr_eq(&Arc::downgrade(b))
+            }
+            (Inner::Bytes(a), Value::Bytes(b)) => a.ptr_eq(&Arc::downgrade(b)),
+            (Inner::Seq(a), Value::Seq(b)) | (Inner::Tuple(a), Value::Tuple(b)) => {
+                a.ptr_eq(&Arc::downgrade(b))
+            }
+            (Inner::Map(a), Value::Map(b)) => a.ptr_eq(&Arc::downgrade(b)),
+            (Inner::Set(a), Value::Set(b)) => a.ptr_eq(&Arc::downgrade(b)),
+            #[cfg(feature = "compression")]
+            (Inner::CompressedBytes(c0, a), Value::CompressedBytes { codec: c1, data: b }) => {
+                c0 == c1 && a.ptr_eq(&Arc::downgrade(b))
+            }
+            #[cfg(feature = "mmap")]
+            (Inner::MappedBytes(a, offset, len), Value::MappedBytes(b)) => {
+                a.ptr_eq(&Arc::downgrade(b.shared())) && *offset == b.offset() && *len == b.len()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for WeakValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.upgrade() {
+            Some(v) => write!(f, "WeakValue({:?})", v),
+            None => write!(f, "WeakValue(<dropped>)"),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Conversion between XML documents and values, behind the `quick-xml` feature.
+//!
+//! An element becomes a map from its attribute names, with a prefix, and the tag names of
+//! its children to their contents, with the children of a repeated tag collected in a
+//! sequence. Text next to children or attributes goes under a text key, and an element
+//! with nothing but text becomes that string. Elements with the same attributes and child
+//! tags share one key vector, and all names are interned, so a feed of similar records
+//! costs little more than its text.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+use quick_xml::errors::IllFormedError;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use path;
+use {Value, KV};
+
+/// Why an XML document could not be read or written.
+#[derive(Debug)]
+pub enum XmlError {
+    /// the input is not well formed XML
+    Syntax(quick_xml::Error),
+    /// the input has no root element
+    NoRoot,
+    /// a node with no XML representation, with its JSON pointer
+    Unsupported(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            XmlError::Syntax(ref e) => write!(f, "invalid XML: {}", e),
+            XmlError::NoRoot => write!(f, "the document has no root element"),
+            XmlError::Unsupported(ref pointer) => {
+                write!(f, "the value at '{}' has no XML representation", pointer)
+            }
+            XmlError::Io(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for XmlError {}
+
+impl From<quick_xml::Error> for XmlError {
+    fn from(e: quick_xml::Error) -> XmlError {
+        XmlError::Syntax(e)
+    }
+}
+
+impl From<quick_xml::encoding::EncodingError> for XmlError {
+    fn from(e: quick_xml::encoding::EncodingError) -> XmlError {
+        XmlError::Syntax(e.into())
+    }
+}
+
+impl From<io::Error> for XmlError {
+    fn from(e: io::Error) -> XmlError {
+        XmlError::Io(e)
+    }
+}
+
+/// How elements map to values, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct XmlOptions {
+    attribute_prefix: String,
+    text_key: String,
+}
+
+impl Default for XmlOptions {
+    fn default() -> XmlOptions {
+        XmlOptions {
+            attribute_prefix: "@".to_owned(),
+            text_key: "#text".to_owned(),
+        }
+    }
+}
+
+/// An element being read, with its contents so far.
+struct Element {
+    name: Arc<str>,
+    entries: BTreeMap<Arc<str>, Vec<Value>>,
+    text: String,
+}
+
+/// Interned names and key vectors of one document.
+#[derive(Default)]
+struct Names {
+    strings: HashSet<Arc<str>>,
+    keys: HashSet<Arc<Vec<Value>>>,
+}
+
+impl Names {
+    fn name(&mut self, name: &str) -> Arc<str> {
+        if let Some(name) = self.strings.get(name) {
+            return name.clone();
+        }
+        let name: Arc<str> = name.into();
+        self.strings.insert(name.clone());
+        name
+    }
+
+    fn keys(&mut self, keys: Vec<Value>) -> Arc<Vec<Value>> {
+        if let Some(keys) = self.keys.get(&keys) {
+            return keys.clone();
+        }
+        let keys = Arc::new(keys);
+        self.keys.insert(keys.clone());
+        keys
+    }
+}
+
+/// The text of a scalar, `None` for units and `None`.
+fn text(value: &Value, pointer: &str) -> Result<Option<String>, XmlError> {
+    Ok(Some(match *value {
+        Value::Unit | Value::Option(None) => return Ok(None),
+        Value::Bool(v) => v.to_string(),
+        Value::Number(v) => v.to_string(),
+        #[cfg(feature = "half")]
+        Value::F16(v) => v.to_string(),
+        Value::Char(v) => v.to_string(),
+        Value::String(ref v) | Value::UnitStruct(ref v) => v.to_string(),
+        Value::Option(Some(ref v)) | Value::Newtype(ref v) | Value::Ext { value: ref v, .. } => {
+            return text(v, pointer)
+        }
+        _ => return Err(XmlError::Unsupported(pointer.to_owned())),
+    }))
+}
+
+/// Writes an element without children, empty if it has no text.
+fn write_text_element<W: io::Write>(
+    writer: &mut Writer<W>,
+    start: BytesStart,
+    text: Option<&str>,
+) -> Result<(), XmlError> {
+    let text = match text {
+        Some(text) => text,
+        None => return Ok(writer.write_event(Event::Empty(start))?),
+    };
+    let end = start.to_end().into_owned();
+    writer.write_event(Event::Start(start))?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(end))?;
+    Ok(())
+}
+
+impl XmlOptions {
+    pub fn new() -> XmlOptions {
+        XmlOptions::default()
+    }
+
+    /// Sets the prefix that marks attributes among the keys of an element, `@` by default.
+    pub fn attribute_prefix(mut self, prefix: &str) -> XmlOptions {
+        self.attribute_prefix = prefix.to_owned();
+        self
+    }
+
+    /// Sets the key of the text of an element that also has attributes or children,
+    /// `#text` by default.
+    pub fn text_key(mut self, key: &str) -> XmlOptions {
+        self.text_key = key.to_owned();
+        self
+    }
+
+    fn start(
+        &self,
+        reader: &Reader<impl BufRead>,
+        names: &mut Names,
+        start: &BytesStart,
+    ) -> Result<Element, XmlError> {
+        let decoder = reader.decoder();
+        let mut element = Element {
+            name: names.name(&decoder.decode(start.name().as_ref())?),
+            entries: BTreeMap::new(),
+            text: String::new(),
+        };
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(quick_xml::Error::from)?;
+            let key = decoder.decode(attribute.key.as_ref())?;
+            let key = names.name(&format!("{}{}", self.attribute_prefix, key));
+            let value = attribute.decode_and_unescape_value(decoder)?;
+            element
+                .entries
+                .entry(key)
+                .or_default()
+                .push(Value::String(value.into()));
+        }
+        Ok(element)
+    }
+
+    fn finish(&self, names: &mut Names, mut element: Element) -> Value {
+        if element.entries.is_empty() {
+            return Value::String(element.text.into());
+        }
+        if !element.text.is_empty() {
+            let key = names.name(&self.text_key);
+            let text = Value::String(element.text.into());
+            element.entries.entry(key).or_default().push(text);
+        }
+        let mut keys = Vec::with_capacity(element.entries.len());
+        let mut values = Vec::with_capacity(element.entries.len());
+        for (key, mut contents) in element.entries {
+            keys.push(Value::String(key));
+            values.push(if contents.len() == 1 {
+                contents.pop().unwrap()
+            } else {
+                Value::Seq(Arc::new(contents))
+            });
+        }
+        Value::Map(Arc::new(KV(names.keys(keys), values)))
+    }
+
+    /// Reads an XML document into a map from the tag name of its root element to the
+    /// element.
+    ///
+    /// Whitespace around text is trimmed, and CDATA sections count as text. The text of
+    /// an element with children is concatenated, so mixed content loses its order.
+    /// Comments, processing instructions and the doctype are dropped, and names keep
+    /// their namespace prefixes.
+    pub fn read<R: BufRead>(&self, reader: R) -> Result<Value, XmlError> {
+        let mut reader = Reader::from_reader(reader);
+        reader.config_mut().trim_text(true);
+        let mut names = Names::default();
+        let mut document = Element {
+            name: "".into(),
+            entries: BTreeMap::new(),
+            text: String::new(),
+        };
+        let mut open: Vec<Element> = Vec::new();
+        let mut buf = Vec::new();
+        loop {
+            let closed = match reader.read_event_into(&mut buf)? {
+                Event::Start(ref start) => {
+                    open.push(self.start(&reader, &mut names, start)?);
+                    None
+                }
+                Event::Empty(ref start) => Some(self.start(&reader, &mut names, start)?),
+                Event::End(_) => open.pop(),
+                Event::Text(ref text) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(&text.unescape()?);
+                    }
+                    None
+                }
+                Event::CData(ref data) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(&data.decode()?);
+                    }
+                    None
+                }
+                Event::Eof => break,
+                _ => None,
+            };
+            if let Some(element) = closed {
+                let name = element.name.clone();
+                let value = self.finish(&mut names, element);
+                open.last_mut()
+                    .unwrap_or(&mut document)
+                    .entries
+                    .entry(name)
+                    .or_default()
+                    .push(value);
+            }
+            buf.clear();
+        }
+        if let Some(element) = open.pop() {
+            let missing = IllFormedError::MissingEndTag(element.name.to_string());
+            return Err(XmlError::Syntax(missing.into()));
+        }
+        if document.entries.is_empty() {
+            return Err(XmlError::NoRoot);
+        }
+        Ok(self.finish(&mut names, document))
+    }
+
+    fn write_element<W: io::Write>(
+        &self,
+        writer: &mut Writer<W>,
+        name: &str,
+        value: &Value,
+        pointer: &str,
+    ) -> Result<(), XmlError> {
+        let kv = match *value {
+            Value::Seq(ref elements) | Value::Tuple(ref elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let pointer = path::join(pointer, &i.to_string());
+                    self.write_element(writer, name, element, &pointer)?;
+                }
+                return Ok(());
+            }
+            Value::Option(Some(ref v))
+            | Value::Newtype(ref v)
+            | Value::Ext { value: ref v, .. } => {
+                return self.write_element(writer, name, v, pointer)
+            }
+            Value::Map(ref kv) => kv,
+            ref other => {
+                let text = text(other, pointer)?;
+                return write_text_element(writer, BytesStart::new(name), text.as_deref());
+            }
+        };
+        let mut start = BytesStart::new(name);
+        let mut content = None;
+        let mut children = Vec::new();
+        for (key, value) in kv.keys().iter().zip(kv.values()) {
+            let pointer = path::join(pointer, &path::key_segment(key));
+            let key = match *key {
+                Value::String(ref key) => key,
+                _ => return Err(XmlError::Unsupported(pointer)),
+            };
+            if **key == *self.text_key {
+                content = text(value, &pointer)?;
+            } else if key.starts_with(&*self.attribute_prefix) {
+                let attribute = &key[self.attribute_prefix.len()..];
+                if let Some(value) = text(value, &pointer)? {
+                    start.push_attribute((attribute, &*value));
+                }
+            } else {
+                children.push((key, value, pointer));
+            }
+        }
+        if children.is_empty() {
+            return write_text_element(writer, start, content.as_deref());
+        }
+        writer.write_event(Event::Start(start))?;
+        if let Some(text) = content {
+            writer.write_event(Event::Text(BytesText::new(&text)))?;
+        }
+        for (key, value, pointer) in children {
+            self.write_element(writer, key, value, &pointer)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(name)))?;
+        Ok(())
+    }
+
+    /// Writes a map from tag names to elements as XML, the inverse of `read`.
+    ///
+    /// A document needs exactly one root element to be well formed, which is left to the
+    /// caller. Sequences become repeated elements, scalars become text, and units and
+    /// `None` become empty elements or are left out as attributes. Bytes, sets and
+    /// non-string keys have no representation.
+    pub fn write<W: io::Write>(&self, value: &Value, writer: W) -> Result<(), XmlError> {
+        let kv = match *value {
+            Value::Map(ref kv) => kv,
+            _ => return Err(XmlError::Unsupported(String::new())),
+        };
+        let mut writer = Writer::new(writer);
+        for (key, value) in kv.keys().iter().zip(kv.values()) {
+            let pointer = path::join("", &path::key_segment(key));
+            match *key {
+                Value::String(ref name) => self.write_element(&mut writer, name, value, &pointer)?,
+                _ => return Err(XmlError::Unsupported(pointer)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads an XML document with the default `XmlOptions`.
+pub fn from_xml_reader<R: BufRead>(reader: R) -> Result<Value, XmlError> {
+    XmlOptions::new().read(reader)
+}
+
+/// Writes a value as XML with the default `XmlOptions`.
+pub fn to_xml_writer<W: io::Write>(value: &Value, writer: W) -> Result<(), XmlError> {
+    XmlOptions::new().write(value, writer)
+}